@@ -0,0 +1,113 @@
+//! A heart rate peripheral exposing the Heart Rate service (0x180D) with a Heart Rate Measurement
+//! characteristic (0x2A37) that notifies a simulated BPM value every second.
+
+use futures::{channel::mpsc::channel, prelude::*};
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use uuid::Uuid;
+
+use bluster::{
+    gatt::{
+        characteristic,
+        characteristic::Characteristic,
+        event::{Event, Response},
+        service::Service,
+    },
+    Peripheral, SdpShortUuid,
+};
+
+const ADVERTISING_NAME: &str = "bluster heart rate";
+const HEART_RATE_SERVICE: u16 = 0x180D;
+const HEART_RATE_MEASUREMENT: u16 = 0x2A37;
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Flags byte `0x00` means the value is a single `u8` BPM without any of the optional fields.
+fn heart_rate_measurement(bpm: u8) -> Vec<u8> {
+    vec![0x00, bpm]
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let (sender, mut receiver) = channel(1);
+
+    // `Characteristic` hashes by its UUID and instance, the senders it holds don't change that
+    #[allow(clippy::mutable_key_type)]
+    let mut characteristics = HashSet::new();
+    characteristics.insert(Characteristic::new(
+        Uuid::from_sdp_short_uuid(HEART_RATE_MEASUREMENT),
+        characteristic::Properties::new(
+            Some(characteristic::Read(characteristic::Secure::Insecure(
+                sender.clone(),
+            ))),
+            None,
+            Some(sender),
+            None,
+        ),
+        None,
+        HashSet::new(),
+    ));
+
+    let peripheral = Peripheral::new().await.unwrap();
+    while !peripheral.is_powered().await.unwrap() {}
+    peripheral
+        .add_service(&Service::new(
+            Uuid::from_sdp_short_uuid(HEART_RATE_SERVICE),
+            true,
+            characteristics,
+        ))
+        .unwrap();
+    peripheral.register_gatt().await.unwrap();
+    peripheral
+        .start_advertising(
+            ADVERTISING_NAME,
+            &[Uuid::from_sdp_short_uuid(HEART_RATE_SERVICE)],
+        )
+        .await
+        .unwrap();
+    println!("Advertising as \"{}\"", ADVERTISING_NAME);
+
+    let notifying = Arc::new(AtomicBool::new(false));
+    while let Some(event) = receiver.next().await {
+        match event {
+            Event::ReadRequest(read_request) => {
+                read_request
+                    .response
+                    .send(Response::Success(heart_rate_measurement(60)))
+                    .unwrap();
+            }
+            Event::NotifySubscribe(notify_subscribe) => {
+                println!("Central subscribed to heart rate measurements");
+                notifying.store(true, Ordering::Relaxed);
+                let notifying = Arc::clone(&notifying);
+                let mut notification = notify_subscribe.notification;
+                tokio::spawn(async move {
+                    let mut bpm: u8 = 60;
+                    while notifying.load(Ordering::Relaxed) {
+                        bpm = if bpm >= 100 { 60 } else { bpm + 1 };
+                        println!("Notifying {} BPM", bpm);
+                        if notification
+                            .send(heart_rate_measurement(bpm))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                        tokio::time::sleep(NOTIFY_INTERVAL).await;
+                    }
+                });
+            }
+            Event::NotifyUnsubscribe => {
+                println!("Central unsubscribed from heart rate measurements");
+                notifying.store(false, Ordering::Relaxed);
+            }
+            Event::NotifyQueueDrained(_) => {}
+            Event::WriteRequest(_) => unreachable!("Heart rate measurement is not writable"),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Streams whatever is typed on stdin to the centrals subscribed to a custom notify
+//! characteristic, one line at a time. Lines longer than a notification are split into chunks
+//! that fit the subscribers' MTU, and the next line is only sent once the transmit queue took the
+//! previous one.
+
+use futures::{channel::mpsc::channel, prelude::*};
+use std::{
+    collections::HashSet,
+    io::{self, BufRead},
+    thread,
+    time::Instant,
+};
+use uuid::Uuid;
+
+use bluster::{
+    gatt::{characteristic, characteristic::Characteristic, event::Event, service::Service},
+    Peripheral,
+};
+
+const ADVERTISING_NAME: &str = "bluster stdin";
+const STREAM_SERVICE: &str = "6e400001-b5a3-f393-e0a9-e50e24dcca9e";
+const STREAM_CHARACTERISTIC: &str = "6e400003-b5a3-f393-e0a9-e50e24dcca9e";
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let service_uuid = Uuid::parse_str(STREAM_SERVICE).unwrap();
+    let characteristic_uuid = Uuid::parse_str(STREAM_CHARACTERISTIC).unwrap();
+    let (sender, mut receiver) = channel(1);
+
+    // Only the UUID and instance of a characteristic are hashed
+    #[allow(clippy::mutable_key_type)]
+    let mut characteristics = HashSet::new();
+    characteristics.insert(Characteristic::new(
+        characteristic_uuid,
+        characteristic::Properties::new(None, None, Some(sender), None),
+        None,
+        HashSet::new(),
+    ));
+
+    let peripheral = Peripheral::new().await.unwrap();
+    peripheral.wait_until_powered_on().await.unwrap();
+    peripheral
+        .add_service(&Service::new(service_uuid, true, characteristics))
+        .unwrap();
+    peripheral.register_gatt().await.unwrap();
+    peripheral
+        .start_advertising(ADVERTISING_NAME, &[service_uuid])
+        .await
+        .unwrap();
+    println!("Advertising as \"{}\"", ADVERTISING_NAME);
+
+    tokio::spawn(async move {
+        while let Some(event) = receiver.next().await {
+            match event {
+                Event::NotifySubscribe(_) => println!("Central subscribed"),
+                Event::NotifyUnsubscribe => println!("Central unsubscribed"),
+                _ => {}
+            }
+        }
+    });
+
+    // Reading stdin blocks, so it's done on a thread of its own
+    let (mut line_sender, mut lines) = channel::<String>(1);
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if futures::executor::block_on(line_sender.send(line)).is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(line) = lines.next().await {
+        let value = format!("{}\n", line).into_bytes();
+        let length = peripheral.max_notification_length(&characteristic_uuid);
+        let started = Instant::now();
+        match peripheral
+            .notify_chunked(&characteristic_uuid, &value)
+            .await
+        {
+            Ok(()) => println!(
+                "Sent {} bytes in chunks of {} within {:?}",
+                value.len(),
+                length,
+                started.elapsed()
+            ),
+            Err(err) => println!("{}", err),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    windows
+))]
+use log::warn;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+use uuid::Uuid;
+
+use crate::uuid::ShortUuid;
+
+/// How many bytes a legacy advertisement or scan response carries.
+const LEGACY_ADVERTISEMENT_LENGTH: usize = 31;
+
+/// The flags both backends put in front of the fields, with their length and type.
+const FLAGS_LENGTH: usize = 3;
+
+/// Data to include in an advertisement.
+///
+/// Not every backend can advertise every field, CoreBluetooth for example only allows the local
+/// name and service UUIDs to be set when acting as a peripheral. Backends return an error when
+/// asked to advertise a field they can't honor, except for the interval hints.
+#[derive(Debug, Clone)]
+pub struct AdvertisingOptions {
+    pub local_name: Option<String>,
+    pub service_uuids: Vec<Uuid>,
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    /// The company identifier assigned by the Bluetooth SIG and the data following it. BlueZ and
+    /// Windows advertise it, CoreBluetooth doesn't let peripherals set it.
+    pub manufacturer_data: Option<(u16, Vec<u8>)>,
+    /// Bounds for the time between advertising events, e.g. long intervals save battery on
+    /// beacons. BlueZ passes them on with millisecond precision, CoreBluetooth and Windows ignore
+    /// them as they don't let peripherals choose an interval.
+    pub min_interval: Option<Duration>,
+    pub max_interval: Option<Duration>,
+    /// Whether centrals may connect, `true` by default. Beacons that only broadcast turn it off,
+    /// which BlueZ and Windows honor. CoreBluetooth always advertises as connectable and logs a
+    /// warning instead.
+    pub connectable: bool,
+    /// The fields to send in the scan response rather than the advertisement, which keeps the
+    /// advertisement short. BlueZ honors it through its experimental `ScanResponse` properties,
+    /// which need `bluetoothd` to run with `--experimental`. CoreBluetooth and Windows decide by
+    /// themselves and ignore it.
+    pub scan_response: HashSet<AdvertisingField>,
+}
+
+impl Default for AdvertisingOptions {
+    fn default() -> Self {
+        AdvertisingOptions {
+            local_name: None,
+            service_uuids: Vec::new(),
+            service_data: HashMap::new(),
+            manufacturer_data: None,
+            min_interval: None,
+            max_interval: None,
+            connectable: true,
+            scan_response: HashSet::new(),
+        }
+    }
+}
+
+impl AdvertisingOptions {
+    pub fn new(local_name: Option<String>, service_uuids: Vec<Uuid>) -> Self {
+        AdvertisingOptions {
+            local_name,
+            service_uuids,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_manufacturer_data(mut self, company_id: u16, data: Vec<u8>) -> Self {
+        self.manufacturer_data = Some((company_id, data));
+        self
+    }
+
+    pub fn with_interval(mut self, min_interval: Duration, max_interval: Duration) -> Self {
+        self.min_interval = Some(min_interval);
+        self.max_interval = Some(max_interval);
+        self
+    }
+
+    pub fn with_connectable(mut self, connectable: bool) -> Self {
+        self.connectable = connectable;
+        self
+    }
+
+    /// Sends `field` in the scan response, see `scan_response`.
+    pub fn with_scan_response(mut self, field: AdvertisingField) -> Self {
+        self.scan_response.insert(field);
+        self
+    }
+
+    /// The fields that don't fit into the 31 bytes of a legacy advertisement, or of the scan
+    /// response for the fields in `scan_response`. The service UUIDs, service data and
+    /// manufacturer data are placed first and the local name last, as the systems move the name
+    /// to the scan response or shorten it, where some scanners never see it. This is an estimate
+    /// of the encoding, the systems may add fields of their own, e.g. the transmit power.
+    pub fn truncated_fields(&self) -> Vec<AdvertisingField> {
+        let mut advertisement = Packet::new(FLAGS_LENGTH);
+        let mut scan_response = Packet::new(0);
+        let mut truncated = vec![];
+        let mut place = |field: AdvertisingField, length: usize, uuid_size: Option<usize>| {
+            let packet = if self.scan_response.contains(&field) {
+                &mut scan_response
+            } else {
+                &mut advertisement
+            };
+            if !packet.add(length, uuid_size) {
+                truncated.push(field);
+            }
+        };
+        for uuid in &self.service_uuids {
+            let size = uuid_size(uuid);
+            place(AdvertisingField::ServiceUuid(*uuid), size, Some(size));
+        }
+        let mut service_data = self.service_data.iter().collect::<Vec<_>>();
+        service_data.sort_by_key(|(uuid, _)| **uuid);
+        for (uuid, data) in service_data {
+            let length = 2 + uuid_size(uuid) + data.len();
+            place(AdvertisingField::ServiceData(*uuid), length, None);
+        }
+        if let Some((_, data)) = &self.manufacturer_data {
+            place(AdvertisingField::ManufacturerData, 4 + data.len(), None);
+        }
+        if let Some(local_name) = &self.local_name {
+            place(AdvertisingField::LocalName, 2 + local_name.len(), None);
+        }
+        truncated
+    }
+
+    /// Tells why some scanners won't see part of the advertisement, see `truncated_fields`.
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        windows
+    ))]
+    pub(crate) fn warn_if_truncated(&self) {
+        let truncated = self.truncated_fields();
+        if !truncated.is_empty() {
+            warn!(
+                "Advertisement exceeds {} bytes, {:?} won't be seen by every scanner",
+                LEGACY_ADVERTISEMENT_LENGTH, truncated
+            );
+        }
+    }
+}
+
+/// A field of `AdvertisingOptions`, see `AdvertisingOptions::truncated_fields`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AdvertisingField {
+    LocalName,
+    ServiceUuid(Uuid),
+    ServiceData(Uuid),
+    ManufacturerData,
+}
+
+/// The bytes taken up so far in an advertisement or scan response.
+struct Packet {
+    length: usize,
+    /// Each size of UUID is listed in a field of its own, whose header is paid once.
+    uuid_lists: HashSet<usize>,
+}
+
+impl Packet {
+    fn new(length: usize) -> Self {
+        Packet {
+            length,
+            uuid_lists: HashSet::new(),
+        }
+    }
+
+    /// Adds a field of `length` bytes if it fits, a UUID of `uuid_size` bytes joins the list of
+    /// its size.
+    fn add(&mut self, length: usize, uuid_size: Option<usize>) -> bool {
+        let length = match uuid_size {
+            Some(size) if !self.uuid_lists.contains(&size) => length + 2,
+            _ => length,
+        };
+        if self.length + length > LEGACY_ADVERTISEMENT_LENGTH {
+            return false;
+        }
+        self.length += length;
+        self.uuid_lists.extend(uuid_size);
+        true
+    }
+}
+
+/// The bytes `uuid` takes up in an advertisement, SIG assigned UUIDs are sent in their short form.
+fn uuid_size(uuid: &Uuid) -> usize {
+    match ShortUuid::from_uuid(uuid) {
+        Some(_) if uuid.as_fields().0 <= 0xFFFF => 2,
+        Some(_) => 4,
+        None => 16,
+    }
+}
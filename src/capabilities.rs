@@ -0,0 +1,29 @@
+/// What the backend behind `Peripheral` supports, see `Peripheral::capabilities`. Lets
+/// cross-platform apps leave out what a platform can't do instead of handling the error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Whether `AdvertisingOptions::service_data` can be advertised.
+    pub advertising_service_data: bool,
+    /// Whether manufacturer specific data can be advertised.
+    pub advertising_manufacturer_data: bool,
+    /// Whether the hints of `AdvertisingOptions::with_interval` are passed on.
+    pub advertising_interval: bool,
+    /// Whether `AdvertisingOptions::connectable` can be turned off.
+    pub advertising_non_connectable: bool,
+    /// Whether `AdvertisingOptions::scan_response` decides which fields go in the scan response.
+    pub advertising_scan_response: bool,
+    /// Whether `Peripheral::central_rssi` can read the RSSI of a central.
+    pub central_rssi: bool,
+    /// Whether the descriptors of characteristics are served. CoreBluetooth only serves User
+    /// Description and Presentation Format descriptors with a fixed value.
+    pub descriptors: bool,
+    /// Whether `Peripheral::subscribers`, `Peripheral::is_subscribed` and `Peripheral::centrals`
+    /// know which centrals subscribed.
+    pub subscribers: bool,
+    /// Whether services can still be added once the GATT application is registered.
+    pub services_after_registration: bool,
+    /// Whether `PeripheralBuilder::restore_identifier` is supported.
+    pub state_restoration: bool,
+    /// See `Peripheral::max_connections`.
+    pub max_connections: Option<usize>,
+}
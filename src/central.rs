@@ -0,0 +1,63 @@
+use std::{collections::HashSet, fmt};
+use uuid::Uuid;
+
+/// Identifies a central connected to the peripheral.
+///
+/// On BlueZ this is the D-Bus object path of the remote device, on CoreBluetooth it is the
+/// `identifier` of the `CBCentral`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CentralId(pub(crate) String);
+
+impl CentralId {
+    pub fn new<T: Into<String>>(id: T) -> Self {
+        CentralId(id.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The central a request came from, as handed to the handlers.
+///
+/// On CoreBluetooth the values are read off the `CBCentral` while the delegate callback still
+/// holds it, so no Objective-C object is kept alive past the callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Central {
+    id: CentralId,
+    maximum_update_value_length: usize,
+}
+
+impl Central {
+    pub fn new(id: CentralId, maximum_update_value_length: usize) -> Self {
+        Central {
+            id,
+            maximum_update_value_length,
+        }
+    }
+
+    pub fn id(&self) -> &CentralId {
+        &self.id
+    }
+
+    /// How many bytes fit into one notification to the central, its ATT MTU minus the 3 byte
+    /// header.
+    pub fn maximum_update_value_length(&self) -> usize {
+        self.maximum_update_value_length
+    }
+}
+
+/// A central subscribed to at least one characteristic, see `Peripheral::centrals`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CentralInfo {
+    pub id: CentralId,
+    /// How many bytes fit into one notification to the central.
+    pub maximum_update_value_length: usize,
+    pub subscriptions: HashSet<Uuid>,
+}
+
+impl fmt::Display for CentralId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
@@ -4,7 +4,9 @@ use std::{error, fmt};
 pub enum ErrorType {
     Bluez,
     CoreBluetooth,
+    Windows,
     Usb,
+    Unsupported,
 }
 
 impl From<ErrorType> for &'static str {
@@ -12,7 +14,9 @@ impl From<ErrorType> for &'static str {
         match error_type {
             ErrorType::Bluez => "Bluez",
             ErrorType::CoreBluetooth => "CoreBluetooth",
+            ErrorType::Windows => "Windows",
             ErrorType::Usb => "USB",
+            ErrorType::Unsupported => "Unsupported",
         }
     }
 }
@@ -32,6 +36,8 @@ pub struct Error {
     description: String,
     combined_description: String,
     error_type: ErrorType,
+    /// The domain and code of the platform's error, e.g. of an `NSError`.
+    code: Option<(String, i64)>,
 }
 
 impl Error {
@@ -44,8 +50,27 @@ impl Error {
             description,
             combined_description,
             error_type,
+            code: None,
         }
     }
+
+    /// Keeps the domain and code the platform reported the error with, which unlike the
+    /// description don't depend on the locale.
+    pub fn with_code<T: Into<String>>(mut self, domain: T, code: i64) -> Self {
+        let domain = domain.into();
+        self.combined_description =
+            format!("{}: {} ({} {})", self.name, self.description, domain, code);
+        self.code = Some((domain, code));
+        self
+    }
+
+    pub fn domain(&self) -> Option<&str> {
+        self.code.as_ref().map(|(domain, _)| domain.as_str())
+    }
+
+    pub fn code(&self) -> Option<i64> {
+        self.code.as_ref().map(|(_, code)| *code)
+    }
 }
 
 impl fmt::Display for Error {
@@ -55,7 +80,11 @@ impl fmt::Display for Error {
             f,
             "**Bluster {} Error**\n\n\t{}:\n\t\t{}",
             error_type, self.name, self.description,
-        )
+        )?;
+        match &self.code {
+            Some((domain, code)) => write!(f, " ({} {})", domain, code),
+            None => Ok(()),
+        }
     }
 }
 
@@ -68,3 +97,27 @@ impl error::Error for Error {
         Some(&self.error_type)
     }
 }
+
+/// Why `Peripheral::notify` didn't send a notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyError {
+    /// None of the services currently served contains the characteristic.
+    UnknownCharacteristic,
+    /// No central is subscribed to the characteristic.
+    NoSubscribers,
+    /// The queue of outgoing notifications didn't take the value.
+    QueueFull,
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(self: &Self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            NotifyError::UnknownCharacteristic => "the characteristic isn't served",
+            NotifyError::NoSubscribers => "no central is subscribed to the characteristic",
+            NotifyError::QueueFull => "the notification queue is full",
+        };
+        write!(f, "Notification not sent, {}", description)
+    }
+}
+
+impl error::Error for NotifyError {}
@@ -0,0 +1,35 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+use crate::CentralId;
+
+/// The centrals allowed to read and write a characteristic. Everyone else is answered with
+/// `AttError::InsufficientAuthorization` before the handler sees the request.
+///
+/// Clones share the same list, so centrals can be allowed or revoked after the characteristic
+/// was added.
+#[derive(Debug, Clone, Default)]
+pub struct CharacteristicAcl {
+    allowed: Arc<RwLock<HashSet<CentralId>>>,
+}
+
+impl CharacteristicAcl {
+    pub fn new() -> Self {
+        CharacteristicAcl::default()
+    }
+
+    pub fn allow(&self, central: CentralId) {
+        self.allowed.write().unwrap().insert(central);
+    }
+
+    pub fn revoke(&self, central: &CentralId) {
+        self.allowed.write().unwrap().remove(central);
+    }
+
+    /// A request whose central the backend couldn't tell is never allowed.
+    pub fn is_allowed(&self, central: Option<&CentralId>) -> bool {
+        central.is_some_and(|central| self.allowed.read().unwrap().contains(central))
+    }
+}
@@ -1,16 +1,45 @@
-use super::{descriptor::Descriptor, event::EventSender};
+use super::{
+    acl::CharacteristicAcl,
+    descriptor::{self, Descriptor, USER_DESCRIPTION_UUID},
+    event::{AttError, EventSender},
+    notify_policy::NotifyPolicy,
+    notify_queue::NotifyQueue,
+    write_schema::WriteSchema,
+};
 use std::{
     collections::HashSet,
+    fmt,
     hash::{Hash, Hasher},
 };
 use uuid::Uuid;
 
+use crate::{uuid::display_uuid, CentralId, SdpShortUuid};
+
 #[derive(Debug, Clone)]
 pub struct Characteristic {
     pub(crate) uuid: Uuid,
     pub(crate) properties: Properties,
     pub(crate) value: Option<Vec<u8>>,
     pub(crate) descriptors: HashSet<Descriptor>,
+    pub(crate) max_value_length: Option<usize>,
+    pub(crate) notify_policy: Option<NotifyPolicy>,
+    pub(crate) notify_queue: Option<NotifyQueue>,
+    pub(crate) acl: Option<CharacteristicAcl>,
+    pub(crate) write_schema: Option<WriteSchema>,
+    pub(crate) redact: bool,
+    pub(crate) instance: u16,
+    /// Property and permission bits from `from_raw`, handed to the backend instead of the ones
+    /// derived from `properties`.
+    pub(crate) raw_flags: Option<(u8, u8)>,
+}
+
+/// Identifies one characteristic of an added service, even if the service has several with the
+/// same UUID. See `Characteristic::with_instance`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CharacteristicHandle {
+    pub service: Uuid,
+    pub characteristic: Uuid,
+    pub instance: u16,
 }
 
 impl Characteristic {
@@ -25,10 +54,300 @@ impl Characteristic {
             properties,
             value,
             descriptors,
+            max_value_length: None,
+            notify_policy: None,
+            notify_queue: None,
+            acl: None,
+            write_schema: None,
+            redact: false,
+            instance: 0,
+            raw_flags: None,
         }
     }
+
+    /// Declares the characteristic with raw property bits, as in the Characteristic Properties
+    /// byte of the Bluetooth Core Specification, Vol 3, Part G, 3.3.1.1, and raw
+    /// `CBAttributePermissions` bits. They are handed to the backend as they are, BlueZ gets the
+    /// flags they stand for.
+    ///
+    /// Nothing checks the bits. CoreBluetooth rejects a service whose bits contradict each other
+    /// or the value, e.g. a cached value on a writable characteristic, and the characteristic has
+    /// no handlers: only what the backend serves by itself, like a static `value`, works. Reads
+    /// and writes reaching the crate are answered as for a characteristic without the property.
+    pub fn from_raw(uuid: Uuid, properties: u8, permissions: u8, value: Option<Vec<u8>>) -> Self {
+        let mut characteristic = Characteristic::new(
+            uuid,
+            Properties::new(None, None, None, None),
+            value,
+            HashSet::new(),
+        );
+        characteristic.raw_flags = Some((properties, permissions));
+        characteristic
+    }
+
+    /// Longest value a central may write, counting the offset. Longer writes are answered with
+    /// `AttError::InvalidAttributeValueLength` and never reach the write handler. Without it the
+    /// service's default applies, see `Service::with_max_value_length`.
+    pub fn with_max_value_length(mut self, max_value_length: usize) -> Self {
+        self.max_value_length = Some(max_value_length);
+        self
+    }
+
+    /// Rate limits the notifications and indications the handler sends through the `notification`
+    /// channel of `NotifySubscribe`. Values passed to `Peripheral::notify` and its variants
+    /// aren't limited.
+    pub fn with_notify_policy(mut self, notify_policy: NotifyPolicy) -> Self {
+        self.notify_policy = Some(notify_policy);
+        self
+    }
+
+    /// Buffers notifications while the transmit queue is full, see `NotifyQueue`.
+    pub fn with_notify_queue(mut self, notify_queue: NotifyQueue) -> Self {
+        self.notify_queue = Some(notify_queue);
+        self
+    }
+
+    /// Only lets the centrals on `acl` read and write the characteristic.
+    pub fn with_acl(mut self, acl: CharacteristicAcl) -> Self {
+        self.acl = Some(acl);
+        self
+    }
+
+    /// Answers writes that don't match `write_schema` before they reach the write handler.
+    pub fn with_write_schema(mut self, write_schema: WriteSchema) -> Self {
+        self.write_schema = Some(write_schema);
+        self
+    }
+
+    /// Keeps the values written to the characteristic, e.g. keys or credentials, out of the log,
+    /// only their length is logged.
+    pub fn with_redaction(mut self, redact: bool) -> Self {
+        self.redact = redact;
+        self
+    }
+
+    /// Tells characteristics with the same UUID apart, as GATT allows a service to repeat a UUID.
+    /// Two characteristics are only the same if both their UUID and instance match, so a service
+    /// keeps every instance, which it has to allow with
+    /// `Service::allow_duplicate_characteristics`. Defaults to 0.
+    pub fn with_instance(mut self, instance: u16) -> Self {
+        self.instance = instance;
+        self
+    }
+
+    /// Adds a Characteristic User Description (0x2901) descriptor, the label generic clients
+    /// show for the characteristic. Replaces a description added before.
+    pub fn with_description<T: Into<String>>(mut self, description: T) -> Self {
+        self.descriptors.replace(Descriptor::new(
+            Uuid::from_sdp_short_uuid(USER_DESCRIPTION_UUID),
+            descriptor::Properties::new(None, None),
+            Some(description.into().into_bytes()),
+        ));
+        self
+    }
+
+    /// The read property a read is handed to, or the error the central is answered with if the
+    /// characteristic doesn't declare one. Backends check this before any handler sees the
+    /// request, so a central can't get around the declared properties.
+    pub fn check_read(&self) -> Result<Read, AttError> {
+        self.properties
+            .read
+            .clone()
+            .ok_or(AttError::ReadNotPermitted)
+    }
+
+    /// Like `check_read` for writes. A characteristic that can be written, but not the way the
+    /// central tried to, answers `AttError::RequestNotSupported`.
+    pub fn check_write(&self, without_response: bool) -> Result<Write, AttError> {
+        match &self.properties.write {
+            None => Err(AttError::WriteNotPermitted),
+            Some(Write::WithResponse(_)) if without_response => Err(AttError::RequestNotSupported),
+            Some(Write::WithoutResponse(_)) if !without_response => {
+                Err(AttError::RequestNotSupported)
+            }
+            Some(write) => Ok(write.clone()),
+        }
+    }
+
+    /// Whether both are registered the same way, only their handlers and the checks done before
+    /// a request reaches them may differ.
+    #[cfg_attr(any(target_os = "linux", windows), allow(dead_code))]
+    pub(crate) fn same_layout(&self, other: &Characteristic) -> bool {
+        self.uuid == other.uuid
+            && self.instance == other.instance
+            && self.value == other.value
+            && self.max_value_length == other.max_value_length
+            && self.properties.layout() == other.properties.layout()
+            && self.raw_flags == other.raw_flags
+            && self.descriptors.len() == other.descriptors.len()
+            && self.descriptors.iter().all(|descriptor| {
+                other
+                    .descriptors
+                    .get(descriptor)
+                    .is_some_and(|other| descriptor.same_layout(other))
+            })
+    }
+
+    /// The characteristic's lines of `Service::describe`.
+    pub(crate) fn describe(&self) -> String {
+        let mut attributes = match self.raw_flags {
+            Some((properties, permissions)) => vec![format!(
+                "raw properties 0x{:02X} permissions 0x{:02X}",
+                properties, permissions
+            )],
+            None => self.properties.describe(),
+        };
+        attributes.push(describe_value(&self.value));
+        if let Some(max_value_length) = self.max_value_length {
+            attributes.push(format!("max length {}", max_value_length));
+        }
+        let mut description = format!(
+            "  characteristic {} #{}: {}\n",
+            display_uuid(&self.uuid),
+            self.instance,
+            attributes.join(", ")
+        );
+        let mut descriptors = self.descriptors.iter().collect::<Vec<_>>();
+        descriptors.sort_by_key(|descriptor| descriptor.uuid);
+        for descriptor in descriptors {
+            description.push_str(&descriptor.describe());
+        }
+        description
+    }
+
+    /// The declaration and the value, one handle per descriptor and the Client Characteristic
+    /// Configuration descriptor the stack adds if the characteristic notifies or indicates.
+    pub(crate) fn handle_count(&self) -> usize {
+        let subscribable = match self.raw_flags {
+            // Notify 0x10 and indicate 0x20
+            Some((properties, _)) => properties & 0x30 != 0,
+            None => self.properties.notify.is_some() || self.properties.indicate.is_some(),
+        };
+        2 + self.descriptors.len() + usize::from(subscribable)
+    }
+
+    pub(crate) fn handle(&self, service: Uuid) -> CharacteristicHandle {
+        CharacteristicHandle {
+            service,
+            characteristic: self.uuid,
+            instance: self.instance,
+        }
+    }
+
+    pub(crate) fn authorizes(&self, central: Option<&CentralId>) -> bool {
+        self.acl.as_ref().is_none_or(|acl| acl.is_allowed(central))
+    }
+
+    pub(crate) fn exceeds_max_value_length(&self, offset: u16, len: usize) -> bool {
+        self.max_value_length
+            .is_some_and(|max_value_length| offset as usize + len > max_value_length)
+    }
+
+    /// A value written to the characteristic as it's logged.
+    pub(crate) fn logged_value<'a>(&self, value: &'a [u8]) -> LoggedValue<'a> {
+        LoggedValue {
+            value,
+            redact: self.redact,
+        }
+    }
+
+    pub(crate) fn check_write_schema(&self, offset: u16, value: &[u8]) -> Result<(), AttError> {
+        self.write_schema
+            .as_ref()
+            .map_or(Ok(()), |write_schema| write_schema.check(offset, value))
+    }
+}
+
+/// The bytes of a value in hex, or only their count for characteristics with redaction.
+pub(crate) struct LoggedValue<'a> {
+    value: &'a [u8],
+    redact: bool,
+}
+
+impl fmt::Display for LoggedValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.redact {
+            return write!(f, "<redacted, {} bytes>", self.value.len());
+        }
+        for byte in self.value {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the reads of a characteristic are answered from, see `Peripheral::read_source`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadSource {
+    /// The value the characteristic was added with, its read handler is never asked.
+    Cached,
+    Handler,
+    /// Reads are answered with `AttError::ReadNotPermitted`.
+    NotReadable,
+}
+
+impl Hash for Characteristic {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.uuid.hash(state);
+        self.instance.hash(state);
+    }
+}
+
+impl PartialEq for Characteristic {
+    fn eq(&self, other: &Characteristic) -> bool {
+        self.uuid == other.uuid && self.instance == other.instance
+    }
 }
 
-impl_uuid_hash_eq!(Characteristic);
+impl Eq for Characteristic {}
 
 properties!(WriteWithAndWithoutResponse, EventSender, { notify: EventSender, indicate: EventSender });
+
+/// The length of a cached value, or that reads go to the handler.
+pub(crate) fn describe_value(value: &Option<Vec<u8>>) -> String {
+    match value {
+        Some(value) => format!("value {} bytes", value.len()),
+        None => String::from("no value"),
+    }
+}
+
+impl Properties {
+    fn describe(&self) -> Vec<String> {
+        let access = |name: &str, secure: &Secure| match secure {
+            Secure::Secure(_) => format!("{} (encrypted)", name),
+            Secure::Insecure(_) => name.to_string(),
+        };
+        let mut properties = vec![];
+        if let Some(read) = &self.read {
+            properties.push(access("read", read));
+        }
+        match &self.write {
+            Some(Write::WithResponse(secure)) => properties.push(access("write", secure)),
+            Some(Write::WithoutResponse(_)) => {
+                properties.push(String::from("write without response"))
+            }
+            None => {}
+        }
+        if self.notify.is_some() {
+            properties.push(String::from("notify"));
+        }
+        if self.indicate.is_some() {
+            properties.push(String::from("indicate"));
+        }
+        properties
+    }
+
+    /// Which properties are declared and whether they need an encrypted link, handlers aside.
+    fn layout(&self) -> (Option<bool>, Option<Option<bool>>, bool, bool) {
+        let is_secure = |secure: &Secure| matches!(secure, Secure::Secure(_));
+        (
+            self.read.as_ref().map(|read| is_secure(read)),
+            self.write.as_ref().map(|write| match write {
+                Write::WithResponse(secure) => Some(is_secure(secure)),
+                Write::WithoutResponse(_) => None,
+            }),
+            self.notify.is_some(),
+            self.indicate.is_some(),
+        )
+    }
+}
@@ -0,0 +1,234 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{characteristic, descriptor, event::EventSender, service::Service};
+
+/// Layout of a service that can be loaded from a file, e.g. JSON or TOML.
+///
+/// UUIDs are either the short form of SIG assigned ones, like `"180F"`, or the full form, values
+/// are hexadecimal strings. Handlers aren't part of the layout, `into_service` attaches them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceConfig {
+    #[serde(with = "uuid_string")]
+    pub uuid: Uuid,
+    #[serde(default = "primary")]
+    pub primary: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_value_length: Option<usize>,
+    #[serde(default)]
+    pub characteristics: Vec<CharacteristicConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CharacteristicConfig {
+    #[serde(with = "uuid_string")]
+    pub uuid: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read: Option<Access>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write: Option<WriteAccess>,
+    #[serde(default)]
+    pub notify: bool,
+    #[serde(default)]
+    pub indicate: bool,
+    #[serde(default, with = "hex_value", skip_serializing_if = "Option::is_none")]
+    pub value: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_value_length: Option<usize>,
+    #[serde(default)]
+    pub descriptors: Vec<DescriptorConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DescriptorConfig {
+    #[serde(with = "uuid_string")]
+    pub uuid: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read: Option<Access>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write: Option<Access>,
+    #[serde(default, with = "hex_value", skip_serializing_if = "Option::is_none")]
+    pub value: Option<Vec<u8>>,
+}
+
+/// Whether reading or writing needs an encrypted link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Access {
+    Secure,
+    Insecure,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteAccess {
+    Secure,
+    Insecure,
+    WithoutResponse,
+}
+
+fn primary() -> bool {
+    true
+}
+
+impl Access {
+    fn secure(self, event_sender: EventSender) -> characteristic::Secure {
+        match self {
+            Access::Secure => characteristic::Secure::Secure(event_sender),
+            Access::Insecure => characteristic::Secure::Insecure(event_sender),
+        }
+    }
+
+    fn descriptor_secure(self, event_sender: EventSender) -> descriptor::Secure {
+        match self {
+            Access::Secure => descriptor::Secure::Secure(event_sender),
+            Access::Insecure => descriptor::Secure::Insecure(event_sender),
+        }
+    }
+}
+
+impl ServiceConfig {
+    /// Builds the service, `handler` is asked for the event sender of every characteristic and
+    /// descriptor that can be read, written or subscribed to.
+    pub fn into_service<F>(self, mut handler: F) -> Service
+    where
+        F: FnMut(&Uuid) -> EventSender,
+    {
+        let service = Service::new(
+            self.uuid,
+            self.primary,
+            self.characteristics
+                .into_iter()
+                .map(|characteristic| characteristic.into_characteristic(&mut handler))
+                .collect(),
+        );
+        match self.max_value_length {
+            Some(max_value_length) => service.with_max_value_length(max_value_length),
+            None => service,
+        }
+    }
+}
+
+impl CharacteristicConfig {
+    fn into_characteristic<F>(self, handler: &mut F) -> characteristic::Characteristic
+    where
+        F: FnMut(&Uuid) -> EventSender,
+    {
+        let has_handler =
+            self.read.is_some() || self.write.is_some() || self.notify || self.indicate;
+        let event_sender = if has_handler {
+            Some(handler(&self.uuid))
+        } else {
+            None
+        };
+        let sender = || event_sender.clone().unwrap();
+        let properties = characteristic::Properties::new(
+            self.read
+                .map(|access| characteristic::Read(access.secure(sender()))),
+            self.write.map(|access| match access {
+                WriteAccess::Secure => {
+                    characteristic::Write::WithResponse(Access::Secure.secure(sender()))
+                }
+                WriteAccess::Insecure => {
+                    characteristic::Write::WithResponse(Access::Insecure.secure(sender()))
+                }
+                WriteAccess::WithoutResponse => characteristic::Write::WithoutResponse(sender()),
+            }),
+            if self.notify { Some(sender()) } else { None },
+            if self.indicate { Some(sender()) } else { None },
+        );
+        let characteristic = characteristic::Characteristic::new(
+            self.uuid,
+            properties,
+            self.value,
+            self.descriptors
+                .into_iter()
+                .map(|descriptor| descriptor.into_descriptor(handler))
+                .collect(),
+        );
+        match self.max_value_length {
+            Some(max_value_length) => characteristic.with_max_value_length(max_value_length),
+            None => characteristic,
+        }
+    }
+}
+
+impl DescriptorConfig {
+    fn into_descriptor<F>(self, handler: &mut F) -> descriptor::Descriptor
+    where
+        F: FnMut(&Uuid) -> EventSender,
+    {
+        let event_sender = if self.read.is_some() || self.write.is_some() {
+            Some(handler(&self.uuid))
+        } else {
+            None
+        };
+        let sender = || event_sender.clone().unwrap();
+        let properties = descriptor::Properties::new(
+            self.read
+                .map(|access| descriptor::Read(access.descriptor_secure(sender()))),
+            self.write
+                .map(|access| descriptor::Write(access.descriptor_secure(sender()))),
+        );
+        descriptor::Descriptor::new(self.uuid, properties, self.value)
+    }
+}
+
+mod uuid_string {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use uuid::Uuid;
+
+    use crate::ShortUuid;
+
+    pub fn serialize<S: Serializer>(uuid: &Uuid, serializer: S) -> Result<S::Ok, S::Error> {
+        match ShortUuid::from_uuid(uuid) {
+            Some(short_uuid) => serializer.collect_str(&short_uuid),
+            None => serializer.collect_str(&uuid.hyphenated()),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Uuid, D::Error> {
+        let uuid = String::deserialize(deserializer)?;
+        match uuid.parse::<ShortUuid>() {
+            Ok(short_uuid) => Ok(short_uuid.to_uuid()),
+            Err(_) => Uuid::parse_str(&uuid).map_err(D::Error::custom),
+        }
+    }
+}
+
+mod hex_value {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_str(
+                &value
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect::<String>(),
+            ),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        let value = match Option::<String>::deserialize(deserializer)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        if value.len() % 2 != 0 || !value.is_ascii() {
+            return Err(D::Error::custom(format!("invalid hex value {:?}", value)));
+        }
+        (0..value.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&value[index..index + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map(Some)
+            .map_err(|_| D::Error::custom(format!("invalid hex value {:?}", value)))
+    }
+}
@@ -0,0 +1,271 @@
+use futures::{channel::mpsc, prelude::*};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+};
+use uuid::Uuid;
+
+use super::event::{Event, EventSender, ReadRequest, WriteRequest};
+use crate::Central;
+
+/// Which characteristic a request handed to a default handler is for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RequestContext {
+    pub characteristic: Uuid,
+    pub central: Option<Central>,
+}
+
+impl RequestContext {
+    pub(crate) fn new(characteristic: Uuid, central: Option<Central>) -> Self {
+        RequestContext {
+            characteristic,
+            central,
+        }
+    }
+}
+
+pub type DefaultReadHandler = Arc<dyn Fn(RequestContext, ReadRequest) + Send + Sync>;
+pub type DefaultWriteHandler = Arc<dyn Fn(RequestContext, WriteRequest) + Send + Sync>;
+
+/// The event sender of a characteristic without a handler of its own. Its reads and writes go to
+/// the default handlers set on the `Peripheral`.
+///
+/// A characteristic's own handler always comes first, the default handlers only see requests for
+/// characteristics whose event sender nobody receives from, like this one. Without a default
+/// handler those requests fail.
+pub fn unhandled() -> EventSender {
+    mpsc::channel(0).0
+}
+
+/// The default handlers of a `Peripheral`, shared with the request callbacks. The write streams
+/// of `Peripheral::writes` are kept along with them, as they take writes before any handler.
+#[derive(Clone, Default)]
+pub(crate) struct DefaultHandlers {
+    read: Arc<RwLock<Option<DefaultReadHandler>>>,
+    write: Arc<RwLock<Option<DefaultWriteHandler>>>,
+    write_streams: Arc<Mutex<HashMap<Uuid, mpsc::UnboundedSender<WriteRequest>>>>,
+}
+
+impl DefaultHandlers {
+    pub fn set_read(&self, handler: DefaultReadHandler) {
+        self.read.write().unwrap().replace(handler);
+    }
+
+    pub fn set_write(&self, handler: DefaultWriteHandler) {
+        self.write.write().unwrap().replace(handler);
+    }
+
+    /// The writes to the characteristic until the receiver is dropped or another stream is
+    /// opened for it.
+    pub fn open_write_stream(&self, characteristic: Uuid) -> mpsc::UnboundedReceiver<WriteRequest> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.write_streams
+            .lock()
+            .unwrap()
+            .insert(characteristic, sender);
+        receiver
+    }
+
+    /// Hands the request to the characteristic's write stream, giving it back if it has none.
+    fn divert_write(&self, characteristic: &Uuid, request: WriteRequest) -> Option<WriteRequest> {
+        let mut write_streams = self.write_streams.lock().unwrap();
+        let result = match write_streams.get(characteristic) {
+            Some(write_stream) => write_stream.unbounded_send(request),
+            None => return Some(request),
+        };
+        match result {
+            Ok(()) => None,
+            Err(error) => {
+                write_streams.remove(characteristic);
+                Some(error.into_inner())
+            }
+        }
+    }
+
+    /// Hands the request to the characteristic's handler, or the default one if the
+    /// characteristic has none. Returns whether anyone got the request.
+    pub async fn send_read(
+        &self,
+        event_sender: &mut EventSender,
+        context: RequestContext,
+        request: ReadRequest,
+    ) -> bool {
+        if !event_sender.is_closed() {
+            return event_sender.send(Event::ReadRequest(request)).await.is_ok();
+        }
+        let handler = self.read.read().unwrap().clone();
+        handler.map(|handler| handler(context, request)).is_some()
+    }
+
+    /// Like `send_read`, but `None` means the request still has to be sent to the
+    /// characteristic's handler, which lets writes without response be queued in order.
+    pub fn try_default_write(
+        &self,
+        event_sender: &EventSender,
+        context: RequestContext,
+        request: WriteRequest,
+    ) -> Option<WriteRequest> {
+        let request = self.divert_write(&context.characteristic, request)?;
+        if !event_sender.is_closed() {
+            return Some(request);
+        }
+        if let Some(handler) = self.write.read().unwrap().clone() {
+            handler(context, request);
+        }
+        None
+    }
+
+    pub async fn send_write(
+        &self,
+        event_sender: &mut EventSender,
+        context: RequestContext,
+        request: WriteRequest,
+    ) -> bool {
+        let request = match self.divert_write(&context.characteristic, request) {
+            Some(request) => request,
+            None => return true,
+        };
+        if !event_sender.is_closed() {
+            return event_sender
+                .send(Event::WriteRequest(request))
+                .await
+                .is_ok();
+        }
+        let handler = self.write.read().unwrap().clone();
+        handler.map(|handler| handler(context, request)).is_some()
+    }
+}
+
+impl std::fmt::Debug for DefaultHandlers {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DefaultHandlers")
+            .field("read", &self.read.read().unwrap().is_some())
+            .field("write", &self.write.read().unwrap().is_some())
+            .field(
+                "write_streams",
+                &self
+                    .write_streams
+                    .lock()
+                    .unwrap()
+                    .keys()
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+// The default handlers are private to the backends, so the resolution order can't be tested from
+// `tests/`.
+#[cfg(test)]
+mod tests {
+    use super::{unhandled, DefaultHandlers, RequestContext};
+    use crate::gatt::event::{Event, ReadRequest, WriteRequest};
+    use futures::{
+        channel::{mpsc, oneshot},
+        prelude::*,
+    };
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    const CHARACTERISTIC: Uuid = Uuid::from_u128(0x2A37);
+
+    fn context() -> RequestContext {
+        RequestContext::new(CHARACTERISTIC, None)
+    }
+
+    fn read_request() -> ReadRequest {
+        ReadRequest {
+            offset: 0,
+            response: oneshot::channel().0,
+            mtu: 23,
+            central: None,
+        }
+    }
+
+    fn write_request(without_response: bool) -> WriteRequest {
+        WriteRequest {
+            data: vec![1],
+            offset: 0,
+            without_response,
+            response: oneshot::channel().0,
+            central: None,
+        }
+    }
+
+    /// Default handlers that record the characteristic of every request they get.
+    fn recording_defaults() -> (DefaultHandlers, Arc<Mutex<Vec<Uuid>>>) {
+        let defaults = DefaultHandlers::default();
+        let handled = Arc::new(Mutex::new(vec![]));
+        let reads = Arc::clone(&handled);
+        defaults.set_read(Arc::new(move |context, _| {
+            reads.lock().unwrap().push(context.characteristic)
+        }));
+        let writes = Arc::clone(&handled);
+        defaults.set_write(Arc::new(move |context, _| {
+            writes.lock().unwrap().push(context.characteristic)
+        }));
+        (defaults, handled)
+    }
+
+    #[tokio::test]
+    async fn own_handler_comes_before_the_default_one() {
+        let (defaults, handled) = recording_defaults();
+        let (mut event_sender, mut events) = mpsc::channel(2);
+
+        assert!(
+            defaults
+                .send_read(&mut event_sender, context(), read_request())
+                .await
+        );
+        assert!(
+            defaults
+                .send_write(&mut event_sender, context(), write_request(false))
+                .await
+        );
+        assert!(defaults
+            .try_default_write(&event_sender, context(), write_request(true))
+            .is_some());
+
+        assert!(matches!(events.next().await, Some(Event::ReadRequest(_))));
+        assert!(matches!(events.next().await, Some(Event::WriteRequest(_))));
+        assert!(handled.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn default_handler_takes_unhandled_characteristics() {
+        let (defaults, handled) = recording_defaults();
+
+        assert!(
+            defaults
+                .send_read(&mut unhandled(), context(), read_request())
+                .await
+        );
+        assert!(
+            defaults
+                .send_write(&mut unhandled(), context(), write_request(false))
+                .await
+        );
+        assert!(defaults
+            .try_default_write(&unhandled(), context(), write_request(true))
+            .is_none());
+
+        assert_eq!(*handled.lock().unwrap(), vec![CHARACTERISTIC; 3]);
+    }
+
+    #[tokio::test]
+    async fn unhandled_characteristics_fail_without_a_default_handler() {
+        let defaults = DefaultHandlers::default();
+
+        assert!(
+            !defaults
+                .send_read(&mut unhandled(), context(), read_request())
+                .await
+        );
+        assert!(
+            !defaults
+                .send_write(&mut unhandled(), context(), write_request(false))
+                .await
+        );
+    }
+}
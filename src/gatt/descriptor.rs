@@ -1,7 +1,11 @@
-use super::event::EventSender;
+use super::{characteristic::describe_value, event::EventSender};
 use std::hash::{Hash, Hasher};
 use uuid::Uuid;
 
+use crate::uuid::display_uuid;
+
+pub const USER_DESCRIPTION_UUID: u16 = 0x2901;
+
 #[derive(Debug, Clone)]
 pub struct Descriptor {
     pub(crate) uuid: Uuid,
@@ -22,3 +26,44 @@ impl Descriptor {
 impl_uuid_hash_eq!(Descriptor);
 
 properties!(WriteWithResponse, EventSender);
+
+impl Descriptor {
+    /// The descriptor's line of `Service::describe`.
+    pub(crate) fn describe(&self) -> String {
+        let mut attributes = vec![];
+        if let Some(read) = &self.properties.read {
+            attributes.push(match read.0 {
+                Secure::Secure(_) => "read (encrypted)",
+                Secure::Insecure(_) => "read",
+            });
+        }
+        if let Some(write) = &self.properties.write {
+            attributes.push(match write.0 {
+                Secure::Secure(_) => "write (encrypted)",
+                Secure::Insecure(_) => "write",
+            });
+        }
+        let value = describe_value(&self.value);
+        attributes.push(&value);
+        format!(
+            "    descriptor {}: {}\n",
+            display_uuid(&self.uuid),
+            attributes.join(", ")
+        )
+    }
+
+    /// Like `Characteristic::same_layout`.
+    #[cfg_attr(any(target_os = "linux", windows), allow(dead_code))]
+    pub(crate) fn same_layout(&self, other: &Descriptor) -> bool {
+        let layout = |properties: &Properties| {
+            let is_secure = |secure: &Secure| matches!(secure, Secure::Secure(_));
+            (
+                properties.read.as_ref().map(|read| is_secure(read)),
+                properties.write.as_ref().map(|write| is_secure(write)),
+            )
+        };
+        self.uuid == other.uuid
+            && self.value == other.value
+            && layout(&self.properties) == layout(&other.properties)
+    }
+}
@@ -1,30 +1,95 @@
-use futures::channel::{mpsc, oneshot};
+use futures::{
+    channel::{mpsc, oneshot},
+    Future, SinkExt, StreamExt,
+};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::Central;
 
 pub type EventSender = mpsc::Sender<Event>;
 pub type ResponseSender = oneshot::Sender<Response>;
 
+/// Queues writes without response along with the event sender of their handler.
+pub(crate) type CommandSender = mpsc::UnboundedSender<(EventSender, WriteRequest)>;
+
+/// How long a read request waits for its response before the central is answered with
+/// `Response::UnlikelyError`. This is kept below the 30 second ATT transaction timeout so a
+/// central never hangs on a read that the application forgot to answer.
+pub const READ_RESPONSE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How many bytes fit into a notification with the default ATT MTU of 23, which every link
+/// supports.
+pub const DEFAULT_NOTIFICATION_LENGTH: usize = 20;
+
 #[derive(Debug)]
 pub enum Event {
     ReadRequest(ReadRequest),
     WriteRequest(WriteRequest),
     NotifySubscribe(NotifySubscribe),
     NotifyUnsubscribe,
+    NotifyQueueDrained(NotifyQueueDrained),
 }
 
+/// A read from a central.
+///
+/// The value doesn't need to be ready when the request arrives, `response` can be held on to and
+/// answered once it is, as long as that happens within `READ_RESPONSE_TIMEOUT`.
 #[derive(Debug)]
 #[non_exhaustive]
 pub struct ReadRequest {
     pub offset: u16,
     pub response: ResponseSender,
     pub mtu: u16,
+    pub central: Option<Central>,
 }
 
+impl ReadRequest {
+    /// Answers with the whole value `value` resolves to, from the requested offset on, or with
+    /// `AttError::UnlikelyError` if it doesn't resolve within `deadline`. A deadline past
+    /// `READ_RESPONSE_TIMEOUT` doesn't help, the backend answers by itself then. Has to be
+    /// awaited within a tokio runtime.
+    pub async fn respond_within<F>(self, deadline: Duration, value: F)
+    where
+        F: Future<Output = Result<Vec<u8>, AttError>>,
+    {
+        let response = match tokio::time::timeout(deadline, value).await {
+            Ok(Ok(value)) => Response::at_offset(&value, self.offset),
+            Ok(Err(error)) => Response::Error(error),
+            Err(_) => Response::UnlikelyError,
+        };
+        // The central may be gone, or the backend already gave up on the response
+        let _ = self.response.send(response);
+    }
+}
+
+/// A write from a central.
+///
+/// When `without_response` is set the central sent a write command: it doesn't wait for an
+/// answer, so nothing listens on `response` and sending on it fails. Commands reach the handler
+/// in the order the central sent them, and the next one is only delivered once the handler has
+/// taken this one off the channel.
 #[derive(Debug)]
 pub struct WriteRequest {
     pub data: Vec<u8>,
     pub offset: u16,
     pub without_response: bool,
     pub response: ResponseSender,
+    pub central: Option<Central>,
+}
+
+/// Hands the queued writes without response to their handlers one at a time, so rapid commands
+/// are neither reordered nor dropped while a handler's channel is full. The future runs until
+/// every sender is dropped.
+pub(crate) fn command_queue() -> (CommandSender, impl Future<Output = ()>) {
+    let (sender, receiver) = mpsc::unbounded::<(EventSender, WriteRequest)>();
+    let forwarder = receiver.for_each(|(mut event_sender, write_request)| async move {
+        event_sender
+            .send(Event::WriteRequest(write_request))
+            .await
+            .ok();
+    });
+    (sender, forwarder)
 }
 
 #[derive(Debug, Clone)]
@@ -32,10 +97,139 @@ pub struct NotifySubscribe {
     pub notification: mpsc::Sender<Vec<u8>>,
 }
 
+/// Sent to the `notify` or `indicate` handler once the transmit queue has room again after a
+/// notification of the characteristic didn't fit, so a handler retrying by itself knows when to
+/// go on. Only CoreBluetooth reports a full queue, BlueZ waits for room instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotifyQueueDrained {
+    pub characteristic: Uuid,
+}
+
 #[derive(Debug, Clone)]
 pub enum Response {
     Success(Vec<u8>),
     InvalidOffset,
     InvalidAttributeLength,
     UnlikelyError,
+    Error(AttError),
+}
+
+impl Response {
+    /// Answers a read of `value` starting at `offset`.
+    pub(crate) fn at_offset(value: &[u8], offset: u16) -> Self {
+        match value.get(offset as usize..) {
+            Some(value) => Response::Success(value.to_vec()),
+            None => Response::Error(AttError::InvalidOffset),
+        }
+    }
+
+    /// The value of a successful response, or the ATT error the central is answered with.
+    pub fn into_result(self) -> Result<Vec<u8>, AttError> {
+        match self {
+            Response::Success(value) => Ok(value),
+            Response::InvalidOffset => Err(AttError::InvalidOffset),
+            Response::InvalidAttributeLength => Err(AttError::InvalidAttributeValueLength),
+            Response::UnlikelyError => Err(AttError::UnlikelyError),
+            Response::Error(error) => Err(error),
+        }
+    }
+}
+
+impl From<Result<Vec<u8>, AttError>> for Response {
+    fn from(result: Result<Vec<u8>, AttError>) -> Self {
+        match result {
+            Ok(value) => Response::Success(value),
+            Err(error) => Response::Error(error),
+        }
+    }
+}
+
+/// The ATT error codes a request can be answered with, as listed in the Bluetooth Core
+/// Specification, Vol 3, Part F, 3.4.1.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AttError {
+    InvalidHandle,
+    ReadNotPermitted,
+    WriteNotPermitted,
+    InvalidPdu,
+    InsufficientAuthentication,
+    RequestNotSupported,
+    InvalidOffset,
+    InsufficientAuthorization,
+    PrepareQueueFull,
+    AttributeNotFound,
+    AttributeNotLong,
+    InsufficientEncryptionKeySize,
+    InvalidAttributeValueLength,
+    UnlikelyError,
+    InsufficientEncryption,
+    UnsupportedGroupType,
+    InsufficientResources,
+}
+
+impl AttError {
+    pub fn code(self) -> u8 {
+        match self {
+            AttError::InvalidHandle => 0x01,
+            AttError::ReadNotPermitted => 0x02,
+            AttError::WriteNotPermitted => 0x03,
+            AttError::InvalidPdu => 0x04,
+            AttError::InsufficientAuthentication => 0x05,
+            AttError::RequestNotSupported => 0x06,
+            AttError::InvalidOffset => 0x07,
+            AttError::InsufficientAuthorization => 0x08,
+            AttError::PrepareQueueFull => 0x09,
+            AttError::AttributeNotFound => 0x0A,
+            AttError::AttributeNotLong => 0x0B,
+            AttError::InsufficientEncryptionKeySize => 0x0C,
+            AttError::InvalidAttributeValueLength => 0x0D,
+            AttError::UnlikelyError => 0x0E,
+            AttError::InsufficientEncryption => 0x0F,
+            AttError::UnsupportedGroupType => 0x10,
+            AttError::InsufficientResources => 0x11,
+        }
+    }
+}
+
+// The command queue is private to the backends, so it can't be tested from `tests/`.
+#[cfg(test)]
+mod tests {
+    use super::{command_queue, Event, WriteRequest};
+    use futures::{channel::mpsc, prelude::*};
+
+    const COMMANDS: u16 = 1000;
+
+    #[tokio::test]
+    async fn commands_reach_the_handler_in_order() {
+        let (commands, forwarder) = command_queue();
+        tokio::spawn(forwarder);
+        // Holds a single request, so most commands wait in the queue for the handler
+        let (event_sender, events) = mpsc::channel(0);
+        for value in 0..COMMANDS {
+            let (response, _) = futures::channel::oneshot::channel();
+            let command = WriteRequest {
+                data: value.to_le_bytes().to_vec(),
+                offset: 0,
+                without_response: true,
+                response,
+                central: None,
+            };
+            commands
+                .unbounded_send((event_sender.clone(), command))
+                .unwrap();
+        }
+        drop((commands, event_sender));
+
+        let values: Vec<Vec<u8>> = events
+            .map(|event| match event {
+                Event::WriteRequest(write_request) => write_request.data,
+                event => panic!("Unexpected event {:?}", event),
+            })
+            .collect()
+            .await;
+        let sent: Vec<Vec<u8>> = (0..COMMANDS)
+            .map(|value| value.to_le_bytes().to_vec())
+            .collect();
+        assert_eq!(values, sent);
+    }
 }
@@ -0,0 +1,110 @@
+/// The largest mantissa an SFLOAT can carry, the ones above stand for special values.
+const SFLOAT_MAX_MANTISSA: i64 = 0x07FD;
+/// The largest mantissa a FLOAT can carry.
+const FLOAT_MAX_MANTISSA: i64 = 0x7F_FFFD;
+
+// The payloads of standard characteristics, as the GATT Specification Supplement defines them
+
+pub fn uint16_le(value: u16) -> [u8; 2] {
+    value.to_le_bytes()
+}
+
+pub fn sint16_le(value: i16) -> [u8; 2] {
+    value.to_le_bytes()
+}
+
+pub fn uint32_le(value: u32) -> [u8; 4] {
+    value.to_le_bytes()
+}
+
+/// A percentage like the Battery Level (0x2A19), capped at 100.
+pub fn percent8(value: u8) -> [u8; 1] {
+    [value.min(100)]
+}
+
+/// The IEEE 11073-20601 16 bit SFLOAT, e.g. for the Blood Pressure Measurement (0x2A35). The value
+/// is rounded to the 4 significant digits the 12 bit mantissa holds, values too large to encode
+/// become infinity.
+pub fn sfloat(value: f32) -> [u8; 2] {
+    let (mantissa, exponent) = match medfloat(value as f64, SFLOAT_MAX_MANTISSA, -8, 7) {
+        Ok((mantissa, exponent)) => (mantissa, exponent),
+        Err(special) => return special_sfloat(special),
+    };
+    let bits = ((exponent as u16 & 0x0F) << 12) | (mantissa as u16 & 0x0FFF);
+    bits.to_le_bytes()
+}
+
+/// The IEEE 11073-20601 32 bit FLOAT, e.g. for the Temperature Measurement (0x2A1C) of the Health
+/// Thermometer service. The 24 bit mantissa holds 7 significant digits.
+pub fn float(value: f64) -> [u8; 4] {
+    let (mantissa, exponent) = match medfloat(value, FLOAT_MAX_MANTISSA, -128, 127) {
+        Ok((mantissa, exponent)) => (mantissa, exponent),
+        Err(special) => return special_float(special),
+    };
+    let bits = ((exponent as u32 & 0xFF) << 24) | (mantissa as u32 & 0x00FF_FFFF);
+    bits.to_le_bytes()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Special {
+    NaN,
+    PositiveInfinity,
+    NegativeInfinity,
+}
+
+/// The mantissa and base 10 exponent closest to `value`, with the smallest exponent whose
+/// mantissa fits, so no precision is lost.
+fn medfloat(
+    value: f64,
+    max_mantissa: i64,
+    min_exponent: i32,
+    max_exponent: i32,
+) -> Result<(i64, i32), Special> {
+    if value.is_nan() {
+        return Err(Special::NaN);
+    }
+    if value.is_finite() {
+        for exponent in min_exponent..=max_exponent {
+            let mantissa = (value / 10f64.powi(exponent)).round();
+            if mantissa.abs() <= max_mantissa as f64 {
+                return Ok(strip_zeros(mantissa as i64, exponent, max_exponent));
+            }
+        }
+    }
+    if value > 0.0 {
+        Err(Special::PositiveInfinity)
+    } else {
+        Err(Special::NegativeInfinity)
+    }
+}
+
+/// Moves the trailing zeros of the mantissa into the exponent, so e.g. 12 isn't sent as
+/// `1200 * 10^-2`.
+fn strip_zeros(mut mantissa: i64, mut exponent: i32, max_exponent: i32) -> (i64, i32) {
+    if mantissa == 0 {
+        return (0, 0);
+    }
+    while mantissa % 10 == 0 && exponent < max_exponent {
+        mantissa /= 10;
+        exponent += 1;
+    }
+    (mantissa, exponent)
+}
+
+fn special_sfloat(special: Special) -> [u8; 2] {
+    let bits: u16 = match special {
+        Special::NaN => 0x07FF,
+        Special::PositiveInfinity => 0x07FE,
+        Special::NegativeInfinity => 0x0802,
+    };
+    bits.to_le_bytes()
+}
+
+fn special_float(special: Special) -> [u8; 4] {
+    let bits: u32 = match special {
+        Special::NaN => 0x007F_FFFF,
+        Special::PositiveInfinity => 0x007F_FFFE,
+        Special::NegativeInfinity => 0x0080_0002,
+    };
+    bits.to_le_bytes()
+}
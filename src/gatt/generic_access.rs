@@ -0,0 +1,57 @@
+use super::{characteristic::Characteristic, service::Service};
+use crate::SdpShortUuid;
+use uuid::Uuid;
+
+pub const GENERIC_ACCESS_UUID: u16 = 0x1800;
+pub const DEVICE_NAME_UUID: u16 = 0x2A00;
+pub const APPEARANCE_UUID: u16 = 0x2A01;
+
+/// The characteristic's read property and the `CBAttributePermissions` readable bit, see
+/// `Characteristic::from_raw`.
+const READ_PROPERTY: u8 = 0x02;
+const READABLE: u8 = 0x01;
+
+/// Values of the Generic Access service (0x1800), which tells connected centrals the device's
+/// name and appearance. Peripherals can't put the appearance into the advertisement on every
+/// platform, serving it this way works with any central.
+///
+/// `appearance` is a Bluetooth SIG assigned number, e.g. `0x00C0` for a generic watch. Bluetooth
+/// stacks that serve a Generic Access service of their own, like CoreBluetooth, may refuse to
+/// add a second one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenericAccess {
+    pub device_name: String,
+    pub appearance: u16,
+}
+
+impl GenericAccess {
+    pub fn new<T: Into<String>>(device_name: T, appearance: u16) -> Self {
+        GenericAccess {
+            device_name: device_name.into(),
+            appearance,
+        }
+    }
+
+    /// A primary service with read-only Device Name (0x2A00) and Appearance (0x2A01)
+    /// characteristics serving these values.
+    pub fn into_service(self) -> Service {
+        let characteristic = |uuid: u16, value: Vec<u8>| {
+            Characteristic::from_raw(
+                Uuid::from_sdp_short_uuid(uuid),
+                READ_PROPERTY,
+                READABLE,
+                Some(value),
+            )
+        };
+        Service::new(
+            Uuid::from_sdp_short_uuid(GENERIC_ACCESS_UUID),
+            true,
+            vec![
+                characteristic(DEVICE_NAME_UUID, self.device_name.into_bytes()),
+                characteristic(APPEARANCE_UUID, self.appearance.to_le_bytes().to_vec()),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+}
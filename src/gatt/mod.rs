@@ -6,8 +6,26 @@ mod gatt_properties;
 #[macro_use]
 mod gatt_uuid_hasher;
 
+pub mod acl;
 pub mod characteristic;
+#[cfg(feature = "serde")]
+pub mod config;
+pub mod default_handler;
 pub mod descriptor;
+pub mod format;
+pub mod generic_access;
+pub mod notification_source;
+#[cfg(any(test, target_os = "macos", target_os = "ios"))]
+pub(crate) mod notify_buffers;
+pub mod notify_interceptor;
+pub mod notify_pause;
+pub mod notify_policy;
+pub mod notify_queue;
+pub mod presentation_format;
+pub mod read_group;
 pub mod service;
+pub mod validation;
+pub mod value_source;
+pub mod write_schema;
 
 pub mod event;
@@ -0,0 +1,83 @@
+use futures::{channel::mpsc, prelude::*};
+use tokio::task::JoinHandle;
+
+use super::event::{Event, EventSender};
+
+/// Produces the values of a `notify` or `indicate` characteristic, but only while someone listens.
+///
+/// `source` is called when the first central subscribes and the stream it returns is forwarded to
+/// the subscriber. Once the last central unsubscribed the stream is dropped, so whatever backs it,
+/// like a sensor, only has to run while it's needed.
+#[derive(Debug, Clone)]
+pub struct NotificationSource<F> {
+    source: F,
+}
+
+impl<F, S> NotificationSource<F>
+where
+    F: FnMut() -> S + Send + 'static,
+    S: Stream<Item = Vec<u8>> + Send + 'static,
+{
+    pub fn new(source: F) -> Self {
+        NotificationSource { source }
+    }
+
+    /// The sender to hand to the characteristic's `notify` or `indicate` property. Other events
+    /// are dropped unanswered. This spawns a task, so it has to be called within a tokio runtime.
+    pub fn into_event_sender(mut self) -> EventSender {
+        let (sender, mut receiver) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut subscriptions = Subscriptions::default();
+            while let Some(event) = receiver.next().await {
+                match event {
+                    Event::NotifySubscribe(notify_subscribe) => {
+                        subscriptions.subscribe(notify_subscribe.notification, &mut self.source)
+                    }
+                    Event::NotifyUnsubscribe => subscriptions.unsubscribe(),
+                    _ => {}
+                }
+            }
+        });
+        sender
+    }
+}
+
+/// Counts the subscribers of a characteristic and forwards a stream of values while there are
+/// any. The forwarding stops when this is dropped.
+#[derive(Debug, Default)]
+pub(crate) struct Subscriptions {
+    subscribers: usize,
+    forwarder: Option<JoinHandle<()>>,
+}
+
+impl Subscriptions {
+    /// `source` is only called for the first subscriber, later ones share its stream.
+    pub fn subscribe<F, S>(&mut self, notification: mpsc::Sender<Vec<u8>>, source: F)
+    where
+        F: FnOnce() -> S,
+        S: Stream<Item = Vec<u8>> + Send + 'static,
+    {
+        self.subscribers += 1;
+        if self.forwarder.is_none() {
+            let values = source().map(Ok);
+            self.forwarder = Some(tokio::spawn(values.forward(notification).map(|_| ())));
+        }
+    }
+
+    pub fn unsubscribe(&mut self) {
+        self.subscribers = self.subscribers.saturating_sub(1);
+        if self.subscribers == 0 {
+            if let Some(forwarder) = self.forwarder.take() {
+                forwarder.abort();
+            }
+        }
+    }
+}
+
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        if let Some(forwarder) = self.forwarder.take() {
+            forwarder.abort();
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+use tokio::sync::Notify;
+
+use super::{
+    characteristic::CharacteristicHandle,
+    notify_queue::{NotifyQueue, OverflowPolicy},
+};
+use crate::{metrics::Metrics, NotifyError};
+
+/// The notifications buffered for characteristics with a `NotifyQueue` while the transmit queue
+/// is full, oldest first.
+#[derive(Debug, Default)]
+pub(crate) struct NotifyBuffers {
+    pending: Mutex<HashMap<CharacteristicHandle, VecDeque<Vec<u8>>>>,
+    /// Woken once buffered notifications went out, for `OverflowPolicy::Block`.
+    room: Notify,
+}
+
+impl NotifyBuffers {
+    /// Sends the value right away if nothing is buffered for the characteristic and `send` takes
+    /// it, otherwise buffers it behind the others until `flush` sends them. `send` tells whether
+    /// the transmit queue took the value.
+    pub async fn push<F>(
+        &self,
+        handle: &CharacteristicHandle,
+        value: Vec<u8>,
+        queue: NotifyQueue,
+        metrics: &Metrics,
+        mut send: F,
+    ) -> Result<(), NotifyError>
+    where
+        F: FnMut(&[u8]) -> Result<bool, NotifyError>,
+    {
+        loop {
+            // Waits for the signal from before checking, so it can't slip through in between
+            let room = self.room.notified();
+            {
+                let mut pending = self.pending.lock().unwrap();
+                let buffered = pending.entry(*handle).or_default();
+                if buffered.is_empty() && send(&value)? {
+                    pending.remove(handle);
+                    metrics.notification_sent();
+                    return Ok(());
+                }
+                if buffered.len() < queue.max_depth {
+                    buffered.push_back(value);
+                    return Ok(());
+                }
+                match queue.overflow {
+                    OverflowPolicy::DropOldest => {
+                        // Without a `max_depth` the new value is the oldest one there is
+                        if buffered.pop_front().is_some() {
+                            buffered.push_back(value);
+                        }
+                        metrics.notification_dropped();
+                        return Ok(());
+                    }
+                    OverflowPolicy::DropNewest => {
+                        metrics.notification_dropped();
+                        return Err(NotifyError::QueueFull);
+                    }
+                    OverflowPolicy::Block => {}
+                }
+            }
+            room.await;
+        }
+    }
+
+    /// Sends the buffered values in order until `send` no longer takes them. `send` returns
+    /// `None` for characteristics no longer added, their values are dropped.
+    pub fn flush<F>(&self, metrics: &Metrics, mut send: F)
+    where
+        F: FnMut(&CharacteristicHandle, &[u8]) -> Option<bool>,
+    {
+        let mut pending = self.pending.lock().unwrap();
+        'buffers: for (handle, buffered) in pending.iter_mut() {
+            while let Some(value) = buffered.front() {
+                match send(handle, value) {
+                    Some(true) => {}
+                    Some(false) => break 'buffers,
+                    None => {
+                        buffered.clear();
+                        continue 'buffers;
+                    }
+                }
+                buffered.pop_front();
+                metrics.notification_sent();
+            }
+        }
+        pending.retain(|_, buffered| !buffered.is_empty());
+        self.room.notify_waiters();
+    }
+
+    /// Drops every buffered value and wakes everyone blocked on a full buffer.
+    pub fn clear(&self) {
+        self.pending.lock().unwrap().clear();
+        self.room.notify_waiters();
+    }
+}
+
+// The buffers are private to the backends, so they can't be tested from `tests/`.
+#[cfg(test)]
+mod tests {
+    use super::NotifyBuffers;
+    use crate::{
+        gatt::{
+            characteristic::CharacteristicHandle,
+            notify_queue::{NotifyQueue, OverflowPolicy},
+        },
+        metrics::Metrics,
+        NotifyError,
+    };
+    use futures::{poll, task::Poll};
+    use uuid::Uuid;
+
+    const HANDLE: CharacteristicHandle = CharacteristicHandle {
+        service: Uuid::from_u128(0x180D),
+        characteristic: Uuid::from_u128(0x2A37),
+        instance: 0,
+    };
+
+    /// Pushes the values while the transmit queue is full.
+    async fn fill(
+        buffers: &NotifyBuffers,
+        queue: NotifyQueue,
+        metrics: &Metrics,
+        values: std::ops::Range<u8>,
+    ) -> Vec<Result<(), NotifyError>> {
+        let mut results = vec![];
+        for value in values {
+            results.push(
+                buffers
+                    .push(&HANDLE, vec![value], queue, metrics, |_| Ok(false))
+                    .await,
+            );
+        }
+        results
+    }
+
+    /// Takes every buffered value.
+    fn drain(buffers: &NotifyBuffers, metrics: &Metrics) -> Vec<Vec<u8>> {
+        let mut sent = vec![];
+        buffers.flush(metrics, |_, value| {
+            sent.push(value.to_vec());
+            Some(true)
+        });
+        sent
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_newest_values() {
+        let (buffers, metrics) = (NotifyBuffers::default(), Metrics::default());
+        let queue = NotifyQueue::new(3, OverflowPolicy::DropOldest);
+
+        let results = fill(&buffers, queue, &metrics, 0..5).await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(drain(&buffers, &metrics), vec![vec![2], vec![3], vec![4]]);
+        assert_eq!(metrics.snapshot().notifications_dropped, 2);
+        assert_eq!(metrics.snapshot().notifications_sent, 3);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_without_depth_buffers_nothing() {
+        let (buffers, metrics) = (NotifyBuffers::default(), Metrics::default());
+        let queue = NotifyQueue::new(0, OverflowPolicy::DropOldest);
+
+        let results = fill(&buffers, queue, &metrics, 0..3).await;
+        assert!(results.iter().all(Result::is_ok));
+        assert!(drain(&buffers, &metrics).is_empty());
+        assert_eq!(metrics.snapshot().notifications_dropped, 3);
+    }
+
+    #[tokio::test]
+    async fn drop_newest_fails_with_queue_full() {
+        let (buffers, metrics) = (NotifyBuffers::default(), Metrics::default());
+        let queue = NotifyQueue::new(2, OverflowPolicy::DropNewest);
+
+        let results = fill(&buffers, queue, &metrics, 0..4).await;
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(NotifyError::QueueFull),
+                Err(NotifyError::QueueFull)
+            ]
+        );
+        assert_eq!(drain(&buffers, &metrics), vec![vec![0], vec![1]]);
+        assert_eq!(metrics.snapshot().notifications_dropped, 2);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_room() {
+        let (buffers, metrics) = (NotifyBuffers::default(), Metrics::default());
+        let queue = NotifyQueue::new(1, OverflowPolicy::Block);
+
+        fill(&buffers, queue, &metrics, 0..1).await;
+        let mut blocked = Box::pin(buffers.push(&HANDLE, vec![1], queue, &metrics, |_| Ok(true)));
+        assert_eq!(poll!(&mut blocked), Poll::Pending);
+
+        assert_eq!(drain(&buffers, &metrics), vec![vec![0]]);
+        assert_eq!(blocked.await, Ok(()));
+        assert_eq!(metrics.snapshot().notifications_sent, 2);
+        assert_eq!(metrics.snapshot().notifications_dropped, 0);
+    }
+
+    #[tokio::test]
+    async fn clear_wakes_blocked_notifications() {
+        let (buffers, metrics) = (NotifyBuffers::default(), Metrics::default());
+        let queue = NotifyQueue::new(1, OverflowPolicy::Block);
+
+        fill(&buffers, queue, &metrics, 0..1).await;
+        let mut blocked = Box::pin(buffers.push(&HANDLE, vec![1], queue, &metrics, |_| {
+            Err(NotifyError::UnknownCharacteristic)
+        }));
+        assert_eq!(poll!(&mut blocked), Poll::Pending);
+
+        buffers.clear();
+        assert_eq!(blocked.await, Err(NotifyError::UnknownCharacteristic));
+        assert!(drain(&buffers, &metrics).is_empty());
+    }
+
+    #[tokio::test]
+    async fn flush_stops_once_the_transmit_queue_is_full() {
+        let (buffers, metrics) = (NotifyBuffers::default(), Metrics::default());
+        let queue = NotifyQueue::new(3, OverflowPolicy::DropNewest);
+
+        fill(&buffers, queue, &metrics, 0..3).await;
+        let mut room = 1;
+        buffers.flush(&metrics, |_, _| {
+            room -= 1;
+            Some(room >= 0)
+        });
+        assert_eq!(drain(&buffers, &metrics), vec![vec![1], vec![2]]);
+    }
+}
@@ -0,0 +1,35 @@
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+/// Sees the payload of every notification `Peripheral::notify` sends right before it's handed to
+/// the backend and may change it, e.g. to add framing or trace it.
+pub type NotifyInterceptor = Arc<dyn Fn(&Uuid, &mut Vec<u8>) + Send + Sync>;
+
+/// The interceptor of a `Peripheral`, shared by its clones.
+#[derive(Clone, Default)]
+pub(crate) struct NotifyInterceptors(Arc<RwLock<Option<NotifyInterceptor>>>);
+
+impl NotifyInterceptors {
+    pub fn set(&self, interceptor: NotifyInterceptor) {
+        self.0.write().unwrap().replace(interceptor);
+    }
+
+    /// The value as the interceptor left it, or as it is without one.
+    pub fn intercept(&self, characteristic: &Uuid, value: &[u8]) -> Vec<u8> {
+        let mut value = value.to_vec();
+        // Cloned so the interceptor may replace itself without deadlocking
+        let interceptor = self.0.read().unwrap().clone();
+        if let Some(interceptor) = interceptor {
+            interceptor(characteristic, &mut value);
+        }
+        value
+    }
+}
+
+impl std::fmt::Debug for NotifyInterceptors {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("NotifyInterceptors")
+            .field(&self.0.read().unwrap().is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,63 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use super::characteristic::CharacteristicHandle;
+
+/// What happens to the notifications sent while they're paused, see
+/// `Peripheral::pause_notifications`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PausePolicy {
+    /// Keeps the latest value of each characteristic and sends it on resume, the ones it
+    /// replaced are dropped.
+    KeepLatest,
+    /// Drops every value, subscribers only hear of the next one sent after resuming.
+    Drop,
+}
+
+#[derive(Debug)]
+struct Paused {
+    policy: PausePolicy,
+    latest: HashMap<CharacteristicHandle, Vec<u8>>,
+}
+
+/// Whether the notifications of a `Peripheral` are paused, shared by its clones.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct NotifyPause(Arc<Mutex<Option<Paused>>>);
+
+impl NotifyPause {
+    /// Pausing again only changes the policy, values kept so far stay until resuming or the
+    /// policy drops them.
+    pub fn pause(&self, policy: PausePolicy) {
+        let mut paused = self.0.lock().unwrap();
+        let latest = match paused.take() {
+            Some(paused) if policy == PausePolicy::KeepLatest => paused.latest,
+            _ => HashMap::new(),
+        };
+        paused.replace(Paused { policy, latest });
+    }
+
+    /// Takes the value instead of it being sent, `false` if notifications aren't paused.
+    pub fn hold(&self, handle: &CharacteristicHandle, value: &[u8]) -> bool {
+        match self.0.lock().unwrap().as_mut() {
+            Some(paused) => {
+                if paused.policy == PausePolicy::KeepLatest {
+                    paused.latest.insert(*handle, value.to_vec());
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The values to send now that notifications go out again.
+    pub fn resume(&self) -> Vec<(CharacteristicHandle, Vec<u8>)> {
+        self.0
+            .lock()
+            .unwrap()
+            .take()
+            .map(|paused| paused.latest.into_iter().collect())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,59 @@
+use futures::{prelude::*, stream};
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+/// Limits how often a characteristic notifies, so a fast source neither floods the connection
+/// nor runs into full notification queues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotifyPolicy {
+    /// At least this much time passes between two notifications.
+    pub min_interval: Duration,
+    /// Drops values superseded within `min_interval` and only sends the latest, instead of
+    /// delaying every value until its turn.
+    pub coalesce: bool,
+}
+
+impl NotifyPolicy {
+    pub fn new(min_interval: Duration, coalesce: bool) -> Self {
+        NotifyPolicy {
+            min_interval,
+            coalesce,
+        }
+    }
+
+    /// The notifications of `values` as this policy lets them through. Has to be polled within a
+    /// tokio runtime.
+    pub fn apply<S>(self, values: S) -> impl Stream<Item = Vec<u8>>
+    where
+        S: Stream<Item = Vec<u8>> + Unpin,
+    {
+        stream::unfold(
+            (values, None::<Instant>, false),
+            move |(mut values, last_sent, ended)| async move {
+                if ended {
+                    return None;
+                }
+                let mut value = values.next().await?;
+                let mut ended = false;
+                if let Some(last_sent) = last_sent {
+                    let next = last_sent + self.min_interval;
+                    if self.coalesce {
+                        loop {
+                            match time::timeout_at(next, values.next()).await {
+                                Ok(Some(newer_value)) => value = newer_value,
+                                Ok(None) => {
+                                    ended = true;
+                                    break;
+                                }
+                                Err(_) => break,
+                            }
+                        }
+                    } else {
+                        time::sleep_until(next).await;
+                    }
+                }
+                Some((value, (values, Some(Instant::now()), ended)))
+            },
+        )
+    }
+}
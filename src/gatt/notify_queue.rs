@@ -0,0 +1,34 @@
+/// What happens to a notification when the buffer of a `NotifyQueue` is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drops the oldest buffered value to make room, stale readings matter least.
+    DropOldest,
+    /// Drops the new value, `Peripheral::notify` returns `NotifyError::QueueFull`.
+    DropNewest,
+    /// `Peripheral::notify` waits until a buffered value went out.
+    Block,
+}
+
+/// Buffers the notifications of a characteristic while the Bluetooth stack's transmit queue is
+/// full and sends them in order once it has room, instead of `Peripheral::notify` failing after
+/// one retry. The buffer holds at most `max_depth` values, `overflow` decides what happens to
+/// more. Dropped values count as `PeripheralMetrics::notifications_dropped`. With a `max_depth`
+/// of 0 nothing is buffered, `DropOldest` then drops the new value like `DropNewest` but without
+/// an error.
+///
+/// Only CoreBluetooth reports a full transmit queue, BlueZ waits for room by itself and never
+/// buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotifyQueue {
+    pub max_depth: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl NotifyQueue {
+    pub fn new(max_depth: usize, overflow: OverflowPolicy) -> Self {
+        NotifyQueue {
+            max_depth,
+            overflow,
+        }
+    }
+}
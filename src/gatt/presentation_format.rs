@@ -0,0 +1,58 @@
+use super::descriptor::{self, Descriptor};
+use crate::SdpShortUuid;
+use uuid::Uuid;
+
+pub const PRESENTATION_FORMAT_UUID: u16 = 0x2904;
+
+/// Namespace of the Bluetooth SIG assigned numbers, used for `PresentationFormat::namespace`.
+pub const BLUETOOTH_SIG_NAMESPACE: u8 = 0x01;
+
+/// Characteristic Presentation Format (0x2904) descriptor value, telling generic clients how to
+/// display a characteristic's value.
+///
+/// `format`, `unit` and `description` are Bluetooth SIG assigned numbers, e.g. a format of `0x06`
+/// is a `uint16` and a unit of `0x272F` is degrees Celsius. The displayed value is
+/// `value * 10^exponent`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresentationFormat {
+    pub format: u8,
+    pub exponent: i8,
+    pub unit: u16,
+    pub namespace: u8,
+    pub description: u16,
+}
+
+impl PresentationFormat {
+    pub fn new(format: u8, exponent: i8, unit: u16) -> Self {
+        PresentationFormat {
+            format,
+            exponent,
+            unit,
+            namespace: BLUETOOTH_SIG_NAMESPACE,
+            description: 0,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 7] {
+        let unit = self.unit.to_le_bytes();
+        let description = self.description.to_le_bytes();
+        [
+            self.format,
+            self.exponent as u8,
+            unit[0],
+            unit[1],
+            self.namespace,
+            description[0],
+            description[1],
+        ]
+    }
+
+    /// A read-only descriptor serving this presentation format as its value.
+    pub fn into_descriptor(self) -> Descriptor {
+        Descriptor::new(
+            Uuid::from_sdp_short_uuid(PRESENTATION_FORMAT_UUID),
+            descriptor::Properties::new(None, None),
+            Some(self.to_bytes().to_vec()),
+        )
+    }
+}
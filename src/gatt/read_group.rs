@@ -0,0 +1,73 @@
+use futures::{channel::mpsc, prelude::*, stream};
+use std::{collections::HashMap, iter};
+use uuid::Uuid;
+
+use super::event::{AttError, Event, EventSender, Response};
+
+/// Characteristics whose values are read together, see `read_group`.
+#[derive(Debug, Clone)]
+pub struct ReadGroup {
+    senders: HashMap<Uuid, EventSender>,
+}
+
+impl ReadGroup {
+    /// The sender to hand to the `read` property of the characteristic, `None` if it isn't part
+    /// of the group.
+    pub fn sender(&self, uuid: &Uuid) -> Option<EventSender> {
+        self.senders.get(uuid).cloned()
+    }
+}
+
+/// Serves the reads of `primary` and the `related` characteristics from a single `handler` call,
+/// so the values a central reads one after another belong together.
+///
+/// `handler` returns the values by UUID. It's called whenever `primary` is read, except for the
+/// continuations of a long read, and the values are cached for the related characteristics. They
+/// only call `handler` themselves when they're read before `primary`. Writes are answered with
+/// `AttError::RequestNotSupported`.
+///
+/// This spawns a task, so it has to be called within a tokio runtime.
+pub fn read_group<F>(primary: Uuid, related: &[Uuid], mut handler: F) -> ReadGroup
+where
+    F: FnMut() -> HashMap<Uuid, Vec<u8>> + Send + 'static,
+{
+    let mut senders = HashMap::new();
+    let mut receivers = vec![];
+    for uuid in iter::once(primary).chain(related.iter().copied()) {
+        let (sender, receiver) = mpsc::channel(1);
+        senders.insert(uuid, sender);
+        receivers.push(receiver.map(move |event| (uuid, event)));
+    }
+
+    tokio::spawn(async move {
+        let mut values = HashMap::new();
+        let mut events = stream::select_all(receivers);
+        while let Some((uuid, event)) = events.next().await {
+            match event {
+                Event::ReadRequest(read_request) => {
+                    let refresh = if uuid == primary {
+                        read_request.offset == 0
+                    } else {
+                        !values.contains_key(&uuid)
+                    };
+                    if refresh {
+                        values = handler();
+                    }
+                    let response = match values.get(&uuid) {
+                        Some(value) => Response::at_offset(value, read_request.offset),
+                        None => Response::Error(AttError::UnlikelyError),
+                    };
+                    let _ = read_request.response.send(response);
+                }
+                Event::WriteRequest(write_request) => {
+                    let _ = write_request
+                        .response
+                        .send(Response::Error(AttError::RequestNotSupported));
+                }
+                _ => {}
+            }
+        }
+    });
+
+    ReadGroup { senders }
+}
@@ -1,12 +1,20 @@
-use super::characteristic::Characteristic;
-use std::collections::HashSet;
+use super::{
+    characteristic::{Characteristic, CharacteristicHandle},
+    validation::{self, ValidationError},
+};
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
+use crate::{uuid::display_uuid, Error, ErrorType};
+
 #[derive(Debug, Clone)]
 pub struct Service {
     pub(crate) uuid: Uuid,
     pub(crate) primary: bool,
     pub(crate) characteristics: HashSet<Characteristic>,
+    pub(crate) max_value_length: Option<usize>,
+    pub(crate) allow_duplicate_characteristics: bool,
+    pub(crate) included_services: Vec<Uuid>,
 }
 
 impl Service {
@@ -15,6 +23,182 @@ impl Service {
             uuid,
             primary,
             characteristics,
+            max_value_length: None,
+            allow_duplicate_characteristics: false,
+            included_services: vec![],
+        }
+    }
+
+    /// Default for the characteristics that don't set their own maximum value length.
+    pub fn with_max_value_length(mut self, max_value_length: usize) -> Self {
+        self.max_value_length = Some(max_value_length);
+        self
+    }
+
+    /// Lets several instances of a characteristic share its UUID, see `Characteristic::with_instance`.
+    /// Backends refuse to add a service repeating a UUID otherwise, as that's mostly a mistake
+    /// and leaves the methods taking a characteristic UUID to pick one of them.
+    pub fn allow_duplicate_characteristics(mut self, allow: bool) -> Self {
+        self.allow_duplicate_characteristics = allow;
+        self
+    }
+
+    /// Includes the service with the UUID, e.g. a secondary service. It has to be added before
+    /// this one, or come before it in `Peripheral::add_services` and `replace_services`.
+    pub fn with_included_service(mut self, uuid: Uuid) -> Self {
+        if !self.included_services.contains(&uuid) {
+            self.included_services.push(uuid);
+        }
+        self
+    }
+
+    /// Fails for the first included service `is_added` doesn't know of.
+    #[cfg_attr(windows, allow(dead_code))]
+    pub(crate) fn check_included_services<F>(
+        &self,
+        is_added: F,
+        error_type: ErrorType,
+    ) -> Result<(), Error>
+    where
+        F: Fn(&Uuid) -> bool,
+    {
+        match self.included_services.iter().find(|uuid| !is_added(uuid)) {
+            Some(included) => Err(Error::new(
+                String::from("IncludedServiceMissing"),
+                format!(
+                    "Service {} includes service {}, which has to be added before it",
+                    display_uuid(&self.uuid),
+                    display_uuid(included)
+                ),
+                error_type,
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The characteristic UUIDs repeated without `allow_duplicate_characteristics`, sorted.
+    pub(crate) fn duplicate_characteristics(&self) -> Vec<Uuid> {
+        if self.allow_duplicate_characteristics {
+            return vec![];
+        }
+        let mut seen = HashSet::new();
+        let mut duplicates = self
+            .characteristics
+            .iter()
+            .map(|characteristic| characteristic.uuid)
+            .filter(|uuid| !seen.insert(*uuid))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        duplicates.sort();
+        duplicates
+    }
+
+    /// The error backends refuse a service repeating a characteristic UUID with.
+    pub(crate) fn check_duplicate_characteristics(
+        &self,
+        error_type: ErrorType,
+    ) -> Result<(), Error> {
+        match self.duplicate_characteristics().first() {
+            Some(characteristic) => Err(Error::new(
+                String::from("DuplicateCharacteristic"),
+                format!(
+                    "Service {} declares characteristic {} more than once, see \
+                     Service::allow_duplicate_characteristics",
+                    display_uuid(&self.uuid),
+                    display_uuid(characteristic)
+                ),
+                error_type,
+            )),
+            None => Ok(()),
         }
     }
+
+    /// Whether both are registered the same way, see `Characteristic::same_layout`.
+    #[cfg_attr(any(target_os = "linux", windows), allow(dead_code))]
+    pub(crate) fn same_layout(&self, other: &Service) -> bool {
+        let others = other
+            .resolved_characteristics()
+            .map(|characteristic| (characteristic.handle(other.uuid), characteristic))
+            .collect::<HashMap<_, _>>();
+        self.uuid == other.uuid
+            && self.primary == other.primary
+            && self.included_services == other.included_services
+            && self.characteristics.len() == others.len()
+            && self.resolved_characteristics().all(|characteristic| {
+                others
+                    .get(&characteristic.handle(other.uuid))
+                    .is_some_and(|other| characteristic.same_layout(other))
+            })
+    }
+
+    /// Checks `services` for mistakes without touching the adapter, which the backends would
+    /// otherwise report one at a time once a service is added, or not at all. Returns every
+    /// mistake found.
+    pub fn validate_all(services: &[Service]) -> Result<(), Vec<ValidationError>> {
+        let errors = validation::validate(services);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The handles of the characteristics, the same `Peripheral::add_service` returns.
+    pub fn handles(&self) -> Vec<CharacteristicHandle> {
+        self.characteristics
+            .iter()
+            .map(|characteristic| characteristic.handle(self.uuid))
+            .collect()
+    }
+
+    /// The number of ATT handles the service takes up in the GATT database: its declaration, one
+    /// per included service, two per characteristic, one per descriptor and one for the Client
+    /// Characteristic Configuration descriptor of every characteristic that notifies or
+    /// indicates.
+    pub fn handle_count(&self) -> usize {
+        1 + self.included_services.len()
+            + self
+                .characteristics
+                .iter()
+                .map(Characteristic::handle_count)
+                .sum::<usize>()
+    }
+
+    /// A readable outline of the service, one line per characteristic and descriptor, indented
+    /// below it. Everything is sorted by UUID, so the outlines of the same layout compare equal.
+    pub fn describe(&self) -> String {
+        let mut characteristics = self.resolved_characteristics().collect::<Vec<_>>();
+        characteristics
+            .sort_by_key(|characteristic| (characteristic.uuid, characteristic.instance));
+        let mut description = format!(
+            "service {} {}\n",
+            display_uuid(&self.uuid),
+            if self.primary { "primary" } else { "secondary" }
+        );
+        for included in &self.included_services {
+            description.push_str(&format!("  includes {}\n", display_uuid(included)));
+        }
+        for characteristic in characteristics {
+            description.push_str(&characteristic.describe());
+        }
+        description
+    }
+
+    /// The outlines of `services` one after the other, sorted by UUID.
+    pub(crate) fn describe_all<'a>(services: impl Iterator<Item = &'a Service>) -> String {
+        let mut services = services.collect::<Vec<_>>();
+        services.sort_by_key(|service| service.uuid);
+        services.into_iter().map(Service::describe).collect()
+    }
+
+    /// The characteristics as they're registered, with the service's defaults applied.
+    pub(crate) fn resolved_characteristics(&self) -> impl Iterator<Item = Characteristic> + '_ {
+        self.characteristics.iter().map(move |characteristic| {
+            let mut characteristic = characteristic.clone();
+            characteristic.max_value_length =
+                characteristic.max_value_length.or(self.max_value_length);
+            characteristic
+        })
+    }
 }
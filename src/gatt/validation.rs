@@ -0,0 +1,226 @@
+use std::{collections::HashSet, error, fmt};
+use uuid::Uuid;
+
+use super::{characteristic::Characteristic, service::Service};
+use crate::{uuid::display_uuid, SdpShortUuid};
+
+/// The longest value an attribute can have, see the Bluetooth Core Specification, Vol 3, Part F,
+/// 3.2.9.
+pub const MAX_ATTRIBUTE_VALUE_LENGTH: usize = 512;
+
+/// The Client and Server Characteristic Configuration descriptors, which the Bluetooth stack adds
+/// and answers by itself.
+const RESERVED_DESCRIPTORS: [u16; 2] = [0x2902, 0x2903];
+
+/// A mistake in a service definition, found by `Service::validate_all`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// Several services share the UUID, only the last one added would be served.
+    DuplicateService { service: Uuid },
+    /// Several characteristics of the service share the UUID without
+    /// `Service::allow_duplicate_characteristics`, backends refuse to add it.
+    DuplicateCharacteristic { service: Uuid, characteristic: Uuid },
+    /// The characteristic declares no property, so a central can't do anything with it.
+    NoProperties { service: Uuid, characteristic: Uuid },
+    /// The characteristic has a value but can be written or subscribed to. CoreBluetooth only
+    /// caches the values of read-only characteristics and rejects the service otherwise, and
+    /// a value that changes has to come from the handlers.
+    CachedValueNotReadOnly { service: Uuid, characteristic: Uuid },
+    /// The value is longer than the characteristic's maximum value length or
+    /// `MAX_ATTRIBUTE_VALUE_LENGTH`.
+    ValueTooLong {
+        service: Uuid,
+        characteristic: Uuid,
+        length: usize,
+        max_length: usize,
+    },
+    /// The raw properties of `Characteristic::from_raw` allow reading or writing while the
+    /// permissions don't.
+    IncoherentRawFlags { service: Uuid, characteristic: Uuid },
+    /// The descriptor is added by the Bluetooth stack, declaring it conflicts with that one.
+    ReservedDescriptor {
+        service: Uuid,
+        characteristic: Uuid,
+        descriptor: Uuid,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::DuplicateService { service } => {
+                write!(
+                    f,
+                    "service {} is declared more than once",
+                    display_uuid(service)
+                )
+            }
+            ValidationError::DuplicateCharacteristic {
+                service,
+                characteristic,
+            } => write!(
+                f,
+                "characteristic {} of service {} is declared more than once",
+                display_uuid(characteristic),
+                display_uuid(service)
+            ),
+            ValidationError::NoProperties {
+                service,
+                characteristic,
+            } => write!(
+                f,
+                "characteristic {} of service {} declares no property",
+                display_uuid(characteristic),
+                display_uuid(service)
+            ),
+            ValidationError::CachedValueNotReadOnly {
+                service,
+                characteristic,
+            } => write!(
+                f,
+                "characteristic {} of service {} has a value but isn't read-only",
+                display_uuid(characteristic),
+                display_uuid(service)
+            ),
+            ValidationError::ValueTooLong {
+                service,
+                characteristic,
+                length,
+                max_length,
+            } => write!(
+                f,
+                "characteristic {} of service {} has a value of {} bytes, at most {} are allowed",
+                display_uuid(characteristic),
+                display_uuid(service),
+                length,
+                max_length
+            ),
+            ValidationError::IncoherentRawFlags {
+                service,
+                characteristic,
+            } => write!(
+                f,
+                "characteristic {} of service {} has raw properties its permissions don't allow",
+                display_uuid(characteristic),
+                display_uuid(service)
+            ),
+            ValidationError::ReservedDescriptor {
+                service,
+                characteristic,
+                descriptor,
+            } => write!(
+                f,
+                "characteristic {} of service {} declares descriptor {}, which the Bluetooth \
+                 stack adds",
+                display_uuid(characteristic),
+                display_uuid(service),
+                display_uuid(descriptor)
+            ),
+        }
+    }
+}
+
+impl error::Error for ValidationError {}
+
+pub(crate) fn validate(services: &[Service]) -> Vec<ValidationError> {
+    let mut errors = vec![];
+    let mut seen = HashSet::new();
+    let mut duplicates = HashSet::new();
+    for service in services {
+        if !seen.insert(service.uuid) && duplicates.insert(service.uuid) {
+            errors.push(ValidationError::DuplicateService {
+                service: service.uuid,
+            });
+        }
+        for characteristic in service.duplicate_characteristics() {
+            errors.push(ValidationError::DuplicateCharacteristic {
+                service: service.uuid,
+                characteristic,
+            });
+        }
+        let mut characteristics = service.resolved_characteristics().collect::<Vec<_>>();
+        characteristics
+            .sort_by_key(|characteristic| (characteristic.uuid, characteristic.instance));
+        for characteristic in characteristics {
+            validate_characteristic(service.uuid, &characteristic, &mut errors);
+        }
+    }
+    errors
+}
+
+fn validate_characteristic(
+    service: Uuid,
+    characteristic: &Characteristic,
+    errors: &mut Vec<ValidationError>,
+) {
+    let uuid = characteristic.uuid;
+    let properties = &characteristic.properties;
+    match characteristic.raw_flags {
+        Some((raw_properties, permissions)) => {
+            // Read 0x02, write without response 0x04 and write 0x08 against readable 0x01,
+            // writeable 0x02 and their encryption required variants 0x04 and 0x08
+            let readable = raw_properties & 0x02 == 0 || permissions & 0x05 != 0;
+            let writeable = raw_properties & 0x0C == 0 || permissions & 0x0A != 0;
+            if !readable || !writeable {
+                errors.push(ValidationError::IncoherentRawFlags {
+                    service,
+                    characteristic: uuid,
+                });
+            }
+        }
+        None => {
+            if properties.read.is_none()
+                && properties.write.is_none()
+                && properties.notify.is_none()
+                && properties.indicate.is_none()
+            {
+                errors.push(ValidationError::NoProperties {
+                    service,
+                    characteristic: uuid,
+                });
+            } else if characteristic.value.is_some()
+                && (properties.write.is_some()
+                    || properties.notify.is_some()
+                    || properties.indicate.is_some())
+            {
+                errors.push(ValidationError::CachedValueNotReadOnly {
+                    service,
+                    characteristic: uuid,
+                });
+            }
+        }
+    }
+    if let Some(value) = &characteristic.value {
+        let max_length = characteristic
+            .max_value_length
+            .map_or(MAX_ATTRIBUTE_VALUE_LENGTH, |max_value_length| {
+                max_value_length.min(MAX_ATTRIBUTE_VALUE_LENGTH)
+            });
+        if value.len() > max_length {
+            errors.push(ValidationError::ValueTooLong {
+                service,
+                characteristic: uuid,
+                length: value.len(),
+                max_length,
+            });
+        }
+    }
+    let mut descriptors = characteristic
+        .descriptors
+        .iter()
+        .map(|descriptor| descriptor.uuid)
+        .filter(|descriptor| {
+            RESERVED_DESCRIPTORS
+                .iter()
+                .any(|&reserved| *descriptor == Uuid::from_sdp_short_uuid(reserved))
+        })
+        .collect::<Vec<_>>();
+    descriptors.sort();
+    for descriptor in descriptors {
+        errors.push(ValidationError::ReservedDescriptor {
+            service,
+            characteristic: uuid,
+            descriptor,
+        });
+    }
+}
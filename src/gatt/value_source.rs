@@ -0,0 +1,53 @@
+use futures::{channel::mpsc, prelude::*};
+
+use super::{
+    event::{AttError, Event, EventSender, Response},
+    notification_source::Subscriptions,
+};
+
+/// A live characteristic value: what a read returns and what subscribers are notified of come
+/// from the same place.
+pub trait ValueSource: Send + 'static {
+    type Updates: Stream<Item = Vec<u8>> + Send + 'static;
+
+    fn current_value(&mut self) -> Vec<u8>;
+
+    /// Called when the first central subscribes, the stream is dropped once the last one
+    /// unsubscribed.
+    fn updates(&mut self) -> Self::Updates;
+
+    /// The sender to hand to both the characteristic's `read` and its `notify` or `indicate`
+    /// property. Reads are answered with the current value from the requested offset on, writes
+    /// with `AttError::RequestNotSupported`. This spawns a task, so it has to be called within a
+    /// tokio runtime.
+    fn into_event_sender(mut self) -> EventSender
+    where
+        Self: Sized,
+    {
+        let (sender, mut receiver) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut subscriptions = Subscriptions::default();
+            while let Some(event) = receiver.next().await {
+                match event {
+                    Event::ReadRequest(read_request) => {
+                        let value = self.current_value();
+                        let _ = read_request
+                            .response
+                            .send(Response::at_offset(&value, read_request.offset));
+                    }
+                    Event::WriteRequest(write_request) => {
+                        let _ = write_request
+                            .response
+                            .send(Response::Error(AttError::RequestNotSupported));
+                    }
+                    Event::NotifySubscribe(notify_subscribe) => {
+                        subscriptions.subscribe(notify_subscribe.notification, || self.updates())
+                    }
+                    Event::NotifyUnsubscribe => subscriptions.unsubscribe(),
+                    Event::NotifyQueueDrained(_) => {}
+                }
+            }
+        });
+        sender
+    }
+}
@@ -0,0 +1,74 @@
+use std::{fmt, sync::Arc};
+
+use super::event::AttError;
+
+pub type WriteValidator = Arc<dyn Fn(&[u8]) -> Result<(), AttError> + Send + Sync>;
+
+/// What a write to a structured characteristic, e.g. a control point, has to look like. Backends
+/// check it before the write handler sees the request and answer malformed writes themselves.
+///
+/// The schema describes the whole value, so writes at an offset other than 0 are answered with
+/// `AttError::InvalidOffset`. A value of the wrong length is answered with
+/// `AttError::InvalidAttributeValueLength` before the validator is asked, which answers with the
+/// error it returns.
+#[derive(Clone, Default)]
+pub struct WriteSchema {
+    min_length: usize,
+    max_length: Option<usize>,
+    validator: Option<WriteValidator>,
+}
+
+impl WriteSchema {
+    /// Accepts any value written at offset 0.
+    pub fn new() -> Self {
+        WriteSchema::default()
+    }
+
+    pub fn with_length(self, length: usize) -> Self {
+        self.with_length_range(length, length)
+    }
+
+    pub fn with_length_range(mut self, min_length: usize, max_length: usize) -> Self {
+        self.min_length = min_length;
+        self.max_length = Some(max_length);
+        self
+    }
+
+    /// Checks the format of values of a valid length, e.g. that the opcode of a control point
+    /// is known.
+    pub fn with_validator<F>(mut self, validator: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<(), AttError> + Send + Sync + 'static,
+    {
+        self.validator = Some(Arc::new(validator));
+        self
+    }
+
+    /// The error the central is answered with, if the write doesn't match.
+    pub fn check(&self, offset: u16, value: &[u8]) -> Result<(), AttError> {
+        if offset != 0 {
+            return Err(AttError::InvalidOffset);
+        }
+        if value.len() < self.min_length
+            || self
+                .max_length
+                .is_some_and(|max_length| value.len() > max_length)
+        {
+            return Err(AttError::InvalidAttributeValueLength);
+        }
+        match &self.validator {
+            Some(validator) => validator(value),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for WriteSchema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WriteSchema")
+            .field("min_length", &self.min_length)
+            .field("max_length", &self.max_length)
+            .field("validator", &self.validator.is_some())
+            .finish()
+    }
+}
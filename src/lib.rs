@@ -2,9 +2,34 @@
 // warnings caused by `ATOMIC_USIZE_INIT` being deprecated
 #![allow(deprecated)]
 
+mod advertising;
+mod capabilities;
+mod central;
 mod error;
 pub mod gatt;
+mod metrics;
 mod peripheral;
+mod snapshot;
+mod span;
+mod state;
 mod uuid;
 
-pub use self::{error::*, peripheral::Peripheral, uuid::*};
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::uuid::parse_uuid;
+    pub use uuid::Uuid;
+}
+
+pub use self::{
+    advertising::{AdvertisingField, AdvertisingOptions},
+    capabilities::Capabilities,
+    central::{Central, CentralId, CentralInfo},
+    error::*,
+    metrics::PeripheralMetrics,
+    peripheral::{
+        NativePeripheral, Peripheral, PeripheralBackend, PeripheralBuilder, QualityOfService,
+    },
+    snapshot::PeripheralSnapshot,
+    state::{AdapterState, AdvertisingState, QueueHealth},
+    uuid::*,
+};
@@ -0,0 +1,65 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How many operations a `Peripheral` served since it was created, see `Peripheral::metrics`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PeripheralMetrics {
+    /// Reads answered with a value.
+    pub reads_served: u64,
+    /// Writes the handler accepted, including writes without response handed to it.
+    pub writes_served: u64,
+    pub notifications_sent: u64,
+    /// Notifications the Bluetooth stack didn't accept, usually because its queue was full.
+    pub notifications_dropped: u64,
+    pub subscribes: u64,
+    pub unsubscribes: u64,
+}
+
+/// The counters behind `PeripheralMetrics`, shared with the callbacks that update them.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    reads_served: AtomicU64,
+    writes_served: AtomicU64,
+    notifications_sent: AtomicU64,
+    notifications_dropped: AtomicU64,
+    subscribes: AtomicU64,
+    unsubscribes: AtomicU64,
+}
+
+impl Metrics {
+    pub fn read_served(&self) {
+        self.reads_served.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn write_served(&self) {
+        self.writes_served.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn notification_sent(&self) {
+        self.notifications_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn notification_dropped(&self) {
+        self.notifications_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn subscribed(&self) {
+        self.subscribes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn unsubscribed(&self) {
+        self.unsubscribes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The counters are read one at a time, so operations served meanwhile may only show up in
+    /// some of them.
+    pub fn snapshot(&self) -> PeripheralMetrics {
+        PeripheralMetrics {
+            reads_served: self.reads_served.load(Ordering::Relaxed),
+            writes_served: self.writes_served.load(Ordering::Relaxed),
+            notifications_sent: self.notifications_sent.load(Ordering::Relaxed),
+            notifications_dropped: self.notifications_dropped.load(Ordering::Relaxed),
+            subscribes: self.subscribes.load(Ordering::Relaxed),
+            unsubscribes: self.unsubscribes.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use futures::future::BoxFuture;
+use std::ops::Deref;
+use uuid::Uuid;
+
+use super::{NativePeripheral, PeripheralBuilder};
+use crate::{
+    gatt::{characteristic::CharacteristicHandle, service::Service},
+    AdvertisingOptions, Error, NotifyError,
+};
+
+/// The core operations of a peripheral, which the platform's backend implements. Other
+/// transports, e.g. an embedded stack or a simulation in tests, implement it too and are plugged
+/// into a `Peripheral` with `Peripheral::with_backend`.
+///
+/// Answering requests isn't part of it, handlers answer through the `ReadRequest` or
+/// `WriteRequest` they're sent, whichever backend received it.
+pub trait PeripheralBackend: Send + Sync {
+    fn is_powered(&self) -> BoxFuture<'_, Result<bool, Error>>;
+
+    fn add_service(&self, service: &Service) -> Result<Vec<CharacteristicHandle>, Error>;
+
+    /// Removing a service that isn't added does nothing.
+    fn remove_service(&self, uuid: &Uuid) -> Result<(), Error>;
+
+    /// Serves the added services.
+    fn register_gatt(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    fn start_advertising_with<'a>(
+        &'a self,
+        options: &'a AdvertisingOptions,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+
+    fn stop_advertising(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    fn notify<'a>(
+        &'a self,
+        characteristic_uuid: &'a Uuid,
+        value: &'a [u8],
+    ) -> BoxFuture<'a, Result<(), NotifyError>>;
+}
+
+/// A peripheral driven by the platform's backend, or the one plugged in with `with_backend`.
+/// Everything the backend offers beyond `PeripheralBackend` is reachable through it as well.
+#[derive(Debug, Clone)]
+pub struct Peripheral<B = NativePeripheral> {
+    backend: B,
+}
+
+impl Peripheral {
+    pub async fn new() -> Result<Self, Error> {
+        PeripheralBuilder::default().build().await
+    }
+
+    pub(crate) async fn from_builder(builder: PeripheralBuilder) -> Result<Self, Error> {
+        NativePeripheral::from_builder(builder)
+            .await
+            .map(Peripheral::with_backend)
+    }
+
+    /// See the platform's `NativePeripheral::shutdown`, which takes the peripheral.
+    pub async fn shutdown(self) -> Result<(), Error> {
+        self.backend.shutdown().await
+    }
+}
+
+impl<B: PeripheralBackend> Peripheral<B> {
+    /// A peripheral served by `backend`, e.g. a `Box<dyn PeripheralBackend>` chosen at runtime.
+    pub fn with_backend(backend: B) -> Self {
+        Peripheral { backend }
+    }
+}
+
+impl<B> Deref for Peripheral<B> {
+    type Target = B;
+
+    fn deref(&self) -> &B {
+        &self.backend
+    }
+}
+
+impl<B: PeripheralBackend> PeripheralBackend for Peripheral<B> {
+    fn is_powered(&self) -> BoxFuture<'_, Result<bool, Error>> {
+        self.backend.is_powered()
+    }
+
+    fn add_service(&self, service: &Service) -> Result<Vec<CharacteristicHandle>, Error> {
+        self.backend.add_service(service)
+    }
+
+    fn remove_service(&self, uuid: &Uuid) -> Result<(), Error> {
+        self.backend.remove_service(uuid)
+    }
+
+    fn register_gatt(&self) -> BoxFuture<'_, Result<(), Error>> {
+        self.backend.register_gatt()
+    }
+
+    fn start_advertising_with<'a>(
+        &'a self,
+        options: &'a AdvertisingOptions,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        self.backend.start_advertising_with(options)
+    }
+
+    fn stop_advertising(&self) -> BoxFuture<'_, Result<(), Error>> {
+        self.backend.stop_advertising()
+    }
+
+    fn notify<'a>(
+        &'a self,
+        characteristic_uuid: &'a Uuid,
+        value: &'a [u8],
+    ) -> BoxFuture<'a, Result<(), NotifyError>> {
+        self.backend.notify(characteristic_uuid, value)
+    }
+}
+
+impl<B: PeripheralBackend + ?Sized> PeripheralBackend for Box<B> {
+    fn is_powered(&self) -> BoxFuture<'_, Result<bool, Error>> {
+        (**self).is_powered()
+    }
+
+    fn add_service(&self, service: &Service) -> Result<Vec<CharacteristicHandle>, Error> {
+        (**self).add_service(service)
+    }
+
+    fn remove_service(&self, uuid: &Uuid) -> Result<(), Error> {
+        (**self).remove_service(uuid)
+    }
+
+    fn register_gatt(&self) -> BoxFuture<'_, Result<(), Error>> {
+        (**self).register_gatt()
+    }
+
+    fn start_advertising_with<'a>(
+        &'a self,
+        options: &'a AdvertisingOptions,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        (**self).start_advertising_with(options)
+    }
+
+    fn stop_advertising(&self) -> BoxFuture<'_, Result<(), Error>> {
+        (**self).stop_advertising()
+    }
+
+    fn notify<'a>(
+        &'a self,
+        characteristic_uuid: &'a Uuid,
+        value: &'a [u8],
+    ) -> BoxFuture<'a, Result<(), NotifyError>> {
+        (**self).notify(characteristic_uuid, value)
+    }
+}
+
+impl PeripheralBackend for NativePeripheral {
+    fn is_powered(&self) -> BoxFuture<'_, Result<bool, Error>> {
+        Box::pin(NativePeripheral::is_powered(self))
+    }
+
+    fn add_service(&self, service: &Service) -> Result<Vec<CharacteristicHandle>, Error> {
+        NativePeripheral::add_service(self, service)
+    }
+
+    fn remove_service(&self, uuid: &Uuid) -> Result<(), Error> {
+        NativePeripheral::remove_service(self, uuid)
+    }
+
+    fn register_gatt(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(NativePeripheral::register_gatt(self))
+    }
+
+    fn start_advertising_with<'a>(
+        &'a self,
+        options: &'a AdvertisingOptions,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(NativePeripheral::start_advertising_with(self, options))
+    }
+
+    fn stop_advertising(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(NativePeripheral::stop_advertising(self))
+    }
+
+    fn notify<'a>(
+        &'a self,
+        characteristic_uuid: &'a Uuid,
+        value: &'a [u8],
+    ) -> BoxFuture<'a, Result<(), NotifyError>> {
+        Box::pin(NativePeripheral::notify(self, characteristic_uuid, value))
+    }
+}
@@ -1,17 +1,22 @@
 use dbus::{
     arg::{messageitem::MessageItem, RefArg, Variant},
-    Path,
+    message::MatchRule,
+    Message, Path,
 };
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use tokio::sync::watch;
 
 use super::{
     connection::Connection,
     constants::{
-        ADAPTER_IFACE, DBUS_OBJECTMANAGER_IFACE, DBUS_PROPERTIES_IFACE,
+        ADAPTER_IFACE, DBUS_OBJECTMANAGER_IFACE, DBUS_PROPERTIES_IFACE, DEVICE_IFACE,
         LE_ADVERTISING_MANAGER_IFACE,
     },
 };
-use crate::Error;
+use crate::{AdapterState, CentralId, Error, ErrorType};
 
 #[derive(Debug, Clone)]
 pub struct Adapter {
@@ -22,6 +27,8 @@ pub struct Adapter {
 type ManagedObjectsProps =
     HashMap<Path<'static>, HashMap<String, HashMap<String, Variant<Box<dyn RefArg>>>>>;
 
+type PropertiesChanged = (String, HashMap<String, Variant<Box<dyn RefArg>>>);
+
 impl Adapter {
     async fn find_adapter(connection: &Arc<Connection>) -> Result<Path<'static>, Error> {
         let path = "/".into();
@@ -71,6 +78,90 @@ impl Adapter {
         Ok(powered.0)
     }
 
+    pub async fn watch_state(self: &Self) -> Result<watch::Receiver<AdapterState>, Error> {
+        let match_rule = MatchRule::new_signal(DBUS_PROPERTIES_IFACE, "PropertiesChanged")
+            .with_path(self.object_path.clone());
+        let msg_match = self.connection.default.add_match(match_rule).await?;
+
+        let initial_state = if self.is_powered().await? {
+            AdapterState::PoweredOn
+        } else {
+            AdapterState::PoweredOff
+        };
+        let (sender, receiver) = watch::channel(initial_state);
+
+        msg_match.cb(
+            move |_, (interface, changed_properties): PropertiesChanged| {
+                if interface == ADAPTER_IFACE {
+                    if let Some(powered) = changed_properties
+                        .get("Powered")
+                        .and_then(|powered| powered.as_u64())
+                    {
+                        sender.send_replace(if powered != 0 {
+                            AdapterState::PoweredOn
+                        } else {
+                            AdapterState::PoweredOff
+                        });
+                    }
+                }
+                true
+            },
+        );
+
+        Ok(receiver)
+    }
+
+    /// The number of devices connected to the adapter, kept up to date from their `Connected`
+    /// property.
+    pub async fn watch_connections(&self) -> Result<watch::Receiver<usize>, Error> {
+        let match_rule = MatchRule::new_signal(DBUS_PROPERTIES_IFACE, "PropertiesChanged")
+            .with_namespaced_path(self.object_path.clone());
+        let msg_match = self.connection.default.add_match(match_rule).await?;
+
+        let path = "/".into();
+        let proxy = self.connection.get_bluez_proxy(&path);
+        let (props,): (ManagedObjectsProps,) = proxy
+            .method_call(DBUS_OBJECTMANAGER_IFACE, "GetManagedObjects", ())
+            .await?;
+        let device_prefix = format!("{}/", self.object_path);
+        let mut connected: HashSet<Path<'static>> = props
+            .into_iter()
+            .filter(|(path, props)| {
+                path.starts_with(&device_prefix)
+                    && props
+                        .get(DEVICE_IFACE)
+                        .and_then(|device| device.get("Connected"))
+                        .and_then(|connected| connected.as_u64())
+                        .is_some_and(|connected| connected != 0)
+            })
+            .map(|(path, _props)| path)
+            .collect();
+        let (sender, receiver) = watch::channel(connected.len());
+
+        msg_match.cb(
+            move |message: Message, (interface, changed_properties): PropertiesChanged| {
+                if interface == DEVICE_IFACE {
+                    if let (Some(path), Some(is_connected)) = (
+                        message.path(),
+                        changed_properties
+                            .get("Connected")
+                            .and_then(|connected| connected.as_u64()),
+                    ) {
+                        if is_connected != 0 {
+                            connected.insert(path.into_static());
+                        } else {
+                            connected.remove(&path.into_static());
+                        }
+                        sender.send_replace(connected.len());
+                    }
+                }
+                true
+            },
+        );
+
+        Ok(receiver)
+    }
+
     pub async fn get_alias(self: &Self) -> Result<String, Error> {
         let proxy = self.connection.get_bluez_proxy(&self.object_path);
         let (alias,): (Variant<String>,) = proxy
@@ -94,4 +185,26 @@ impl Adapter {
             .await?;
         Ok(())
     }
+
+    pub async fn get_device_rssi(&self, device: &CentralId) -> Result<Option<i16>, Error> {
+        let path = Path::new(device.as_str())
+            .map_err(|_| Error::new("InvalidCentral", device.as_str(), ErrorType::Bluez))?;
+        let proxy = self.connection.get_bluez_proxy(&path);
+        // BlueZ only exposes `RSSI` while it has a recent reading for the device, otherwise the
+        // property is missing and `Get` fails.
+        let result: Result<(Variant<i16>,), _> = proxy
+            .method_call(DBUS_PROPERTIES_IFACE, "Get", (DEVICE_IFACE, "RSSI"))
+            .await;
+        Ok(result.ok().map(|(rssi,)| rssi.0))
+    }
+
+    /// The `Name` the device advertised, BlueZ leaves it out for devices that didn't.
+    pub async fn get_device_name(&self, device: &CentralId) -> Option<String> {
+        let path = Path::new(device.as_str()).ok()?;
+        let proxy = self.connection.get_bluez_proxy(&path);
+        let result: Result<(Variant<String>,), _> = proxy
+            .method_call(DBUS_PROPERTIES_IFACE, "Get", (DEVICE_IFACE, "Name"))
+            .await;
+        result.ok().map(|(name,)| name.0)
+    }
 }
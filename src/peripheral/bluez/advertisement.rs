@@ -4,6 +4,7 @@ use dbus::{
     message::MatchRule,
     Path,
 };
+use dbus_crossroads::MethodErr;
 use std::{
     collections::HashMap,
     sync::{
@@ -11,13 +12,31 @@ use std::{
         Arc, Mutex,
     },
 };
+use tokio::sync::watch;
 
 use super::{
     common,
     connection::Connection,
     constants::{LE_ADVERTISEMENT_IFACE, LE_ADVERTISING_MANAGER_IFACE, PATH_BASE},
 };
-use crate::Error;
+use crate::{AdvertisingState, Error};
+
+type ServiceData = HashMap<String, Vec<u8>>;
+
+/// A company identifier and the data following it.
+type ManufacturerData = (u16, Vec<u8>);
+
+/// The AD type of the complete local name, see the Assigned Numbers, 2.3.
+const COMPLETE_LOCAL_NAME: u8 = 0x09;
+
+/// The fields sent in the scan response instead of the advertisement.
+#[derive(Debug, Clone, Default)]
+pub struct ScanResponse {
+    pub name: Option<String>,
+    pub uuids: Vec<String>,
+    pub service_data: ServiceData,
+    pub manufacturer_data: Option<ManufacturerData>,
+}
 
 #[derive(Debug, Clone)]
 pub struct Advertisement {
@@ -26,8 +45,15 @@ pub struct Advertisement {
     pub object_path: Path<'static>,
     tree: Arc<Mutex<common::Tree>>,
     is_advertising: Arc<AtomicBool>,
+    state: Arc<watch::Sender<AdvertisingState>>,
     name: Arc<Mutex<Option<String>>>,
     uuids: Arc<Mutex<Option<Vec<String>>>>,
+    service_data: Arc<Mutex<Option<ServiceData>>>,
+    manufacturer_data: Arc<Mutex<Option<ManufacturerData>>>,
+    timeout: Arc<Mutex<Option<u16>>>,
+    interval: Arc<Mutex<(Option<u32>, Option<u32>)>>,
+    connectable: Arc<AtomicBool>,
+    scan_response: Arc<Mutex<ScanResponse>>,
 }
 
 impl Advertisement {
@@ -35,23 +61,67 @@ impl Advertisement {
         let mut tree = common::Tree::new();
         let is_advertising = Arc::new(AtomicBool::new(false));
         let is_advertising_release = is_advertising.clone();
+        let state = Arc::new(watch::channel(AdvertisingState::Stopped).0);
+        let state_release = state.clone();
 
         let name = Arc::new(Mutex::new(None));
         let name_property = name.clone();
 
+        let scan_response = Arc::new(Mutex::new(ScanResponse::default()));
+        let name_scan_response = scan_response.clone();
+        let scan_response_uuids_property = scan_response.clone();
+        let scan_response_service_data_property = scan_response.clone();
+        let scan_response_data_property = scan_response.clone();
+        let scan_response_manufacturer_data_property = scan_response.clone();
+
         let uuids = Arc::new(Mutex::new(None));
         let uuids_property = uuids.clone();
 
+        let service_data = Arc::new(Mutex::new(None));
+        let service_data_property = service_data.clone();
+
+        let manufacturer_data = Arc::new(Mutex::new(None));
+        let manufacturer_data_property = manufacturer_data.clone();
+
+        let timeout = Arc::new(Mutex::new(None));
+        let timeout_property = timeout.clone();
+        let timeout_release = timeout.clone();
+
+        let interval = Arc::new(Mutex::new((None, None)));
+        let min_interval_property = interval.clone();
+        let max_interval_property = interval.clone();
+
+        let connectable = Arc::new(AtomicBool::new(true));
+        let connectable_property = connectable.clone();
+
         let object_path: Path = format!("{}/advertisement{:04}", PATH_BASE, 0).into();
 
         let iface_token = tree.register(LE_ADVERTISEMENT_IFACE, |b| {
             b.method_with_cr_async("Release", (), (), move |mut ctx, _cr, ()| {
-                is_advertising_release.store(false, Ordering::Relaxed);
+                // Unregistering already reported `Stopped`, a late release doesn't override it.
+                // Without a timeout BlueZ only releases the advertisement when it drops it, e.g.
+                // because the adapter was powered off
+                if is_advertising_release.swap(false, Ordering::Relaxed) {
+                    state_release.send_replace(if timeout_release.lock().unwrap().is_some() {
+                        AdvertisingState::TimedOut
+                    } else {
+                        AdvertisingState::Interrupted
+                    });
+                }
                 futures::future::ready(ctx.reply(Ok(())))
             });
-            b.property("Type")
-                .get(|_ctx, _cr| Ok("peripheral".to_owned()));
+            // A broadcast advertisement isn't connectable
+            b.property("Type").get(move |_ctx, _cr| {
+                Ok(if connectable_property.load(Ordering::Relaxed) {
+                    "peripheral".to_owned()
+                } else {
+                    "broadcast".to_owned()
+                })
+            });
             b.property("LocalName").get(move |_ctx, _cr| {
+                if name_scan_response.lock().unwrap().name.is_some() {
+                    return Err(MethodErr::no_property("LocalName"));
+                }
                 Ok(name_property
                     .lock()
                     .expect("Poisoned mutex")
@@ -65,6 +135,96 @@ impl Advertisement {
                     .clone()
                     .unwrap_or_else(Vec::new))
             });
+            b.property("ServiceData").get(move |_ctx, _cr| {
+                Ok(service_data_property
+                    .lock()
+                    .expect("Poisoned mutex")
+                    .clone()
+                    .unwrap_or_else(HashMap::new)
+                    .into_iter()
+                    .map(|(uuid, data)| (uuid, Variant(data)))
+                    .collect::<HashMap<String, Variant<Vec<u8>>>>())
+            });
+            b.property("ManufacturerData").get(move |_ctx, _cr| {
+                Ok(manufacturer_data_property
+                    .lock()
+                    .expect("Poisoned mutex")
+                    .clone()
+                    .into_iter()
+                    .map(|(company_id, data)| (company_id, Variant(data)))
+                    .collect::<HashMap<u16, Variant<Vec<u8>>>>())
+            });
+            // The scan response properties are experimental, they're left out unless used so
+            // BlueZ without them accepts the advertisement
+            b.property("ScanResponseServiceUUIDs")
+                .get(move |_ctx, _cr| {
+                    let uuids = scan_response_uuids_property.lock().unwrap().uuids.clone();
+                    if uuids.is_empty() {
+                        return Err(MethodErr::no_property("ScanResponseServiceUUIDs"));
+                    }
+                    Ok(uuids)
+                });
+            b.property("ScanResponseServiceData").get(move |_ctx, _cr| {
+                let service_data = scan_response_service_data_property
+                    .lock()
+                    .unwrap()
+                    .service_data
+                    .clone();
+                if service_data.is_empty() {
+                    return Err(MethodErr::no_property("ScanResponseServiceData"));
+                }
+                Ok(service_data
+                    .into_iter()
+                    .map(|(uuid, data)| (uuid, Variant(data)))
+                    .collect::<HashMap<String, Variant<Vec<u8>>>>())
+            });
+            b.property("ScanResponseManufacturerData")
+                .get(move |_ctx, _cr| {
+                    match scan_response_manufacturer_data_property
+                        .lock()
+                        .unwrap()
+                        .manufacturer_data
+                        .clone()
+                    {
+                        Some((company_id, data)) => Ok(vec![(company_id, Variant(data))]
+                            .into_iter()
+                            .collect::<HashMap<u16, Variant<Vec<u8>>>>()),
+                        None => Err(MethodErr::no_property("ScanResponseManufacturerData")),
+                    }
+                });
+            // BlueZ has no scan response property for the name, it's sent as raw data
+            b.property("ScanResponseData")
+                .get(move |_ctx, _cr| {
+                    match scan_response_data_property.lock().unwrap().name.clone() {
+                        Some(name) => Ok(vec![(COMPLETE_LOCAL_NAME, Variant(name.into_bytes()))]
+                            .into_iter()
+                            .collect::<HashMap<u8, Variant<Vec<u8>>>>()),
+                        None => Err(MethodErr::no_property("ScanResponseData")),
+                    }
+                });
+            // BlueZ calls `Release` once the timeout expired, 0 advertises until unregistered
+            b.property("Timeout").get(move |_ctx, _cr| {
+                Ok(timeout_property
+                    .lock()
+                    .expect("Poisoned mutex")
+                    .unwrap_or(0))
+            });
+            // Unset intervals are left out, BlueZ rejects the advertisement if they're present
+            // but out of range
+            b.property("MinInterval").get(move |_ctx, _cr| {
+                min_interval_property
+                    .lock()
+                    .expect("Poisoned mutex")
+                    .0
+                    .ok_or_else(|| MethodErr::no_property("MinInterval"))
+            });
+            b.property("MaxInterval").get(move |_ctx, _cr| {
+                max_interval_property
+                    .lock()
+                    .expect("Poisoned mutex")
+                    .1
+                    .ok_or_else(|| MethodErr::no_property("MaxInterval"))
+            });
         });
         let ifaces = [iface_token, tree.object_manager()];
         tree.insert(object_path.clone(), &ifaces, ());
@@ -90,8 +250,15 @@ impl Advertisement {
             object_path,
             tree,
             is_advertising,
+            state,
             name,
             uuids,
+            service_data,
+            manufacturer_data,
+            timeout,
+            interval,
+            connectable,
+            scan_response,
         }
     }
 
@@ -103,6 +270,34 @@ impl Advertisement {
         self.uuids.lock().unwrap().replace(uuids.into());
     }
 
+    pub fn add_service_data<T: Into<ServiceData>>(self: &Self, service_data: T) {
+        self.service_data
+            .lock()
+            .unwrap()
+            .replace(service_data.into());
+    }
+
+    pub fn add_manufacturer_data(&self, manufacturer_data: Option<ManufacturerData>) {
+        *self.manufacturer_data.lock().unwrap() = manufacturer_data;
+    }
+
+    pub fn add_timeout(&self, timeout: Option<u16>) {
+        *self.timeout.lock().unwrap() = timeout;
+    }
+
+    /// The minimum and maximum interval in milliseconds.
+    pub fn add_interval(&self, min_interval: Option<u32>, max_interval: Option<u32>) {
+        *self.interval.lock().unwrap() = (min_interval, max_interval);
+    }
+
+    pub fn add_connectable(&self, connectable: bool) {
+        self.connectable.store(connectable, Ordering::Relaxed);
+    }
+
+    pub fn add_scan_response(&self, scan_response: ScanResponse) {
+        *self.scan_response.lock().unwrap() = scan_response;
+    }
+
     pub async fn register(self: &Self) -> Result<(), Error> {
         // Register with DBus
         let proxy = self.connection.get_bluez_proxy(&self.adapter);
@@ -117,6 +312,7 @@ impl Advertisement {
             )
             .await?;
         self.is_advertising.store(true, Ordering::Relaxed);
+        self.state.send_replace(AdvertisingState::Advertising);
         Ok(())
     }
 
@@ -130,11 +326,16 @@ impl Advertisement {
         );
 
         self.is_advertising.store(false, Ordering::Relaxed);
+        self.state.send_replace(AdvertisingState::Stopped);
 
         method_call.await?;
         Ok(())
     }
 
+    pub fn state_receiver(&self) -> watch::Receiver<AdvertisingState> {
+        self.state.subscribe()
+    }
+
     pub fn is_advertising(self: &Self) -> bool {
         let is_advertising = self.is_advertising.clone();
         is_advertising.load(Ordering::Relaxed)
@@ -1,7 +1,12 @@
-use crate::{Error, ErrorType};
-use dbus::{arg::TypeMismatchError as DbusTypeMismatchError, Error as DbusError};
+use crate::{gatt::event::AttError, Error, ErrorType};
+use dbus::{arg::TypeMismatchError as DbusTypeMismatchError, Error as DbusError, MethodErr};
 use std::io::Error as IoError;
 
+use super::constants::{
+    BLUEZ_ERROR_FAILED, BLUEZ_ERROR_INVALIDOFFSET, BLUEZ_ERROR_INVALIDVALUELENGTH,
+    BLUEZ_ERROR_NOTAUTHORIZED, BLUEZ_ERROR_NOTPERMITTED, BLUEZ_ERROR_NOTSUPPORTED,
+};
+
 impl From<DbusError> for Error {
     fn from(dbus_error: DbusError) -> Error {
         Error::new(
@@ -33,3 +38,22 @@ impl From<()> for Error {
         Error::new("no name", "no description", ErrorType::Bluez)
     }
 }
+
+/// BlueZ turns the errors of GATT methods into ATT error codes, but only knows a handful of them.
+/// Everything without a counterpart reaches the central as an unlikely error.
+impl From<AttError> for MethodErr {
+    fn from(error: AttError) -> MethodErr {
+        let name = match error {
+            AttError::ReadNotPermitted | AttError::WriteNotPermitted => BLUEZ_ERROR_NOTPERMITTED,
+            AttError::InsufficientAuthentication
+            | AttError::InsufficientAuthorization
+            | AttError::InsufficientEncryption
+            | AttError::InsufficientEncryptionKeySize => BLUEZ_ERROR_NOTAUTHORIZED,
+            AttError::InvalidOffset => BLUEZ_ERROR_INVALIDOFFSET,
+            AttError::InvalidAttributeValueLength => BLUEZ_ERROR_INVALIDVALUELENGTH,
+            AttError::RequestNotSupported => BLUEZ_ERROR_NOTSUPPORTED,
+            _ => BLUEZ_ERROR_FAILED,
+        };
+        MethodErr::from((name, ""))
+    }
+}
@@ -9,7 +9,14 @@ use futures::{
     channel::{mpsc, oneshot},
     prelude::*,
 };
-use std::{collections::HashMap, sync::Arc};
+use log::trace;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use super::{
     super::{
@@ -20,13 +27,39 @@ use super::{
     },
     flags::Flags,
 };
-use crate::{gatt, Error};
+use crate::{
+    gatt,
+    gatt::{
+        default_handler::{DefaultHandlers, RequestContext},
+        event::AttError,
+    },
+    metrics::Metrics,
+    span::Span,
+    Central, CentralId, Error,
+};
 
-type OptionsMap = HashMap<String, Variant<Box<dyn RefArg>>>;
+pub(super) type OptionsMap = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+/// The central BlueZ names in the `device` option of a request. Its MTU is in `mtu`, which BlueZ
+/// leaves out before 5.47, so it's the default one then.
+pub(super) fn central(options: &OptionsMap) -> Option<Central> {
+    let device = options.get("device").and_then(RefArg::as_str)?;
+    let mtu = options.get("mtu").and_then(RefArg::as_u64).unwrap_or(23) as usize;
+    Some(Central::new(CentralId::new(device), mtu.saturating_sub(3)))
+}
 
 #[derive(Debug, Clone)]
 pub struct Characteristic {
     pub object_path: Path<'static>,
+    pub notifier: Notifier,
+}
+
+/// Sends notifications of a characteristic outside of a subscription's stream.
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    pub sender: mpsc::Sender<Vec<u8>>,
+    /// BlueZ only calls `StartNotify` for the first subscriber and `StopNotify` after the last.
+    pub notifying: Arc<AtomicBool>,
 }
 
 impl Characteristic {
@@ -36,6 +69,8 @@ impl Characteristic {
         characteristic: &Arc<gatt::characteristic::Characteristic>,
         service: &Path<'static>,
         index: u64,
+        defaults: &DefaultHandlers,
+        metrics: &Arc<Metrics>,
     ) -> Result<Self, Error> {
         let object_path: Path = format!("{}/characteristic{:04}", service, index).into();
         let object_path_data = common::GattDataType::Characteristic(Arc::clone(characteristic));
@@ -44,9 +79,12 @@ impl Characteristic {
         {
             let object_path = object_path.clone();
             let connection = Arc::clone(connection);
+            let metrics = Arc::clone(metrics);
+            let uuid = characteristic.uuid;
             tokio::spawn(
                 message_receiver
                     .map(move |notification: Vec<u8>| {
+                        let len = notification.len();
                         // For notifications, BlueZ wants a PropertiesChanged
                         // signal on the optional `Value` property. It doesn't
                         // require that the property actually exists.
@@ -63,106 +101,223 @@ impl Characteristic {
                             &"PropertiesChanged".into(),
                         );
                         signal_message.append_all(signal);
-                        connection.default.send(signal_message).ok();
+                        let sent = connection.default.send(signal_message).is_ok();
+                        if sent {
+                            metrics.notification_sent();
+                        } else {
+                            metrics.notification_dropped();
+                        }
+                        trace!("Notification {} len={} sent={}", uuid, len, sent);
                     })
                     .collect::<()>(),
             );
         }
 
+        // Writes without response are queued here as BlueZ delivers them and handed to the write
+        // handler one at a time, so rapid commands neither get reordered nor dropped.
+        let (command_sender, commands) = gatt::event::command_queue();
+        if characteristic.properties.write.is_some() {
+            tokio::spawn(commands);
+        }
+
+        let notifying = Arc::new(AtomicBool::new(false));
+
         let iface_token = tree.register::<GattDataType, _, _>(GATT_CHARACTERISTIC_IFACE, |b| {
             let message_sender = message_sender.clone();
+            let read_defaults = defaults.clone();
+            let read_metrics = Arc::clone(metrics);
             b.method_with_cr_async(
                 "ReadValue",
                 ("options",),
                 ("value",),
-                |mut ctx, cr, (options,): (OptionsMap,)| {
+                move |mut ctx, cr, (options,): (OptionsMap,)| {
                     let offset = options.get("offset").and_then(RefArg::as_u64).unwrap_or(0) as u16;
                     let mtu = options.get("mtu").and_then(RefArg::as_u64).unwrap_or(23) as u16;
+                    let central = central(&options);
 
                     let characteristic = cr
                         .data_mut::<GattDataType>(ctx.path())
                         .unwrap()
                         .get_characteristic();
-                    async move {
-                        let event_sender = characteristic
-                            .properties
-                            .read
-                            .clone()
-                            .ok_or_else(|| MethodErr::from((BLUEZ_ERROR_NOTSUPPORTED, "")))?;
+                    trace!(
+                        "ReadValue {} offset={} mtu={} central={:?}",
+                        characteristic.uuid,
+                        offset,
+                        mtu,
+                        central
+                    );
+                    let defaults = read_defaults.clone();
+                    let metrics = Arc::clone(&read_metrics);
+                    let span = Span::new(
+                        "read",
+                        &characteristic.uuid,
+                        central.as_ref().map(Central::id),
+                    );
+                    span.instrument(async move {
+                        let event_sender = characteristic.check_read()?;
+                        if !characteristic.authorizes(central.as_ref().map(Central::id)) {
+                            return Err(AttError::InsufficientAuthorization.into());
+                        }
                         let (sender, receiver) = oneshot::channel();
-                        event_sender
-                            .sender()
-                            .send(gatt::event::Event::ReadRequest(gatt::event::ReadRequest {
-                                offset,
-                                response: sender,
-                                mtu,
-                            }))
-                            .await
-                            .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))?;
-                        receiver
+                        let context = RequestContext::new(characteristic.uuid, central.clone());
+                        let read_request = gatt::event::ReadRequest {
+                            offset,
+                            response: sender,
+                            mtu,
+                            central,
+                        };
+                        if !defaults
+                            .send_read(&mut event_sender.sender(), context, read_request)
                             .await
-                            .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
-                            .and_then(|resp| match resp {
-                                gatt::event::Response::Success(value) => Ok((value,)),
-                                _ => Err(MethodErr::from((BLUEZ_ERROR_FAILED, ""))),
-                            })
-                    }
+                        {
+                            return Err(MethodErr::from((BLUEZ_ERROR_FAILED, "")));
+                        }
+                        let value =
+                            tokio::time::timeout(gatt::event::READ_RESPONSE_TIMEOUT, receiver)
+                                .await
+                                .unwrap_or(Ok(gatt::event::Response::UnlikelyError))
+                                .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
+                                .and_then(|resp| resp.into_result().map_err(MethodErr::from))?;
+                        metrics.read_served();
+                        Ok((value,))
+                    })
                     .map(move |result| ctx.reply(result))
                 },
             );
+            let write_defaults = defaults.clone();
+            let write_metrics = Arc::clone(metrics);
             b.method_with_cr_async(
                 "WriteValue",
                 ("data", "options"),
                 ("value",),
-                |mut ctx, cr, (data, options): (Vec<u8>, OptionsMap)| {
+                move |mut ctx, cr, (data, options): (Vec<u8>, OptionsMap)| {
                     let offset = options.get("offset").and_then(RefArg::as_u64).unwrap_or(0) as u16;
+                    let central = central(&options);
                     let characteristic = cr
                         .data_mut::<GattDataType>(ctx.path())
                         .unwrap()
                         .get_characteristic();
-                    async move {
-                        let event_sender = characteristic
-                            .properties
-                            .write
-                            .clone()
-                            .ok_or_else(|| MethodErr::from((BLUEZ_ERROR_NOTSUPPORTED, "")))?;
+                    // BlueZ only tells the kind of write since 5.51, before that it's the one the
+                    // characteristic declares
+                    let without_response = match options.get("type").and_then(RefArg::as_str) {
+                        Some(write_type) => write_type == "command",
+                        None => matches!(
+                            characteristic.properties.write,
+                            Some(gatt::characteristic::Write::WithoutResponse(_))
+                        ),
+                    };
+                    trace!(
+                        "WriteValue {} value={} offset={} without_response={} central={:?}",
+                        characteristic.uuid,
+                        characteristic.logged_value(&data),
+                        offset,
+                        without_response,
+                        central
+                    );
+                    let write = characteristic
+                        .check_write(without_response)
+                        .and_then(|write| {
+                            if !characteristic.authorizes(central.as_ref().map(Central::id)) {
+                                Err(AttError::InsufficientAuthorization)
+                            } else if characteristic.exceeds_max_value_length(offset, data.len()) {
+                                Err(AttError::InvalidAttributeValueLength)
+                            } else {
+                                characteristic
+                                    .check_write_schema(offset, &data)
+                                    .map(|_| write)
+                            }
+                        });
+                    let write = match write {
+                        Ok(write) => write,
+                        Err(error) => {
+                            let result: Result<(Vec<u8>,), _> = Err(error.into());
+                            return future::Either::Left(future::ready(ctx.reply(result)));
+                        }
+                    };
+                    let span = Span::new(
+                        "write",
+                        &characteristic.uuid,
+                        central.as_ref().map(Central::id),
+                    );
+                    let context = RequestContext::new(characteristic.uuid, central.clone());
+                    if without_response {
+                        // Nothing waits on the handler for a command, it's queued before this
+                        // call returns so the next one can't overtake it.
+                        let (sender, _) = oneshot::channel();
+                        let command = gatt::event::WriteRequest {
+                            data,
+                            offset,
+                            without_response,
+                            response: sender,
+                            central,
+                        };
+                        let event_sender = write.sender();
+                        let result = span.in_scope(|| {
+                            match write_defaults.try_default_write(&event_sender, context, command)
+                            {
+                                Some(command) => command_sender
+                                    .unbounded_send((event_sender, command))
+                                    .map(|_| (Vec::new(),))
+                                    .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, ""))),
+                                None => Ok((Vec::new(),)),
+                            }
+                        });
+                        if result.is_ok() {
+                            write_metrics.write_served();
+                        }
+                        return future::Either::Left(future::ready(ctx.reply(result)));
+                    }
+                    let defaults = write_defaults.clone();
+                    let metrics = Arc::clone(&write_metrics);
+                    let write = async move {
                         let (sender, receiver) = oneshot::channel();
-                        event_sender
-                            .sender()
-                            .send(gatt::event::Event::WriteRequest(
-                                gatt::event::WriteRequest {
-                                    data,
-                                    offset,
-                                    without_response: false,
-                                    response: sender,
-                                },
-                            ))
+                        let write_request = gatt::event::WriteRequest {
+                            data,
+                            offset,
+                            without_response: false,
+                            response: sender,
+                            central,
+                        };
+                        if !defaults
+                            .send_write(&mut write.sender(), context, write_request)
                             .await
-                            .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))?;
-                        receiver
+                        {
+                            return Err(MethodErr::from((BLUEZ_ERROR_FAILED, "")));
+                        }
+                        let value = receiver
                             .await
                             .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
-                            .and_then(|resp| match resp {
-                                gatt::event::Response::Success(value) => Ok((value,)),
-                                _ => Err(MethodErr::from((BLUEZ_ERROR_FAILED, ""))),
-                            })
-                    }
-                    .map(move |result| ctx.reply(result))
+                            .and_then(|resp| resp.into_result().map_err(MethodErr::from))?;
+                        metrics.write_served();
+                        Ok((value,))
+                    };
+                    future::Either::Right(
+                        span.instrument(write).map(move |result| ctx.reply(result)),
+                    )
                 },
             );
+            let subscribe_metrics = Arc::clone(metrics);
+            let subscribe_notifying = Arc::clone(&notifying);
             b.method_with_cr_async("StartNotify", (), (), move |mut ctx, cr, ()| {
                 let characteristic = cr
                     .data_mut::<GattDataType>(ctx.path())
                     .unwrap()
                     .get_characteristic();
+                trace!("StartNotify {}", characteristic.uuid);
                 let message_sender = message_sender.clone();
+                let metrics = Arc::clone(&subscribe_metrics);
+                let notifying = Arc::clone(&subscribe_notifying);
                 async move {
-                    let (sender, mut receiver) = mpsc::channel(1);
+                    let (sender, receiver) = mpsc::channel(1);
                     let notify_subscribe = gatt::event::NotifySubscribe {
                         notification: sender,
                     };
+                    let mut notifications = match characteristic.notify_policy {
+                        Some(notify_policy) => notify_policy.apply(receiver).boxed(),
+                        None => receiver.boxed(),
+                    };
                     tokio::spawn(async move {
-                        while let Some(notification) = receiver.next().await {
+                        while let Some(notification) = notifications.next().await {
                             let mut message_sender = message_sender.clone();
                             let _ = message_sender.send(notification).await;
                         }
@@ -176,16 +331,23 @@ impl Characteristic {
                     event_sender
                         .send(gatt::event::Event::NotifySubscribe(notify_subscribe))
                         .await
-                        .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
-                        .map(|_| ())
+                        .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))?;
+                    metrics.subscribed();
+                    notifying.store(true, Ordering::Relaxed);
+                    Ok(())
                 }
                 .map(move |result| ctx.reply(result))
             });
-            b.method_with_cr_async("StopNotify", (), (), |mut ctx, cr, ()| {
+            let unsubscribe_metrics = Arc::clone(metrics);
+            let unsubscribe_notifying = Arc::clone(&notifying);
+            b.method_with_cr_async("StopNotify", (), (), move |mut ctx, cr, ()| {
                 let characteristic = cr
                     .data_mut::<GattDataType>(ctx.path())
                     .unwrap()
                     .get_characteristic();
+                trace!("StopNotify {}", characteristic.uuid);
+                let metrics = Arc::clone(&unsubscribe_metrics);
+                let notifying = Arc::clone(&unsubscribe_notifying);
                 async move {
                     let mut event_sender = characteristic
                         .properties
@@ -196,8 +358,10 @@ impl Characteristic {
                     event_sender
                         .send(gatt::event::Event::NotifyUnsubscribe)
                         .await
-                        .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
-                        .map(|_| ())
+                        .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))?;
+                    metrics.unsubscribed();
+                    notifying.store(false, Ordering::Relaxed);
+                    Ok(())
                 }
                 .map(move |result| ctx.reply(result))
             });
@@ -207,11 +371,17 @@ impl Characteristic {
             b.property("Service")
                 .get(move |_ctx, _data| Ok(service.clone()));
             b.property("Flags")
-                .get(move |_ctx, data| Ok(data.get_characteristic().properties.flags()));
+                .get(move |_ctx, data| Ok(data.get_characteristic().flags()));
         });
 
         tree.insert(object_path.clone(), &[iface_token], object_path_data);
 
-        Ok(Characteristic { object_path })
+        Ok(Characteristic {
+            object_path,
+            notifier: Notifier {
+                sender: message_sender,
+                notifying,
+            },
+        })
     }
 }
@@ -1,10 +1,8 @@
-use dbus::{
-    arg::{RefArg, Variant},
-    Path,
-};
+use dbus::{arg::RefArg, Path};
 use dbus_crossroads::MethodErr;
 use futures::{channel::oneshot, prelude::*};
-use std::{collections::HashMap, sync::Arc};
+use log::trace;
+use std::sync::Arc;
 
 use super::{
     super::{
@@ -12,12 +10,11 @@ use super::{
         common::GattDataType,
         constants::{BLUEZ_ERROR_FAILED, BLUEZ_ERROR_NOTSUPPORTED, GATT_DESCRIPTOR_IFACE},
     },
+    characteristic::{central, OptionsMap},
     flags::Flags,
 };
 use crate::{gatt, Error};
 
-type OptionsMap = HashMap<String, Variant<Box<dyn RefArg>>>;
-
 #[derive(Debug, Clone)]
 pub struct Descriptor {
     pub object_path: Path<'static>,
@@ -39,16 +36,29 @@ impl Descriptor {
                 |mut ctx, cr, (options,): (OptionsMap,)| {
                     let offset = options.get("offset").and_then(RefArg::as_u64).unwrap_or(0) as u16;
                     let mtu = options.get("mtu").and_then(RefArg::as_u64).unwrap_or(23) as u16;
+                    let central = central(&options);
                     let descriptor = cr
                         .data_mut::<GattDataType>(ctx.path())
                         .unwrap()
                         .get_descriptor();
+                    trace!(
+                        "ReadValue {} offset={} mtu={} central={:?}",
+                        descriptor.uuid,
+                        offset,
+                        mtu,
+                        central
+                    );
                     async move {
-                        let event_sender = descriptor
-                            .properties
-                            .read
-                            .clone()
-                            .ok_or_else(|| MethodErr::from((BLUEZ_ERROR_NOTSUPPORTED, "")))?;
+                        let event_sender = match descriptor.properties.read.clone() {
+                            Some(event_sender) => event_sender,
+                            // Without a read handler the static value is served instead
+                            None => {
+                                let value = descriptor.value.clone().ok_or_else(|| {
+                                    MethodErr::from((BLUEZ_ERROR_NOTSUPPORTED, ""))
+                                })?;
+                                return Ok((value.get(offset as usize..).unwrap_or(&[]).to_vec(),));
+                            }
+                        };
                         let (sender, receiver) = oneshot::channel();
                         event_sender
                             .sender()
@@ -56,15 +66,18 @@ impl Descriptor {
                                 offset,
                                 response: sender,
                                 mtu,
+                                central,
                             }))
                             .await
                             .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))?;
-                        receiver
+                        tokio::time::timeout(gatt::event::READ_RESPONSE_TIMEOUT, receiver)
                             .await
+                            .unwrap_or(Ok(gatt::event::Response::UnlikelyError))
                             .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
-                            .and_then(|resp| match resp {
-                                gatt::event::Response::Success(value) => Ok((value,)),
-                                _ => Err(MethodErr::from((BLUEZ_ERROR_FAILED, ""))),
+                            .and_then(|resp| {
+                                resp.into_result()
+                                    .map(|value| (value,))
+                                    .map_err(MethodErr::from)
                             })
                     }
                     .map(move |result| ctx.reply(result))
@@ -76,10 +89,18 @@ impl Descriptor {
                 ("value",),
                 |mut ctx, cr, (data, options): (Vec<u8>, OptionsMap)| {
                     let offset = options.get("offset").and_then(RefArg::as_u64).unwrap_or(0) as u16;
+                    let central = central(&options);
                     let descriptor = cr
                         .data_mut::<GattDataType>(ctx.path())
                         .unwrap()
                         .get_descriptor();
+                    trace!(
+                        "WriteValue {} len={} offset={} central={:?}",
+                        descriptor.uuid,
+                        data.len(),
+                        offset,
+                        central
+                    );
                     async move {
                         let event_sender = descriptor
                             .properties
@@ -95,6 +116,7 @@ impl Descriptor {
                                     offset,
                                     without_response: false,
                                     response: sender,
+                                    central,
                                 },
                             ))
                             .await
@@ -102,9 +124,10 @@ impl Descriptor {
                         receiver
                             .await
                             .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
-                            .and_then(|resp| match resp {
-                                gatt::event::Response::Success(value) => Ok((value,)),
-                                _ => Err(MethodErr::from((BLUEZ_ERROR_FAILED, ""))),
+                            .and_then(|resp| {
+                                resp.into_result()
+                                    .map(|value| (value,))
+                                    .map_err(MethodErr::from)
                             })
                     }
                     .map(move |result| ctx.reply(result))
@@ -115,8 +138,14 @@ impl Descriptor {
             let characteristic = characteristic.clone();
             b.property("Characteristic")
                 .get(move |_ctx, _data| Ok(characteristic.clone()));
-            b.property("Flags")
-                .get(move |_ctx, data| Ok(data.get_descriptor().properties.flags()));
+            b.property("Flags").get(move |_ctx, data| {
+                let descriptor = data.get_descriptor();
+                let mut flags = descriptor.properties.flags();
+                if descriptor.properties.read.is_none() && descriptor.value.is_some() {
+                    flags.push(String::from("read"));
+                }
+                Ok(flags)
+            });
         });
         let object_path: Path =
             format!("{}/descriptor{:04}", characteristic.to_string(), index).into();
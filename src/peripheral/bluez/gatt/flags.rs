@@ -43,6 +43,37 @@ impl Flags for CharacteristicProperties {
     }
 }
 
+impl Flags for characteristic::Characteristic {
+    fn flags(self: &Self) -> Vec<String> {
+        match self.raw_flags {
+            Some((properties, permissions)) => raw_flags(properties, permissions),
+            None => self.properties.flags(),
+        }
+    }
+}
+
+/// The flags standing for the property bits of the Characteristic Properties byte and the
+/// encryption bits of `CBAttributePermissions`.
+fn raw_flags(properties: u8, permissions: u8) -> Vec<String> {
+    const PROPERTIES: [(u8, &str); 8] = [
+        (0x01, "broadcast"),
+        (0x02, "read"),
+        (0x04, "write-without-response"),
+        (0x08, "write"),
+        (0x10, "notify"),
+        (0x20, "indicate"),
+        (0x40, "authenticated-signed-writes"),
+        (0x80, "extended-properties"),
+    ];
+    const PERMISSIONS: [(u8, &str); 2] = [(0x04, "encrypt-read"), (0x08, "encrypt-write")];
+    PROPERTIES
+        .iter()
+        .filter(|(bit, _)| properties & bit != 0)
+        .chain(PERMISSIONS.iter().filter(|(bit, _)| permissions & bit != 0))
+        .map(|(_, flag)| String::from(*flag))
+        .collect()
+}
+
 impl Flags for DescriptorProperties {
     fn flags(self: &Self) -> Vec<String> {
         let mut flags = vec![];
@@ -5,75 +5,355 @@ mod flags;
 mod service;
 
 use dbus::{channel::MatchingReceiver, message::MatchRule, Path};
-use std::sync::{Arc, Mutex};
+use futures::{channel::mpsc, prelude::*};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{atomic::Ordering, Arc, Mutex},
+};
+use uuid::Uuid;
 
 use self::{
-    application::Application, characteristic::Characteristic, descriptor::Descriptor,
+    application::Application,
+    characteristic::{Characteristic, Notifier},
+    descriptor::Descriptor,
     service::Service,
 };
 use super::{common, constants::PATH_BASE, Connection};
-use crate::{gatt, Error};
+use crate::{
+    gatt::{
+        self,
+        characteristic::{CharacteristicHandle, ReadSource},
+        default_handler::{DefaultHandlers, DefaultReadHandler, DefaultWriteHandler},
+        event::WriteRequest,
+        notify_interceptor::{NotifyInterceptor, NotifyInterceptors},
+        notify_pause::{NotifyPause, PausePolicy},
+    },
+    metrics::Metrics,
+    span::Span,
+    Error, ErrorType, NotifyError, PeripheralMetrics,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Gatt {
     connection: Arc<Connection>,
     adapter: Path<'static>,
     tree: Arc<Mutex<Option<common::Tree>>>,
     application: Arc<Mutex<Option<Application>>>,
+    services: Arc<Mutex<Vec<gatt::service::Service>>>,
+    /// The object path of every service in the tree, for the services including it.
+    service_paths: Arc<Mutex<HashMap<Uuid, Path<'static>>>>,
     service_index: Arc<Mutex<u64>>,
     characteristic_index: Arc<Mutex<u64>>,
     descriptor_index: Arc<Mutex<u64>>,
+    defaults: DefaultHandlers,
+    metrics: Arc<Metrics>,
+    notifiers: Arc<Mutex<HashMap<CharacteristicHandle, Notifier>>>,
+    notify_interceptors: NotifyInterceptors,
+    notify_pause: NotifyPause,
 }
 
 impl Gatt {
     pub fn new(connection: Arc<Connection>, adapter: Path<'static>) -> Self {
-        let mut tree = common::Tree::new();
-        tree.set_async_support(Some((
-            connection.default.clone(),
-            Box::new(|x| {
-                tokio::spawn(x);
-            }),
-        )));
+        let tree = new_tree(&connection);
         Gatt {
             adapter,
             connection,
             tree: Arc::new(Mutex::new(Some(tree))),
             application: Arc::new(Mutex::new(None)),
+            services: Arc::new(Mutex::new(vec![])),
+            service_paths: Arc::new(Mutex::new(HashMap::new())),
             service_index: Arc::new(Mutex::new(0)),
             characteristic_index: Arc::new(Mutex::new(0)),
             descriptor_index: Arc::new(Mutex::new(0)),
+            defaults: DefaultHandlers::default(),
+            metrics: Arc::new(Metrics::default()),
+            notifiers: Arc::new(Mutex::new(HashMap::new())),
+            notify_interceptors: NotifyInterceptors::default(),
+            notify_pause: NotifyPause::default(),
         }
     }
 
-    pub fn add_service(self: &Self, service: &gatt::service::Service) -> Result<(), Error> {
+    pub fn add_service(
+        self: &Self,
+        service: &gatt::service::Service,
+    ) -> Result<Vec<CharacteristicHandle>, Error> {
+        self.add_services(std::slice::from_ref(service))?;
+        Ok(service.handles())
+    }
+
+    /// Either all services are added to the tree or, if one fails, none are.
+    pub fn add_services(self: &Self, services: &[gatt::service::Service]) -> Result<(), Error> {
         let mut tree = self.tree.lock().unwrap();
-        let tree = tree.as_mut().unwrap();
+        // The tree is handed over to the D-Bus connection once the application is registered
+        let tree = tree.as_mut().ok_or_else(already_registered)?;
+        self.insert_services(tree, services)
+    }
+
+    /// Swaps in a tree with only `services`, the previous one is kept if one of them fails.
+    pub fn replace_services(&self, services: &[gatt::service::Service]) -> Result<(), Error> {
+        let mut tree = self.tree.lock().unwrap();
+        let tree = tree.as_mut().ok_or_else(already_registered)?;
+
+        let previous_tree = std::mem::replace(tree, new_tree(&self.connection));
+        let previous_services = std::mem::take(&mut *self.services.lock().unwrap());
+        let previous_service_paths = std::mem::take(&mut *self.service_paths.lock().unwrap());
+        let previous_notifiers = std::mem::take(&mut *self.notifiers.lock().unwrap());
+        if let Err(err) = self.insert_services(tree, services) {
+            *tree = previous_tree;
+            *self.services.lock().unwrap() = previous_services;
+            *self.service_paths.lock().unwrap() = previous_service_paths;
+            *self.notifiers.lock().unwrap() = previous_notifiers;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    pub fn remove_service(&self, uuid: &Uuid) -> Result<(), Error> {
+        let remaining = self
+            .services
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|service| service.uuid != *uuid)
+            .cloned()
+            .collect::<Vec<_>>();
+        self.replace_services(&remaining)
+    }
 
+    fn insert_services(
+        &self,
+        tree: &mut common::Tree,
+        services: &[gatt::service::Service],
+    ) -> Result<(), Error> {
+        let mut added_paths = vec![];
+        let previous_notifiers: HashSet<CharacteristicHandle> =
+            self.notifiers.lock().unwrap().keys().copied().collect();
+        for service in services {
+            if let Err(err) = self.insert_service(tree, service, &mut added_paths) {
+                for path in added_paths.iter() {
+                    tree.remove::<()>(path);
+                }
+                self.service_paths
+                    .lock()
+                    .unwrap()
+                    .retain(|_, path| !added_paths.contains(path));
+                self.notifiers
+                    .lock()
+                    .unwrap()
+                    .retain(|handle, _| previous_notifiers.contains(handle));
+                return Err(err);
+            }
+        }
+        self.services
+            .lock()
+            .unwrap()
+            .extend(services.iter().cloned());
+
+        Ok(())
+    }
+
+    pub fn get_value(&self, characteristic_uuid: &Uuid) -> Option<Vec<u8>> {
+        self.services
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|service| service.characteristics.iter())
+            .find(|characteristic| characteristic.uuid == *characteristic_uuid)
+            .and_then(|characteristic| characteristic.value.clone())
+    }
+
+    pub fn pause_notifications(&self, policy: PausePolicy) {
+        self.notify_pause.pause(policy);
+    }
+
+    pub async fn resume_notifications(&self) -> Result<(), NotifyError> {
+        let mut result = Ok(());
+        for (handle, value) in self.notify_pause.resume() {
+            match self.notify_handle(&handle, &value).await {
+                // The centrals may have unsubscribed while notifications were paused
+                Ok(()) | Err(NotifyError::NoSubscribers) => {}
+                Err(err) => result = result.and(Err(err)),
+            }
+        }
+        result
+    }
+
+    pub fn set_notify_interceptor(&self, interceptor: NotifyInterceptor) {
+        self.notify_interceptors.set(interceptor);
+    }
+
+    pub fn set_default_read_handler(&self, handler: DefaultReadHandler) {
+        self.defaults.set_read(handler);
+    }
+
+    pub fn set_default_write_handler(&self, handler: DefaultWriteHandler) {
+        self.defaults.set_write(handler);
+    }
+
+    pub fn writes(&self, characteristic_uuid: &Uuid) -> mpsc::UnboundedReceiver<WriteRequest> {
+        self.defaults.open_write_stream(*characteristic_uuid)
+    }
+
+    pub fn describe(&self) -> String {
+        gatt::service::Service::describe_all(self.services.lock().unwrap().iter())
+    }
+
+    /// The UUIDs of the added services and their characteristics.
+    pub fn service_uuids(&self) -> HashMap<Uuid, Vec<Uuid>> {
+        self.services
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|service| {
+                let characteristics = service
+                    .characteristics
+                    .iter()
+                    .map(|characteristic| characteristic.uuid)
+                    .collect();
+                (service.uuid, characteristics)
+            })
+            .collect()
+    }
+
+    pub fn has_characteristic(&self, characteristic_uuid: &Uuid) -> bool {
+        self.is_registered()
+            && self
+                .services
+                .lock()
+                .unwrap()
+                .iter()
+                .flat_map(|service| service.characteristics.iter())
+                .any(|characteristic| characteristic.uuid == *characteristic_uuid)
+    }
+
+    pub async fn notify(
+        &self,
+        characteristic_uuid: &Uuid,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        let handle = self
+            .notifiers
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|handle| handle.characteristic == *characteristic_uuid)
+            .min_by_key(|handle| handle.instance)
+            .copied()
+            .ok_or(NotifyError::UnknownCharacteristic)?;
+        self.notify_handle(&handle, value).await
+    }
+
+    /// Hands the value to the same queue subscriptions send their notifications through.
+    pub async fn notify_handle(
+        &self,
+        handle: &CharacteristicHandle,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        Span::new("notify", &handle.characteristic, None)
+            .instrument(self.send_notification(handle, value))
+            .await
+    }
+
+    async fn send_notification(
+        &self,
+        handle: &CharacteristicHandle,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        if !self.is_registered() {
+            return Err(NotifyError::UnknownCharacteristic);
+        }
+        let notifier = self
+            .notifiers
+            .lock()
+            .unwrap()
+            .get(handle)
+            .cloned()
+            .ok_or(NotifyError::UnknownCharacteristic)?;
+        if self.notify_pause.hold(handle, value) {
+            return Ok(());
+        }
+        if !notifier.notifying.load(Ordering::Relaxed) {
+            return Err(NotifyError::NoSubscribers);
+        }
+        notifier
+            .sender
+            .clone()
+            .send(
+                self.notify_interceptors
+                    .intercept(&handle.characteristic, value),
+            )
+            .await
+            .map_err(|_| NotifyError::QueueFull)
+    }
+
+    pub fn metrics(&self) -> PeripheralMetrics {
+        self.metrics.snapshot()
+    }
+
+    pub fn read_source(&self, characteristic_uuid: &Uuid) -> Option<ReadSource> {
+        self.services
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|service| service.characteristics.iter())
+            .find(|characteristic| characteristic.uuid == *characteristic_uuid)
+            .map(|characteristic| match characteristic.properties.read {
+                Some(_) => ReadSource::Handler,
+                None => ReadSource::NotReadable,
+            })
+    }
+
+    fn insert_service(
+        self: &Self,
+        tree: &mut common::Tree,
+        service: &gatt::service::Service,
+        added_paths: &mut Vec<Path<'static>>,
+    ) -> Result<(), Error> {
+        service.check_duplicate_characteristics(ErrorType::Bluez)?;
+        let mut service_paths = self.service_paths.lock().unwrap();
+        service
+            .check_included_services(|uuid| service_paths.contains_key(uuid), ErrorType::Bluez)?;
+        let includes = service
+            .included_services
+            .iter()
+            .filter_map(|uuid| service_paths.get(uuid).cloned())
+            .collect();
         let mut service_index = self.service_index.lock().unwrap();
         let mut characteristic_index = self.characteristic_index.lock().unwrap();
         let mut descriptor_index = self.descriptor_index.lock().unwrap();
 
-        let gatt_service = Service::new(tree, &Arc::new(service.clone()), *service_index)?;
+        let gatt_service =
+            Service::new(tree, &Arc::new(service.clone()), includes, *service_index)?;
         *service_index += 1;
+        added_paths.push(gatt_service.object_path.clone());
+        service_paths.insert(service.uuid, gatt_service.object_path.clone());
 
-        for characteristic in service.characteristics.iter() {
+        for characteristic in service.resolved_characteristics() {
             let gatt_characteristic = Characteristic::new(
                 &self.connection.clone(),
                 tree,
                 &Arc::new(characteristic.clone()),
                 &Arc::new(gatt_service.object_path.clone()),
                 *characteristic_index,
+                &self.defaults,
+                &self.metrics,
             )?;
             *characteristic_index += 1;
+            added_paths.push(gatt_characteristic.object_path.clone());
+            self.notifiers.lock().unwrap().insert(
+                characteristic.handle(service.uuid),
+                gatt_characteristic.notifier.clone(),
+            );
 
             for descriptor in characteristic.descriptors.iter() {
-                Descriptor::new(
+                let gatt_descriptor = Descriptor::new(
                     tree,
                     &Arc::new(descriptor.clone()),
                     &Arc::new(gatt_characteristic.object_path.clone()),
                     *descriptor_index,
                 )?;
                 *descriptor_index += 1;
+                added_paths.push(gatt_descriptor.object_path);
             }
         }
 
@@ -108,6 +388,10 @@ impl Gatt {
         new_application.register().await
     }
 
+    pub fn is_registered(&self) -> bool {
+        self.application.lock().unwrap().is_some()
+    }
+
     pub async fn unregister(self: &Self) -> Result<(), Error> {
         self.application
             .lock()
@@ -119,3 +403,22 @@ impl Gatt {
             .map(|_| ())
     }
 }
+
+fn new_tree(connection: &Connection) -> common::Tree {
+    let mut tree = common::Tree::new();
+    tree.set_async_support(Some((
+        connection.default.clone(),
+        Box::new(|x| {
+            tokio::spawn(x);
+        }),
+    )));
+    tree
+}
+
+fn already_registered() -> Error {
+    Error::new(
+        "AlreadyRegistered",
+        "Services can't be added after the GATT application was registered",
+        ErrorType::Bluez,
+    )
+}
@@ -14,6 +14,7 @@ impl Service {
     pub fn new(
         tree: &mut common::Tree,
         service: &Arc<gatt::service::Service>,
+        includes: Vec<Path<'static>>,
         index: u64,
     ) -> Result<Self, Error> {
         let get_all = tree.register(GATT_SERVICE_IFACE, |b| {
@@ -23,6 +24,8 @@ impl Service {
             let service1 = service.clone();
             b.property("Primary")
                 .get(move |_ctx, _cr| Ok(service1.primary));
+            b.property("Includes")
+                .get(move |_ctx, _cr| Ok(includes.clone()));
         });
         let object_path: Path = format!("{}/service{:04}", PATH_BASE, index).into();
         tree.insert(object_path.clone(), &[get_all], ());
@@ -6,32 +6,88 @@ mod constants;
 mod error;
 mod gatt;
 
-use std::{string::ToString, sync::Arc};
+use futures::channel::mpsc;
+use log::warn;
+use std::{
+    collections::HashMap,
+    string::ToString,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::watch;
 use uuid::Uuid;
 
-use self::{adapter::Adapter, advertisement::Advertisement, connection::Connection, gatt::Gatt};
-use crate::{gatt::service::Service, Error};
+use self::{
+    adapter::Adapter,
+    advertisement::{Advertisement, ScanResponse},
+    connection::Connection,
+    gatt::Gatt,
+};
+use super::PeripheralBuilder;
+use crate::{
+    gatt::{
+        characteristic::{CharacteristicHandle, ReadSource},
+        default_handler::RequestContext,
+        event::{ReadRequest, WriteRequest, DEFAULT_NOTIFICATION_LENGTH},
+        notify_pause::PausePolicy,
+        service::Service,
+    },
+    state, AdapterState, AdvertisingField, AdvertisingOptions, AdvertisingState, Capabilities,
+    CentralId, CentralInfo, Error, ErrorType, NotifyError, PeripheralMetrics, PeripheralSnapshot,
+};
 
-#[derive(Debug)]
+/// Cheap to clone, all clones drive the same adapter, GATT application and advertisement.
+#[derive(Debug, Clone)]
 pub struct Peripheral {
     adapter: Adapter,
     gatt: Gatt,
     advertisement: Advertisement,
+    state_receiver: watch::Receiver<AdapterState>,
+    advertising_options: Arc<Mutex<Option<AdvertisingOptions>>>,
+    /// Set while advertising is stopped for `auto_stop_advertising_on_connect`.
+    advertising_paused: Arc<AtomicBool>,
 }
 
 impl Peripheral {
     #[allow(clippy::new_ret_no_self)]
     pub async fn new() -> Result<Self, Error> {
+        Self::from_builder(PeripheralBuilder::default()).await
+    }
+
+    pub(crate) async fn from_builder(builder: PeripheralBuilder) -> Result<Self, Error> {
+        if builder.restore_identifier.is_some() {
+            return Err(Error::new(
+                "Unsupported",
+                "State restoration is only supported on iOS",
+                ErrorType::Bluez,
+            ));
+        }
         let connection = Arc::new(Connection::new()?);
         let adapter = Adapter::new(connection.clone()).await?;
         adapter.powered(true).await?;
+        let state_receiver = adapter.watch_state().await?;
         let gatt = Gatt::new(connection.clone(), adapter.object_path.clone());
         let advertisement = Advertisement::new(connection, adapter.object_path.clone());
 
+        let advertising_paused = Arc::new(AtomicBool::new(false));
+        if builder.auto_stop_advertising {
+            tokio::spawn(pause_advertising(
+                advertisement.clone(),
+                Arc::clone(&advertising_paused),
+                adapter.watch_connections().await?,
+            ));
+        }
+
         Ok(Peripheral {
             adapter,
             gatt,
             advertisement,
+            state_receiver,
+            advertising_options: Arc::new(Mutex::new(None)),
+            advertising_paused,
         })
     }
 
@@ -47,6 +103,149 @@ impl Peripheral {
         self.adapter.is_powered().await
     }
 
+    /// The adapter state, which `new` already read from BlueZ so this doesn't wait.
+    pub async fn current_state(&self) -> AdapterState {
+        state::current_state(self.state_receiver.clone()).await
+    }
+
+    /// The adapter state last reported by BlueZ, like `current_state` without awaiting.
+    pub fn state(&self) -> AdapterState {
+        *self.state_receiver.borrow()
+    }
+
+    /// Resolves once the adapter is powered on. A missing adapter already fails `new`, so BlueZ
+    /// never reports `AdapterState::Unsupported`.
+    pub async fn wait_until_powered_on(&self) -> Result<(), Error> {
+        state::wait_until_powered_on(self.state_receiver.clone(), ErrorType::Bluez).await
+    }
+
+    pub async fn central_rssi(&self, central: &CentralId) -> Result<Option<i16>, Error> {
+        self.adapter.get_device_rssi(central).await
+    }
+
+    /// The name the central's device goes by, `None` if BlueZ doesn't know it.
+    pub async fn central_name(&self, central: &CentralId) -> Option<String> {
+        self.adapter.get_device_name(central).await
+    }
+
+    /// BlueZ doesn't expose how many connections the controller supports over D-Bus, so this is
+    /// always `None`.
+    pub fn max_connections(&self) -> Option<usize> {
+        None
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            advertising_service_data: true,
+            advertising_manufacturer_data: true,
+            advertising_interval: true,
+            advertising_non_connectable: true,
+            advertising_scan_response: true,
+            central_rssi: true,
+            descriptors: true,
+            subscribers: false,
+            services_after_registration: false,
+            state_restoration: false,
+            max_connections: self.max_connections(),
+        }
+    }
+
+    /// Everything tracked about the peripheral in one go, without asking BlueZ. Subscribers
+    /// aren't tracked, so there are none.
+    pub fn snapshot(&self) -> PeripheralSnapshot {
+        PeripheralSnapshot {
+            adapter_state: *self.state_receiver.borrow(),
+            advertising_state: *self.advertisement.state_receiver().borrow(),
+            advertising_options: self.advertising_options.lock().unwrap().clone(),
+            services: self.gatt.service_uuids(),
+            subscribers: HashMap::new(),
+            metrics: self.gatt.metrics(),
+        }
+    }
+
+    /// A readable outline of the added services, see `Service::describe`.
+    pub fn describe(&self) -> String {
+        self.gatt.describe()
+    }
+
+    /// Whether the registered GATT application contains the characteristic. BlueZ keeps the
+    /// application registered while the adapter is powered off.
+    pub fn has_characteristic(&self, characteristic_uuid: &Uuid) -> bool {
+        self.gatt.has_characteristic(characteristic_uuid)
+    }
+
+    /// Notifies the subscribers of the characteristic, the one with the lowest instance if several
+    /// share the UUID, waiting until the notification queue
+    /// has room for the value.
+    pub async fn notify(
+        &self,
+        characteristic_uuid: &Uuid,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        self.gatt.notify(characteristic_uuid, value).await
+    }
+
+    /// The most bytes one notification of the characteristic carries. BlueZ doesn't tell the MTU
+    /// of subscriptions made with `StartNotify`, so this is always the 20 bytes of the default
+    /// ATT MTU every link supports.
+    pub fn max_notification_length(&self, _characteristic_uuid: &Uuid) -> usize {
+        DEFAULT_NOTIFICATION_LENGTH
+    }
+
+    /// Splits the value into chunks of `max_notification_length` and notifies them in order,
+    /// waiting for the notification queue like `notify` does. Stops at the first chunk that
+    /// fails.
+    pub async fn notify_chunked(
+        &self,
+        characteristic_uuid: &Uuid,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        let length = self.max_notification_length(characteristic_uuid);
+        for chunk in value.chunks(length.max(1)) {
+            self.notify(characteristic_uuid, chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// Would notify every subscriber the value `value` returns for it, but BlueZ sends each
+    /// notification to all subscribers and doesn't tell which central subscribed. So `value` is
+    /// never called and this fails with `NoSubscribers` for every added characteristic, `notify`
+    /// reaches them all with one value.
+    pub async fn update_all<F>(
+        &self,
+        characteristic_uuid: &Uuid,
+        _value: F,
+    ) -> Result<(), NotifyError>
+    where
+        F: Fn(&CentralId) -> Vec<u8>,
+    {
+        if self.has_characteristic(characteristic_uuid) {
+            Err(NotifyError::NoSubscribers)
+        } else {
+            Err(NotifyError::UnknownCharacteristic)
+        }
+    }
+
+    /// Like `notify`, for the characteristic of one of the handles `add_service` returned.
+    pub async fn notify_handle(
+        &self,
+        handle: &CharacteristicHandle,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        self.gatt.notify_handle(handle, value).await
+    }
+
+    /// Receives every adapter state change, `changed().await` resolves on the next one.
+    pub fn power_state_stream(&self) -> watch::Receiver<AdapterState> {
+        self.state_receiver.clone()
+    }
+
+    /// Reports when BlueZ drops the advertisement on its own, e.g. because the adapter was
+    /// powered off.
+    pub fn advertising_state_stream(&self) -> watch::Receiver<AdvertisingState> {
+        self.advertisement.state_receiver()
+    }
+
     pub async fn register_gatt(&self) -> Result<(), Error> {
         self.gatt.register().await
     }
@@ -55,28 +254,262 @@ impl Peripheral {
         self.gatt.unregister().await
     }
 
+    /// An empty name advertises the adapter's alias, see `system_default_name`.
     pub async fn start_advertising(self: &Self, name: &str, uuids: &[Uuid]) -> Result<(), Error> {
-        self.advertisement.add_name(name);
-        self.advertisement.add_uuids(
+        self.start_advertising_with(&AdvertisingOptions::new(
+            Some(name.to_string()).filter(|name| !name.is_empty()),
+            uuids.to_vec(),
+        ))
+        .await
+    }
+
+    /// The adapter's alias, which is advertised when the advertisement has no local name.
+    pub async fn system_default_name(&self) -> Option<String> {
+        self.adapter.get_alias().await.ok()
+    }
+
+    pub async fn start_advertising_with(&self, options: &AdvertisingOptions) -> Result<(), Error> {
+        self.advertise(options, None).await
+    }
+
+    /// Advertises until `duration` elapsed, rounded up to whole seconds. Stopping the
+    /// advertisement earlier cancels the timeout, afterwards `is_advertising` returns `false` and
+    /// `advertising_state_stream` reports `AdvertisingState::TimedOut`.
+    pub async fn advertise_for(
+        &self,
+        options: &AdvertisingOptions,
+        duration: Duration,
+    ) -> Result<(), Error> {
+        let seconds = duration.as_millis().div_ceil(1000);
+        self.advertise(options, Some(seconds.clamp(1, u16::MAX as u128) as u16))
+            .await
+    }
+
+    async fn advertise(
+        &self,
+        options: &AdvertisingOptions,
+        timeout: Option<u16>,
+    ) -> Result<(), Error> {
+        self.advertising_paused.store(false, Ordering::SeqCst);
+        options.warn_if_truncated();
+        self.advertisement.add_timeout(timeout);
+        self.advertisement.add_connectable(options.connectable);
+        let milliseconds = |interval: Duration| interval.as_millis().min(u32::MAX as u128) as u32;
+        self.advertisement.add_interval(
+            options.min_interval.map(milliseconds),
+            options.max_interval.map(milliseconds),
+        );
+        let name = match &options.local_name {
+            Some(local_name) => local_name.clone(),
+            None => self.system_default_name().await.unwrap_or_default(),
+        };
+        let in_scan_response = |field: AdvertisingField| options.scan_response.contains(&field);
+        let (scan_response_uuids, uuids): (Vec<&Uuid>, Vec<&Uuid>) = options
+            .service_uuids
+            .iter()
+            .partition(|uuid| in_scan_response(AdvertisingField::ServiceUuid(**uuid)));
+        let (scan_response_service_data, service_data): (HashMap<_, _>, HashMap<_, _>) = options
+            .service_data
+            .iter()
+            .partition(|(uuid, _)| in_scan_response(AdvertisingField::ServiceData(**uuid)));
+        let uuid_strings = |uuids: Vec<&Uuid>| {
             uuids
-                .to_vec()
                 .iter()
                 .map(ToString::to_string)
-                .collect::<Vec<String>>(),
-        );
+                .collect::<Vec<String>>()
+        };
+        let service_data_strings = |service_data: HashMap<&Uuid, &Vec<u8>>| {
+            service_data
+                .into_iter()
+                .map(|(uuid, data)| (uuid.to_string(), data.clone()))
+                .collect::<HashMap<String, Vec<u8>>>()
+        };
+        let (scan_response_manufacturer_data, manufacturer_data) =
+            match options.manufacturer_data.clone() {
+                Some(manufacturer_data) if in_scan_response(AdvertisingField::ManufacturerData) => {
+                    (Some(manufacturer_data), None)
+                }
+                manufacturer_data => (None, manufacturer_data),
+            };
+        self.advertisement.add_scan_response(ScanResponse {
+            name: Some(name.clone()).filter(|_| in_scan_response(AdvertisingField::LocalName)),
+            uuids: uuid_strings(scan_response_uuids),
+            service_data: service_data_strings(scan_response_service_data),
+            manufacturer_data: scan_response_manufacturer_data,
+        });
+        self.advertisement.add_name(name);
+        self.advertisement.add_manufacturer_data(manufacturer_data);
+        self.advertisement.add_uuids(uuid_strings(uuids));
+        self.advertisement
+            .add_service_data(service_data_strings(service_data));
 
-        self.advertisement.register().await
+        self.advertisement.register().await?;
+        *self.advertising_options.lock().unwrap() = Some(options.clone());
+        Ok(())
     }
 
     pub async fn stop_advertising(self: &Self) -> Result<(), Error> {
+        self.advertising_paused.store(false, Ordering::SeqCst);
         self.advertisement.unregister().await
     }
 
+    /// Stops advertising, then unregisters the GATT application, returning once BlueZ confirmed
+    /// both. BlueZ sends no requests for the application afterwards. Clones of this `Peripheral`
+    /// share the advertisement and application, so they are torn down for them as well.
+    pub async fn shutdown(self) -> Result<(), Error> {
+        self.advertising_paused.store(false, Ordering::SeqCst);
+        if self.advertisement.is_advertising() {
+            self.advertisement.unregister().await?;
+        }
+        if self.gatt.is_registered() {
+            self.gatt.unregister().await?;
+        }
+        Ok(())
+    }
+
     pub async fn is_advertising(self: &Self) -> Result<bool, Error> {
         Ok(self.advertisement.is_advertising())
     }
 
-    pub fn add_service(self: &Self, service: &Service) -> Result<(), Error> {
+    /// Returns a handle for every characteristic, which tells apart characteristics sharing a
+    /// UUID. A service repeating a UUID without `Service::allow_duplicate_characteristics` is an
+    /// error.
+    pub fn add_service(self: &Self, service: &Service) -> Result<Vec<CharacteristicHandle>, Error> {
         self.gatt.add_service(service)
     }
+
+    /// Whether reads of the characteristic reach its handler. BlueZ never serves the value a
+    /// characteristic was added with, so it's never `ReadSource::Cached`. `None` if no added
+    /// service contains it.
+    pub fn read_source(&self, characteristic_uuid: &Uuid) -> Option<ReadSource> {
+        self.gatt.read_source(characteristic_uuid)
+    }
+
+    /// Adds the services in order. If one of them fails the ones added before it are removed
+    /// again, so either all services are added or none are.
+    pub async fn add_services(&self, services: &[Service]) -> Result<(), Error> {
+        self.gatt.add_services(services)
+    }
+
+    /// Makes `services` the added services. Like `add_service` this only works before
+    /// `register_gatt`, BlueZ can't change the services of a registered application. Nothing is
+    /// connected then, so the services are simply swapped; if one fails the previous ones stay.
+    pub fn replace_services(&self, services: &[Service]) -> Result<(), Error> {
+        self.gatt.replace_services(services)
+    }
+
+    /// Like `replace_services` with every service but this one, so it only works before
+    /// `register_gatt`. Removing a service that isn't added does nothing.
+    pub fn remove_service(&self, uuid: &Uuid) -> Result<(), Error> {
+        self.gatt.remove_service(uuid)
+    }
+
+    pub fn remove_all_services(&self) -> Result<(), Error> {
+        self.gatt.replace_services(&[])
+    }
+
+    /// The value the characteristic was added with, `None` if it has none or no added service
+    /// contains it.
+    pub fn get_value(&self, characteristic_uuid: &Uuid) -> Option<Vec<u8>> {
+        self.gatt.get_value(characteristic_uuid)
+    }
+
+    /// Always empty, BlueZ doesn't tell which central subscribed.
+    pub fn subscribers(&self, _characteristic_uuid: &Uuid) -> Vec<CentralId> {
+        vec![]
+    }
+
+    /// Always empty, BlueZ doesn't tell which central subscribed.
+    pub fn centrals(&self) -> Vec<CentralInfo> {
+        vec![]
+    }
+
+    /// Always `false`, BlueZ doesn't tell which central subscribed.
+    pub fn is_subscribed(&self, _central: &CentralId, _characteristic_uuid: &Uuid) -> bool {
+        false
+    }
+
+    /// Does nothing, BlueZ ends the subscriptions of centrals once they disconnect.
+    pub fn forget_central(&self, _central: &CentralId) {}
+
+    /// Counters of the operations served since the peripheral was created, shared by all clones.
+    pub fn metrics(&self) -> PeripheralMetrics {
+        self.gatt.metrics()
+    }
+
+    /// Holds back the notifications `notify`, `notify_handle` and `notify_chunked` send until
+    /// `resume_notifications`, e.g. during a firmware update. They return `Ok` right away while
+    /// paused and `policy` decides whether the values are kept. Keeping the latest value of a
+    /// characteristic also means only the last chunk of a `notify_chunked` call survives.
+    /// Notifications sent through a subscription's `notification` channel aren't paused.
+    pub fn pause_notifications(&self, policy: PausePolicy) {
+        self.gatt.pause_notifications(policy);
+    }
+
+    /// Sends the values kept while notifications were paused, in no particular order, and lets
+    /// notifications through again. Values nobody is subscribed to anymore are dropped, the first
+    /// other failure is returned once the rest were sent.
+    pub async fn resume_notifications(&self) -> Result<(), NotifyError> {
+        self.gatt.resume_notifications().await
+    }
+
+    /// Hands the payload of every notification `notify`, `notify_handle` and `notify_chunked`
+    /// send to `interceptor` right before it's queued, which may change it. It runs on the
+    /// thread that awaits the notify call, once per chunk. Notifications sent through a
+    /// subscription's `notification` channel don't pass it. Replaces the previous interceptor.
+    pub fn set_notify_interceptor<F>(&self, interceptor: F)
+    where
+        F: Fn(&Uuid, &mut Vec<u8>) + Send + Sync + 'static,
+    {
+        self.gatt.set_notify_interceptor(Arc::new(interceptor));
+    }
+
+    /// Handles the reads of characteristics declared with `default_handler::unhandled` as their
+    /// event sender. A characteristic's own handler always takes precedence. Replaces the
+    /// previous default handler.
+    pub fn set_default_read_handler<F>(&self, handler: F)
+    where
+        F: Fn(RequestContext, ReadRequest) + Send + Sync + 'static,
+    {
+        self.gatt.set_default_read_handler(Arc::new(handler));
+    }
+
+    /// Like `set_default_read_handler`, but for writes with and without response.
+    pub fn set_default_write_handler<F>(&self, handler: F)
+    where
+        F: Fn(RequestContext, WriteRequest) + Send + Sync + 'static,
+    {
+        self.gatt.set_default_write_handler(Arc::new(handler));
+    }
+
+    /// The writes to the characteristic, with and without response, for handling them in a
+    /// `while let Some(write) = writes.next().await` loop, e.g. for a control point. While the
+    /// stream is alive writes reach it instead of the characteristic's handler or the default
+    /// one. Opening another stream for the characteristic ends this one.
+    pub fn writes(&self, characteristic_uuid: &Uuid) -> mpsc::UnboundedReceiver<WriteRequest> {
+        self.gatt.writes(characteristic_uuid)
+    }
+}
+
+/// Unregisters the advertisement once a central connects and registers it again once the last
+/// one disconnected, unless advertising was started or stopped in between.
+async fn pause_advertising(
+    advertisement: Advertisement,
+    advertising_paused: Arc<AtomicBool>,
+    mut connections: watch::Receiver<usize>,
+) {
+    while connections.changed().await.is_ok() {
+        let connected = *connections.borrow_and_update() > 0;
+        let result = if connected && advertisement.is_advertising() {
+            advertising_paused.store(true, Ordering::SeqCst);
+            advertisement.unregister().await
+        } else if !connected && advertising_paused.swap(false, Ordering::SeqCst) {
+            advertisement.register().await
+        } else {
+            continue;
+        };
+        if let Err(error) = result {
+            warn!("Couldn't pause or resume advertising: {:?}", error);
+        }
+    }
 }
@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use super::Peripheral;
+use crate::Error;
+
+/// Quality of service class of the dispatch queue CoreBluetooth delivers its events on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QualityOfService {
+    UserInteractive,
+    UserInitiated,
+    Default,
+    Utility,
+    Background,
+    #[default]
+    Unspecified,
+}
+
+/// Configuration of a `Peripheral`, the defaults are what `Peripheral::new` uses.
+///
+/// The options only apply to CoreBluetooth unless they say otherwise, BlueZ and Windows ignore
+/// them.
+#[derive(Debug, Clone)]
+pub struct PeripheralBuilder {
+    pub(crate) queue_label: String,
+    pub(crate) quality_of_service: QualityOfService,
+    pub(crate) restore_identifier: Option<String>,
+    pub(crate) queue_watchdog: Option<Duration>,
+    pub(crate) subscription_timeout: Option<Duration>,
+    pub(crate) auto_restore: bool,
+    pub(crate) fail_if_unsupported: bool,
+    pub(crate) auto_stop_advertising: bool,
+}
+
+impl Default for PeripheralBuilder {
+    fn default() -> Self {
+        PeripheralBuilder {
+            queue_label: String::from("CBqueue"),
+            quality_of_service: QualityOfService::default(),
+            restore_identifier: None,
+            queue_watchdog: None,
+            subscription_timeout: None,
+            auto_restore: false,
+            fail_if_unsupported: false,
+            auto_stop_advertising: false,
+        }
+    }
+}
+
+impl PeripheralBuilder {
+    /// Label of the dispatch queue, shows up in crash reports and the debugger.
+    pub fn queue_label<T: Into<String>>(mut self, queue_label: T) -> Self {
+        self.queue_label = queue_label.into();
+        self
+    }
+
+    pub fn quality_of_service(mut self, quality_of_service: QualityOfService) -> Self {
+        self.quality_of_service = quality_of_service;
+        self
+    }
+
+    /// Opts into CoreBluetooth state restoration, which is only available on iOS. Building fails
+    /// on other platforms when this is set. Centrals subscribed before the app was relaunched
+    /// keep receiving notifications right away.
+    pub fn restore_identifier<T: Into<String>>(mut self, restore_identifier: T) -> Self {
+        self.restore_identifier = Some(restore_identifier.into());
+        self
+    }
+
+    /// Checks every `threshold` that the dispatch queue still runs blocks and reports it as
+    /// stalled when one takes longer than `threshold`, see `Peripheral::queue_health_stream`.
+    /// Off by default, as it keeps dispatching blocks onto the queue.
+    pub fn queue_watchdog(mut self, threshold: Duration) -> Self {
+        self.queue_watchdog = Some(threshold);
+        self
+    }
+
+    /// Forgets the subscriptions of centrals that haven't subscribed or sent a request for
+    /// `timeout`, as CoreBluetooth doesn't tell when a central disconnects. Centrals that only
+    /// listen to notifications are forgotten as well, so the timeout has to be longer than the
+    /// time between their requests. Off by default, see `Peripheral::forget_central`.
+    pub fn subscription_timeout(mut self, timeout: Duration) -> Self {
+        self.subscription_timeout = Some(timeout);
+        self
+    }
+
+    /// Adds the services again once CoreBluetooth is powered on after being powered off or reset,
+    /// as it drops all of them then. Off by default, `Peripheral::has_characteristic` tells
+    /// whether they have to be added again by hand.
+    pub fn auto_restore(mut self, auto_restore: bool) -> Self {
+        self.auto_restore = auto_restore;
+        self
+    }
+
+    /// Fails building with an `Unsupported` error if CoreBluetooth or Windows already reported that
+    /// the hardware can't act as a BLE peripheral, instead of handing out a peripheral that won't
+    /// ever power on. Off by default, `Peripheral::wait_until_powered_on` fails the same way later
+    /// on.
+    pub fn fail_if_unsupported(mut self, fail_if_unsupported: bool) -> Self {
+        self.fail_if_unsupported = fail_if_unsupported;
+        self
+    }
+
+    /// Stops advertising once a central subscribes and starts it again with the same options once
+    /// the last subscribed central unsubscribed or was forgotten, so the peripheral looks taken
+    /// while it's in use. CoreBluetooth and Windows don't tell about connections, so a subscription
+    /// is taken as one. BlueZ goes by the devices connected to the adapter instead. Off by default.
+    pub fn auto_stop_advertising_on_connect(mut self, auto_stop_advertising: bool) -> Self {
+        self.auto_stop_advertising = auto_stop_advertising;
+        self
+    }
+
+    pub async fn build(self) -> Result<Peripheral, Error> {
+        Peripheral::from_builder(self).await
+    }
+}
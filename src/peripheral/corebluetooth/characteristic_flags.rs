@@ -1,54 +1,172 @@
 use super::ffi::{CBAttributePermissions, CBCharacteristicProperties};
 use crate::gatt::characteristic::{Characteristic, Secure, Write};
 
+/// Whether an attribute needs an encrypted link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Secure,
+    Insecure,
+}
+
+impl From<&Secure> for Access {
+    fn from(secure: &Secure) -> Self {
+        match secure {
+            Secure::Secure(_) => Access::Secure,
+            Secure::Insecure(_) => Access::Insecure,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteAccess {
+    WithResponse(Access),
+    WithoutResponse,
+}
+
+impl From<&Write> for WriteAccess {
+    fn from(write: &Write) -> Self {
+        match write {
+            Write::WithResponse(secure) => WriteAccess::WithResponse(secure.into()),
+            Write::WithoutResponse(_) => WriteAccess::WithoutResponse,
+        }
+    }
+}
+
 pub fn get_properties_and_permissions(characteristic: &Characteristic) -> (u16, u8) {
+    if let Some((properties, permissions)) = characteristic.raw_flags {
+        return (u16::from(properties), permissions);
+    }
+    let properties = &characteristic.properties;
+    compute_flags(
+        properties.read.as_ref().map(|read| Access::from(&read.0)),
+        properties.write.as_ref().map(WriteAccess::from),
+        properties.notify.is_some(),
+        properties.indicate.is_some(),
+    )
+}
+
+/// The `CBCharacteristicProperties` and `CBAttributePermissions` of a characteristic. Properties
+/// are a `u16` as the encryption required variants don't fit into a byte.
+pub fn compute_flags(
+    read: Option<Access>,
+    write: Option<WriteAccess>,
+    notify: bool,
+    indicate: bool,
+) -> (u16, u8) {
     let mut properties: u16 = 0;
     let mut permissions: u8 = 0;
 
-    if let Some(secure) = &characteristic.properties.read {
+    if let Some(access) = read {
         properties |= CBCharacteristicProperties::CBCharacteristicPropertyRead as u16;
 
-        match secure.0 {
-            Secure::Secure(_) => {
+        match access {
+            Access::Secure => {
                 permissions |=
                     CBAttributePermissions::CBAttributePermissionsReadEncryptionRequired as u8;
             }
-            Secure::Insecure(_) => {
+            Access::Insecure => {
                 permissions |= CBAttributePermissions::CBAttributePermissionsReadable as u8;
             }
         };
     }
 
-    if let Some(write) = &characteristic.properties.write {
+    if let Some(write) = write {
         match write {
-            Write::WithResponse(secure) => {
+            WriteAccess::WithResponse(access) => {
                 properties |= CBCharacteristicProperties::CBCharacteristicPropertyWrite as u16;
-                match secure {
-                    Secure::Secure(_) => {
+                match access {
+                    Access::Secure => {
                         permissions |=
                             CBAttributePermissions::CBAttributePermissionsWriteEncryptionRequired
                                 as u8;
                     }
-                    Secure::Insecure(_) => {
+                    Access::Insecure => {
                         permissions |=
                             CBAttributePermissions::CBAttributePermissionsWriteable as u8;
                     }
                 };
             }
-            Write::WithoutResponse(_) => {
+            WriteAccess::WithoutResponse => {
                 properties |=
                     CBCharacteristicProperties::CBCharacteristicPropertyWriteWithoutResponse as u16;
             }
         };
     }
 
-    if characteristic.properties.notify.is_some() {
+    if notify {
         properties |= CBCharacteristicProperties::CBCharacteristicPropertyNotify as u16;
     }
 
-    if characteristic.properties.indicate.is_some() {
+    if indicate {
         properties |= CBCharacteristicProperties::CBCharacteristicPropertyIndicate as u16;
     }
 
     (properties, permissions)
 }
+
+// The flags are private to the CoreBluetooth backend, so they can't be tested from `tests/`.
+#[cfg(test)]
+mod tests {
+    use super::{compute_flags, get_properties_and_permissions, Access, WriteAccess};
+    use crate::gatt::characteristic::Characteristic;
+    use uuid::Uuid;
+
+    const READS: [(Option<Access>, u16, u8); 3] = [
+        (None, 0x00, 0x00),
+        (Some(Access::Insecure), 0x02, 0x01),
+        (Some(Access::Secure), 0x02, 0x04),
+    ];
+
+    const WRITES: [(Option<WriteAccess>, u16, u8); 4] = [
+        (None, 0x00, 0x00),
+        (
+            Some(WriteAccess::WithResponse(Access::Insecure)),
+            0x08,
+            0x02,
+        ),
+        (Some(WriteAccess::WithResponse(Access::Secure)), 0x08, 0x08),
+        (Some(WriteAccess::WithoutResponse), 0x04, 0x00),
+    ];
+
+    #[test]
+    fn compute_flags_covers_every_combination() {
+        for &(read, read_properties, read_permissions) in READS.iter() {
+            for &(write, write_properties, write_permissions) in WRITES.iter() {
+                for &notify in [false, true].iter() {
+                    for &indicate in [false, true].iter() {
+                        let mut properties = read_properties | write_properties;
+                        if notify {
+                            properties |= 0x10;
+                        }
+                        if indicate {
+                            properties |= 0x20;
+                        }
+                        assert_eq!(
+                            compute_flags(read, write, notify, indicate),
+                            (properties, read_permissions | write_permissions),
+                            "read: {:?}, write: {:?}, notify: {}, indicate: {}",
+                            read,
+                            write,
+                            notify,
+                            indicate,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compute_flags_without_properties_is_empty() {
+        assert_eq!(compute_flags(None, None, false, false), (0, 0));
+    }
+
+    #[test]
+    fn raw_flags_are_passed_through() {
+        let characteristic = Characteristic::from_raw(Uuid::nil(), 0x92, 0x05, None);
+        assert_eq!(
+            get_properties_and_permissions(&characteristic),
+            (0x92, 0x05)
+        );
+    }
+}
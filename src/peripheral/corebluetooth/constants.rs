@@ -1,3 +1,11 @@
+use std::time::Duration;
+
 pub const PERIPHERAL_MANAGER_DELEGATE_CLASS_NAME: &str = "PeripheralManagerDelegate";
 pub const PERIPHERAL_MANAGER_IVAR: &str = "peripheralManager";
 pub const POWERED_ON_IVAR: &str = "poweredOn";
+pub const STATE_SENDER_IVAR: &str = "stateSender";
+pub const HANDLERS_IVAR: &str = "handlers";
+pub const QUEUE_IVAR: &str = "queue";
+
+/// How often `isAdvertising` is polled to notice the system stopping the advertisement.
+pub const ADVERTISING_CHECK_INTERVAL: Duration = Duration::from_secs(1);
@@ -0,0 +1,59 @@
+use log::warn;
+use objc::{class, msg_send, runtime::Object, sel, sel_impl};
+use objc_foundation::{INSData, INSString, NSData, NSObject, NSString};
+use objc_id::Id;
+use uuid::Uuid;
+
+use super::into_cbuuid::IntoCBUUID;
+use crate::{
+    gatt::{
+        characteristic::Characteristic,
+        descriptor::{Descriptor, USER_DESCRIPTION_UUID},
+        presentation_format::PRESENTATION_FORMAT_UUID,
+    },
+    uuid::display_uuid,
+    SdpShortUuid,
+};
+
+/// The `CBMutableDescriptor`s of the characteristic. CoreBluetooth only takes User Description
+/// and Presentation Format descriptors with a value fixed when the service is added and answers
+/// their reads by itself, any other descriptor is left out with a warning.
+pub unsafe fn mutable_descriptors(characteristic: &Characteristic) -> Vec<Id<NSObject>> {
+    let mut descriptors = characteristic.descriptors.iter().collect::<Vec<_>>();
+    descriptors.sort_by_key(|descriptor| descriptor.uuid);
+    descriptors
+        .into_iter()
+        .filter_map(|descriptor| {
+            let mutable_descriptor = mutable_descriptor(descriptor);
+            if mutable_descriptor.is_none() {
+                warn!(
+                    "CoreBluetooth can't serve descriptor {} of characteristic {}, leaving it out",
+                    display_uuid(&descriptor.uuid),
+                    display_uuid(&characteristic.uuid)
+                );
+            }
+            mutable_descriptor
+        })
+        .collect()
+}
+
+unsafe fn mutable_descriptor(descriptor: &Descriptor) -> Option<Id<NSObject>> {
+    let value = descriptor.value.as_ref()?;
+    if descriptor.properties.read.is_some() || descriptor.properties.write.is_some() {
+        return None;
+    }
+    let obj: *mut Object = msg_send![class!(CBMutableDescriptor), alloc];
+    let mutable_descriptor: *mut Object =
+        if descriptor.uuid == Uuid::from_sdp_short_uuid(USER_DESCRIPTION_UUID) {
+            // CoreBluetooth expects the description as a string
+            let description = NSString::from_str(&String::from_utf8_lossy(value));
+            msg_send![obj, initWithType:descriptor.uuid.into_cbuuid() value:description]
+        } else if descriptor.uuid == Uuid::from_sdp_short_uuid(PRESENTATION_FORMAT_UUID) {
+            msg_send![obj, initWithType:descriptor.uuid.into_cbuuid()
+                                  value:NSData::with_bytes(value)]
+        } else {
+            let _: () = msg_send![obj, release];
+            return None;
+        };
+    Some(Id::from_retained_ptr(mutable_descriptor as *mut NSObject))
+}
@@ -1,5 +1,43 @@
+use objc::{msg_send, runtime::Object, sel, sel_impl};
+use std::ffi::CStr;
+
+use super::into_bool::IntoBool;
 use crate::{Error, ErrorType};
 
+/// The `NSError` CoreBluetooth passed to a delegate callback, `None` for nil.
+pub unsafe fn ns_error(name: &str, error: *mut Object) -> Option<Error> {
+    if !error.into_bool() {
+        return None;
+    }
+    let localized_description: *mut Object = msg_send![error, localizedDescription];
+    let domain: *mut Object = msg_send![error, domain];
+    let code: isize = msg_send![error, code];
+    Some(
+        Error::new(
+            name.to_string(),
+            utf8_string(localized_description),
+            ErrorType::CoreBluetooth,
+        )
+        .with_code(utf8_string(domain), code as i64),
+    )
+}
+
+pub fn not_powered_on() -> Error {
+    Error::new(
+        "NotPoweredOn",
+        "Advertising can only start once the peripheral is powered on",
+        ErrorType::CoreBluetooth,
+    )
+}
+
+unsafe fn utf8_string(string: *mut Object) -> String {
+    if !string.into_bool() {
+        return String::new();
+    }
+    let utf8: *const std::os::raw::c_char = msg_send![string, UTF8String];
+    CStr::from_ptr(utf8).to_string_lossy().into_owned()
+}
+
 impl From<()> for Error {
     fn from(_: ()) -> Error {
         Error::new("no name", "no description", ErrorType::CoreBluetooth)
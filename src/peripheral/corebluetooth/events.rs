@@ -1,11 +1,51 @@
+use futures::channel::oneshot;
+use log::{trace, warn};
 use objc::{msg_send, runtime::{BOOL, NO, Object, Sel, YES}, sel, sel_impl};
-use objc_foundation::{INSArray, INSString, NSArray, NSObject, NSString};
+use objc_id::Id;
+use std::ffi::c_void;
+use tokio::sync::watch;
 
 use super::{
-    constants::POWERED_ON_IVAR,
+    constants::{HANDLERS_IVAR, POWERED_ON_IVAR, STATE_SENDER_IVAR},
+    error::{not_powered_on, ns_error},
     ffi::{CBATTError, CBManagerState},
     into_bool::IntoBool,
+    request::{
+        attribute_uuid, central_id, maximum_update_value_length, objects, Handlers, PendingRequest,
+    },
 };
+use crate::{
+    gatt::{
+        characteristic::{CharacteristicHandle, Write},
+        default_handler::RequestContext,
+        event::{self, AttError, ReadRequest, Response, WriteRequest, DEFAULT_NOTIFICATION_LENGTH},
+    },
+    span::Span,
+    AdapterState, Central, CentralId,
+};
+
+impl From<CBManagerState> for AdapterState {
+    fn from(state: CBManagerState) -> Self {
+        match state {
+            CBManagerState::CBManagerStateUnknown => AdapterState::Unknown,
+            CBManagerState::CBManagerStateResetting => AdapterState::Resetting,
+            CBManagerState::CBManagerStateUnsupported => AdapterState::Unsupported,
+            CBManagerState::CBManagerStateUnauthorized => AdapterState::Unauthorized,
+            CBManagerState::CBManagerStatePoweredOff => AdapterState::PoweredOff,
+            CBManagerState::CBManagerStatePoweredOn => AdapterState::PoweredOn,
+        }
+    }
+}
+
+/// The sender is owned by the delegate, it's created in `init` and dropped in `dealloc`.
+pub unsafe fn state_sender(delegate: &Object) -> &watch::Sender<AdapterState> {
+    &*(*delegate.get_ivar::<*mut c_void>(STATE_SENDER_IVAR) as *const watch::Sender<AdapterState>)
+}
+
+/// Owned by the delegate as well, it's set right after `init` and dropped in `dealloc`.
+pub unsafe fn handlers(delegate: &Object) -> &Handlers {
+    &*(*delegate.get_ivar::<*mut c_void>(HANDLERS_IVAR) as *const Handlers)
+}
 
 // TODO: Implement event stream for all below callback
 
@@ -14,86 +54,444 @@ pub extern "C" fn peripheral_manager_did_update_state(
     _cmd: Sel,
     peripheral: *mut Object,
 ) {
-    println!("peripheral_manager_did_update_state");
-
+    let state: CBManagerState = unsafe { msg_send![peripheral, state] };
+    let state = AdapterState::from(state);
+    trace!("peripheralManagerDidUpdateState {:?}", state);
     unsafe {
-        let state: CBManagerState = msg_send![peripheral, state];
         match state {
-            CBManagerState::CBManagerStateUnknown => {
-                println!("CBManagerStateUnknown");
-            }
-            CBManagerState::CBManagerStateResetting => {
-                println!("CBManagerStateResetting");
-            }
-            CBManagerState::CBManagerStateUnsupported => {
-                println!("CBManagerStateUnsupported");
-            }
-            CBManagerState::CBManagerStateUnauthorized => {
-                println!("CBManagerStateUnauthorized");
-            }
-            CBManagerState::CBManagerStatePoweredOff => {
-                println!("CBManagerStatePoweredOff");
-                delegate.set_ivar::<BOOL>(POWERED_ON_IVAR, NO);
-            }
-            CBManagerState::CBManagerStatePoweredOn => {
-                println!("CBManagerStatePoweredOn");
-                delegate.set_ivar(POWERED_ON_IVAR, YES);
-            }
+            AdapterState::PoweredOff => delegate.set_ivar::<BOOL>(POWERED_ON_IVAR, NO),
+            AdapterState::PoweredOn => delegate.set_ivar(POWERED_ON_IVAR, YES),
+            _ => {}
+        }
+        // The handlers are only set once `init` returned, the first state may arrive before
+        let handlers = *delegate.get_ivar::<*mut c_void>(HANDLERS_IVAR) as *const Handlers;
+        if !state.is_powered_on() && !handlers.is_null() {
+            (*handlers).registered.lock().unwrap().clear();
+            (*handlers).advertising_started(Err(not_powered_on()));
+            (*handlers).services_not_added(not_powered_on());
+        }
+        state_sender(delegate).send_replace(state);
+    }
+}
+
+/// Only called on iOS when a restore identifier was set, the restored services and advertisement
+/// are left to CoreBluetooth. The centrals still subscribed to the restored characteristics are
+/// tracked again, so notifications reach them without waiting for them to subscribe anew.
+pub extern "C" fn peripheral_manager_will_restore_state(
+    delegate: &mut Object,
+    _cmd: Sel,
+    _peripheral: *mut Object,
+    state: *mut Object,
+) {
+    trace!("willRestoreState");
+    // The handlers are only set once `init` returned, the state may be restored before
+    let handlers = unsafe { *delegate.get_ivar::<*mut c_void>(HANDLERS_IVAR) as *const Handlers };
+    if handlers.is_null() {
+        warn!("State restored before the handlers were set, subscriptions are lost");
+        return;
+    }
+    let mut subscribers = unsafe { (*handlers).subscribers.lock().unwrap() };
+    for (handle, central, update_length) in unsafe { restored_subscriptions(state) } {
+        trace!(
+            "Restored subscription {:?} central={:?} maximumUpdateValueLength={}",
+            handle,
+            central,
+            update_length
+        );
+        subscribers.subscribe(handle, central, update_length);
+    }
+}
+
+/// The `subscribedCentrals` of every characteristic of the services in the restored state.
+/// CoreBluetooth doesn't keep the instances, so characteristics sharing a UUID within a service
+/// are numbered in the order it lists them.
+#[cfg(target_os = "ios")]
+unsafe fn restored_subscriptions(
+    state: *mut Object,
+) -> Vec<(CharacteristicHandle, CentralId, usize)> {
+    use super::ffi::CBPeripheralManagerRestoredStateServicesKey;
+
+    if !state.into_bool() {
+        return vec![];
+    }
+    let services: *mut Object =
+        msg_send![state, objectForKey: CBPeripheralManagerRestoredStateServicesKey];
+    let mut subscriptions = vec![];
+    for service in objects(services) {
+        let service_uuid = match attribute_uuid(service) {
+            Some(uuid) => uuid,
+            None => continue,
         };
+        let mut instances = std::collections::HashMap::new();
+        let characteristics: *mut Object = msg_send![service, characteristics];
+        for characteristic in objects(characteristics) {
+            let uuid = match attribute_uuid(characteristic) {
+                Some(uuid) => uuid,
+                None => continue,
+            };
+            let instance = instances.entry(uuid).or_insert(0);
+            let handle = CharacteristicHandle {
+                service: service_uuid,
+                characteristic: uuid,
+                instance: *instance,
+            };
+            *instance += 1;
+            let centrals: *mut Object = msg_send![characteristic, subscribedCentrals];
+            for central in objects(centrals) {
+                if let Some(id) = central_id(central) {
+                    let update_length =
+                        maximum_update_value_length(central).unwrap_or(DEFAULT_NOTIFICATION_LENGTH);
+                    subscriptions.push((handle, id, update_length));
+                }
+            }
+        }
     }
+    subscriptions
+}
+
+/// State is only restored on iOS.
+#[cfg(not(target_os = "ios"))]
+unsafe fn restored_subscriptions(
+    _state: *mut Object,
+) -> Vec<(CharacteristicHandle, CentralId, usize)> {
+    vec![]
 }
 
 pub extern "C" fn peripheral_manager_did_start_advertising_error(
-    _delegate: &mut Object,
+    delegate: &mut Object,
     _cmd: Sel,
     _peripheral: *mut Object,
     error: *mut Object,
 ) {
-    println!("peripheral_manager_did_start_advertising_error");
-    if error.into_bool() {
-        let localized_description: *mut Object = unsafe { msg_send![error, localizedDescription] };
-        let string = localized_description as *mut NSString;
-        println!("{:?}", unsafe { (*string).as_str() });
-    }
+    trace!("didStartAdvertising");
+    let result = match unsafe { ns_error("AdvertisingFailed", error) } {
+        Some(error) => {
+            warn!("{}", error);
+            Err(error)
+        }
+        None => Ok(()),
+    };
+    unsafe { handlers(delegate) }.advertising_started(result);
 }
 
 pub extern "C" fn peripheral_manager_did_add_service_error(
-    _delegate: &mut Object,
+    delegate: &mut Object,
     _cmd: Sel,
     _peripheral: *mut Object,
-    _service: *mut Object,
+    service: *mut Object,
     error: *mut Object,
 ) {
-    println!("peripheral_manager_did_add_service_error");
-    if error.into_bool() {
-        let localized_description: *mut Object = unsafe { msg_send![error, localizedDescription] };
-        let string = localized_description as *mut NSString;
-        println!("{:?}", unsafe { (*string).as_str() });
+    trace!("didAddService");
+    let result = match unsafe { ns_error("AddServiceFailed", error) } {
+        Some(error) => {
+            warn!("{}", error);
+            Err(error)
+        }
+        None => Ok(()),
+    };
+    if let Some(uuid) = unsafe { attribute_uuid(service) } {
+        let handlers = unsafe { handlers(delegate) };
+        if result.is_ok() {
+            handlers.registered.lock().unwrap().insert(uuid);
+        }
+        handlers.service_added(&uuid, result);
     }
 }
 
+pub extern "C" fn peripheral_manager_is_ready_to_update_subscribers(
+    delegate: &mut Object,
+    _cmd: Sel,
+    peripheral: *mut Object,
+) {
+    trace!("peripheralManagerIsReadyToUpdateSubscribers");
+    let handlers = unsafe { handlers(delegate) };
+    unsafe { handlers.flush_notify_queues(peripheral) };
+    handlers.queue_drained();
+}
+
+// The requests are only handed over to the handlers here, they are answered from the runtime once
+// the handlers respond so a slow handler doesn't hold up the dispatch queue.
+
 pub extern "C" fn peripheral_manager_did_receive_read_request(
-    _delegate: &mut Object,
+    delegate: &mut Object,
     _cmd: Sel,
     peripheral: *mut Object,
     request: *mut Object,
 ) {
-    unsafe {
-        let _: Result<(), ()> = msg_send![peripheral, respondToRequest:request
-                                    withResult:CBATTError::CBATTErrorSuccess];
+    trace!("didReceiveReadRequest");
+    let handlers = unsafe { handlers(delegate) };
+    let request = unsafe { PendingRequest::new(peripheral, request) };
+    let central = request.central();
+    if let Some(central) = central.as_ref() {
+        handlers.subscribers.lock().unwrap().seen(central.id());
     }
+    let read = handlers
+        .handle(request.characteristic())
+        .and_then(|handle| {
+            handlers
+                .characteristics
+                .lock()
+                .unwrap()
+                .get(&handle)
+                .map(|characteristic| {
+                    let read = characteristic.check_read()?;
+                    if !characteristic.authorizes(central.as_ref().map(Central::id)) {
+                        return Err(AttError::InsufficientAuthorization);
+                    }
+                    Ok((handle.characteristic, read.sender()))
+                })
+        });
+    let (uuid, mut event_sender) = match read {
+        Some(Ok(read)) => read,
+        Some(Err(error)) => return request.respond(error.into()),
+        None => return request.respond(CBATTError::CBATTErrorAttributeNotFound),
+    };
+
+    let span = Span::new("read", &uuid, central.as_ref().map(Central::id));
+    let (sender, receiver) = oneshot::channel();
+    let context = RequestContext::new(uuid, central.clone());
+    let read_request = ReadRequest {
+        offset: request.offset(),
+        response: sender,
+        mtu: request.mtu(),
+        central,
+    };
+    let defaults = handlers.defaults.clone();
+    let metrics = handlers.metrics.clone();
+    handlers.runtime.spawn(span.instrument(async move {
+        let sent = defaults
+            .send_read(&mut event_sender, context, read_request)
+            .await;
+        let response = if sent {
+            tokio::time::timeout(event::READ_RESPONSE_TIMEOUT, receiver)
+                .await
+                .unwrap_or(Ok(Response::UnlikelyError))
+                .unwrap_or(Response::UnlikelyError)
+        } else {
+            Response::UnlikelyError
+        };
+        if let Response::Success(_) = response {
+            metrics.read_served();
+        }
+        request.respond_to_read(response);
+    }));
 }
 
 pub extern "C" fn peripheral_manager_did_receive_write_requests(
-    _delegate: &mut Object,
+    delegate: &mut Object,
     _cmd: Sel,
     peripheral: *mut Object,
     requests: *mut Object,
 ) {
-    unsafe {
-        for request in (*(requests as *mut NSArray<NSObject>)).to_vec() {
-            let _: Result<(), ()> = msg_send![peripheral, respondToRequest:request
-                                        withResult:CBATTError::CBATTErrorSuccess];
+    let handlers = unsafe { handlers(delegate) };
+    let mut requests = unsafe {
+        objects(requests)
+            .into_iter()
+            .filter(|request| request.into_bool())
+            .map(|request| PendingRequest::new(peripheral, request))
+            .collect::<Vec<_>>()
+    };
+    trace!("didReceiveWriteRequests count={}", requests.len());
+    if requests.is_empty() {
+        return;
+    }
+
+    let mut writes = vec![];
+    {
+        let handles = requests
+            .iter()
+            .map(|request| handlers.handle(request.characteristic()))
+            .collect::<Vec<_>>();
+        let characteristics = handlers.characteristics.lock().unwrap();
+        for (request, handle) in requests.iter().zip(handles) {
+            // Only the first request of the batch is ever answered
+            let (uuid, characteristic) =
+                match handle.and_then(|handle| characteristics.get(&handle)) {
+                    Some(characteristic) => (characteristic.uuid, characteristic),
+                    None => {
+                        // Points at a mistake in the service configuration rather than the central
+                        warn!(
+                            "Write to unknown characteristic {:?}, ignoring batch",
+                            request.characteristic_uuid()
+                        );
+                        return requests
+                            .swap_remove(0)
+                            .respond(CBATTError::CBATTErrorAttributeNotFound);
+                    }
+                };
+            // CoreBluetooth doesn't tell whether the central sent a command, so the write is
+            // taken to be the kind the characteristic declares
+            let without_response = matches!(
+                characteristic.properties.write,
+                Some(Write::WithoutResponse(_))
+            );
+            let write = match characteristic.check_write(without_response) {
+                Ok(write) => write,
+                Err(error) => return requests.swap_remove(0).respond(error.into()),
+            };
+            let central = request.central();
+            if let Some(central) = central.as_ref() {
+                handlers.subscribers.lock().unwrap().seen(central.id());
+            }
+            if !characteristic.authorizes(central.as_ref().map(Central::id)) {
+                return requests
+                    .swap_remove(0)
+                    .respond(CBATTError::CBATTErrorInsufficientAuthorization);
+            }
+            let (data, offset) = (request.value(), request.offset());
+            trace!(
+                "Write {} offset={} value={} central={:?}",
+                uuid,
+                offset,
+                characteristic.logged_value(&data),
+                central.as_ref().map(Central::id)
+            );
+            if characteristic.exceeds_max_value_length(offset, data.len()) {
+                return requests
+                    .swap_remove(0)
+                    .respond(CBATTError::CBATTErrorInvalidAttributeValueLength);
+            }
+            if let Err(error) = characteristic.check_write_schema(offset, &data) {
+                return requests.swap_remove(0).respond(error.into());
+            }
+            writes.push((uuid, write, data, offset, central));
+        }
+    }
+
+    let mut with_response = vec![];
+    for (uuid, write, data, offset, central) in writes {
+        let without_response = match write {
+            Write::WithResponse(_) => false,
+            Write::WithoutResponse(_) => true,
+        };
+        let span = Span::new("write", &uuid, central.as_ref().map(Central::id));
+        let (sender, receiver) = oneshot::channel();
+        let context = RequestContext::new(uuid, central.clone());
+        let write_request = WriteRequest {
+            data,
+            offset,
+            without_response,
+            response: sender,
+            central,
+        };
+        let event_sender = write.sender();
+        if without_response {
+            span.in_scope(|| {
+                if let Some(write_request) =
+                    handlers
+                        .defaults
+                        .try_default_write(&event_sender, context, write_request)
+                {
+                    handlers
+                        .commands
+                        .unbounded_send((event_sender, write_request))
+                        .ok();
+                }
+            });
+            handlers.metrics.write_served();
+        } else {
+            with_response.push((span, event_sender, context, write_request, receiver));
+        }
+    }
+    if with_response.is_empty() {
+        return;
+    }
+
+    let request = requests.swap_remove(0);
+    let defaults = handlers.defaults.clone();
+    let metrics = handlers.metrics.clone();
+    handlers.runtime.spawn(async move {
+        let mut result = CBATTError::CBATTErrorSuccess;
+        for (span, mut event_sender, context, write_request, receiver) in with_response {
+            let response = span
+                .instrument(async {
+                    let sent = defaults
+                        .send_write(&mut event_sender, context, write_request)
+                        .await;
+                    if sent {
+                        receiver.await.unwrap_or(Response::UnlikelyError)
+                    } else {
+                        Response::UnlikelyError
+                    }
+                })
+                .await;
+            result = response.into();
+            if result != CBATTError::CBATTErrorSuccess {
+                break;
+            }
+            metrics.write_served();
+        }
+        request.respond(result);
+    });
+}
+
+pub extern "C" fn peripheral_manager_central_did_subscribe_to_characteristic(
+    delegate: &mut Object,
+    _cmd: Sel,
+    _peripheral: *mut Object,
+    central: *mut Object,
+    characteristic: *mut Object,
+) {
+    let (central_id, uuid, update_length) = unsafe {
+        (
+            central_id(central),
+            attribute_uuid(characteristic),
+            maximum_update_value_length(central),
+        )
+    };
+    trace!(
+        "didSubscribeToCharacteristic {:?} central={:?} maximumUpdateValueLength={:?}",
+        uuid,
+        central_id,
+        update_length
+    );
+    let handlers = unsafe { handlers(delegate) };
+    let handle = uuid.and_then(|_| handlers.handle(characteristic));
+    if let (Some(central_id), Some(handle)) = (central_id, handle) {
+        handlers
+            .centrals
+            .lock()
+            .unwrap()
+            .insert(central_id.clone(), unsafe { Id::from_ptr(central) }.share());
+        let first = handlers.subscribers.lock().unwrap().subscribe(
+            handle,
+            central_id,
+            update_length.unwrap_or(DEFAULT_NOTIFICATION_LENGTH),
+        );
+        handlers.metrics.subscribed();
+        if first {
+            handlers.subscribed(handle);
+        }
+    }
+}
+
+pub extern "C" fn peripheral_manager_central_did_unsubscribe_from_characteristic(
+    delegate: &mut Object,
+    _cmd: Sel,
+    _peripheral: *mut Object,
+    central: *mut Object,
+    characteristic: *mut Object,
+) {
+    let (central, uuid) = unsafe { (central_id(central), attribute_uuid(characteristic)) };
+    trace!(
+        "didUnsubscribeFromCharacteristic {:?} central={:?}",
+        uuid,
+        central
+    );
+    let handlers = unsafe { handlers(delegate) };
+    let handle = uuid.and_then(|_| handlers.handle(characteristic));
+    if let (Some(central), Some(handle)) = (central, handle) {
+        let last = {
+            let mut subscribers = handlers.subscribers.lock().unwrap();
+            let last = subscribers.unsubscribe(&handle, &central);
+            if !subscribers.is_subscribed(&central) {
+                handlers.centrals.lock().unwrap().remove(&central);
+            }
+            last
+        };
+        handlers.metrics.unsubscribed();
+        if last {
+            handlers.unsubscribed(handle);
         }
     }
 }
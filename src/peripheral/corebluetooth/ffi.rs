@@ -1,7 +1,7 @@
 #![allow(non_camel_case_types)]
 #![allow(non_upper_case_globals)]
 
-use std::os::raw::c_char;
+use std::{ffi::c_void, os::raw::c_char};
 
 use objc::runtime::Object;
 
@@ -11,6 +11,14 @@ pub enum dispatch_object_s {}
 pub type dispatch_queue_t = *mut dispatch_object_s;
 pub type dispatch_queue_attr_t = *const dispatch_object_s;
 pub const DISPATCH_QUEUE_SERIAL: dispatch_queue_attr_t = 0 as dispatch_queue_attr_t;
+pub type qos_class_t = u32;
+pub const QOS_CLASS_USER_INTERACTIVE: qos_class_t = 0x21;
+pub const QOS_CLASS_USER_INITIATED: qos_class_t = 0x19;
+pub const QOS_CLASS_DEFAULT: qos_class_t = 0x15;
+pub const QOS_CLASS_UTILITY: qos_class_t = 0x11;
+pub const QOS_CLASS_BACKGROUND: qos_class_t = 0x09;
+pub type dispatch_time_t = u64;
+pub const DISPATCH_TIME_NOW: dispatch_time_t = 0;
 
 #[link(name = "AppKit", kind = "framework")]
 #[link(name = "Foundation", kind = "framework")]
@@ -20,8 +28,30 @@ extern "C" {
         label: *const c_char,
         attr: dispatch_queue_attr_t,
     ) -> dispatch_queue_t;
+    pub fn dispatch_queue_attr_make_with_qos_class(
+        attr: dispatch_queue_attr_t,
+        qos_class: qos_class_t,
+        relative_priority: i32,
+    ) -> dispatch_queue_attr_t;
+    pub fn dispatch_release(object: dispatch_queue_t);
+    pub fn dispatch_async_f(
+        queue: dispatch_queue_t,
+        context: *mut c_void,
+        work: extern "C" fn(*mut c_void),
+    );
+    pub fn dispatch_time(when: dispatch_time_t, delta: i64) -> dispatch_time_t;
+    pub fn dispatch_after_f(
+        when: dispatch_time_t,
+        queue: dispatch_queue_t,
+        context: *mut c_void,
+        work: extern "C" fn(*mut c_void),
+    );
     pub static CBAdvertisementDataServiceUUIDsKey: *mut Object;
     pub static CBAdvertisementDataLocalNameKey: *mut Object;
+    #[cfg(target_os = "ios")]
+    pub static CBPeripheralManagerOptionRestoreIdentifierKey: *mut Object;
+    #[cfg(target_os = "ios")]
+    pub static CBPeripheralManagerRestoredStateServicesKey: *mut Object;
 }
 
 #[allow(dead_code)]
@@ -61,6 +91,7 @@ pub enum CBAttributePermissions {
 
 #[allow(dead_code)]
 #[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CBATTError {
     CBATTErrorSuccess = 0x00,
     CBATTErrorInvalidHandle = 0x01,
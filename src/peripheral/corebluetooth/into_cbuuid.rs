@@ -2,13 +2,21 @@ use objc::{class, msg_send, runtime::Object, sel, sel_impl};
 use objc_foundation::{INSString, NSString};
 use uuid::Uuid;
 
+use crate::uuid::display_uuid;
+
+/// `CBUUID initWithString:` raises an `NSException` for malformed strings, which can't be caught
+/// from Rust and aborts the process. To rule that out this is only implemented for `Uuid`, so any
+/// string has to go through `Uuid::parse_str` before it reaches CoreBluetooth.
+///
+/// SIG assigned UUIDs are handed over in their 16 or 32 bit form, otherwise CoreBluetooth
+/// advertises all 128 bits of them.
 pub trait IntoCBUUID {
     fn into_cbuuid(self) -> *mut Object;
 }
 
 impl IntoCBUUID for Uuid {
     fn into_cbuuid(self) -> *mut Object {
-        let uuid = self.hyphenated().to_string();
+        let uuid = display_uuid(&self);
         let cls = class!(CBUUID);
         unsafe {
             let obj: *mut Object = msg_send![cls, alloc];
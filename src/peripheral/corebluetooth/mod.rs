@@ -1,33 +1,240 @@
 mod characteristic_flags;
 mod constants;
+mod descriptors;
 mod error;
 mod events;
 mod ffi;
 mod into_bool;
 mod into_cbuuid;
 mod peripheral_manager;
+mod request;
+mod subscribers;
 
+use futures::channel::mpsc;
+use log::warn;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::watch;
 use uuid::Uuid;
 
-use self::peripheral_manager::PeripheralManager;
-use crate::{gatt::service::Service, Error};
+use self::peripheral_manager::{system_default_name, PeripheralManager};
+use super::PeripheralBuilder;
+use crate::{
+    gatt::{
+        characteristic::{CharacteristicHandle, ReadSource},
+        default_handler::RequestContext,
+        event::{ReadRequest, WriteRequest},
+        notify_pause::PausePolicy,
+        service::Service,
+    },
+    state, AdapterState, AdvertisingOptions, AdvertisingState, Capabilities, CentralId,
+    CentralInfo, Error, ErrorType, NotifyError, PeripheralMetrics, PeripheralSnapshot, QueueHealth,
+};
 
+/// Cheap to clone, all clones drive the same `CBPeripheralManager`.
+///
+/// CoreBluetooth calls the delegate on a dispatch queue of the peripheral's own, so neither the
+/// main run loop nor an `NSApplication` has to run and the peripheral can live on any thread. The
+/// requests are handed to the handlers on the tokio runtime `new` is awaited on, which has to keep
+/// running for as long as the peripheral serves them.
+#[derive(Debug, Clone)]
 pub struct Peripheral {
-    peripheral_manager: PeripheralManager,
+    peripheral_manager: Arc<PeripheralManager>,
 }
 
 impl Peripheral {
     #[allow(clippy::new_ret_no_self)]
     pub async fn new() -> Result<Self, Error> {
-        Ok(Peripheral {
-            peripheral_manager: PeripheralManager::new(),
-        })
+        Self::from_builder(PeripheralBuilder::default()).await
+    }
+
+    pub(crate) async fn from_builder(builder: PeripheralBuilder) -> Result<Self, Error> {
+        if cfg!(not(target_os = "ios")) && builder.restore_identifier.is_some() {
+            return Err(Error::new(
+                "Unsupported",
+                "State restoration is only supported on iOS",
+                ErrorType::CoreBluetooth,
+            ));
+        }
+        let peripheral = Peripheral {
+            peripheral_manager: Arc::new(PeripheralManager::new(&builder)?),
+        };
+        peripheral.peripheral_manager.forward_notifications();
+        if builder.fail_if_unsupported {
+            // The first state is reported on the dispatch queue right after creating the manager
+            peripheral.peripheral_manager.flush_queue().await;
+            if *peripheral.peripheral_manager.state_receiver().borrow() == AdapterState::Unsupported
+            {
+                return Err(AdapterState::unsupported_error(ErrorType::CoreBluetooth));
+            }
+        }
+        Ok(peripheral)
     }
 
     pub async fn is_powered(&self) -> Result<bool, Error> {
         Ok(self.peripheral_manager.is_powered())
     }
 
+    /// The adapter state once `peripheralManagerDidUpdateState:` first reported it, until then
+    /// `power_state_stream` only holds `AdapterState::Unknown`.
+    pub async fn current_state(&self) -> AdapterState {
+        state::current_state(self.peripheral_manager.state_receiver()).await
+    }
+
+    /// The adapter state last reported by `peripheralManagerDidUpdateState:`, without waiting for
+    /// the first report like `current_state` does.
+    pub fn state(&self) -> AdapterState {
+        *self.peripheral_manager.state_receiver().borrow()
+    }
+
+    /// Resolves once CoreBluetooth is powered on. Fails right away once it reports that the
+    /// hardware doesn't support acting as a BLE peripheral or that the app isn't authorized to use
+    /// Bluetooth, as it won't power on for the app then.
+    pub async fn wait_until_powered_on(&self) -> Result<(), Error> {
+        state::wait_until_powered_on(
+            self.peripheral_manager.state_receiver(),
+            ErrorType::CoreBluetooth,
+        )
+        .await
+    }
+
+    /// CoreBluetooth does not expose the RSSI of centrals while acting as a peripheral.
+    pub async fn central_rssi(&self, _central: &CentralId) -> Result<Option<i16>, Error> {
+        Err(Error::new(
+            "Unsupported",
+            "Reading the RSSI of a central is not supported by CoreBluetooth",
+            ErrorType::CoreBluetooth,
+        ))
+    }
+
+    /// CoreBluetooth doesn't expose the names of centrals while acting as a peripheral, so this is
+    /// always `None`.
+    pub async fn central_name(&self, _central: &CentralId) -> Option<String> {
+        None
+    }
+
+    /// CoreBluetooth neither exposes nor documents how many centrals may connect at once, so
+    /// this is always `None`.
+    pub fn max_connections(&self) -> Option<usize> {
+        None
+    }
+
+    /// CoreBluetooth has no registration step, services can be added whenever the peripheral is
+    /// powered on.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            advertising_service_data: false,
+            advertising_manufacturer_data: false,
+            advertising_interval: false,
+            advertising_non_connectable: false,
+            advertising_scan_response: false,
+            central_rssi: false,
+            descriptors: true,
+            subscribers: true,
+            services_after_registration: true,
+            state_restoration: cfg!(target_os = "ios"),
+            max_connections: self.max_connections(),
+        }
+    }
+
+    /// Everything tracked about the peripheral in one go, without a trip to the dispatch queue.
+    pub fn snapshot(&self) -> PeripheralSnapshot {
+        self.peripheral_manager.snapshot()
+    }
+
+    /// A readable outline of the added services, see `Service::describe`. It's built from what
+    /// was added, not read back from CoreBluetooth.
+    pub fn describe(&self) -> String {
+        self.peripheral_manager.describe()
+    }
+
+    /// Whether a service CoreBluetooth currently serves contains the characteristic. It drops
+    /// all services when it's powered off or reset, unless `PeripheralBuilder::auto_restore` is
+    /// set they have to be added again once it's powered on.
+    pub fn has_characteristic(&self, characteristic_uuid: &Uuid) -> bool {
+        self.peripheral_manager
+            .has_characteristic(characteristic_uuid)
+    }
+
+    /// Notifies the subscribers of the characteristic, the one with the lowest instance if several
+    /// share the UUID. If CoreBluetooth's transmit queue is full this waits until it has room
+    /// again and tries once more.
+    pub async fn notify(
+        &self,
+        characteristic_uuid: &Uuid,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        self.peripheral_manager
+            .notify(characteristic_uuid, value)
+            .await
+    }
+
+    /// The most bytes one notification of the characteristic carries to all of its subscribers,
+    /// CoreBluetooth tells each central's `maximumUpdateValueLength` when it subscribes. Without
+    /// subscribers it's the 20 bytes of the default ATT MTU.
+    pub fn max_notification_length(&self, characteristic_uuid: &Uuid) -> usize {
+        self.peripheral_manager
+            .max_notification_length(characteristic_uuid)
+    }
+
+    /// Splits the value into chunks of `max_notification_length` and notifies them in order,
+    /// waiting for the transmit queue like `notify` does. Stops at the first chunk that fails.
+    pub async fn notify_chunked(
+        &self,
+        characteristic_uuid: &Uuid,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        let length = self.max_notification_length(characteristic_uuid);
+        for chunk in value.chunks(length.max(1)) {
+            self.notify(characteristic_uuid, chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// Notifies every subscriber of the characteristic the value `value` returns for it, the
+    /// characteristic with the lowest instance if several share the UUID. Each central waits for
+    /// the transmit queue like `notify` does. The values aren't held while notifications are
+    /// paused, nor buffered by a `NotifyQueue`. Every subscriber is tried, the last failure is
+    /// returned.
+    pub async fn update_all<F>(
+        &self,
+        characteristic_uuid: &Uuid,
+        value: F,
+    ) -> Result<(), NotifyError>
+    where
+        F: Fn(&CentralId) -> Vec<u8>,
+    {
+        self.peripheral_manager
+            .update_all(characteristic_uuid, value)
+            .await
+    }
+
+    /// Like `notify`, for the characteristic of one of the handles `add_service` returned.
+    pub async fn notify_handle(
+        &self,
+        handle: &CharacteristicHandle,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        self.peripheral_manager.notify_handle(handle, value).await
+    }
+
+    /// Receives every adapter state change, `changed().await` resolves on the next one.
+    pub fn power_state_stream(&self) -> watch::Receiver<AdapterState> {
+        self.peripheral_manager.state_receiver()
+    }
+
+    /// Reports when the system stops advertising on its own, e.g. when the app goes to the
+    /// background. CoreBluetooth doesn't tell, so it's noticed by polling `isAdvertising` every
+    /// second and whenever the adapter state changes.
+    pub fn advertising_state_stream(&self) -> watch::Receiver<AdvertisingState> {
+        self.peripheral_manager.advertising_state()
+    }
+
+    /// Reports whether the dispatch queue keeps up. It always stays `Responsive` unless the
+    /// watchdog was enabled with `PeripheralBuilder::queue_watchdog`.
+    pub fn queue_health_stream(&self) -> watch::Receiver<QueueHealth> {
+        self.peripheral_manager.queue_health()
+    }
+
     pub async fn register_gatt(&self) -> Result<(), Error> {
         Ok(())
     }
@@ -36,8 +243,59 @@ impl Peripheral {
         Ok(())
     }
 
+    /// With an empty name CoreBluetooth advertises the system's, see `system_default_name`.
     pub async fn start_advertising(self: &Self, name: &str, uuids: &[Uuid]) -> Result<(), Error> {
-        self.peripheral_manager.start_advertising(name, uuids);
+        self.start_advertising_with(&AdvertisingOptions::new(
+            Some(name.to_string()).filter(|name| !name.is_empty()),
+            uuids.to_vec(),
+        ))
+        .await
+    }
+
+    /// The name CoreBluetooth advertises when the advertisement has no local name.
+    pub async fn system_default_name(&self) -> Option<String> {
+        system_default_name()
+    }
+
+    /// CoreBluetooth only advertises the local name and service UUIDs, any other field results
+    /// in an error. It's always connectable, turning `connectable` off only logs a warning, and
+    /// fills the scan response by itself regardless of `scan_response`.
+    ///
+    /// Waits for CoreBluetooth to confirm advertising started. The error it reports instead
+    /// carries the `NSError` domain and code, and advertising before the peripheral is powered on
+    /// fails right away.
+    pub async fn start_advertising_with(&self, options: &AdvertisingOptions) -> Result<(), Error> {
+        if !options.service_data.is_empty() {
+            return Err(Error::new(
+                "UnsupportedAdvertisingField",
+                "CoreBluetooth does not allow advertising service data",
+                ErrorType::CoreBluetooth,
+            ));
+        }
+        if options.manufacturer_data.is_some() {
+            return Err(Error::new(
+                "UnsupportedAdvertisingField",
+                "CoreBluetooth does not allow advertising manufacturer data",
+                ErrorType::CoreBluetooth,
+            ));
+        }
+        if !options.connectable {
+            warn!("CoreBluetooth always advertises as connectable");
+        }
+        options.warn_if_truncated();
+        self.peripheral_manager.start_advertising(options).await
+    }
+
+    /// Advertises until `duration` elapsed. Stopping or restarting the advertisement earlier
+    /// cancels the timeout, afterwards `is_advertising` returns `false` and
+    /// `advertising_state_stream` reports `AdvertisingState::TimedOut`.
+    pub async fn advertise_for(
+        &self,
+        options: &AdvertisingOptions,
+        duration: Duration,
+    ) -> Result<(), Error> {
+        self.start_advertising_with(options).await?;
+        self.peripheral_manager.stop_advertising_after(duration);
         Ok(())
     }
 
@@ -50,8 +308,210 @@ impl Peripheral {
         Ok(self.peripheral_manager.is_advertising())
     }
 
-    pub fn add_service(&self, service: &Service) -> Result<(), Error> {
-        self.peripheral_manager.add_service(service);
+    /// `addService:` only queues the service on the peripheral manager's dispatch queue, so this
+    /// doesn't block and is safe to call from within event handlers.
+    ///
+    /// CoreBluetooth drops services added before it's powered on, so that's an error instead.
+    ///
+    /// Returns a handle for every characteristic, which tells apart characteristics sharing a
+    /// UUID. A service repeating a UUID without `Service::allow_duplicate_characteristics` is an
+    /// error.
+    pub fn add_service(&self, service: &Service) -> Result<Vec<CharacteristicHandle>, Error> {
+        self.check_service(service)?;
+        Ok(self.peripheral_manager.add_service(service))
+    }
+
+    fn check_service(&self, service: &Service) -> Result<(), Error> {
+        if !self.peripheral_manager.is_powered() {
+            return Err(Error::new(
+                "NotPoweredOn",
+                "Services can only be added once the peripheral is powered on",
+                ErrorType::CoreBluetooth,
+            ));
+        }
+        service.check_duplicate_characteristics(ErrorType::CoreBluetooth)?;
+        service.check_included_services(
+            |uuid| self.peripheral_manager.has_service(uuid),
+            ErrorType::CoreBluetooth,
+        )
+    }
+
+    /// Stops advertising and the tasks driving the peripheral, removes all services, drops the
+    /// buffered notifications and waits for the delegate callbacks already queued to finish, so
+    /// no request reaches a handler afterwards. Pending notify calls fail. Reads and writes that
+    /// were handed to a handler before may still be answered. Clones of this `Peripheral` share
+    /// the peripheral manager, so they are shut down as well. It's released once the last of them
+    /// is dropped.
+    pub async fn shutdown(self) -> Result<(), Error> {
+        self.peripheral_manager.stop_advertising();
+        // Before removing the services, so they aren't added again
+        self.peripheral_manager.stop_tasks();
+        self.peripheral_manager.remove_all_services();
+        self.peripheral_manager.drop_notifications();
+        self.peripheral_manager.flush_queue().await;
+        Ok(())
+    }
+
+    /// The `CBCharacteristicProperties` and `CBAttributePermissions` bitmasks a characteristic
+    /// was registered with, useful to find out why a central can't perform some operation.
+    pub fn effective_properties(&self, characteristic_uuid: &Uuid) -> Option<(u16, u8)> {
+        self.peripheral_manager
+            .effective_properties(characteristic_uuid)
+    }
+
+    /// Whether reads of the characteristic reach its handler or are answered with the value it
+    /// was added with. `None` if no added service contains it.
+    pub fn read_source(&self, characteristic_uuid: &Uuid) -> Option<ReadSource> {
+        self.peripheral_manager.read_source(characteristic_uuid)
+    }
+
+    /// Adds the services in order, each once CoreBluetooth confirmed adding the one before. If
+    /// one of them fails the ones added before it are removed again, so either all services are
+    /// added or none are.
+    pub async fn add_services(&self, services: &[Service]) -> Result<(), Error> {
+        for (index, service) in services.iter().enumerate() {
+            if let Err(err) = self.check_service(service) {
+                self.remove_services(&services[..index]);
+                return Err(err);
+            }
+            if let Err(err) = self.peripheral_manager.add_service_confirmed(service).await {
+                self.remove_services(&services[..=index]);
+                return Err(err);
+            }
+        }
         Ok(())
     }
+
+    fn remove_services(&self, services: &[Service]) {
+        for service in services {
+            self.peripheral_manager.remove_service(&service.uuid);
+        }
+    }
+
+    /// Makes `services` the added services: the ones not among them are removed, new ones are
+    /// added and the ones that only differ in their handlers stay in place, so centrals
+    /// subscribed to them don't notice.
+    pub fn replace_services(&self, services: &[Service]) -> Result<(), Error> {
+        if !self.peripheral_manager.is_powered() {
+            return Err(Error::new(
+                "NotPoweredOn",
+                "Services can only be added once the peripheral is powered on",
+                ErrorType::CoreBluetooth,
+            ));
+        }
+        for (index, service) in services.iter().enumerate() {
+            service.check_duplicate_characteristics(ErrorType::CoreBluetooth)?;
+            service.check_included_services(
+                |uuid| services[..index].iter().any(|added| added.uuid == *uuid),
+                ErrorType::CoreBluetooth,
+            )?;
+        }
+        self.peripheral_manager.replace_services(services);
+        Ok(())
+    }
+
+    /// Removes the service, its characteristics no longer reach their handlers. Removing a
+    /// service that isn't added does nothing.
+    pub fn remove_service(&self, uuid: &Uuid) -> Result<(), Error> {
+        self.peripheral_manager.remove_service(uuid);
+        Ok(())
+    }
+
+    pub fn remove_all_services(&self) -> Result<(), Error> {
+        self.peripheral_manager.remove_all_services();
+        Ok(())
+    }
+
+    /// The value the characteristic was added with, `None` if it has none or no added service
+    /// contains it.
+    pub fn get_value(&self, characteristic_uuid: &Uuid) -> Option<Vec<u8>> {
+        self.peripheral_manager.get_value(characteristic_uuid)
+    }
+
+    /// The centrals subscribed to the characteristic's notifications or indications, to any of
+    /// them if several characteristics share the UUID.
+    pub fn subscribers(&self, characteristic_uuid: &Uuid) -> Vec<CentralId> {
+        self.peripheral_manager.subscribers(characteristic_uuid)
+    }
+
+    /// Every central subscribed to any characteristic, with what it's subscribed to.
+    pub fn centrals(&self) -> Vec<CentralInfo> {
+        self.peripheral_manager.centrals()
+    }
+
+    /// Whether the central is subscribed to the characteristic's notifications or indications.
+    pub fn is_subscribed(&self, central: &CentralId, characteristic_uuid: &Uuid) -> bool {
+        self.peripheral_manager
+            .is_subscribed(central, characteristic_uuid)
+    }
+
+    /// Ends the subscriptions of a central the application knows to be gone, CoreBluetooth
+    /// doesn't report disconnects to the peripheral. See `PeripheralBuilder::subscription_timeout`
+    /// to have them pruned instead.
+    pub fn forget_central(&self, central: &CentralId) {
+        self.peripheral_manager.forget_central(central);
+    }
+
+    /// Counters of the operations served since the peripheral was created, shared by all clones.
+    pub fn metrics(&self) -> PeripheralMetrics {
+        self.peripheral_manager.metrics()
+    }
+
+    /// Holds back the notifications `notify`, `notify_handle` and `notify_chunked` send until
+    /// `resume_notifications`, e.g. during a firmware update. They return `Ok` right away while
+    /// paused and `policy` decides whether the values are kept. Keeping the latest value of a
+    /// characteristic also means only the last chunk of a `notify_chunked` call survives. Held
+    /// values never reach the transmit queue, so they can't fill it.
+    /// Notifications sent through a subscription's `notification` channel aren't paused.
+    pub fn pause_notifications(&self, policy: PausePolicy) {
+        self.peripheral_manager.pause_notifications(policy);
+    }
+
+    /// Sends the values kept while notifications were paused, in no particular order, and lets
+    /// notifications through again. Each is sent like a `notify_handle` call, waiting once for
+    /// room if the transmit queue is full. Values nobody is subscribed to anymore are dropped,
+    /// the first other failure is returned once the rest were sent.
+    pub async fn resume_notifications(&self) -> Result<(), NotifyError> {
+        self.peripheral_manager.resume_notifications().await
+    }
+
+    /// Hands the payload of every notification `notify`, `notify_handle` and `notify_chunked`
+    /// send to `interceptor` right before `updateValue:`, which may change it. It runs on the
+    /// thread that awaits the notify call, once per chunk and not again when the value is retried
+    /// after the transmit queue was full. Replaces the previous interceptor.
+    pub fn set_notify_interceptor<F>(&self, interceptor: F)
+    where
+        F: Fn(&Uuid, &mut Vec<u8>) + Send + Sync + 'static,
+    {
+        self.peripheral_manager
+            .set_notify_interceptor(Arc::new(interceptor));
+    }
+
+    /// Handles the reads of characteristics declared with `default_handler::unhandled` as their
+    /// event sender. A characteristic's own handler always takes precedence. Replaces the
+    /// previous default handler.
+    pub fn set_default_read_handler<F>(&self, handler: F)
+    where
+        F: Fn(RequestContext, ReadRequest) + Send + Sync + 'static,
+    {
+        self.peripheral_manager
+            .set_default_read_handler(Arc::new(handler));
+    }
+
+    /// Like `set_default_read_handler`, but for writes with and without response.
+    pub fn set_default_write_handler<F>(&self, handler: F)
+    where
+        F: Fn(RequestContext, WriteRequest) + Send + Sync + 'static,
+    {
+        self.peripheral_manager
+            .set_default_write_handler(Arc::new(handler));
+    }
+
+    /// The writes to the characteristic, with and without response, for handling them in a
+    /// `while let Some(write) = writes.next().await` loop, e.g. for a control point. While the
+    /// stream is alive writes reach it instead of the characteristic's handler or the default
+    /// one. Opening another stream for the characteristic ends this one.
+    pub fn writes(&self, characteristic_uuid: &Uuid) -> mpsc::UnboundedReceiver<WriteRequest> {
+        self.peripheral_manager.writes(characteristic_uuid)
+    }
 }
@@ -1,6 +1,22 @@
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+    stream::BoxStream,
+};
+use log::{debug, warn};
 use std::{
-    ffi::CString,
-    sync::{Once, ONCE_INIT},
+    collections::{HashMap, HashSet},
+    ffi::{c_void, CString},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Once, Weak, ONCE_INIT,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    runtime::Handle,
+    sync::{watch, Notify},
+    task::JoinHandle,
 };
 
 use objc::{class, declare::ClassDecl, msg_send, runtime::{BOOL, Class, NO, Object, Protocol, Sel, YES}, sel, sel_impl};
@@ -8,36 +24,94 @@ use objc_foundation::{
     INSArray, INSData, INSDictionary, INSString, NSArray, NSData, NSDictionary, NSObject, NSString,
 };
 use objc_id::{Id, Shared};
-
 use uuid::Uuid;
 
-use crate::gatt::service::Service;
+use crate::{
+    gatt::{
+        characteristic::{CharacteristicHandle, ReadSource},
+        default_handler::{DefaultHandlers, DefaultReadHandler, DefaultWriteHandler},
+        event::{command_queue, WriteRequest, DEFAULT_NOTIFICATION_LENGTH},
+        notify_buffers::NotifyBuffers,
+        notify_interceptor::{NotifyInterceptor, NotifyInterceptors},
+        notify_pause::{NotifyPause, PausePolicy},
+        notify_queue::NotifyQueue,
+        service::Service,
+    },
+    metrics::Metrics,
+    span::Span,
+    AdapterState, AdvertisingOptions, AdvertisingState, CentralId, CentralInfo, Error, ErrorType,
+    NotifyError, PeripheralBuilder, PeripheralMetrics, PeripheralSnapshot, QualityOfService,
+    QueueHealth,
+};
 
 use super::{
     characteristic_flags::get_properties_and_permissions,
-    constants::{PERIPHERAL_MANAGER_DELEGATE_CLASS_NAME, PERIPHERAL_MANAGER_IVAR, POWERED_ON_IVAR},
+    constants::{
+        ADVERTISING_CHECK_INTERVAL, HANDLERS_IVAR, PERIPHERAL_MANAGER_DELEGATE_CLASS_NAME,
+        PERIPHERAL_MANAGER_IVAR, POWERED_ON_IVAR, QUEUE_IVAR, STATE_SENDER_IVAR,
+    },
+    descriptors::mutable_descriptors,
+    error::not_powered_on,
     events::{
+        handlers, peripheral_manager_central_did_subscribe_to_characteristic,
+        peripheral_manager_central_did_unsubscribe_from_characteristic,
         peripheral_manager_did_add_service_error, peripheral_manager_did_receive_read_request,
         peripheral_manager_did_receive_write_requests,
         peripheral_manager_did_start_advertising_error, peripheral_manager_did_update_state,
+        peripheral_manager_is_ready_to_update_subscribers, peripheral_manager_will_restore_state,
+        state_sender,
     },
     ffi::{
-        dispatch_queue_create, nil, CBAdvertisementDataLocalNameKey,
-        CBAdvertisementDataServiceUUIDsKey, DISPATCH_QUEUE_SERIAL,
+        dispatch_after_f, dispatch_async_f, dispatch_queue_attr_make_with_qos_class,
+        dispatch_queue_create, dispatch_queue_t, dispatch_release, dispatch_time, nil, qos_class_t,
+        CBAdvertisementDataLocalNameKey, CBAdvertisementDataServiceUUIDsKey, DISPATCH_QUEUE_SERIAL,
+        DISPATCH_TIME_NOW, QOS_CLASS_BACKGROUND, QOS_CLASS_DEFAULT, QOS_CLASS_USER_INITIATED,
+        QOS_CLASS_USER_INTERACTIVE, QOS_CLASS_UTILITY,
     },
     into_bool::IntoBool,
     into_cbuuid::IntoCBUUID,
+    request::Handlers,
+    subscribers::Subscribers,
 };
 
 static REGISTER_DELEGATE_CLASS: Once = ONCE_INIT;
 
+/// The added services along with the `CBMutableService`s they were registered as.
+type Services = HashMap<Uuid, (Service, Id<Object, Shared>)>;
+
+/// The notification streams handed to the handlers of subscribed characteristics.
+type Subscriptions = mpsc::UnboundedReceiver<(CharacteristicHandle, BoxStream<'static, Vec<u8>>)>;
+
 #[derive(Debug)]
 pub struct PeripheralManager {
     peripheral_manager_delegate: Id<Object, Shared>,
+    state_receiver: watch::Receiver<AdapterState>,
+    /// Locked before the mutexes of the handlers, never while holding one of them.
+    services: Arc<Mutex<Services>>,
+    /// Bumped whenever advertising starts or stops, a scheduled stop only goes through if
+    /// nothing happened in between.
+    advertising_generation: Arc<AtomicUsize>,
+    /// The generation a scheduled stop ended advertising in, so the advertising monitor reports
+    /// it as `AdvertisingState::TimedOut`.
+    advertising_timed_out: Arc<AtomicUsize>,
+    advertising_state: watch::Receiver<AdvertisingState>,
+    advertising_options: Arc<Mutex<Option<AdvertisingOptions>>>,
+    advertising_monitor: JoinHandle<()>,
+    advertising_pauser: Option<JoinHandle<()>>,
+    queue_health: watch::Receiver<QueueHealth>,
+    watchdog: Option<JoinHandle<()>>,
+    subscription_pruner: Option<JoinHandle<()>>,
+    service_restorer: Option<JoinHandle<()>>,
+    notify_interceptors: NotifyInterceptors,
+    notify_pause: NotifyPause,
+    command_forwarder: JoinHandle<()>,
+    /// Taken by `forward_notifications`, which needs the manager behind its `Arc`.
+    subscriptions: Mutex<Option<Subscriptions>>,
+    notification_forwarder: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl PeripheralManager {
-    pub fn new() -> Self {
+    pub fn new(builder: &PeripheralBuilder) -> Result<Self, Error> {
         REGISTER_DELEGATE_CLASS.call_once(|| {
             let mut decl =
                 ClassDecl::new(PERIPHERAL_MANAGER_DELEGATE_CLASS_NAME, class!(NSObject)).unwrap();
@@ -45,17 +119,27 @@ impl PeripheralManager {
 
             decl.add_ivar::<*mut Object>(PERIPHERAL_MANAGER_IVAR);
             decl.add_ivar::<BOOL>(POWERED_ON_IVAR);
+            decl.add_ivar::<*mut c_void>(STATE_SENDER_IVAR);
+            decl.add_ivar::<*mut c_void>(HANDLERS_IVAR);
+            decl.add_ivar::<*mut c_void>(QUEUE_IVAR);
 
             unsafe {
                 decl.add_method(
-                    sel!(init),
-                    init as extern "C" fn(&mut Object, Sel) -> *mut Object,
+                    sel!(initWithBuilder:),
+                    init_with_builder
+                        as extern "C" fn(&mut Object, Sel, *const c_void) -> *mut Object,
                 );
+                decl.add_method(sel!(dealloc), dealloc as extern "C" fn(&mut Object, Sel));
                 decl.add_method(
                     sel!(peripheralManagerDidUpdateState:),
                     peripheral_manager_did_update_state
                         as extern "C" fn(&mut Object, Sel, *mut Object),
                 );
+                decl.add_method(
+                    sel!(peripheralManager:willRestoreState:),
+                    peripheral_manager_will_restore_state
+                        as extern "C" fn(&mut Object, Sel, *mut Object, *mut Object),
+                );
                 decl.add_method(
                     sel!(peripheralManagerDidStartAdvertising:error:),
                     peripheral_manager_did_start_advertising_error
@@ -76,23 +160,173 @@ impl PeripheralManager {
                     peripheral_manager_did_receive_write_requests
                         as extern "C" fn(&mut Object, Sel, *mut Object, *mut Object),
                 );
+                decl.add_method(
+                    sel!(peripheralManagerIsReadyToUpdateSubscribers:),
+                    peripheral_manager_is_ready_to_update_subscribers
+                        as extern "C" fn(&mut Object, Sel, *mut Object),
+                );
+                decl.add_method(
+                    sel!(peripheralManager:central:didSubscribeToCharacteristic:),
+                    peripheral_manager_central_did_subscribe_to_characteristic
+                        as extern "C" fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object),
+                );
+                decl.add_method(
+                    sel!(peripheralManager:central:didUnsubscribeFromCharacteristic:),
+                    peripheral_manager_central_did_unsubscribe_from_characteristic
+                        as extern "C" fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object),
+                );
             }
 
             decl.register();
         });
 
+        // Requests are handed to the handlers on the runtime `Peripheral::new` is awaited on
+        let runtime = Handle::current();
+        let (commands, forwarder) = command_queue();
+        let command_forwarder = runtime.spawn(forwarder);
+        let (subscription_sender, subscriptions) = mpsc::unbounded();
+        let handlers = Handlers {
+            runtime: runtime.clone(),
+            characteristics: Mutex::new(HashMap::new()),
+            identities: Mutex::new(HashMap::new()),
+            commands,
+            subscriptions: subscription_sender,
+            defaults: DefaultHandlers::default(),
+            metrics: Arc::new(Metrics::default()),
+            subscribers: Arc::new(Mutex::new(Subscribers::default())),
+            advertising_waiters: Mutex::new(vec![]),
+            add_service_waiters: Mutex::new(HashMap::new()),
+            registered: Mutex::new(HashSet::new()),
+            ready_to_update: Notify::new(),
+            queue_full: Mutex::new(HashSet::new()),
+            notify_buffers: NotifyBuffers::default(),
+            centrals: Mutex::new(HashMap::new()),
+        };
+        let subscribers = handlers.subscribers.clone();
+        let centrals = subscribers.lock().unwrap().central_count_receiver();
+
         let peripheral_manager_delegate = unsafe {
             let cls = Class::get(PERIPHERAL_MANAGER_DELEGATE_CLASS_NAME).unwrap();
             let mut obj: *mut Object = msg_send![cls, alloc];
-            obj = msg_send![obj, init];
-            Id::from_ptr(obj).share()
+            let builder = builder as *const PeripheralBuilder as *const c_void;
+            obj = msg_send![obj, initWithBuilder: builder];
+            if !obj.into_bool() {
+                return Err(Error::new(
+                    "PeripheralManagerUnavailable",
+                    "CoreBluetooth could not create a CBPeripheralManager",
+                    ErrorType::CoreBluetooth,
+                ));
+            }
+            let mut delegate: Id<Object> = Id::from_ptr(obj);
+            delegate.set_ivar::<*mut c_void>(
+                HANDLERS_IVAR,
+                Box::into_raw(Box::new(handlers)) as *mut c_void,
+            );
+            delegate.share()
         };
 
-        PeripheralManager {
+        let state_receiver = unsafe { state_sender(&peripheral_manager_delegate).subscribe() };
+        let subscription_pruner = builder
+            .subscription_timeout
+            .map(|timeout| runtime.spawn(prune_subscribers(subscribers, timeout)));
+
+        let (queue_health_sender, queue_health) = watch::channel(QueueHealth::Responsive);
+        let watchdog = builder.queue_watchdog.map(|threshold| {
+            let queue = unsafe {
+                *peripheral_manager_delegate.get_ivar::<*mut c_void>(QUEUE_IVAR) as dispatch_queue_t
+            };
+            runtime.spawn(watch_queue(Queue(queue), threshold, queue_health_sender))
+        });
+
+        let advertising_generation = Arc::new(AtomicUsize::new(0));
+        let advertising_timed_out = Arc::new(AtomicUsize::new(usize::MAX));
+        let (advertising_state_sender, advertising_state) =
+            watch::channel(AdvertisingState::Stopped);
+        let advertising_monitor = runtime.spawn(watch_advertising(
+            unsafe {
+                RetainedManager::new(*peripheral_manager_delegate.get_ivar(PERIPHERAL_MANAGER_IVAR))
+            },
+            Arc::clone(&advertising_generation),
+            Arc::clone(&advertising_timed_out),
+            state_receiver.clone(),
+            advertising_state_sender,
+        ));
+
+        let advertising_options = Arc::new(Mutex::new(None));
+        let advertising_pauser = builder.auto_stop_advertising.then(|| {
+            runtime.spawn(pause_advertising(
+                unsafe {
+                    RetainedManager::new(
+                        *peripheral_manager_delegate.get_ivar(PERIPHERAL_MANAGER_IVAR),
+                    )
+                },
+                Arc::clone(&advertising_generation),
+                Arc::clone(&advertising_options),
+                centrals,
+            ))
+        });
+
+        let services = Arc::new(Mutex::new(HashMap::new()));
+        let service_restorer = builder.auto_restore.then(|| {
+            runtime.spawn(restore_services(
+                unsafe {
+                    RetainedManager::new(
+                        *peripheral_manager_delegate.get_ivar(PERIPHERAL_MANAGER_IVAR),
+                    )
+                },
+                SharedServices(Arc::clone(&services)),
+                state_receiver.clone(),
+            ))
+        });
+
+        Ok(PeripheralManager {
             peripheral_manager_delegate,
+            state_receiver,
+            services,
+            advertising_generation,
+            advertising_timed_out,
+            advertising_state,
+            advertising_options,
+            advertising_monitor,
+            advertising_pauser,
+            queue_health,
+            watchdog,
+            subscription_pruner,
+            service_restorer,
+            notify_interceptors: NotifyInterceptors::default(),
+            notify_pause: NotifyPause::default(),
+            command_forwarder,
+            subscriptions: Mutex::new(Some(subscriptions)),
+            notification_forwarder: Mutex::new(None),
+        })
+    }
+
+    /// Sends what the handlers push into the channels of their `NotifySubscribe` events like
+    /// `notify` does, until the peripheral is dropped.
+    pub fn forward_notifications(self: &Arc<Self>) {
+        if let Some(subscriptions) = self.subscriptions.lock().unwrap().take() {
+            let forwarder = unsafe { handlers(&self.peripheral_manager_delegate) }
+                .runtime
+                .spawn(forward_notifications(Arc::downgrade(self), subscriptions));
+            self.notification_forwarder
+                .lock()
+                .unwrap()
+                .replace(forwarder);
         }
     }
 
+    pub fn state_receiver(&self) -> watch::Receiver<AdapterState> {
+        self.state_receiver.clone()
+    }
+
+    pub fn advertising_state(&self) -> watch::Receiver<AdvertisingState> {
+        self.advertising_state.clone()
+    }
+
+    pub fn queue_health(&self) -> watch::Receiver<QueueHealth> {
+        self.queue_health.clone()
+    }
+
     pub fn is_powered(self: &Self) -> bool {
         unsafe {
             let powered_on = *self
@@ -102,42 +336,30 @@ impl PeripheralManager {
         }
     }
 
-    pub fn start_advertising(self: &Self, name: &str, uuids: &[Uuid]) {
-        let peripheral_manager = unsafe {
-            *self
-                .peripheral_manager_delegate
-                .get_ivar::<*mut Object>(PERIPHERAL_MANAGER_IVAR)
-        };
-
-        let mut keys: Vec<&NSString> = vec![];
-        let mut objects: Vec<Id<NSObject>> = vec![];
-
-        unsafe {
-            keys.push(&*(CBAdvertisementDataLocalNameKey as *mut NSString));
-            objects.push(Id::from_retained_ptr(msg_send![
-                NSString::from_str(name),
-                copy
-            ]));
-            keys.push(&*(CBAdvertisementDataServiceUUIDsKey as *mut NSString));
-            objects.push(Id::from_retained_ptr(msg_send![
-                NSArray::from_vec(
-                    uuids
-                        .iter()
-                        .map(|u| NSString::from_str(&u.hyphenated().to_string()))
-                        .collect::<Vec<Id<NSString>>>()
-                ),
-                copy
-            ]));
+    /// Resolves once `peripheralManagerDidStartAdvertising:error:` reported whether advertising
+    /// started, or fails if the peripheral stops being powered on before.
+    pub async fn start_advertising(&self, options: &AdvertisingOptions) -> Result<(), Error> {
+        if !self.is_powered() {
+            return Err(not_powered_on());
         }
-
-        let advertising_data = NSDictionary::from_keys_and_objects(keys.as_slice(), objects);
+        let (waiter, started) = oneshot::channel();
+        unsafe { handlers(&self.peripheral_manager_delegate) }
+            .advertising_waiters
+            .lock()
+            .unwrap()
+            .push(waiter);
+        self.advertising_generation.fetch_add(1, Ordering::SeqCst);
+        *self.advertising_options.lock().unwrap() = Some(options.clone());
+        let advertising_data = advertising_data(options);
         unsafe {
             let _: Result<(), ()> =
-                msg_send![peripheral_manager, startAdvertising: advertising_data];
+                msg_send![self.peripheral_manager(), startAdvertising: advertising_data];
         }
+        started.await.unwrap_or_else(|_| Err(not_powered_on()))
     }
 
     pub fn stop_advertising(self: &Self) {
+        self.advertising_generation.fetch_add(1, Ordering::SeqCst);
         unsafe {
             let peripheral_manager = *self
                 .peripheral_manager_delegate
@@ -146,6 +368,30 @@ impl PeripheralManager {
         }
     }
 
+    /// Schedules `stopAdvertising` on the dispatch queue, unless advertising is started or
+    /// stopped again before `duration` elapsed.
+    pub fn stop_advertising_after(&self, duration: Duration) {
+        let peripheral_manager = self.peripheral_manager();
+        let scheduled_stop = Box::new(ScheduledStop {
+            peripheral_manager,
+            advertising_generation: Arc::clone(&self.advertising_generation),
+            advertising_timed_out: Arc::clone(&self.advertising_timed_out),
+            scheduled: self.advertising_generation.load(Ordering::SeqCst),
+        });
+        unsafe {
+            let _: *mut Object = msg_send![peripheral_manager, retain];
+            let queue = *self
+                .peripheral_manager_delegate
+                .get_ivar::<*mut c_void>(QUEUE_IVAR) as dispatch_queue_t;
+            dispatch_after_f(
+                dispatch_time(DISPATCH_TIME_NOW, duration.as_nanos() as i64),
+                queue,
+                Box::into_raw(scheduled_stop) as *mut c_void,
+                stop_advertising_after,
+            );
+        }
+    }
+
     pub fn is_advertising(self: &Self) -> bool {
         unsafe {
             let peripheral_manager = *self
@@ -156,12 +402,23 @@ impl PeripheralManager {
         }
     }
 
-    pub fn add_service(self: &Self, service: &Service) {
+    fn peripheral_manager(&self) -> *mut Object {
+        unsafe {
+            *self
+                .peripheral_manager_delegate
+                .get_ivar::<*mut Object>(PERIPHERAL_MANAGER_IVAR)
+        }
+    }
+
+    /// Replaces the service with the same UUID, if there is one.
+    pub fn add_service(self: &Self, service: &Service) -> Vec<CharacteristicHandle> {
+        self.remove_service(&service.uuid);
+        let handlers = unsafe { handlers(&self.peripheral_manager_delegate) };
+        let mut handles = vec![];
         let characteristics: Vec<Id<NSObject>> = service
-            .characteristics
-            .iter()
+            .resolved_characteristics()
             .map(|characteristic| {
-                let (properties, permissions) = get_properties_and_permissions(characteristic);
+                let (properties, permissions) = get_properties_and_permissions(&characteristic);
                 unsafe {
                     let cls = class!(CBMutableCharacteristic);
                     let obj: *mut Object = msg_send![cls, alloc];
@@ -177,52 +434,1027 @@ impl PeripheralManager {
                                                       value:nil
                                                 permissions:permissions],
                     };
+                    let descriptors = mutable_descriptors(&characteristic);
+                    if !descriptors.is_empty() {
+                        let _: () = msg_send![mutable_characteristic,
+                                              setDescriptors:NSArray::from_vec(descriptors)];
+                    }
+
+                    let handle = characteristic.handle(service.uuid);
+                    handlers
+                        .identities
+                        .lock()
+                        .unwrap()
+                        .insert(mutable_characteristic as usize, handle);
+                    handlers
+                        .characteristics
+                        .lock()
+                        .unwrap()
+                        .insert(handle, characteristic);
+                    handles.push(handle);
 
                     Id::from_ptr(mutable_characteristic as *mut NSObject)
                 }
             })
             .collect();
 
-        unsafe {
+        // `Peripheral::add_service` made sure they were added before
+        let included_services: Vec<Id<NSObject, Shared>> = {
+            let services = self.services.lock().unwrap();
+            service
+                .included_services
+                .iter()
+                .filter_map(|uuid| services.get(uuid))
+                .map(|(_, mutable_service)| unsafe {
+                    Id::from_ptr(&**mutable_service as *const Object as *mut NSObject)
+                })
+                .collect()
+        };
+        let mutable_service: Id<Object, Shared> = unsafe {
             let cls = class!(CBMutableService);
             let obj: *mut Object = msg_send![cls, alloc];
-            let service: *mut Object = msg_send![obj, initWithType:service.uuid.into_cbuuid()
-                                                           primary:YES];
-            let _: Result<(), ()> = msg_send![service, setValue:NSArray::from_vec(characteristics)
-                                 forKey:NSString::from_str("characteristics")];
+            let primary = if service.primary { YES } else { NO };
+            let mutable_service: *mut Object =
+                msg_send![obj, initWithType:service.uuid.into_cbuuid() primary:primary];
+            let _: Result<(), ()> = msg_send![mutable_service,
+                                              setValue:NSArray::from_vec(characteristics)
+                                                forKey:NSString::from_str("characteristics")];
+            if !included_services.is_empty() {
+                let _: () = msg_send![mutable_service,
+                                      setIncludedServices:NSArray::from_vec(included_services)];
+            }
 
-            let peripheral_manager = *self
+            let _: Result<(), ()> =
+                msg_send![self.peripheral_manager(), addService: mutable_service];
+
+            Id::from_retained_ptr(mutable_service).share()
+        };
+
+        self.services
+            .lock()
+            .unwrap()
+            .insert(service.uuid, (service.clone(), mutable_service));
+        handles
+    }
+
+    /// Like `add_service`, waiting for `peripheralManager:didAddService:error:` to tell whether
+    /// CoreBluetooth added it.
+    pub async fn add_service_confirmed(
+        &self,
+        service: &Service,
+    ) -> Result<Vec<CharacteristicHandle>, Error> {
+        if !self.is_powered() {
+            return Err(not_powered_on());
+        }
+        let (waiter, added) = oneshot::channel();
+        unsafe { handlers(&self.peripheral_manager_delegate) }
+            .add_service_waiters
+            .lock()
+            .unwrap()
+            .entry(service.uuid)
+            .or_default()
+            .push(waiter);
+        let handles = self.add_service(service);
+        added.await.unwrap_or_else(|_| Err(not_powered_on()))?;
+        Ok(handles)
+    }
+
+    pub fn has_service(&self, uuid: &Uuid) -> bool {
+        self.services.lock().unwrap().contains_key(uuid)
+    }
+
+    pub fn remove_service(&self, uuid: &Uuid) {
+        let removed = self.services.lock().unwrap().remove(uuid);
+        if let Some((service, mutable_service)) = removed {
+            let handlers = unsafe { handlers(&self.peripheral_manager_delegate) };
+            handlers.registered.lock().unwrap().remove(uuid);
+            let mut characteristics = handlers.characteristics.lock().unwrap();
+            for handle in service.handles() {
+                characteristics.remove(&handle);
+            }
+            handlers
+                .identities
+                .lock()
+                .unwrap()
+                .retain(|_, handle| handle.service != *uuid);
+            unsafe {
+                let _: Result<(), ()> =
+                    msg_send![self.peripheral_manager(), removeService: &*mutable_service];
+            }
+        }
+    }
+
+    /// Whether a service CoreBluetooth currently has contains the characteristic. Services are
+    /// only live once CoreBluetooth confirmed adding them and until it's no longer powered on.
+    pub fn has_characteristic(&self, characteristic_uuid: &Uuid) -> bool {
+        let services = self.services.lock().unwrap();
+        let registered = unsafe { handlers(&self.peripheral_manager_delegate) }
+            .registered
+            .lock()
+            .unwrap();
+        services.values().any(|(service, _)| {
+            registered.contains(&service.uuid)
+                && service
+                    .characteristics
+                    .iter()
+                    .any(|characteristic| characteristic.uuid == *characteristic_uuid)
+        })
+    }
+
+    /// The handle of the first characteristic with the UUID, lowest instance first.
+    fn first_handle(&self, characteristic_uuid: &Uuid) -> Option<CharacteristicHandle> {
+        self.services
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|(service, _)| service.handles())
+            .filter(|handle| handle.characteristic == *characteristic_uuid)
+            .min_by_key(|handle| handle.instance)
+    }
+
+    pub async fn notify(
+        &self,
+        characteristic_uuid: &Uuid,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        let handle = self
+            .first_handle(characteristic_uuid)
+            .ok_or(NotifyError::UnknownCharacteristic)?;
+        self.notify_handle(&handle, value).await
+    }
+
+    pub async fn notify_handle(
+        &self,
+        handle: &CharacteristicHandle,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        Span::new("notify", &handle.characteristic, None)
+            .instrument(self.update_subscribers(handle, value))
+            .await
+    }
+
+    /// Tries `updateValue:` once more after CoreBluetooth signalled the transmit queue has room
+    /// again, if it was full.
+    async fn update_subscribers(
+        &self,
+        handle: &CharacteristicHandle,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        if !self.has_characteristic(&handle.characteristic) {
+            return Err(NotifyError::UnknownCharacteristic);
+        }
+        if self.notify_pause.hold(handle, value) {
+            return Ok(());
+        }
+        let handlers = unsafe { handlers(&self.peripheral_manager_delegate) };
+        if handlers
+            .subscribers
+            .lock()
+            .unwrap()
+            .subscribers(handle)
+            .is_empty()
+        {
+            return Err(NotifyError::NoSubscribers);
+        }
+        let value = self
+            .notify_interceptors
+            .intercept(&handle.characteristic, value);
+        let notify_queue = handlers
+            .characteristics
+            .lock()
+            .unwrap()
+            .get(handle)
+            .and_then(|characteristic| characteristic.notify_queue);
+        if let Some(notify_queue) = notify_queue {
+            return self.queue_notification(handle, value, notify_queue).await;
+        }
+        for retry in [false, true] {
+            // Waits for the signal from before trying, so it can't slip through in between
+            let ready_to_update = handlers.ready_to_update.notified();
+            if self
+                .update_value(handle, &value)
+                .ok_or(NotifyError::UnknownCharacteristic)?
+            {
+                handlers.metrics.notification_sent();
+                return Ok(());
+            }
+            handlers.queue_full.lock().unwrap().insert(*handle);
+            if !retry {
+                ready_to_update.await;
+            }
+        }
+        handlers.metrics.notification_dropped();
+        Err(NotifyError::QueueFull)
+    }
+
+    /// Sends the value right away if nothing is buffered for the characteristic and the transmit
+    /// queue takes it, otherwise buffers it behind the others until
+    /// `peripheralManagerIsReadyToUpdateSubscribers` flushes them.
+    async fn queue_notification(
+        &self,
+        handle: &CharacteristicHandle,
+        value: Vec<u8>,
+        notify_queue: NotifyQueue,
+    ) -> Result<(), NotifyError> {
+        let handlers = unsafe { handlers(&self.peripheral_manager_delegate) };
+        handlers
+            .notify_buffers
+            .push(handle, value, notify_queue, &handlers.metrics, |value| {
+                self.update_value(handle, value)
+                    .ok_or(NotifyError::UnknownCharacteristic)
+            })
+            .await
+    }
+
+    pub async fn update_all<F>(
+        &self,
+        characteristic_uuid: &Uuid,
+        value: F,
+    ) -> Result<(), NotifyError>
+    where
+        F: Fn(&CentralId) -> Vec<u8>,
+    {
+        let handle = self
+            .first_handle(characteristic_uuid)
+            .ok_or(NotifyError::UnknownCharacteristic)?;
+        if !self.has_characteristic(characteristic_uuid) {
+            return Err(NotifyError::UnknownCharacteristic);
+        }
+        let handlers = unsafe { handlers(&self.peripheral_manager_delegate) };
+        let subscribers = handlers.subscribers.lock().unwrap().subscribers(&handle);
+        if subscribers.is_empty() {
+            return Err(NotifyError::NoSubscribers);
+        }
+        let mut result = Ok(());
+        for central in subscribers.iter() {
+            let value = self
+                .notify_interceptors
+                .intercept(characteristic_uuid, &value(central));
+            let span = Span::new("notify", characteristic_uuid, Some(central));
+            if let Err(err) = span
+                .instrument(self.update_central(&handle, central, &value))
+                .await
+            {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    /// Tries `updateValue:` for the central alone once more after CoreBluetooth signalled the
+    /// transmit queue has room again, if it was full.
+    async fn update_central(
+        &self,
+        handle: &CharacteristicHandle,
+        central: &CentralId,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        let handlers = unsafe { handlers(&self.peripheral_manager_delegate) };
+        let centrals: Id<NSArray<NSObject, Shared>> = {
+            let subscribers = handlers.subscribers.lock().unwrap();
+            let mut centrals = handlers.centrals.lock().unwrap();
+            // Centrals forgotten or pruned since are released here
+            centrals.retain(|central, _| subscribers.is_subscribed(central));
+            let central = centrals.get(central).ok_or(NotifyError::NoSubscribers)?;
+            NSArray::from_vec(vec![unsafe {
+                Id::from_ptr(&**central as *const Object as *mut NSObject)
+            }])
+        };
+        let centrals = &*centrals as *const NSArray<NSObject, Shared> as *mut Object;
+        for retry in [false, true] {
+            // Waits for the signal from before trying, so it can't slip through in between
+            let ready_to_update = handlers.ready_to_update.notified();
+            if self
+                .update_value_on(handle, value, centrals)
+                .ok_or(NotifyError::UnknownCharacteristic)?
+            {
+                handlers.metrics.notification_sent();
+                return Ok(());
+            }
+            handlers.queue_full.lock().unwrap().insert(*handle);
+            if !retry {
+                ready_to_update.await;
+            }
+        }
+        handlers.metrics.notification_dropped();
+        Err(NotifyError::QueueFull)
+    }
+
+    pub fn max_notification_length(&self, characteristic_uuid: &Uuid) -> usize {
+        self.first_handle(characteristic_uuid)
+            .and_then(|handle| {
+                unsafe { handlers(&self.peripheral_manager_delegate) }
+                    .subscribers
+                    .lock()
+                    .unwrap()
+                    .update_length(&handle)
+            })
+            .unwrap_or(DEFAULT_NOTIFICATION_LENGTH)
+    }
+
+    /// Whether `updateValue:forCharacteristic:onSubscribedCentrals:` queued the value, `None` if
+    /// no added service contains the characteristic.
+    fn update_value(&self, handle: &CharacteristicHandle, value: &[u8]) -> Option<bool> {
+        self.update_value_on(handle, value, nil)
+    }
+
+    /// Like `update_value`, for the centrals of the `NSArray` only, or all subscribers if it's
+    /// nil.
+    fn update_value_on(
+        &self,
+        handle: &CharacteristicHandle,
+        value: &[u8],
+        centrals: *mut Object,
+    ) -> Option<bool> {
+        // The service keeps its characteristics alive, so their addresses stay valid as long as
+        // it's tracked
+        let services = self.services.lock().unwrap();
+        services.get(&handle.service)?;
+        let characteristic = unsafe { handlers(&self.peripheral_manager_delegate) }
+            .identities
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, identity)| *identity == handle)
+            .map(|(address, _)| *address as *mut Object)?;
+        let updated: BOOL = unsafe {
+            msg_send![self.peripheral_manager(), updateValue:NSData::with_bytes(value)
+                                           forCharacteristic:characteristic
+                                        onSubscribedCentrals:centrals]
+        };
+        Some(updated.into_bool())
+    }
+
+    pub fn snapshot(&self) -> PeripheralSnapshot {
+        let handlers = unsafe { handlers(&self.peripheral_manager_delegate) };
+        PeripheralSnapshot {
+            adapter_state: *self.state_receiver.borrow(),
+            advertising_state: *self.advertising_state.borrow(),
+            advertising_options: self.advertising_options.lock().unwrap().clone(),
+            services: self
+                .services
+                .lock()
+                .unwrap()
+                .values()
+                .map(|(service, _)| {
+                    let characteristics = service
+                        .characteristics
+                        .iter()
+                        .map(|characteristic| characteristic.uuid)
+                        .collect();
+                    (service.uuid, characteristics)
+                })
+                .collect(),
+            subscribers: handlers.subscribers.lock().unwrap().counts(),
+            metrics: handlers.metrics.snapshot(),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        Service::describe_all(
+            self.services
+                .lock()
+                .unwrap()
+                .values()
+                .map(|(service, _)| service),
+        )
+    }
+
+    pub fn metrics(&self) -> PeripheralMetrics {
+        unsafe { handlers(&self.peripheral_manager_delegate) }
+            .metrics
+            .snapshot()
+    }
+
+    pub fn subscribers(&self, characteristic_uuid: &Uuid) -> Vec<CentralId> {
+        unsafe { handlers(&self.peripheral_manager_delegate) }
+            .subscribers
+            .lock()
+            .unwrap()
+            .subscribers_by_uuid(characteristic_uuid)
+    }
+
+    pub fn centrals(&self) -> Vec<CentralInfo> {
+        unsafe { handlers(&self.peripheral_manager_delegate) }
+            .subscribers
+            .lock()
+            .unwrap()
+            .centrals()
+    }
+
+    pub fn is_subscribed(&self, central: &CentralId, characteristic_uuid: &Uuid) -> bool {
+        unsafe { handlers(&self.peripheral_manager_delegate) }
+            .subscribers
+            .lock()
+            .unwrap()
+            .is_subscribed_to(central, characteristic_uuid)
+    }
+
+    pub fn forget_central(&self, central: &CentralId) {
+        unsafe { handlers(&self.peripheral_manager_delegate) }
+            .subscribers
+            .lock()
+            .unwrap()
+            .forget(central);
+    }
+
+    pub fn pause_notifications(&self, policy: PausePolicy) {
+        self.notify_pause.pause(policy);
+    }
+
+    pub async fn resume_notifications(&self) -> Result<(), NotifyError> {
+        let mut result = Ok(());
+        for (handle, value) in self.notify_pause.resume() {
+            match self.notify_handle(&handle, &value).await {
+                // The centrals may have unsubscribed while notifications were paused
+                Ok(()) | Err(NotifyError::NoSubscribers) => {}
+                Err(err) => result = result.and(Err(err)),
+            }
+        }
+        result
+    }
+
+    pub fn set_notify_interceptor(&self, interceptor: NotifyInterceptor) {
+        self.notify_interceptors.set(interceptor);
+    }
+
+    pub fn set_default_read_handler(&self, handler: DefaultReadHandler) {
+        unsafe { handlers(&self.peripheral_manager_delegate) }
+            .defaults
+            .set_read(handler);
+    }
+
+    pub fn set_default_write_handler(&self, handler: DefaultWriteHandler) {
+        unsafe { handlers(&self.peripheral_manager_delegate) }
+            .defaults
+            .set_write(handler);
+    }
+
+    pub fn writes(&self, characteristic_uuid: &Uuid) -> mpsc::UnboundedReceiver<WriteRequest> {
+        unsafe { handlers(&self.peripheral_manager_delegate) }
+            .defaults
+            .open_write_stream(*characteristic_uuid)
+    }
+
+    pub fn remove_all_services(&self) {
+        let uuids: Vec<Uuid> = self.services.lock().unwrap().keys().copied().collect();
+        for uuid in uuids.iter() {
+            self.remove_service(uuid);
+        }
+    }
+
+    /// Services whose layout didn't change stay registered with CoreBluetooth, so their
+    /// subscriptions survive; only the handlers behind them are swapped.
+    pub fn replace_services(&self, services: &[Service]) {
+        let unchanged: Vec<Uuid> = self
+            .services
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|(current, _)| {
+                services
+                    .iter()
+                    .any(|service| service.uuid == current.uuid && service.same_layout(current))
+            })
+            .map(|(current, _)| current.uuid)
+            .collect();
+        let gone: Vec<Uuid> = self
+            .services
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|uuid| !unchanged.contains(uuid))
+            .copied()
+            .collect();
+        for uuid in gone.iter() {
+            self.remove_service(uuid);
+        }
+
+        for service in services {
+            if !unchanged.contains(&service.uuid) {
+                self.add_service(service);
+                continue;
+            }
+            unsafe { handlers(&self.peripheral_manager_delegate) }
+                .characteristics
+                .lock()
+                .unwrap()
+                .extend(
+                    service.resolved_characteristics().map(|characteristic| {
+                        (characteristic.handle(service.uuid), characteristic)
+                    }),
+                );
+            if let Some((current, _)) = self.services.lock().unwrap().get_mut(&service.uuid) {
+                *current = service.clone();
+            }
+        }
+    }
+
+    /// Waits until the delegate callbacks already on the dispatch queue ran.
+    /// Aborts the tasks spawned on the runtime, writes without response not yet handed to their
+    /// handler are dropped.
+    pub fn stop_tasks(&self) {
+        self.advertising_monitor.abort();
+        self.command_forwarder.abort();
+        let tasks = [
+            &self.advertising_pauser,
+            &self.watchdog,
+            &self.subscription_pruner,
+            &self.service_restorer,
+        ];
+        for task in tasks.iter().copied().flatten() {
+            task.abort();
+        }
+        if let Some(notification_forwarder) = self.notification_forwarder.lock().unwrap().take() {
+            notification_forwarder.abort();
+        }
+    }
+
+    /// Drops the buffered notifications and wakes everyone waiting for the transmit queue to
+    /// have room, their notifications fail once the services are removed.
+    pub fn drop_notifications(&self) {
+        let handlers = unsafe { handlers(&self.peripheral_manager_delegate) };
+        handlers.notify_buffers.clear();
+        handlers.ready_to_update.notify_waiters();
+    }
+
+    pub async fn flush_queue(&self) {
+        let queue = unsafe {
+            *self
                 .peripheral_manager_delegate
-                .get_ivar::<*mut Object>(PERIPHERAL_MANAGER_IVAR);
+                .get_ivar::<*mut c_void>(QUEUE_IVAR) as dispatch_queue_t
+        };
+        let _ = unsafe { ping_queue(queue) }.await;
+    }
+
+    pub fn effective_properties(&self, characteristic_uuid: &Uuid) -> Option<(u16, u8)> {
+        self.services
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|(service, _)| service.characteristics.iter())
+            .find(|characteristic| characteristic.uuid == *characteristic_uuid)
+            .map(get_properties_and_permissions)
+    }
+
+    pub fn read_source(&self, characteristic_uuid: &Uuid) -> Option<ReadSource> {
+        self.services
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|(service, _)| service.characteristics.iter())
+            .find(|characteristic| characteristic.uuid == *characteristic_uuid)
+            .map(|characteristic| {
+                // A `CBMutableCharacteristic` with a value is served from CoreBluetooth's cache
+                match (&characteristic.value, &characteristic.properties.read) {
+                    (Some(_), _) => ReadSource::Cached,
+                    (None, Some(_)) => ReadSource::Handler,
+                    (None, None) => ReadSource::NotReadable,
+                }
+            })
+    }
+
+    pub fn get_value(&self, characteristic_uuid: &Uuid) -> Option<Vec<u8>> {
+        self.services
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|(service, _)| service.characteristics.iter())
+            .find(|characteristic| characteristic.uuid == *characteristic_uuid)
+            .and_then(|characteristic| characteristic.value.clone())
+    }
+}
+
+/// The name the system advertises when no local name is given: the computer name on macOS and
+/// the device name on iOS.
+pub fn system_default_name() -> Option<String> {
+    unsafe {
+        let name: *mut Object = if cfg!(target_os = "ios") {
+            let device: *mut Object = msg_send![Class::get("UIDevice")?, currentDevice];
+            if !device.into_bool() {
+                return None;
+            }
+            msg_send![device, name]
+        } else {
+            let host: *mut Object = msg_send![class!(NSHost), currentHost];
+            if !host.into_bool() {
+                return None;
+            }
+            msg_send![host, localizedName]
+        };
+        if !name.into_bool() {
+            return None;
+        }
+        Some((*(name as *mut NSString)).as_str().to_owned())
+    }
+}
+
+impl Drop for PeripheralManager {
+    fn drop(&mut self) {
+        self.stop_tasks();
+    }
+}
+
+/// Only holds on to the manager while sending, so the forwarding doesn't keep it alive.
+async fn forward_notifications(
+    peripheral_manager: Weak<PeripheralManager>,
+    subscriptions: Subscriptions,
+) {
+    subscriptions
+        .for_each_concurrent(None, |(handle, notifications)| {
+            let peripheral_manager = peripheral_manager.clone();
+            notifications.for_each(move |value| {
+                let peripheral_manager = peripheral_manager.upgrade();
+                async move {
+                    if let Some(peripheral_manager) = peripheral_manager {
+                        if let Err(error) = peripheral_manager.notify_handle(&handle, &value).await
+                        {
+                            debug!(
+                                "Notification of {} not sent: {}",
+                                handle.characteristic, error
+                            );
+                        }
+                    }
+                }
+            })
+        })
+        .await;
+}
+
+/// Checks every `timeout` for centrals that haven't been seen for `timeout`, so a subscription
+/// outlives its central by less than twice the timeout.
+async fn prune_subscribers(subscribers: Arc<Mutex<Subscribers>>, timeout: Duration) {
+    loop {
+        tokio::time::sleep(timeout).await;
+        subscribers.lock().unwrap().prune(timeout);
+    }
+}
+
+/// The advertisement data dictionary `startAdvertising:` takes.
+fn advertising_data(options: &AdvertisingOptions) -> Id<NSDictionary<NSString, NSObject>> {
+    let mut keys: Vec<&NSString> = vec![];
+    let mut objects: Vec<Id<NSObject>> = vec![];
+
+    unsafe {
+        // Without a local name CoreBluetooth advertises the device's name
+        if let Some(local_name) = &options.local_name {
+            keys.push(&*(CBAdvertisementDataLocalNameKey as *mut NSString));
+            objects.push(Id::from_retained_ptr(msg_send![
+                NSString::from_str(local_name),
+                copy
+            ]));
+        }
+        keys.push(&*(CBAdvertisementDataServiceUUIDsKey as *mut NSString));
+        objects.push(Id::from_retained_ptr(msg_send![
+            NSArray::from_vec(
+                options
+                    .service_uuids
+                    .iter()
+                    .map(|u| Id::from_retained_ptr(u.into_cbuuid() as *mut NSObject))
+                    .collect::<Vec<Id<NSObject>>>()
+            ),
+            copy
+        ]));
+    }
+
+    NSDictionary::from_keys_and_objects(keys.as_slice(), objects)
+}
 
-            let _: Result<(), ()> = msg_send![peripheral_manager, addService: service];
+/// A retained `CBPeripheralManager` for the tasks that poll or drive it from the runtime.
+struct RetainedManager(*mut Object);
+
+// `isAdvertising` may be read and `addService:` or the advertising methods called from any thread
+unsafe impl Send for RetainedManager {}
+
+impl RetainedManager {
+    unsafe fn new(peripheral_manager: *mut Object) -> Self {
+        let _: *mut Object = msg_send![peripheral_manager, retain];
+        RetainedManager(peripheral_manager)
+    }
+
+    fn is_advertising(&self) -> bool {
+        let is_advertising: *mut Object = unsafe { msg_send![self.0, isAdvertising] };
+        is_advertising.into_bool()
+    }
+
+    fn add_service(&self, mutable_service: &Object) {
+        unsafe {
+            let _: Result<(), ()> = msg_send![self.0, addService: mutable_service];
+        }
+    }
+
+    fn start_advertising(&self, options: &AdvertisingOptions) {
+        let advertising_data = advertising_data(options);
+        unsafe {
+            let _: Result<(), ()> = msg_send![self.0, startAdvertising: advertising_data];
+        }
+    }
+
+    fn stop_advertising(&self) {
+        unsafe {
+            let _: Result<(), ()> = msg_send![self.0, stopAdvertising];
         }
     }
 }
 
-impl Default for PeripheralManager {
-    fn default() -> Self {
-        PeripheralManager::new()
+impl Drop for RetainedManager {
+    fn drop(&mut self) {
+        unsafe {
+            let _: () = msg_send![self.0, release];
+        }
     }
 }
 
-extern "C" fn init(delegate: &mut Object, _cmd: Sel) -> *mut Object {
+/// CoreBluetooth doesn't tell when the system stops advertising, so `isAdvertising` is polled
+/// regularly and whenever the adapter state changes. Advertising that stops while the generation
+/// is still the one it was seen running in wasn't stopped through the peripheral, which is
+/// reported as interrupted until advertising is started or stopped again.
+async fn watch_advertising(
+    peripheral_manager: RetainedManager,
+    advertising_generation: Arc<AtomicUsize>,
+    advertising_timed_out: Arc<AtomicUsize>,
+    mut adapter_state: watch::Receiver<AdapterState>,
+    advertising_state: watch::Sender<AdvertisingState>,
+) {
+    let mut running_in = None;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(ADVERTISING_CHECK_INTERVAL) => {}
+            changed = adapter_state.changed() => if changed.is_err() {
+                return;
+            }
+        }
+        let generation = advertising_generation.load(Ordering::SeqCst);
+        let state = if peripheral_manager.is_advertising() {
+            running_in = Some(generation);
+            AdvertisingState::Advertising
+        } else if running_in == Some(generation) {
+            AdvertisingState::Interrupted
+        } else if advertising_timed_out.load(Ordering::SeqCst) == generation {
+            AdvertisingState::TimedOut
+        } else {
+            AdvertisingState::Stopped
+        };
+        if *advertising_state.borrow() != state {
+            if state == AdvertisingState::Interrupted {
+                warn!("CoreBluetooth stopped advertising");
+            }
+            advertising_state.send_replace(state);
+        }
+    }
+}
+
+struct SharedServices(Arc<Mutex<Services>>);
+
+// The `CBMutableService`s are only retained, released and handed to `addService:`, which is
+// fine from any thread
+unsafe impl Send for SharedServices {}
+
+/// CoreBluetooth drops every added service once it's no longer powered on. They are added again
+/// when it's powered on again, `didAddService` marks them as live once that went through.
+async fn restore_services(
+    peripheral_manager: RetainedManager,
+    services: SharedServices,
+    mut adapter_state: watch::Receiver<AdapterState>,
+) {
+    let mut dropped = false;
+    while adapter_state.changed().await.is_ok() {
+        if !adapter_state.borrow().is_powered_on() {
+            dropped = true;
+        } else if dropped {
+            dropped = false;
+            // Included services go first, as they have to be added before the ones including
+            // them
+            let services = services.0.lock().unwrap();
+            let mut added = HashSet::new();
+            while added.len() < services.len() {
+                let ready = services
+                    .values()
+                    .filter(|(service, _)| {
+                        !added.contains(&service.uuid)
+                            && service.included_services.iter().all(|included| {
+                                added.contains(included) || !services.contains_key(included)
+                            })
+                    })
+                    .collect::<Vec<_>>();
+                if ready.is_empty() {
+                    break;
+                }
+                for (service, mutable_service) in ready {
+                    peripheral_manager.add_service(mutable_service);
+                    added.insert(service.uuid);
+                }
+            }
+        }
+    }
+}
+
+/// Stops advertising while any central is subscribed and starts it again with the options it
+/// was started with once none is. It's only started again if nothing started or stopped
+/// advertising in between, like the scheduled stop.
+async fn pause_advertising(
+    peripheral_manager: RetainedManager,
+    advertising_generation: Arc<AtomicUsize>,
+    advertising_options: Arc<Mutex<Option<AdvertisingOptions>>>,
+    mut centrals: watch::Receiver<usize>,
+) {
+    let mut paused_in = None;
+    while centrals.changed().await.is_ok() {
+        let connected = *centrals.borrow_and_update() > 0;
+        if connected && paused_in.is_none() && peripheral_manager.is_advertising() {
+            paused_in = Some(advertising_generation.fetch_add(1, Ordering::SeqCst) + 1);
+            peripheral_manager.stop_advertising();
+        } else if !connected {
+            let options = advertising_options.lock().unwrap().clone();
+            if let (Some(paused_in), Some(options)) = (paused_in.take(), options) {
+                if advertising_generation
+                    .compare_exchange(paused_in, paused_in + 1, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    peripheral_manager.start_advertising(&options);
+                }
+            }
+        }
+    }
+}
+
+struct Queue(dispatch_queue_t);
+
+// Blocks may be dispatched onto a queue from any thread
+unsafe impl Send for Queue {}
+
+/// Dispatches a no-op every `threshold` and reports the queue as stalled while one doesn't run
+/// within `threshold`.
+async fn watch_queue(queue: Queue, threshold: Duration, queue_health: watch::Sender<QueueHealth>) {
+    loop {
+        tokio::time::sleep(threshold).await;
+        let dispatched = Instant::now();
+        let mut receiver = unsafe { ping_queue(queue.0) };
+        if tokio::time::timeout(threshold, &mut receiver)
+            .await
+            .is_err()
+        {
+            warn!(
+                "CoreBluetooth dispatch queue stalled for over {:?}",
+                threshold
+            );
+            queue_health.send_replace(QueueHealth::Stalled);
+            let _ = receiver.await;
+            warn!(
+                "CoreBluetooth dispatch queue recovered after {:?}",
+                dispatched.elapsed()
+            );
+            queue_health.send_replace(QueueHealth::Responsive);
+        }
+    }
+}
+
+/// Resolves once the queue ran everything dispatched onto it before.
+unsafe fn ping_queue(queue: dispatch_queue_t) -> oneshot::Receiver<()> {
+    let (sender, receiver) = oneshot::channel::<()>();
+    dispatch_async_f(
+        queue,
+        Box::into_raw(Box::new(sender)) as *mut c_void,
+        queue_ping,
+    );
+    receiver
+}
+
+extern "C" fn queue_ping(context: *mut c_void) {
+    let sender = unsafe { Box::from_raw(context as *mut oneshot::Sender<()>) };
+    let _ = sender.send(());
+}
+
+struct ScheduledStop {
+    peripheral_manager: *mut Object,
+    advertising_generation: Arc<AtomicUsize>,
+    advertising_timed_out: Arc<AtomicUsize>,
+    scheduled: usize,
+}
+
+extern "C" fn stop_advertising_after(context: *mut c_void) {
+    unsafe {
+        let scheduled_stop = Box::from_raw(context as *mut ScheduledStop);
+        // Counts as stopping, so the advertising monitor doesn't take it for an interruption
+        if scheduled_stop
+            .advertising_generation
+            .compare_exchange(
+                scheduled_stop.scheduled,
+                scheduled_stop.scheduled + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_ok()
+        {
+            scheduled_stop
+                .advertising_timed_out
+                .store(scheduled_stop.scheduled + 1, Ordering::SeqCst);
+            let _: Result<(), ()> = msg_send![scheduled_stop.peripheral_manager, stopAdvertising];
+        }
+        let _: () = msg_send![scheduled_stop.peripheral_manager, release];
+    }
+}
+
+fn qos_class(quality_of_service: QualityOfService) -> Option<qos_class_t> {
+    match quality_of_service {
+        QualityOfService::UserInteractive => Some(QOS_CLASS_USER_INTERACTIVE),
+        QualityOfService::UserInitiated => Some(QOS_CLASS_USER_INITIATED),
+        QualityOfService::Default => Some(QOS_CLASS_DEFAULT),
+        QualityOfService::Utility => Some(QOS_CLASS_UTILITY),
+        QualityOfService::Background => Some(QOS_CLASS_BACKGROUND),
+        QualityOfService::Unspecified => None,
+    }
+}
+
+#[cfg(target_os = "ios")]
+unsafe fn restore_options(
+    builder: &PeripheralBuilder,
+) -> Option<Id<NSDictionary<NSString, NSObject>>> {
+    use super::ffi::CBPeripheralManagerOptionRestoreIdentifierKey;
+
+    builder
+        .restore_identifier
+        .as_ref()
+        .map(|restore_identifier| {
+            let key = &*(CBPeripheralManagerOptionRestoreIdentifierKey as *mut NSString);
+            let object: Id<NSObject> =
+                Id::from_retained_ptr(msg_send![NSString::from_str(restore_identifier), copy]);
+            NSDictionary::from_keys_and_objects(&[key], vec![object])
+        })
+}
+
+/// `Peripheral::from_builder` already refused restore identifiers on other platforms.
+#[cfg(not(target_os = "ios"))]
+unsafe fn restore_options(
+    _builder: &PeripheralBuilder,
+) -> Option<Id<NSDictionary<NSString, NSObject>>> {
+    None
+}
+
+extern "C" fn init_with_builder(
+    delegate: &mut Object,
+    _cmd: Sel,
+    builder: *const c_void,
+) -> *mut Object {
     unsafe {
+        let builder = &*(builder as *const PeripheralBuilder);
         let cls = class!(CBPeripheralManager);
         let mut obj: *mut Object = msg_send![cls, alloc];
 
         #[allow(clippy::cast_ptr_alignment)]
         let init_with_delegate = delegate as *mut Object as *mut *mut Object;
 
-        let label = CString::new("CBqueue").unwrap();
-        let queue = dispatch_queue_create(label.as_ptr(), DISPATCH_QUEUE_SERIAL);
+        // A label with a nul byte can't be passed on, the queue stays unlabeled then
+        let label = CString::new(builder.queue_label.as_str()).unwrap_or_default();
+        let attr = match qos_class(builder.quality_of_service) {
+            Some(qos_class) => {
+                dispatch_queue_attr_make_with_qos_class(DISPATCH_QUEUE_SERIAL, qos_class, 0)
+            }
+            None => DISPATCH_QUEUE_SERIAL,
+        };
+        let queue = dispatch_queue_create(label.as_ptr(), attr);
 
-        obj = msg_send![obj, initWithDelegate:init_with_delegate
-                                        queue:queue];
+        obj = match restore_options(builder) {
+            Some(options) => msg_send![obj, initWithDelegate:init_with_delegate
+                                                       queue:queue
+                                                     options:&*options],
+            None => msg_send![obj, initWithDelegate:init_with_delegate
+                                              queue:queue],
+        };
+        // The ivars stay null, which `dealloc` skips
+        if !obj.into_bool() {
+            dispatch_release(queue);
+            let _: () = msg_send![delegate, release];
+            return nil;
+        }
         delegate.set_ivar::<*mut Object>(PERIPHERAL_MANAGER_IVAR, obj);
+        delegate.set_ivar::<*mut c_void>(QUEUE_IVAR, queue as *mut c_void);
 
         delegate.set_ivar::<BOOL>(POWERED_ON_IVAR, NO);
 
+        let (state_sender, _) = watch::channel(AdapterState::Unknown);
+        delegate.set_ivar::<*mut c_void>(
+            STATE_SENDER_IVAR,
+            Box::into_raw(Box::new(state_sender)) as *mut c_void,
+        );
+
         delegate
     }
 }
+
+extern "C" fn dealloc(delegate: &mut Object, _cmd: Sel) {
+    unsafe {
+        let state_sender = *delegate.get_ivar::<*mut c_void>(STATE_SENDER_IVAR);
+        if !state_sender.is_null() {
+            drop(Box::from_raw(
+                state_sender as *mut watch::Sender<AdapterState>,
+            ));
+        }
+        let handlers = *delegate.get_ivar::<*mut c_void>(HANDLERS_IVAR);
+        if !handlers.is_null() {
+            drop(Box::from_raw(handlers as *mut Handlers));
+        }
+
+        let _: () = msg_send![super(delegate, class!(NSObject)), dealloc];
+    }
+}
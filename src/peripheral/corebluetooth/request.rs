@@ -0,0 +1,453 @@
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+    stream::BoxStream,
+};
+use objc::{
+    msg_send,
+    runtime::{Object, BOOL},
+    sel, sel_impl,
+};
+use objc_foundation::{INSArray, INSData, INSString, NSArray, NSData, NSObject, NSString};
+use objc_id::{Id, Shared};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+use tokio::{runtime::Handle, sync::Notify};
+use uuid::Uuid;
+
+use super::{
+    ffi::{nil, CBATTError},
+    into_bool::IntoBool,
+    subscribers::Subscribers,
+};
+use crate::{
+    gatt::{
+        characteristic::{Characteristic, CharacteristicHandle},
+        default_handler::DefaultHandlers,
+        event::{
+            AttError, CommandSender, Event, EventSender, NotifyQueueDrained, NotifySubscribe,
+            Response,
+        },
+        notify_buffers::NotifyBuffers,
+    },
+    metrics::Metrics,
+    Central, CentralId, Error, SdpShortUuid,
+};
+
+/// Told whether an operation CoreBluetooth confirms in a delegate callback went through.
+pub type Waiter = oneshot::Sender<Result<(), Error>>;
+
+/// Everything the delegate needs to hand requests over to the characteristic handlers without
+/// blocking the CoreBluetooth dispatch queue. Like the state sender it's owned by the delegate.
+#[derive(Debug)]
+pub struct Handlers {
+    pub runtime: Handle,
+    pub characteristics: Mutex<HashMap<CharacteristicHandle, Characteristic>>,
+    /// The handle of every added `CBMutableCharacteristic` by its address, as the UUID alone
+    /// doesn't tell apart characteristics sharing it.
+    pub identities: Mutex<HashMap<usize, CharacteristicHandle>>,
+    /// Writes without response are forwarded by a single task so they keep their order.
+    pub commands: CommandSender,
+    /// The notifications handlers send on the channel of a `NotifySubscribe`, sent like the
+    /// ones of `Peripheral::notify` by the peripheral manager.
+    pub subscriptions: mpsc::UnboundedSender<(CharacteristicHandle, BoxStream<'static, Vec<u8>>)>,
+    pub defaults: DefaultHandlers,
+    pub metrics: Arc<Metrics>,
+    pub subscribers: Arc<Mutex<Subscribers>>,
+    /// Everyone waiting for `peripheralManagerDidStartAdvertising:error:` to tell whether
+    /// advertising started.
+    pub advertising_waiters: Mutex<Vec<Waiter>>,
+    /// Everyone waiting for `peripheralManager:didAddService:error:` to tell whether the service
+    /// with the UUID was added.
+    pub add_service_waiters: Mutex<HashMap<Uuid, Vec<Waiter>>>,
+    /// The services CoreBluetooth confirmed adding. It drops all of them once it's no longer
+    /// powered on, while they stay tracked so they can be added again.
+    pub registered: Mutex<HashSet<Uuid>>,
+    /// Woken by `peripheralManagerIsReadyToUpdateSubscribers:` once the transmit queue has room
+    /// again.
+    pub ready_to_update: Notify,
+    /// The characteristics with a notification that didn't fit into the transmit queue, their
+    /// handlers are told once it has room again.
+    pub queue_full: Mutex<HashSet<CharacteristicHandle>>,
+    pub notify_buffers: NotifyBuffers,
+    /// The `CBCentral` of every subscribed central, to address a notification to it alone.
+    pub centrals: Mutex<HashMap<CentralId, Id<Object, Shared>>>,
+}
+
+impl Handlers {
+    /// Sends the buffered notifications in order until the transmit queue is full again. The
+    /// ones of characteristics no longer added are dropped.
+    pub unsafe fn flush_notify_queues(&self, peripheral: *mut Object) {
+        self.notify_buffers.flush(&self.metrics, |handle, value| {
+            let characteristic = self
+                .identities
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(_, identity)| *identity == handle)
+                .map(|(address, _)| *address as *mut Object)?;
+            let updated: BOOL = msg_send![peripheral, updateValue:NSData::with_bytes(value)
+                                                 forCharacteristic:characteristic
+                                              onSubscribedCentrals:nil];
+            Some(updated.into_bool())
+        });
+    }
+
+    pub fn advertising_started(&self, result: Result<(), Error>) {
+        for waiter in self.advertising_waiters.lock().unwrap().drain(..) {
+            waiter.send(result.clone()).ok();
+        }
+    }
+
+    pub fn service_added(&self, uuid: &Uuid, result: Result<(), Error>) {
+        let waiters = self.add_service_waiters.lock().unwrap().remove(uuid);
+        for waiter in waiters.into_iter().flatten() {
+            waiter.send(result.clone()).ok();
+        }
+    }
+
+    /// Fails everyone waiting for a service to be added.
+    pub fn services_not_added(&self, error: Error) {
+        for (_, waiters) in self.add_service_waiters.lock().unwrap().drain() {
+            for waiter in waiters {
+                waiter.send(Err(error.clone())).ok();
+            }
+        }
+    }
+
+    /// Hands the `notify` or `indicate` handler of the characteristic a channel for its
+    /// notifications, once the first central subscribed to it.
+    pub fn subscribed(&self, handle: CharacteristicHandle) {
+        let handler =
+            self.characteristics
+                .lock()
+                .unwrap()
+                .get(&handle)
+                .and_then(|characteristic| {
+                    Some((notify_sender(characteristic)?, characteristic.notify_policy))
+                });
+        let (mut event_sender, notify_policy) = match handler {
+            Some(handler) => handler,
+            None => return,
+        };
+        let (notification, receiver) = mpsc::channel(1);
+        let notifications = match notify_policy {
+            Some(notify_policy) => notify_policy.apply(receiver).boxed(),
+            None => receiver.boxed(),
+        };
+        if self
+            .subscriptions
+            .unbounded_send((handle, notifications))
+            .is_err()
+        {
+            return;
+        }
+        self.runtime.spawn(async move {
+            event_sender
+                .send(Event::NotifySubscribe(NotifySubscribe { notification }))
+                .await
+                .ok();
+        });
+    }
+
+    /// Tells the handler of the characteristic once the last central unsubscribed from it.
+    pub fn unsubscribed(&self, handle: CharacteristicHandle) {
+        let event_sender = self
+            .characteristics
+            .lock()
+            .unwrap()
+            .get(&handle)
+            .and_then(notify_sender);
+        if let Some(mut event_sender) = event_sender {
+            self.runtime.spawn(async move {
+                event_sender.send(Event::NotifyUnsubscribe).await.ok();
+            });
+        }
+    }
+
+    /// Wakes `notify` and tells the handlers of the characteristics that found the transmit queue
+    /// full that it has room again.
+    pub fn queue_drained(&self) {
+        self.ready_to_update.notify_waiters();
+        let drained = std::mem::take(&mut *self.queue_full.lock().unwrap());
+        let characteristics = self.characteristics.lock().unwrap();
+        for handle in drained {
+            let event_sender = characteristics.get(&handle).and_then(notify_sender);
+            if let Some(mut event_sender) = event_sender {
+                let drained = NotifyQueueDrained {
+                    characteristic: handle.characteristic,
+                };
+                self.runtime.spawn(async move {
+                    event_sender
+                        .send(Event::NotifyQueueDrained(drained))
+                        .await
+                        .ok();
+                });
+            }
+        }
+    }
+
+    /// The handle of a `CBCharacteristic` CoreBluetooth handed to the delegate. Should it ever
+    /// hand over a different object than the one that was added, the first characteristic with
+    /// its UUID is taken.
+    pub fn handle(&self, characteristic: *mut Object) -> Option<CharacteristicHandle> {
+        if let Some(handle) = self
+            .identities
+            .lock()
+            .unwrap()
+            .get(&(characteristic as usize))
+        {
+            return Some(*handle);
+        }
+        let uuid = unsafe { attribute_uuid(characteristic) }?;
+        self.characteristics
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|handle| handle.characteristic == uuid)
+            .min_by_key(|handle| handle.instance)
+            .copied()
+    }
+}
+
+fn notify_sender(characteristic: &Characteristic) -> Option<EventSender> {
+    let properties = &characteristic.properties;
+    properties
+        .notify
+        .clone()
+        .or_else(|| properties.indicate.clone())
+}
+
+/// A retained `CBATTRequest` and the `CBPeripheralManager` it has to be answered on. It can be
+/// moved to whichever task ends up with the handler's response, so the delegate callback can
+/// return right away.
+#[derive(Debug)]
+pub struct PendingRequest {
+    peripheral: *mut Object,
+    request: *mut Object,
+}
+
+// `respondToRequest:withResult:` may be called from any thread
+unsafe impl Send for PendingRequest {}
+
+impl PendingRequest {
+    pub unsafe fn new(peripheral: *mut Object, request: *mut Object) -> Self {
+        let _: *mut Object = msg_send![peripheral, retain];
+        let _: *mut Object = msg_send![request, retain];
+        PendingRequest {
+            peripheral,
+            request,
+        }
+    }
+
+    pub fn characteristic(&self) -> *mut Object {
+        unsafe { msg_send![self.request, characteristic] }
+    }
+
+    pub fn characteristic_uuid(&self) -> Option<Uuid> {
+        unsafe { attribute_uuid(self.characteristic()) }
+    }
+
+    pub fn offset(&self) -> u16 {
+        let offset: usize = unsafe { msg_send![self.request, offset] };
+        offset as u16
+    }
+
+    pub fn value(&self) -> Vec<u8> {
+        unsafe {
+            let value: *mut Object = msg_send![self.request, value];
+            if !value.into_bool() {
+                return vec![];
+            }
+            (*(value as *mut NSData)).bytes().to_vec()
+        }
+    }
+
+    pub fn central(&self) -> Option<Central> {
+        unsafe { central(msg_send![self.request, central]) }
+    }
+
+    /// CoreBluetooth only tells how much fits into a notification, which is the ATT MTU minus
+    /// the 3 byte header.
+    pub fn mtu(&self) -> u16 {
+        unsafe { maximum_update_value_length(msg_send![self.request, central]) }
+            .map_or(23, |length| length as u16 + 3)
+    }
+
+    pub fn respond(self, result: CBATTError) {
+        unsafe {
+            let _: Result<(), ()> = msg_send![self.peripheral, respondToRequest:self.request
+                                                                withResult:result];
+        }
+    }
+
+    /// An error is answered without setting a value, so the central sees the read fail instead of
+    /// an empty value.
+    pub fn respond_to_read(self, response: Response) {
+        match response.into_result() {
+            Ok(value) => {
+                unsafe {
+                    let _: () = msg_send![self.request, setValue: NSData::with_bytes(&value)];
+                }
+                self.respond(CBATTError::CBATTErrorSuccess)
+            }
+            Err(error) => self.respond(error.into()),
+        }
+    }
+}
+
+// CoreBluetooth isn't supposed to hand nil to the delegate, but these callbacks mustn't crash the
+// process if it ever does.
+
+/// The objects of an `NSArray`, empty for nil.
+pub unsafe fn objects(array: *mut Object) -> Vec<*mut Object> {
+    if !array.into_bool() {
+        return vec![];
+    }
+    (*(array as *mut NSArray<NSObject>))
+        .to_vec()
+        .into_iter()
+        .map(|object| object as *const NSObject as *mut Object)
+        .collect()
+}
+
+/// The UUID of a `CBCharacteristic`, `CBService` or any other `CBAttribute`.
+pub unsafe fn attribute_uuid(attribute: *mut Object) -> Option<Uuid> {
+    if !attribute.into_bool() {
+        return None;
+    }
+    let uuid: *mut Object = msg_send![attribute, UUID];
+    if !uuid.into_bool() {
+        return None;
+    }
+    let uuid = string(msg_send![uuid, UUIDString])?;
+    // SIG assigned UUIDs are given in their short form
+    match uuid.len() {
+        4 => u16::from_str_radix(uuid, 16)
+            .ok()
+            .map(Uuid::from_sdp_short_uuid),
+        8 => u32::from_str_radix(uuid, 16)
+            .ok()
+            .map(Uuid::from_sdp_short_uuid),
+        _ => Uuid::parse_str(uuid).ok(),
+    }
+}
+
+/// The `identifier` of a `CBCentral`.
+pub unsafe fn central_id(central: *mut Object) -> Option<CentralId> {
+    if !central.into_bool() {
+        return None;
+    }
+    let identifier: *mut Object = msg_send![central, identifier];
+    if !identifier.into_bool() {
+        return None;
+    }
+    string(msg_send![identifier, UUIDString]).map(CentralId::new)
+}
+
+/// The values of a `CBCentral` the handlers are given.
+pub unsafe fn central(central: *mut Object) -> Option<Central> {
+    Some(Central::new(
+        central_id(central)?,
+        maximum_update_value_length(central)?,
+    ))
+}
+
+/// The `maximumUpdateValueLength` of a `CBCentral`, how many bytes fit into one notification.
+pub unsafe fn maximum_update_value_length(central: *mut Object) -> Option<usize> {
+    if !central.into_bool() {
+        return None;
+    }
+    Some(msg_send![central, maximumUpdateValueLength])
+}
+
+unsafe fn string<'a>(string: *mut Object) -> Option<&'a str> {
+    if !string.into_bool() {
+        return None;
+    }
+    Some((*(string as *mut NSString)).as_str())
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        unsafe {
+            let _: () = msg_send![self.request, release];
+            let _: () = msg_send![self.peripheral, release];
+        }
+    }
+}
+
+impl From<Response> for CBATTError {
+    fn from(response: Response) -> Self {
+        match response.into_result() {
+            Ok(_) => CBATTError::CBATTErrorSuccess,
+            Err(error) => error.into(),
+        }
+    }
+}
+
+impl From<AttError> for CBATTError {
+    fn from(error: AttError) -> Self {
+        match error {
+            AttError::InvalidHandle => CBATTError::CBATTErrorInvalidHandle,
+            AttError::ReadNotPermitted => CBATTError::CBATTErrorReadNotPermitted,
+            AttError::WriteNotPermitted => CBATTError::CBATTErrorWriteNotPermitted,
+            AttError::InvalidPdu => CBATTError::CBATTErrorInvalidPdu,
+            AttError::InsufficientAuthentication => {
+                CBATTError::CBATTErrorInsufficientAuthentication
+            }
+            AttError::RequestNotSupported => CBATTError::CBATTErrorRequestNotSupported,
+            AttError::InvalidOffset => CBATTError::CBATTErrorInvalidOffset,
+            AttError::InsufficientAuthorization => CBATTError::CBATTErrorInsufficientAuthorization,
+            AttError::PrepareQueueFull => CBATTError::CBATTErrorPrepareQueueFull,
+            AttError::AttributeNotFound => CBATTError::CBATTErrorAttributeNotFound,
+            AttError::AttributeNotLong => CBATTError::CBATTErrorAttributeNotLong,
+            AttError::InsufficientEncryptionKeySize => {
+                CBATTError::CBATTErrorInsufficientEncryptionKeySize
+            }
+            AttError::InvalidAttributeValueLength => {
+                CBATTError::CBATTErrorInvalidAttributeValueLength
+            }
+            AttError::UnlikelyError => CBATTError::CBATTErrorUnlikelyError,
+            AttError::InsufficientEncryption => CBATTError::CBATTErrorInsufficientEncryption,
+            AttError::UnsupportedGroupType => CBATTError::CBATTErrorUnsupportedGroupType,
+            AttError::InsufficientResources => CBATTError::CBATTErrorInsufficientResources,
+        }
+    }
+}
+
+// The mapping is private to the CoreBluetooth backend, so it can't be tested from `tests/`.
+#[cfg(test)]
+mod tests {
+    use super::CBATTError;
+    use crate::gatt::event::AttError;
+
+    const ATT_ERRORS: [AttError; 17] = [
+        AttError::InvalidHandle,
+        AttError::ReadNotPermitted,
+        AttError::WriteNotPermitted,
+        AttError::InvalidPdu,
+        AttError::InsufficientAuthentication,
+        AttError::RequestNotSupported,
+        AttError::InvalidOffset,
+        AttError::InsufficientAuthorization,
+        AttError::PrepareQueueFull,
+        AttError::AttributeNotFound,
+        AttError::AttributeNotLong,
+        AttError::InsufficientEncryptionKeySize,
+        AttError::InvalidAttributeValueLength,
+        AttError::UnlikelyError,
+        AttError::InsufficientEncryption,
+        AttError::UnsupportedGroupType,
+        AttError::InsufficientResources,
+    ];
+
+    #[test]
+    fn att_errors_map_to_their_result_codes() {
+        for &error in ATT_ERRORS.iter() {
+            assert_eq!(CBATTError::from(error) as u8, error.code(), "{:?}", error);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::{gatt::characteristic::CharacteristicHandle, CentralId, CentralInfo};
+
+/// Which centrals are subscribed to which characteristic and when each of them was last heard
+/// from. Characteristics sharing a UUID are told apart by their handle. CoreBluetooth doesn't tell the peripheral when a central disconnects, so subscriptions
+/// are only ever ended by the central unsubscribing, `forget` or `prune`.
+#[derive(Debug)]
+pub struct Subscribers {
+    characteristics: HashMap<CharacteristicHandle, HashSet<CentralId>>,
+    last_seen: HashMap<CentralId, Instant>,
+    /// How many bytes fit into one notification to each subscribed central.
+    update_lengths: HashMap<CentralId, usize>,
+    /// How many centrals are subscribed to anything, the closest CoreBluetooth gets to telling
+    /// how many are connected.
+    centrals: watch::Sender<usize>,
+}
+
+impl Default for Subscribers {
+    fn default() -> Self {
+        Subscribers {
+            characteristics: HashMap::new(),
+            last_seen: HashMap::new(),
+            update_lengths: HashMap::new(),
+            centrals: watch::channel(0).0,
+        }
+    }
+}
+
+impl Subscribers {
+    /// Whether `central` is the first one subscribed to the characteristic.
+    pub fn subscribe(
+        &mut self,
+        characteristic: CharacteristicHandle,
+        central: CentralId,
+        update_length: usize,
+    ) -> bool {
+        self.last_seen.insert(central.clone(), Instant::now());
+        self.update_lengths.insert(central.clone(), update_length);
+        let centrals = self.characteristics.entry(characteristic).or_default();
+        let first = centrals.is_empty();
+        centrals.insert(central);
+        self.centrals.send_replace(self.last_seen.len());
+        first
+    }
+
+    /// Whether `central` was the last one subscribed to the characteristic.
+    pub fn unsubscribe(
+        &mut self,
+        characteristic: &CharacteristicHandle,
+        central: &CentralId,
+    ) -> bool {
+        let mut last = false;
+        if let Some(centrals) = self.characteristics.get_mut(characteristic) {
+            last = centrals.remove(central) && centrals.is_empty();
+            if centrals.is_empty() {
+                self.characteristics.remove(characteristic);
+            }
+        }
+        if !self.is_subscribed(central) {
+            self.last_seen.remove(central);
+            self.update_lengths.remove(central);
+        }
+        self.centrals.send_replace(self.last_seen.len());
+        last
+    }
+
+    /// Notes a request from `central`, which keeps its subscriptions from being pruned.
+    pub fn seen(&mut self, central: &CentralId) {
+        if let Some(last_seen) = self.last_seen.get_mut(central) {
+            *last_seen = Instant::now();
+        }
+    }
+
+    pub fn subscribers(&self, characteristic: &CharacteristicHandle) -> Vec<CentralId> {
+        self.characteristics
+            .get(characteristic)
+            .map(|centrals| centrals.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The centrals subscribed to any of the characteristics with the UUID.
+    pub fn subscribers_by_uuid(&self, characteristic: &Uuid) -> Vec<CentralId> {
+        self.with_uuid(characteristic)
+            .flatten()
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    pub fn is_subscribed_to(&self, central: &CentralId, characteristic: &Uuid) -> bool {
+        self.with_uuid(characteristic)
+            .any(|centrals| centrals.contains(central))
+    }
+
+    fn with_uuid<'a>(
+        &'a self,
+        characteristic: &'a Uuid,
+    ) -> impl Iterator<Item = &'a HashSet<CentralId>> {
+        self.characteristics
+            .iter()
+            .filter(move |(handle, _)| handle.characteristic == *characteristic)
+            .map(|(_, centrals)| centrals)
+    }
+
+    /// The most bytes one notification of the characteristic can carry to every subscriber, `None`
+    /// without any.
+    pub fn update_length(&self, characteristic: &CharacteristicHandle) -> Option<usize> {
+        self.characteristics
+            .get(characteristic)?
+            .iter()
+            .filter_map(|central| self.update_lengths.get(central))
+            .min()
+            .copied()
+    }
+
+    pub fn centrals(&self) -> Vec<CentralInfo> {
+        self.update_lengths
+            .iter()
+            .map(|(central, &maximum_update_value_length)| CentralInfo {
+                id: central.clone(),
+                maximum_update_value_length,
+                subscriptions: self
+                    .characteristics
+                    .iter()
+                    .filter(|(_, centrals)| centrals.contains(central))
+                    .map(|(handle, _)| handle.characteristic)
+                    .collect(),
+            })
+            .collect()
+    }
+
+    pub fn central_count_receiver(&self) -> watch::Receiver<usize> {
+        self.centrals.subscribe()
+    }
+
+    /// How many centrals are subscribed to each characteristic UUID that has any.
+    pub fn counts(&self) -> HashMap<Uuid, usize> {
+        self.characteristics
+            .keys()
+            .map(|handle| {
+                let subscribers = self.subscribers_by_uuid(&handle.characteristic);
+                (handle.characteristic, subscribers.len())
+            })
+            .collect()
+    }
+
+    pub fn forget(&mut self, central: &CentralId) {
+        self.characteristics.retain(|_, centrals| {
+            centrals.remove(central);
+            !centrals.is_empty()
+        });
+        self.last_seen.remove(central);
+        self.update_lengths.remove(central);
+        self.centrals.send_replace(self.last_seen.len());
+    }
+
+    /// Forgets the centrals that haven't been seen for `timeout`.
+    pub fn prune(&mut self, timeout: Duration) {
+        let stale = self
+            .last_seen
+            .iter()
+            .filter(|(_, last_seen)| last_seen.elapsed() >= timeout)
+            .map(|(central, _)| central.clone())
+            .collect::<Vec<_>>();
+        for central in stale.iter() {
+            self.forget(central);
+        }
+    }
+
+    pub fn is_subscribed(&self, central: &CentralId) -> bool {
+        self.characteristics
+            .values()
+            .any(|centrals| centrals.contains(central))
+    }
+}
@@ -1,12 +1,42 @@
+mod backend;
+mod builder;
+
+pub use self::{
+    backend::{Peripheral, PeripheralBackend},
+    builder::{PeripheralBuilder, QualityOfService},
+};
+
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 mod corebluetooth;
 #[cfg(any(target_os = "macos", target_os = "ios"))]
-pub use self::corebluetooth::Peripheral;
+pub use self::corebluetooth::Peripheral as NativePeripheral;
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
 mod bluez;
 #[cfg(any(target_os = "linux", target_os = "android"))]
-pub use self::bluez::Peripheral;
+pub use self::bluez::Peripheral as NativePeripheral;
+
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+pub use self::windows::Peripheral as NativePeripheral;
+
+#[cfg(not(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "android",
+    windows
+)))]
+mod unsupported;
+#[cfg(not(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "android",
+    windows
+)))]
+pub use self::unsupported::Peripheral as NativePeripheral;
 
 // TODO: Add struct / traits to implement for each OS
 //
@@ -24,20 +54,10 @@ pub use self::bluez::Peripheral;
 // }
 //
 // #[derive(Debug, Clone)]
-// pub enum State {
-//     Unknown,
-//     Resetting,
-//     Unsupported,
-//     Unauthorized,
-//     PoweredOff,
-//     PoweredOn,
-// }
-//
-// #[derive(Debug, Clone)]
 // pub struct Ble {
 //     initialized: bool,
 //     platform: String, // TODO: Make this an enum?
-//     state: State,
+//     state: AdapterState,
 //     address: String, // TODO: Make this a struct or something?
 //     rssi: u8,
 //     mtu: u8,
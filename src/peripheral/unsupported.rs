@@ -0,0 +1,251 @@
+use futures::channel::mpsc;
+use std::{convert::Infallible, time::Duration};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use super::PeripheralBuilder;
+use crate::{
+    gatt::{
+        characteristic::{CharacteristicHandle, ReadSource},
+        default_handler::RequestContext,
+        event::{ReadRequest, WriteRequest},
+        notify_pause::PausePolicy,
+        service::Service,
+    },
+    AdapterState, AdvertisingOptions, AdvertisingState, Capabilities, CentralId, CentralInfo,
+    Error, ErrorType, NotifyError, PeripheralMetrics, PeripheralSnapshot,
+};
+
+/// Stands in on platforms without a backend so code using the crate still compiles there.
+/// Creating one always fails, so none of its methods can ever be called.
+#[derive(Debug, Clone)]
+pub struct Peripheral {
+    never: Infallible,
+}
+
+impl Peripheral {
+    #[allow(clippy::new_ret_no_self)]
+    pub async fn new() -> Result<Self, Error> {
+        Self::from_builder(PeripheralBuilder::default()).await
+    }
+
+    pub(crate) async fn from_builder(_builder: PeripheralBuilder) -> Result<Self, Error> {
+        Err(Error::new(
+            "Unsupported",
+            "There is no Bluetooth backend for this platform",
+            ErrorType::Unsupported,
+        ))
+    }
+
+    pub async fn is_powered(&self) -> Result<bool, Error> {
+        match self.never {}
+    }
+
+    pub async fn central_rssi(&self, _central: &CentralId) -> Result<Option<i16>, Error> {
+        match self.never {}
+    }
+
+    pub async fn central_name(&self, _central: &CentralId) -> Option<String> {
+        match self.never {}
+    }
+
+    pub fn max_connections(&self) -> Option<usize> {
+        match self.never {}
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        match self.never {}
+    }
+
+    pub fn snapshot(&self) -> PeripheralSnapshot {
+        match self.never {}
+    }
+
+    pub fn describe(&self) -> String {
+        match self.never {}
+    }
+
+    pub fn has_characteristic(&self, _characteristic_uuid: &Uuid) -> bool {
+        match self.never {}
+    }
+
+    pub async fn notify(
+        &self,
+        _characteristic_uuid: &Uuid,
+        _value: &[u8],
+    ) -> Result<(), NotifyError> {
+        match self.never {}
+    }
+
+    pub fn max_notification_length(&self, _characteristic_uuid: &Uuid) -> usize {
+        match self.never {}
+    }
+
+    pub async fn notify_chunked(
+        &self,
+        _characteristic_uuid: &Uuid,
+        _value: &[u8],
+    ) -> Result<(), NotifyError> {
+        match self.never {}
+    }
+
+    pub async fn update_all<F>(
+        &self,
+        _characteristic_uuid: &Uuid,
+        _value: F,
+    ) -> Result<(), NotifyError>
+    where
+        F: Fn(&CentralId) -> Vec<u8>,
+    {
+        match self.never {}
+    }
+
+    pub async fn notify_handle(
+        &self,
+        _handle: &CharacteristicHandle,
+        _value: &[u8],
+    ) -> Result<(), NotifyError> {
+        match self.never {}
+    }
+
+    pub async fn current_state(&self) -> AdapterState {
+        match self.never {}
+    }
+
+    pub fn state(&self) -> AdapterState {
+        match self.never {}
+    }
+
+    pub async fn wait_until_powered_on(&self) -> Result<(), Error> {
+        match self.never {}
+    }
+
+    pub fn power_state_stream(&self) -> watch::Receiver<AdapterState> {
+        match self.never {}
+    }
+
+    pub fn advertising_state_stream(&self) -> watch::Receiver<AdvertisingState> {
+        match self.never {}
+    }
+
+    pub async fn register_gatt(&self) -> Result<(), Error> {
+        match self.never {}
+    }
+
+    pub async fn unregister_gatt(&self) -> Result<(), Error> {
+        match self.never {}
+    }
+
+    pub async fn start_advertising(&self, _name: &str, _uuids: &[Uuid]) -> Result<(), Error> {
+        match self.never {}
+    }
+
+    pub async fn start_advertising_with(&self, _options: &AdvertisingOptions) -> Result<(), Error> {
+        match self.never {}
+    }
+
+    pub async fn advertise_for(
+        &self,
+        _options: &AdvertisingOptions,
+        _duration: Duration,
+    ) -> Result<(), Error> {
+        match self.never {}
+    }
+
+    pub async fn system_default_name(&self) -> Option<String> {
+        match self.never {}
+    }
+
+    pub async fn stop_advertising(&self) -> Result<(), Error> {
+        match self.never {}
+    }
+
+    pub async fn is_advertising(&self) -> Result<bool, Error> {
+        match self.never {}
+    }
+
+    pub async fn shutdown(self) -> Result<(), Error> {
+        match self.never {}
+    }
+
+    pub fn add_service(&self, _service: &Service) -> Result<Vec<CharacteristicHandle>, Error> {
+        match self.never {}
+    }
+
+    pub fn read_source(&self, _characteristic_uuid: &Uuid) -> Option<ReadSource> {
+        match self.never {}
+    }
+
+    pub async fn add_services(&self, _services: &[Service]) -> Result<(), Error> {
+        match self.never {}
+    }
+
+    pub fn replace_services(&self, _services: &[Service]) -> Result<(), Error> {
+        match self.never {}
+    }
+
+    pub fn remove_service(&self, _uuid: &Uuid) -> Result<(), Error> {
+        match self.never {}
+    }
+
+    pub fn remove_all_services(&self) -> Result<(), Error> {
+        match self.never {}
+    }
+
+    pub fn get_value(&self, _characteristic_uuid: &Uuid) -> Option<Vec<u8>> {
+        match self.never {}
+    }
+
+    pub fn subscribers(&self, _characteristic_uuid: &Uuid) -> Vec<CentralId> {
+        match self.never {}
+    }
+
+    pub fn centrals(&self) -> Vec<CentralInfo> {
+        match self.never {}
+    }
+
+    pub fn is_subscribed(&self, _central: &CentralId, _characteristic_uuid: &Uuid) -> bool {
+        match self.never {}
+    }
+
+    pub fn forget_central(&self, _central: &CentralId) {
+        match self.never {}
+    }
+
+    pub fn metrics(&self) -> PeripheralMetrics {
+        match self.never {}
+    }
+
+    pub fn pause_notifications(&self, _policy: PausePolicy) {
+        match self.never {}
+    }
+
+    pub async fn resume_notifications(&self) -> Result<(), NotifyError> {
+        match self.never {}
+    }
+
+    pub fn set_notify_interceptor<F>(&self, _interceptor: F)
+    where
+        F: Fn(&Uuid, &mut Vec<u8>) + Send + Sync + 'static,
+    {
+        match self.never {}
+    }
+
+    pub fn set_default_read_handler<F>(&self, _handler: F)
+    where
+        F: Fn(RequestContext, ReadRequest) + Send + Sync + 'static,
+    {
+        match self.never {}
+    }
+
+    pub fn set_default_write_handler<F>(&self, _handler: F)
+    where
+        F: Fn(RequestContext, WriteRequest) + Send + Sync + 'static,
+    {
+        match self.never {}
+    }
+
+    pub fn writes(&self, _characteristic_uuid: &Uuid) -> mpsc::UnboundedReceiver<WriteRequest> {
+        match self.never {}
+    }
+}
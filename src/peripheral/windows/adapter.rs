@@ -0,0 +1,76 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+use windows::{
+    core::{Ref, HSTRING},
+    Devices::{
+        Bluetooth::{BluetoothAdapter, BluetoothLEDevice},
+        Radios::{Radio, RadioAccessStatus, RadioState},
+    },
+    Foundation::TypedEventHandler,
+};
+
+use crate::{AdapterState, CentralId, Error, ErrorType};
+
+/// The system's default Bluetooth adapter. Its radio tells whether it's powered on.
+#[derive(Debug, Clone)]
+pub struct Adapter {
+    radio: Radio,
+    state: Arc<watch::Sender<AdapterState>>,
+}
+
+impl Adapter {
+    pub async fn new() -> Result<Self, Error> {
+        let adapter = BluetoothAdapter::GetDefaultAsync()?.await.map_err(|_| {
+            Error::new(
+                "NoAdapter",
+                "There is no Bluetooth adapter",
+                ErrorType::Windows,
+            )
+        })?;
+        let radio = adapter.GetRadioAsync()?.await?;
+        let authorized = Radio::RequestAccessAsync()?.await? == RadioAccessStatus::Allowed;
+        let state = Arc::new(watch::channel(adapter_state(&adapter, &radio, authorized)).0);
+        {
+            let state = Arc::clone(&state);
+            radio.StateChanged(&TypedEventHandler::new(move |radio: Ref<Radio>, _| {
+                if let Some(radio) = radio.as_ref() {
+                    state.send_replace(adapter_state(&adapter, radio, authorized));
+                }
+                Ok(())
+            }))?;
+        }
+        Ok(Adapter { radio, state })
+    }
+
+    pub fn is_powered(&self) -> Result<bool, Error> {
+        Ok(self.radio.State()? == RadioState::On)
+    }
+
+    pub fn state_receiver(&self) -> watch::Receiver<AdapterState> {
+        self.state.subscribe()
+    }
+
+    /// The name of the central's device, `None` if Windows doesn't know it.
+    pub async fn get_device_name(&self, central: &CentralId) -> Option<String> {
+        let device = BluetoothLEDevice::FromIdAsync(&HSTRING::from(central.as_str()))
+            .ok()?
+            .await
+            .ok()?;
+        Some(device.Name().ok()?.to_string_lossy()).filter(|name| !name.is_empty())
+    }
+}
+
+/// An adapter that can't act as a BLE peripheral is unsupported regardless of its radio.
+fn adapter_state(adapter: &BluetoothAdapter, radio: &Radio, authorized: bool) -> AdapterState {
+    if !adapter.IsPeripheralRoleSupported().unwrap_or(false) {
+        return AdapterState::Unsupported;
+    }
+    if !authorized {
+        return AdapterState::Unauthorized;
+    }
+    match radio.State() {
+        Ok(RadioState::On) => AdapterState::PoweredOn,
+        Ok(RadioState::Off) | Ok(RadioState::Disabled) => AdapterState::PoweredOff,
+        _ => AdapterState::Unknown,
+    }
+}
@@ -0,0 +1,199 @@
+use log::warn;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::watch;
+use windows::{
+    core::Ref,
+    Devices::Bluetooth::{
+        Advertisement::{
+            BluetoothLEAdvertisementPublisher, BluetoothLEAdvertisementPublisherStatus,
+            BluetoothLEAdvertisementPublisherStatusChangedEventArgs, BluetoothLEManufacturerData,
+        },
+        GenericAttributeProfile::{
+            GattServiceProvider, GattServiceProviderAdvertisementStatus,
+            GattServiceProviderAdvertisementStatusChangedEventArgs,
+        },
+    },
+    Foundation::TypedEventHandler,
+};
+
+use super::{
+    common::to_buffer,
+    gatt::{is_started, publish},
+};
+use crate::{AdvertisingState, Error};
+
+/// A company identifier and the data following it.
+type ManufacturerData = (u16, Vec<u8>);
+
+/// A registered service to advertise, with the service data it's advertised with.
+pub type AdvertisedService = (GattServiceProvider, Option<Vec<u8>>);
+
+/// Windows advertises the UUID and service data of a service through its `GattServiceProvider`
+/// and everything else through a `BluetoothLEAdvertisementPublisher`, which can only send
+/// non-connectable advertisements. So the manufacturer data goes out separately from the
+/// services.
+#[derive(Debug, Clone)]
+pub struct Advertisement {
+    publisher: BluetoothLEAdvertisementPublisher,
+    services: Arc<Mutex<Vec<AdvertisedService>>>,
+    /// The `AdvertisementStatusChanged` registrations of the advertised providers.
+    tokens: Arc<Mutex<Vec<(GattServiceProvider, i64)>>>,
+    connectable: Arc<AtomicBool>,
+    manufacturer_data: Arc<Mutex<Option<ManufacturerData>>>,
+    is_advertising: Arc<AtomicBool>,
+    state: Arc<watch::Sender<AdvertisingState>>,
+    /// Bumped whenever advertising starts or stops, a scheduled stop only goes through if
+    /// nothing happened in between.
+    generation: Arc<AtomicUsize>,
+}
+
+impl Advertisement {
+    pub fn new() -> Result<Self, Error> {
+        let publisher = BluetoothLEAdvertisementPublisher::new()?;
+        let is_advertising = Arc::new(AtomicBool::new(false));
+        let state = Arc::new(watch::channel(AdvertisingState::Stopped).0);
+        {
+            let is_advertising = Arc::clone(&is_advertising);
+            let state = Arc::clone(&state);
+            publisher.StatusChanged(&TypedEventHandler::new(
+                move |_, args: Ref<BluetoothLEAdvertisementPublisherStatusChangedEventArgs>| {
+                    if args.ok()?.Status()? == BluetoothLEAdvertisementPublisherStatus::Aborted {
+                        interrupted(&is_advertising, &state);
+                    }
+                    Ok(())
+                },
+            ))?;
+        }
+        Ok(Advertisement {
+            publisher,
+            services: Arc::new(Mutex::new(vec![])),
+            tokens: Arc::new(Mutex::new(vec![])),
+            connectable: Arc::new(AtomicBool::new(true)),
+            manufacturer_data: Arc::new(Mutex::new(None)),
+            is_advertising,
+            state,
+            generation: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub fn add_services(&self, services: Vec<AdvertisedService>) {
+        *self.services.lock().unwrap() = services;
+    }
+
+    pub fn add_connectable(&self, connectable: bool) {
+        self.connectable.store(connectable, Ordering::Relaxed);
+    }
+
+    pub fn add_manufacturer_data(&self, manufacturer_data: Option<ManufacturerData>) {
+        *self.manufacturer_data.lock().unwrap() = manufacturer_data;
+    }
+
+    /// Makes the services discoverable and starts the publisher if there is manufacturer data.
+    /// Registering again while advertising replaces the advertisement.
+    pub fn register(&self) -> Result<(), Error> {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.remove_status_handlers();
+        let connectable = self.connectable.load(Ordering::Relaxed);
+        for (provider, service_data) in self.services.lock().unwrap().iter() {
+            publish(provider, connectable, true, service_data.as_deref())?;
+            let is_advertising = Arc::clone(&self.is_advertising);
+            let state = Arc::clone(&self.state);
+            let token = provider.AdvertisementStatusChanged(&TypedEventHandler::new(
+                move |_, args: Ref<GattServiceProviderAdvertisementStatusChangedEventArgs>| {
+                    if args.ok()?.Status()? == GattServiceProviderAdvertisementStatus::Aborted {
+                        interrupted(&is_advertising, &state);
+                    }
+                    Ok(())
+                },
+            ))?;
+            self.tokens.lock().unwrap().push((provider.clone(), token));
+        }
+
+        let advertisement = self.publisher.Advertisement()?;
+        advertisement.ManufacturerData()?.Clear()?;
+        self.publisher.Stop()?;
+        if let Some((company_id, data)) = &*self.manufacturer_data.lock().unwrap() {
+            let manufacturer_data =
+                BluetoothLEManufacturerData::Create(*company_id, &to_buffer(data)?)?;
+            advertisement
+                .ManufacturerData()?
+                .Append(&manufacturer_data)?;
+            self.publisher.Start()?;
+        }
+
+        self.is_advertising.store(true, Ordering::Relaxed);
+        self.state.send_replace(AdvertisingState::Advertising);
+        Ok(())
+    }
+
+    pub fn unregister(&self) -> Result<(), Error> {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.stop(AdvertisingState::Stopped)
+    }
+
+    /// Stops advertising once `duration` elapsed, unless advertising was started or stopped in
+    /// between, and reports it as `AdvertisingState::TimedOut`.
+    pub fn stop_after(&self, duration: Duration) {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let advertisement = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            if advertisement
+                .generation
+                .compare_exchange(
+                    generation,
+                    generation.wrapping_add(1),
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_ok()
+            {
+                if let Err(error) = advertisement.stop(AdvertisingState::TimedOut) {
+                    warn!("Couldn't stop advertising: {:?}", error);
+                }
+            }
+        });
+    }
+
+    /// The services stay published, connectable but no longer discoverable, so centrals that
+    /// know the peripheral can still connect.
+    fn stop(&self, state: AdvertisingState) -> Result<(), Error> {
+        self.remove_status_handlers();
+        self.is_advertising.store(false, Ordering::Relaxed);
+        self.state.send_replace(state);
+        for (provider, _) in self.services.lock().unwrap().iter() {
+            if is_started(provider) {
+                publish(provider, true, false, None)?;
+            }
+        }
+        self.publisher.Stop()?;
+        Ok(())
+    }
+
+    fn remove_status_handlers(&self) {
+        for (provider, token) in self.tokens.lock().unwrap().drain(..) {
+            provider.RemoveAdvertisementStatusChanged(token).ok();
+        }
+    }
+
+    pub fn state_receiver(&self) -> watch::Receiver<AdvertisingState> {
+        self.state.subscribe()
+    }
+
+    pub fn is_advertising(&self) -> bool {
+        self.is_advertising.load(Ordering::Relaxed)
+    }
+}
+
+/// Windows aborted the advertisement on its own, e.g. because the radio was turned off.
+fn interrupted(is_advertising: &AtomicBool, state: &watch::Sender<AdvertisingState>) {
+    if is_advertising.swap(false, Ordering::Relaxed) {
+        state.send_replace(AdvertisingState::Interrupted);
+    }
+}
@@ -0,0 +1,35 @@
+use uuid::Uuid;
+use windows::{
+    core::{Result, GUID},
+    Devices::Bluetooth::GenericAttributeProfile::GattSession,
+    Storage::Streams::{DataReader, DataWriter, IBuffer},
+};
+
+use crate::{Central, CentralId};
+
+pub fn guid(uuid: &Uuid) -> GUID {
+    GUID::from_u128(uuid.as_u128())
+}
+
+pub fn to_buffer(value: &[u8]) -> Result<IBuffer> {
+    let writer = DataWriter::new()?;
+    writer.WriteBytes(value)?;
+    writer.DetachBuffer()
+}
+
+pub fn from_buffer(buffer: &IBuffer) -> Result<Vec<u8>> {
+    let mut value = vec![0; buffer.Length()? as usize];
+    DataReader::FromBuffer(buffer)?.ReadBytes(&mut value)?;
+    Ok(value)
+}
+
+/// The central of a GATT session, named by the device ID Windows knows it by. The session's
+/// largest PDU is its ATT MTU.
+pub fn central(session: &GattSession) -> Result<Central> {
+    let id = session.DeviceId()?.Id()?.to_string_lossy();
+    let mtu = session.MaxPduSize()?;
+    Ok(Central::new(
+        CentralId::new(id),
+        usize::from(mtu).saturating_sub(3),
+    ))
+}
@@ -0,0 +1,32 @@
+use windows::{core::Error as WindowsError, Devices::Bluetooth::BluetoothError};
+
+use crate::{Error, ErrorType};
+
+impl From<WindowsError> for Error {
+    fn from(windows_error: WindowsError) -> Error {
+        Error::new(
+            String::from("WindowsError"),
+            windows_error.message(),
+            ErrorType::Windows,
+        )
+        .with_code("HRESULT", i64::from(windows_error.code().0))
+    }
+}
+
+/// Fails with the `BluetoothError` an operation reported, unless it's `Success`.
+pub fn check(name: &str, error: BluetoothError) -> Result<(), Error> {
+    let description = match error {
+        BluetoothError::Success => return Ok(()),
+        BluetoothError::RadioNotAvailable => "The Bluetooth radio isn't available",
+        BluetoothError::ResourceInUse => "The resource is in use",
+        BluetoothError::DeviceNotConnected => "The device isn't connected",
+        BluetoothError::DisabledByPolicy => "Bluetooth is disabled by policy",
+        BluetoothError::NotSupported => "The operation isn't supported",
+        BluetoothError::DisabledByUser => "Bluetooth is disabled by the user",
+        BluetoothError::ConsentRequired => "The user has to consent to using Bluetooth",
+        BluetoothError::TransportNotSupported => "The transport isn't supported",
+        _ => "An unexpected error occurred",
+    };
+    Err(Error::new(name, description, ErrorType::Windows)
+        .with_code("BluetoothError", i64::from(error.0)))
+}
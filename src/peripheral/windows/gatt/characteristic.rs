@@ -0,0 +1,475 @@
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+};
+use log::{trace, warn};
+use std::sync::Arc;
+use uuid::Uuid;
+use windows::{
+    core::{Ref, HSTRING},
+    Devices::Bluetooth::GenericAttributeProfile::{
+        GattCommunicationStatus, GattLocalCharacteristic, GattLocalCharacteristicParameters,
+        GattLocalService, GattPresentationFormat, GattReadRequestedEventArgs, GattSubscribedClient,
+        GattWriteOption, GattWriteRequestedEventArgs,
+    },
+    Foundation::TypedEventHandler,
+};
+
+use super::{
+    super::{
+        common::{self, guid, to_buffer},
+        error::check,
+    },
+    descriptor::add_descriptor,
+    flags::characteristic_flags,
+    Handlers,
+};
+use crate::{
+    gatt::{
+        self,
+        characteristic::CharacteristicHandle,
+        default_handler::RequestContext,
+        descriptor::USER_DESCRIPTION_UUID,
+        event::{AttError, CommandSender, Event, EventSender, NotifySubscribe, Response},
+        presentation_format::PRESENTATION_FORMAT_UUID,
+    },
+    metrics::Metrics,
+    span::Span,
+    uuid::display_uuid,
+    Central, Error, NotifyError, SdpShortUuid,
+};
+
+/// A characteristic published by a `GattServiceProvider`. The request handlers hold on to what
+/// they need, so it's only kept to send notifications and end subscriptions.
+#[derive(Debug, Clone)]
+pub struct Characteristic {
+    pub characteristic: Arc<gatt::characteristic::Characteristic>,
+    pub local: GattLocalCharacteristic,
+}
+
+impl Characteristic {
+    pub async fn new(
+        service: &GattLocalService,
+        characteristic: &Arc<gatt::characteristic::Characteristic>,
+        handle: CharacteristicHandle,
+        handlers: &Handlers,
+    ) -> Result<Self, Error> {
+        let operation = service
+            .CreateCharacteristicAsync(guid(&characteristic.uuid), &parameters(characteristic)?)?;
+        let result = operation.await?;
+        check("CreateCharacteristicFailed", result.Error()?)?;
+        let local = result.Characteristic()?;
+
+        // Writes without response are queued here as Windows delivers them and handed to the
+        // write handler one at a time, so rapid commands neither get reordered nor dropped.
+        let (command_sender, commands) = gatt::event::command_queue();
+        if characteristic.properties.write.is_some() {
+            handlers.runtime.spawn(commands);
+        }
+
+        {
+            let characteristic = Arc::clone(characteristic);
+            let handlers = handlers.clone();
+            local.ReadRequested(&TypedEventHandler::new(
+                move |_, args: Ref<GattReadRequestedEventArgs>| {
+                    let args = args.ok()?.clone();
+                    // Windows waits for the deferral to complete before it answers the central
+                    let deferral = args.GetDeferral()?;
+                    let characteristic = Arc::clone(&characteristic);
+                    let request_handlers = handlers.clone();
+                    handlers.runtime.spawn(async move {
+                        if let Err(error) = read(&characteristic, &request_handlers, &args).await {
+                            warn!(
+                                "Couldn't answer a read of {}: {}",
+                                characteristic.uuid, error
+                            );
+                        }
+                        deferral.Complete().ok();
+                    });
+                    Ok(())
+                },
+            ))?;
+        }
+        {
+            let characteristic = Arc::clone(characteristic);
+            let handlers = handlers.clone();
+            local.WriteRequested(&TypedEventHandler::new(
+                move |_, args: Ref<GattWriteRequestedEventArgs>| {
+                    let args = args.ok()?.clone();
+                    let deferral = args.GetDeferral()?;
+                    let characteristic = Arc::clone(&characteristic);
+                    let request_handlers = handlers.clone();
+                    let command_sender = command_sender.clone();
+                    handlers.runtime.spawn(async move {
+                        if let Err(error) =
+                            write(&characteristic, &request_handlers, &command_sender, &args).await
+                        {
+                            warn!(
+                                "Couldn't answer a write of {}: {}",
+                                characteristic.uuid, error
+                            );
+                        }
+                        deferral.Complete().ok();
+                    });
+                    Ok(())
+                },
+            ))?;
+        }
+        {
+            let characteristic = Arc::clone(characteristic);
+            let handlers = handlers.clone();
+            local.SubscribedClientsChanged(&TypedEventHandler::new(
+                move |local: Ref<GattLocalCharacteristic>, _| {
+                    let local = local.ok()?;
+                    let centrals = local
+                        .SubscribedClients()?
+                        .into_iter()
+                        .map(|client| {
+                            let central = common::central(&client.Session()?)?;
+                            let length = usize::from(client.MaxNotificationSize()?);
+                            Ok(Central::new(central.id().clone(), length))
+                        })
+                        .collect::<windows::core::Result<Vec<_>>>()?;
+                    trace!(
+                        "SubscribedClientsChanged {} centrals={:?}",
+                        characteristic.uuid,
+                        centrals
+                    );
+                    handlers.subscribers_changed(handle, &characteristic, local, centrals);
+                    Ok(())
+                },
+            ))?;
+        }
+
+        let mut descriptors = characteristic.descriptors.iter().collect::<Vec<_>>();
+        descriptors.sort_by_key(|descriptor| descriptor.uuid);
+        for descriptor in descriptors {
+            if is_parameter(&descriptor.uuid) {
+                continue;
+            }
+            add_descriptor(&local, descriptor, handlers).await?;
+        }
+
+        Ok(Characteristic {
+            characteristic: Arc::clone(characteristic),
+            local,
+        })
+    }
+}
+
+/// The User Description and Presentation Format descriptors are set as parameters of the
+/// characteristic, Windows doesn't let them be created like other descriptors.
+fn is_parameter(uuid: &Uuid) -> bool {
+    *uuid == Uuid::from_sdp_short_uuid(USER_DESCRIPTION_UUID)
+        || *uuid == Uuid::from_sdp_short_uuid(PRESENTATION_FORMAT_UUID)
+}
+
+fn parameters(
+    characteristic: &gatt::characteristic::Characteristic,
+) -> Result<GattLocalCharacteristicParameters, Error> {
+    let flags = characteristic_flags(characteristic);
+    let parameters = GattLocalCharacteristicParameters::new()?;
+    parameters.SetCharacteristicProperties(flags.properties)?;
+    parameters.SetReadProtectionLevel(flags.read_protection)?;
+    parameters.SetWriteProtectionLevel(flags.write_protection)?;
+    // Windows answers the reads of a characteristic with a static value by itself
+    if let Some(value) = &characteristic.value {
+        parameters.SetStaticValue(&to_buffer(value)?)?;
+    }
+    for descriptor in characteristic.descriptors.iter() {
+        let value = match &descriptor.value {
+            Some(value) => value,
+            None => continue,
+        };
+        if descriptor.uuid == Uuid::from_sdp_short_uuid(USER_DESCRIPTION_UUID) {
+            parameters
+                .SetUserDescription(&HSTRING::from(String::from_utf8_lossy(value).as_ref()))?;
+        } else if descriptor.uuid == Uuid::from_sdp_short_uuid(PRESENTATION_FORMAT_UUID) {
+            match presentation_format(value)? {
+                Some(format) => parameters.PresentationFormats()?.Append(&format)?,
+                None => warn!(
+                    "Presentation format of characteristic {} isn't 7 bytes long, leaving it out",
+                    display_uuid(&characteristic.uuid)
+                ),
+            }
+        }
+    }
+    Ok(parameters)
+}
+
+/// The format, exponent, unit, name space and description of a Presentation Format value.
+fn presentation_format(value: &[u8]) -> windows::core::Result<Option<GattPresentationFormat>> {
+    let value = match value {
+        [format, exponent, unit_low, unit_high, name_space, description_low, description_high] => {
+            GattPresentationFormat::FromParts(
+                *format,
+                i32::from(*exponent as i8),
+                u16::from_le_bytes([*unit_low, *unit_high]),
+                *name_space,
+                u16::from_le_bytes([*description_low, *description_high]),
+            )?
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+async fn read(
+    characteristic: &gatt::characteristic::Characteristic,
+    handlers: &Handlers,
+    args: &GattReadRequestedEventArgs,
+) -> Result<(), Error> {
+    let session = args.Session()?;
+    let central = common::central(&session).ok();
+    let mtu = session.MaxPduSize()?;
+    let request = args.GetRequestAsync()?.await?;
+    let offset = request.Offset()? as u16;
+    trace!(
+        "ReadRequested {} offset={} mtu={} central={:?}",
+        characteristic.uuid,
+        offset,
+        mtu,
+        central
+    );
+    let span = Span::new(
+        "read",
+        &characteristic.uuid,
+        central.as_ref().map(Central::id),
+    );
+    let value = span
+        .instrument(async {
+            let event_sender = characteristic.check_read()?;
+            if !characteristic.authorizes(central.as_ref().map(Central::id)) {
+                return Err(AttError::InsufficientAuthorization);
+            }
+            let (sender, receiver) = oneshot::channel();
+            let context = RequestContext::new(characteristic.uuid, central.clone());
+            let read_request = gatt::event::ReadRequest {
+                offset,
+                response: sender,
+                mtu,
+                central,
+            };
+            if !handlers
+                .defaults
+                .send_read(&mut event_sender.sender(), context, read_request)
+                .await
+            {
+                return Err(AttError::UnlikelyError);
+            }
+            tokio::time::timeout(gatt::event::READ_RESPONSE_TIMEOUT, receiver)
+                .await
+                .unwrap_or(Ok(Response::UnlikelyError))
+                .map_err(|_| AttError::UnlikelyError)
+                .and_then(Response::into_result)
+        })
+        .await;
+    match value {
+        Ok(value) => {
+            request.RespondWithValue(&to_buffer(&value)?)?;
+            handlers.metrics.read_served();
+        }
+        Err(error) => request.RespondWithProtocolError(error.code())?,
+    }
+    Ok(())
+}
+
+async fn write(
+    characteristic: &gatt::characteristic::Characteristic,
+    handlers: &Handlers,
+    command_sender: &CommandSender,
+    args: &GattWriteRequestedEventArgs,
+) -> Result<(), Error> {
+    let central = common::central(&args.Session()?).ok();
+    let request = args.GetRequestAsync()?.await?;
+    let data = common::from_buffer(&request.Value()?)?;
+    let offset = request.Offset()? as u16;
+    let without_response = request.Option()? == GattWriteOption::WriteWithoutResponse;
+    trace!(
+        "WriteRequested {} value={} offset={} without_response={} central={:?}",
+        characteristic.uuid,
+        characteristic.logged_value(&data),
+        offset,
+        without_response,
+        central
+    );
+    let write = characteristic
+        .check_write(without_response)
+        .and_then(|write| {
+            if !characteristic.authorizes(central.as_ref().map(Central::id)) {
+                Err(AttError::InsufficientAuthorization)
+            } else if characteristic.exceeds_max_value_length(offset, data.len()) {
+                Err(AttError::InvalidAttributeValueLength)
+            } else {
+                characteristic
+                    .check_write_schema(offset, &data)
+                    .map(|_| write)
+            }
+        });
+    let write = match write {
+        Ok(write) => write,
+        // A write without response can't be answered, it's dropped
+        Err(_) if without_response => return Ok(()),
+        Err(error) => {
+            request.RespondWithProtocolError(error.code())?;
+            return Ok(());
+        }
+    };
+    let span = Span::new(
+        "write",
+        &characteristic.uuid,
+        central.as_ref().map(Central::id),
+    );
+    let context = RequestContext::new(characteristic.uuid, central.clone());
+    if without_response {
+        // Nothing waits on the handler for a command, it's queued before the deferral completes
+        // so the next one can't overtake it.
+        let (sender, _) = oneshot::channel();
+        let command = gatt::event::WriteRequest {
+            data,
+            offset,
+            without_response,
+            response: sender,
+            central,
+        };
+        let event_sender = write.sender();
+        let queued = span.in_scope(|| {
+            match handlers
+                .defaults
+                .try_default_write(&event_sender, context, command)
+            {
+                Some(command) => command_sender
+                    .unbounded_send((event_sender, command))
+                    .is_ok(),
+                None => true,
+            }
+        });
+        if queued {
+            handlers.metrics.write_served();
+        }
+        return Ok(());
+    }
+    let result = span
+        .instrument(async {
+            let (sender, receiver) = oneshot::channel();
+            let write_request = gatt::event::WriteRequest {
+                data,
+                offset,
+                without_response: false,
+                response: sender,
+                central,
+            };
+            if !handlers
+                .defaults
+                .send_write(&mut write.sender(), context, write_request)
+                .await
+            {
+                return Err(AttError::UnlikelyError);
+            }
+            receiver
+                .await
+                .map_err(|_| AttError::UnlikelyError)
+                .and_then(Response::into_result)
+        })
+        .await;
+    match result {
+        Ok(_) => {
+            request.Respond()?;
+            handlers.metrics.write_served();
+        }
+        Err(error) => request.RespondWithProtocolError(error.code())?,
+    }
+    Ok(())
+}
+
+/// The handler a subscription to the characteristic is announced to.
+pub fn notify_sender(characteristic: &gatt::characteristic::Characteristic) -> Option<EventSender> {
+    let properties = &characteristic.properties;
+    properties
+        .notify
+        .clone()
+        .or_else(|| properties.indicate.clone())
+}
+
+/// Hands the `notify` or `indicate` handler of the characteristic a channel for its
+/// notifications. They are sent until the handler drops it.
+pub fn subscribed(
+    characteristic: &gatt::characteristic::Characteristic,
+    local: GattLocalCharacteristic,
+    handlers: &Handlers,
+) {
+    let mut event_sender = match notify_sender(characteristic) {
+        Some(event_sender) => event_sender,
+        None => return,
+    };
+    let (notification, receiver) = mpsc::channel(1);
+    let mut notifications = match characteristic.notify_policy {
+        Some(notify_policy) => notify_policy.apply(receiver).boxed(),
+        None => receiver.boxed(),
+    };
+    let metrics = Arc::clone(&handlers.metrics);
+    handlers.runtime.spawn(async move {
+        while let Some(notification) = notifications.next().await {
+            notify_value(&local, &notification, &metrics).await.ok();
+        }
+    });
+    handlers.runtime.spawn(async move {
+        event_sender
+            .send(Event::NotifySubscribe(NotifySubscribe { notification }))
+            .await
+            .ok();
+    });
+}
+
+/// Tells the handler of the characteristic once the last central unsubscribed from it.
+pub fn unsubscribed(characteristic: &gatt::characteristic::Characteristic, handlers: &Handlers) {
+    if let Some(mut event_sender) = notify_sender(characteristic) {
+        handlers.runtime.spawn(async move {
+            event_sender.send(Event::NotifyUnsubscribe).await.ok();
+        });
+    }
+}
+
+/// Notifies or indicates the value to every subscribed client, whichever the characteristic
+/// declares. Fails unless at least one of them got it.
+pub async fn notify_value(
+    local: &GattLocalCharacteristic,
+    value: &[u8],
+    metrics: &Metrics,
+) -> Result<(), NotifyError> {
+    let operation = to_buffer(value)
+        .and_then(|buffer| local.NotifyValueAsync(&buffer))
+        .map_err(|_| NotifyError::QueueFull)?;
+    let results = operation.await.map_err(|_| NotifyError::QueueFull)?;
+    let mut result = Err(NotifyError::NoSubscribers);
+    for client in results {
+        if client.Status() == Ok(GattCommunicationStatus::Success) {
+            metrics.notification_sent();
+            result = Ok(());
+        } else {
+            metrics.notification_dropped();
+            result = result.or(Err(NotifyError::QueueFull));
+        }
+    }
+    result
+}
+
+/// Like `notify_value`, for one of the subscribed clients only.
+pub async fn notify_client(
+    local: &GattLocalCharacteristic,
+    client: &GattSubscribedClient,
+    value: &[u8],
+    metrics: &Metrics,
+) -> Result<(), NotifyError> {
+    let operation = to_buffer(value)
+        .and_then(|buffer| local.NotifyValueForSubscribedClientAsync(&buffer, client))
+        .map_err(|_| NotifyError::QueueFull)?;
+    let status = operation.await.and_then(|result| result.Status());
+    if status == Ok(GattCommunicationStatus::Success) {
+        metrics.notification_sent();
+        Ok(())
+    } else {
+        metrics.notification_dropped();
+        Err(NotifyError::QueueFull)
+    }
+}
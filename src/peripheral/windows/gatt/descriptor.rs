@@ -0,0 +1,190 @@
+use futures::{channel::oneshot, prelude::*};
+use log::{trace, warn};
+use std::sync::Arc;
+use windows::{
+    core::Ref,
+    Devices::Bluetooth::GenericAttributeProfile::{
+        GattLocalCharacteristic, GattLocalDescriptorParameters, GattReadRequestedEventArgs,
+        GattWriteRequestedEventArgs,
+    },
+    Foundation::TypedEventHandler,
+};
+
+use super::{
+    super::{
+        common::{self, guid, to_buffer},
+        error::check,
+    },
+    flags::descriptor_flags,
+    Handlers,
+};
+use crate::{
+    gatt::{
+        self,
+        event::{AttError, Response},
+    },
+    Error,
+};
+
+/// Adds the descriptor to the characteristic. Without a read handler Windows serves the value
+/// it was declared with by itself.
+pub async fn add_descriptor(
+    characteristic: &GattLocalCharacteristic,
+    descriptor: &gatt::descriptor::Descriptor,
+    handlers: &Handlers,
+) -> Result<(), Error> {
+    let operation =
+        characteristic.CreateDescriptorAsync(guid(&descriptor.uuid), &parameters(descriptor)?)?;
+    let result = operation.await?;
+    check("CreateDescriptorFailed", result.Error()?)?;
+    let local = result.Descriptor()?;
+    let descriptor = Arc::new(descriptor.clone());
+
+    if descriptor.properties.read.is_some() {
+        let descriptor = Arc::clone(&descriptor);
+        let handlers = handlers.clone();
+        local.ReadRequested(&TypedEventHandler::new(
+            move |_, args: Ref<GattReadRequestedEventArgs>| {
+                let args = args.ok()?.clone();
+                let deferral = args.GetDeferral()?;
+                let descriptor = Arc::clone(&descriptor);
+                handlers.runtime.spawn(async move {
+                    if let Err(error) = read(&descriptor, &args).await {
+                        warn!("Couldn't answer a read of {}: {}", descriptor.uuid, error);
+                    }
+                    deferral.Complete().ok();
+                });
+                Ok(())
+            },
+        ))?;
+    }
+    if descriptor.properties.write.is_some() {
+        let descriptor = Arc::clone(&descriptor);
+        let handlers = handlers.clone();
+        local.WriteRequested(&TypedEventHandler::new(
+            move |_, args: Ref<GattWriteRequestedEventArgs>| {
+                let args = args.ok()?.clone();
+                let deferral = args.GetDeferral()?;
+                let descriptor = Arc::clone(&descriptor);
+                handlers.runtime.spawn(async move {
+                    if let Err(error) = write(&descriptor, &args).await {
+                        warn!("Couldn't answer a write of {}: {}", descriptor.uuid, error);
+                    }
+                    deferral.Complete().ok();
+                });
+                Ok(())
+            },
+        ))?;
+    }
+    Ok(())
+}
+
+fn parameters(
+    descriptor: &gatt::descriptor::Descriptor,
+) -> Result<GattLocalDescriptorParameters, Error> {
+    let flags = descriptor_flags(descriptor);
+    let parameters = GattLocalDescriptorParameters::new()?;
+    parameters.SetReadProtectionLevel(flags.read_protection)?;
+    parameters.SetWriteProtectionLevel(flags.write_protection)?;
+    if descriptor.properties.read.is_none() {
+        if let Some(value) = &descriptor.value {
+            parameters.SetStaticValue(&to_buffer(value)?)?;
+        }
+    }
+    Ok(parameters)
+}
+
+async fn read(
+    descriptor: &gatt::descriptor::Descriptor,
+    args: &GattReadRequestedEventArgs,
+) -> Result<(), Error> {
+    let session = args.Session()?;
+    let central = common::central(&session).ok();
+    let mtu = session.MaxPduSize()?;
+    let request = args.GetRequestAsync()?.await?;
+    let offset = request.Offset()? as u16;
+    trace!(
+        "ReadRequested {} offset={} mtu={} central={:?}",
+        descriptor.uuid,
+        offset,
+        mtu,
+        central
+    );
+    let value = async {
+        let event_sender = descriptor
+            .properties
+            .read
+            .clone()
+            .ok_or(AttError::ReadNotPermitted)?;
+        let (sender, receiver) = oneshot::channel();
+        event_sender
+            .sender()
+            .send(gatt::event::Event::ReadRequest(gatt::event::ReadRequest {
+                offset,
+                response: sender,
+                mtu,
+                central,
+            }))
+            .await
+            .map_err(|_| AttError::UnlikelyError)?;
+        tokio::time::timeout(gatt::event::READ_RESPONSE_TIMEOUT, receiver)
+            .await
+            .unwrap_or(Ok(Response::UnlikelyError))
+            .map_err(|_| AttError::UnlikelyError)
+            .and_then(Response::into_result)
+    }
+    .await;
+    match value {
+        Ok(value) => request.RespondWithValue(&to_buffer(&value)?)?,
+        Err(error) => request.RespondWithProtocolError(error.code())?,
+    }
+    Ok(())
+}
+
+async fn write(
+    descriptor: &gatt::descriptor::Descriptor,
+    args: &GattWriteRequestedEventArgs,
+) -> Result<(), Error> {
+    let central = common::central(&args.Session()?).ok();
+    let request = args.GetRequestAsync()?.await?;
+    let data = common::from_buffer(&request.Value()?)?;
+    let offset = request.Offset()? as u16;
+    trace!(
+        "WriteRequested {} len={} offset={} central={:?}",
+        descriptor.uuid,
+        data.len(),
+        offset,
+        central
+    );
+    let result = async {
+        let event_sender = descriptor
+            .properties
+            .write
+            .clone()
+            .ok_or(AttError::WriteNotPermitted)?;
+        let (sender, receiver) = oneshot::channel();
+        event_sender
+            .sender()
+            .send(gatt::event::Event::WriteRequest(
+                gatt::event::WriteRequest {
+                    data,
+                    offset,
+                    without_response: false,
+                    response: sender,
+                    central,
+                },
+            ))
+            .await
+            .map_err(|_| AttError::UnlikelyError)?;
+        receiver
+            .await
+            .map_err(|_| AttError::UnlikelyError)
+            .and_then(Response::into_result)
+    }
+    .await;
+    match result {
+        Ok(_) => request.Respond()?,
+        Err(error) => request.RespondWithProtocolError(error.code())?,
+    }
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use windows::Devices::Bluetooth::GenericAttributeProfile::{
+    GattCharacteristicProperties, GattProtectionLevel,
+};
+
+use crate::gatt::{
+    characteristic::{self, Characteristic, Write},
+    descriptor::{self, Descriptor},
+};
+
+/// Whether an attribute needs an encrypted link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Secure,
+    Insecure,
+}
+
+impl From<&characteristic::Secure> for Access {
+    fn from(secure: &characteristic::Secure) -> Self {
+        match secure {
+            characteristic::Secure::Secure(_) => Access::Secure,
+            characteristic::Secure::Insecure(_) => Access::Insecure,
+        }
+    }
+}
+
+impl From<&descriptor::Secure> for Access {
+    fn from(secure: &descriptor::Secure) -> Self {
+        match secure {
+            descriptor::Secure::Secure(_) => Access::Secure,
+            descriptor::Secure::Insecure(_) => Access::Insecure,
+        }
+    }
+}
+
+impl From<Access> for GattProtectionLevel {
+    fn from(access: Access) -> Self {
+        match access {
+            Access::Secure => GattProtectionLevel::EncryptionRequired,
+            Access::Insecure => GattProtectionLevel::Plain,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteAccess {
+    WithResponse(Access),
+    WithoutResponse,
+}
+
+impl From<&Write> for WriteAccess {
+    fn from(write: &Write) -> Self {
+        match write {
+            Write::WithResponse(secure) => WriteAccess::WithResponse(secure.into()),
+            Write::WithoutResponse(_) => WriteAccess::WithoutResponse,
+        }
+    }
+}
+
+/// What a `GattLocalCharacteristicParameters` or `GattLocalDescriptorParameters` is created with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flags {
+    pub properties: GattCharacteristicProperties,
+    pub read_protection: GattProtectionLevel,
+    pub write_protection: GattProtectionLevel,
+}
+
+pub fn characteristic_flags(characteristic: &Characteristic) -> Flags {
+    if let Some((properties, permissions)) = characteristic.raw_flags {
+        return raw_flags(properties, permissions);
+    }
+    let properties = &characteristic.properties;
+    compute_flags(
+        properties.read.as_ref().map(|read| Access::from(&read.0)),
+        properties.write.as_ref().map(WriteAccess::from),
+        properties.notify.is_some(),
+        properties.indicate.is_some(),
+    )
+}
+
+/// Descriptors have no properties of their own, only the protection levels apply.
+pub fn descriptor_flags(descriptor: &Descriptor) -> Flags {
+    let protection = |secure: Option<&descriptor::Secure>| {
+        secure.map_or(GattProtectionLevel::Plain, |secure| {
+            Access::from(secure).into()
+        })
+    };
+    Flags {
+        properties: GattCharacteristicProperties::None,
+        read_protection: protection(descriptor.properties.read.as_ref().map(|read| &read.0)),
+        write_protection: protection(descriptor.properties.write.as_ref().map(|write| &write.0)),
+    }
+}
+
+/// The `GattCharacteristicProperties` and protection levels of a characteristic. A secure read
+/// or write requires encryption, like CoreBluetooth's encryption required permissions do.
+pub fn compute_flags(
+    read: Option<Access>,
+    write: Option<WriteAccess>,
+    notify: bool,
+    indicate: bool,
+) -> Flags {
+    let mut flags = Flags {
+        properties: GattCharacteristicProperties::None,
+        read_protection: GattProtectionLevel::Plain,
+        write_protection: GattProtectionLevel::Plain,
+    };
+
+    if let Some(access) = read {
+        flags.properties |= GattCharacteristicProperties::Read;
+        flags.read_protection = access.into();
+    }
+
+    match write {
+        Some(WriteAccess::WithResponse(access)) => {
+            flags.properties |= GattCharacteristicProperties::Write;
+            flags.write_protection = access.into();
+        }
+        Some(WriteAccess::WithoutResponse) => {
+            flags.properties |= GattCharacteristicProperties::WriteWithoutResponse;
+        }
+        None => {}
+    }
+
+    if notify {
+        flags.properties |= GattCharacteristicProperties::Notify;
+    }
+
+    if indicate {
+        flags.properties |= GattCharacteristicProperties::Indicate;
+    }
+
+    flags
+}
+
+/// The property bits of the Characteristic Properties byte map onto `GattCharacteristicProperties`
+/// as they are, the encryption bits of `CBAttributePermissions` onto the protection levels.
+fn raw_flags(properties: u8, permissions: u8) -> Flags {
+    let protection = |bit: u8| {
+        if permissions & bit != 0 {
+            GattProtectionLevel::EncryptionRequired
+        } else {
+            GattProtectionLevel::Plain
+        }
+    };
+    Flags {
+        properties: GattCharacteristicProperties(u32::from(properties)),
+        read_protection: protection(0x04),
+        write_protection: protection(0x08),
+    }
+}
+
+// The flags are private to the Windows backend, so they can't be tested from `tests/`.
+#[cfg(test)]
+mod tests {
+    use super::{characteristic_flags, compute_flags, Access, Flags, WriteAccess};
+    use crate::gatt::characteristic::Characteristic;
+    use uuid::Uuid;
+    use windows::Devices::Bluetooth::GenericAttributeProfile::{
+        GattCharacteristicProperties, GattProtectionLevel,
+    };
+
+    const PLAIN: GattProtectionLevel = GattProtectionLevel::Plain;
+    const ENCRYPTED: GattProtectionLevel = GattProtectionLevel::EncryptionRequired;
+
+    const READS: [(Option<Access>, u32, GattProtectionLevel); 3] = [
+        (None, 0x00, PLAIN),
+        (Some(Access::Insecure), 0x02, PLAIN),
+        (Some(Access::Secure), 0x02, ENCRYPTED),
+    ];
+
+    const WRITES: [(Option<WriteAccess>, u32, GattProtectionLevel); 4] = [
+        (None, 0x00, PLAIN),
+        (
+            Some(WriteAccess::WithResponse(Access::Insecure)),
+            0x08,
+            PLAIN,
+        ),
+        (
+            Some(WriteAccess::WithResponse(Access::Secure)),
+            0x08,
+            ENCRYPTED,
+        ),
+        (Some(WriteAccess::WithoutResponse), 0x04, PLAIN),
+    ];
+
+    #[test]
+    fn compute_flags_covers_every_combination() {
+        for &(read, read_properties, read_protection) in READS.iter() {
+            for &(write, write_properties, write_protection) in WRITES.iter() {
+                for &notify in [false, true].iter() {
+                    for &indicate in [false, true].iter() {
+                        let mut properties = read_properties | write_properties;
+                        if notify {
+                            properties |= 0x10;
+                        }
+                        if indicate {
+                            properties |= 0x20;
+                        }
+                        assert_eq!(
+                            compute_flags(read, write, notify, indicate),
+                            Flags {
+                                properties: GattCharacteristicProperties(properties),
+                                read_protection,
+                                write_protection,
+                            },
+                            "read: {:?}, write: {:?}, notify: {}, indicate: {}",
+                            read,
+                            write,
+                            notify,
+                            indicate,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn raw_flags_are_passed_through() {
+        let characteristic = Characteristic::from_raw(Uuid::nil(), 0x92, 0x04, None);
+        assert_eq!(
+            characteristic_flags(&characteristic),
+            Flags {
+                properties: GattCharacteristicProperties(0x92),
+                read_protection: ENCRYPTED,
+                write_protection: PLAIN,
+            }
+        );
+    }
+}
@@ -0,0 +1,534 @@
+mod characteristic;
+mod descriptor;
+mod flags;
+mod service;
+
+use futures::channel::mpsc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+use tokio::{runtime::Handle, sync::watch};
+use uuid::Uuid;
+use windows::Devices::Bluetooth::GenericAttributeProfile::{
+    GattLocalCharacteristic, GattServiceProvider,
+};
+
+pub use self::service::{is_started, publish};
+use self::{
+    characteristic::{notify_client, notify_value, subscribed, unsubscribed},
+    service::Service,
+};
+use super::common;
+use crate::{
+    gatt::{
+        self,
+        characteristic::{CharacteristicHandle, ReadSource},
+        default_handler::{DefaultHandlers, DefaultReadHandler, DefaultWriteHandler},
+        event::{WriteRequest, DEFAULT_NOTIFICATION_LENGTH},
+        notify_interceptor::{NotifyInterceptor, NotifyInterceptors},
+        notify_pause::{NotifyPause, PausePolicy},
+    },
+    metrics::Metrics,
+    span::Span,
+    Central, CentralId, CentralInfo, Error, ErrorType, NotifyError, PeripheralMetrics,
+};
+
+/// What the request and subscription handlers of every characteristic share.
+#[derive(Debug, Clone)]
+pub struct Handlers {
+    /// Windows calls the handlers on threads of its own, the requests are handed on from there.
+    pub runtime: Handle,
+    pub defaults: DefaultHandlers,
+    pub metrics: Arc<Metrics>,
+    /// The centrals Windows reports as subscribed, per characteristic with any.
+    subscribers: Arc<Mutex<HashMap<CharacteristicHandle, Vec<Central>>>>,
+    centrals: Arc<watch::Sender<usize>>,
+}
+
+impl Handlers {
+    fn new(runtime: Handle) -> Self {
+        Handlers {
+            runtime,
+            defaults: DefaultHandlers::default(),
+            metrics: Arc::new(Metrics::default()),
+            subscribers: Arc::new(Mutex::new(HashMap::new())),
+            centrals: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// Windows reports the clients subscribed to a characteristic as a whole, its handler is
+    /// told when the first one subscribed and the last one left.
+    pub fn subscribers_changed(
+        &self,
+        handle: CharacteristicHandle,
+        characteristic: &gatt::characteristic::Characteristic,
+        local: &GattLocalCharacteristic,
+        centrals: Vec<Central>,
+    ) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let count = centrals.len();
+        let previous = if centrals.is_empty() {
+            subscribers.remove(&handle)
+        } else {
+            subscribers.insert(handle, centrals)
+        }
+        .map_or(0, |previous| previous.len());
+        let connected = subscribers
+            .values()
+            .flatten()
+            .map(Central::id)
+            .collect::<HashSet<_>>()
+            .len();
+        drop(subscribers);
+
+        for _ in previous..count {
+            self.metrics.subscribed();
+        }
+        for _ in count..previous {
+            self.metrics.unsubscribed();
+        }
+        self.centrals.send_replace(connected);
+        if previous == 0 && count > 0 {
+            subscribed(characteristic, local.clone(), self);
+        } else if previous > 0 && count == 0 {
+            unsubscribed(characteristic, self);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Gatt {
+    handlers: Handlers,
+    services: Arc<Mutex<Vec<gatt::service::Service>>>,
+    /// The services Windows publishes, `None` until `register` created their providers.
+    registered: Arc<Mutex<Option<Vec<Service>>>>,
+    notify_interceptors: NotifyInterceptors,
+    notify_pause: NotifyPause,
+}
+
+impl Gatt {
+    pub fn new(runtime: Handle) -> Self {
+        Gatt {
+            handlers: Handlers::new(runtime),
+            services: Arc::new(Mutex::new(vec![])),
+            registered: Arc::new(Mutex::new(None)),
+            notify_interceptors: NotifyInterceptors::default(),
+            notify_pause: NotifyPause::default(),
+        }
+    }
+
+    pub fn add_service(
+        &self,
+        service: &gatt::service::Service,
+    ) -> Result<Vec<CharacteristicHandle>, Error> {
+        self.add_services(std::slice::from_ref(service))?;
+        Ok(service.handles())
+    }
+
+    /// Either all services are added or, if one fails, none are.
+    pub fn add_services(&self, services: &[gatt::service::Service]) -> Result<(), Error> {
+        if self.is_registered() {
+            return Err(already_registered());
+        }
+        let mut added = self.services.lock().unwrap();
+        for service in services {
+            service.check_duplicate_characteristics(ErrorType::Windows)?;
+            // Every service is published by a provider of its own, they can't include each other
+            if !service.included_services.is_empty() {
+                return Err(Error::new(
+                    "Unsupported",
+                    "Windows doesn't support included services",
+                    ErrorType::Windows,
+                ));
+            }
+        }
+        added.extend(services.iter().cloned());
+        Ok(())
+    }
+
+    /// Makes `services` the added services, the previous ones stay if one of them fails.
+    pub fn replace_services(&self, services: &[gatt::service::Service]) -> Result<(), Error> {
+        if self.is_registered() {
+            return Err(already_registered());
+        }
+        let previous = std::mem::take(&mut *self.services.lock().unwrap());
+        if let Err(err) = self.add_services(services) {
+            *self.services.lock().unwrap() = previous;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    pub fn remove_service(&self, uuid: &Uuid) -> Result<(), Error> {
+        let remaining = self
+            .services
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|service| service.uuid != *uuid)
+            .cloned()
+            .collect::<Vec<_>>();
+        self.replace_services(&remaining)
+    }
+
+    pub fn get_value(&self, characteristic_uuid: &Uuid) -> Option<Vec<u8>> {
+        self.services
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|service| service.characteristics.iter())
+            .find(|characteristic| characteristic.uuid == *characteristic_uuid)
+            .and_then(|characteristic| characteristic.value.clone())
+    }
+
+    pub fn pause_notifications(&self, policy: PausePolicy) {
+        self.notify_pause.pause(policy);
+    }
+
+    pub async fn resume_notifications(&self) -> Result<(), NotifyError> {
+        let mut result = Ok(());
+        for (handle, value) in self.notify_pause.resume() {
+            match self.notify_handle(&handle, &value).await {
+                // The centrals may have unsubscribed while notifications were paused
+                Ok(()) | Err(NotifyError::NoSubscribers) => {}
+                Err(err) => result = result.and(Err(err)),
+            }
+        }
+        result
+    }
+
+    pub fn set_notify_interceptor(&self, interceptor: NotifyInterceptor) {
+        self.notify_interceptors.set(interceptor);
+    }
+
+    pub fn set_default_read_handler(&self, handler: DefaultReadHandler) {
+        self.handlers.defaults.set_read(handler);
+    }
+
+    pub fn set_default_write_handler(&self, handler: DefaultWriteHandler) {
+        self.handlers.defaults.set_write(handler);
+    }
+
+    pub fn writes(&self, characteristic_uuid: &Uuid) -> mpsc::UnboundedReceiver<WriteRequest> {
+        self.handlers
+            .defaults
+            .open_write_stream(*characteristic_uuid)
+    }
+
+    pub fn describe(&self) -> String {
+        gatt::service::Service::describe_all(self.services.lock().unwrap().iter())
+    }
+
+    /// The UUIDs of the added services and their characteristics.
+    pub fn service_uuids(&self) -> HashMap<Uuid, Vec<Uuid>> {
+        self.services
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|service| {
+                let characteristics = service
+                    .characteristics
+                    .iter()
+                    .map(|characteristic| characteristic.uuid)
+                    .collect();
+                (service.uuid, characteristics)
+            })
+            .collect()
+    }
+
+    pub fn has_characteristic(&self, characteristic_uuid: &Uuid) -> bool {
+        self.first_handle(characteristic_uuid).is_some()
+    }
+
+    /// The registered characteristic with the UUID and the lowest instance.
+    fn first_handle(&self, characteristic_uuid: &Uuid) -> Option<CharacteristicHandle> {
+        self.registered
+            .lock()
+            .unwrap()
+            .as_ref()?
+            .iter()
+            .flat_map(|service| service.characteristics.iter())
+            .map(|(handle, _)| *handle)
+            .filter(|handle| handle.characteristic == *characteristic_uuid)
+            .min_by_key(|handle| handle.instance)
+    }
+
+    fn local(&self, handle: &CharacteristicHandle) -> Option<GattLocalCharacteristic> {
+        self.registered
+            .lock()
+            .unwrap()
+            .as_ref()?
+            .iter()
+            .flat_map(|service| service.characteristics.iter())
+            .find(|(registered, _)| registered == handle)
+            .map(|(_, characteristic)| characteristic.local.clone())
+    }
+
+    pub async fn notify(
+        &self,
+        characteristic_uuid: &Uuid,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        let handle = self
+            .first_handle(characteristic_uuid)
+            .ok_or(NotifyError::UnknownCharacteristic)?;
+        self.notify_handle(&handle, value).await
+    }
+
+    pub async fn notify_handle(
+        &self,
+        handle: &CharacteristicHandle,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        Span::new("notify", &handle.characteristic, None)
+            .instrument(self.send_notification(handle, value))
+            .await
+    }
+
+    async fn send_notification(
+        &self,
+        handle: &CharacteristicHandle,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        let local = self
+            .local(handle)
+            .ok_or(NotifyError::UnknownCharacteristic)?;
+        if self.notify_pause.hold(handle, value) {
+            return Ok(());
+        }
+        if !self
+            .handlers
+            .subscribers
+            .lock()
+            .unwrap()
+            .contains_key(handle)
+        {
+            return Err(NotifyError::NoSubscribers);
+        }
+        let value = self
+            .notify_interceptors
+            .intercept(&handle.characteristic, value);
+        notify_value(&local, &value, &self.handlers.metrics).await
+    }
+
+    /// Every subscriber is tried, the last failure is returned.
+    pub async fn update_all<F>(
+        &self,
+        characteristic_uuid: &Uuid,
+        value: F,
+    ) -> Result<(), NotifyError>
+    where
+        F: Fn(&CentralId) -> Vec<u8>,
+    {
+        let local = self
+            .first_handle(characteristic_uuid)
+            .and_then(|handle| self.local(&handle))
+            .ok_or(NotifyError::UnknownCharacteristic)?;
+        let clients = local
+            .SubscribedClients()
+            .into_iter()
+            .flatten()
+            .filter_map(|client| {
+                let central = common::central(&client.Session().ok()?).ok()?;
+                Some((central.id().clone(), client))
+            })
+            .collect::<Vec<_>>();
+        if clients.is_empty() {
+            return Err(NotifyError::NoSubscribers);
+        }
+        let mut result = Ok(());
+        for (central, client) in clients.iter() {
+            let value = self
+                .notify_interceptors
+                .intercept(characteristic_uuid, &value(central));
+            let span = Span::new("notify", characteristic_uuid, Some(central));
+            if let Err(err) = span
+                .instrument(notify_client(
+                    &local,
+                    client,
+                    &value,
+                    &self.handlers.metrics,
+                ))
+                .await
+            {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    /// The most bytes one notification of the characteristic can carry to every subscriber.
+    pub fn max_notification_length(&self, characteristic_uuid: &Uuid) -> usize {
+        self.first_handle(characteristic_uuid)
+            .and_then(|handle| {
+                self.handlers
+                    .subscribers
+                    .lock()
+                    .unwrap()
+                    .get(&handle)?
+                    .iter()
+                    .map(Central::maximum_update_value_length)
+                    .min()
+            })
+            .unwrap_or(DEFAULT_NOTIFICATION_LENGTH)
+    }
+
+    /// The centrals subscribed to any of the characteristics with the UUID.
+    pub fn subscribers(&self, characteristic_uuid: &Uuid) -> Vec<CentralId> {
+        self.handlers
+            .subscribers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(handle, _)| handle.characteristic == *characteristic_uuid)
+            .flat_map(|(_, centrals)| centrals.iter().map(|central| central.id().clone()))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    pub fn centrals(&self) -> Vec<CentralInfo> {
+        let mut centrals: HashMap<CentralId, CentralInfo> = HashMap::new();
+        for (handle, subscribers) in self.handlers.subscribers.lock().unwrap().iter() {
+            for central in subscribers {
+                let info = centrals
+                    .entry(central.id().clone())
+                    .or_insert_with(|| CentralInfo {
+                        id: central.id().clone(),
+                        maximum_update_value_length: central.maximum_update_value_length(),
+                        subscriptions: HashSet::new(),
+                    });
+                info.subscriptions.insert(handle.characteristic);
+            }
+        }
+        centrals.into_values().collect()
+    }
+
+    pub fn is_subscribed(&self, central: &CentralId, characteristic_uuid: &Uuid) -> bool {
+        self.subscribers(characteristic_uuid).contains(central)
+    }
+
+    /// How many centrals are subscribed to each characteristic UUID that has any.
+    pub fn subscriber_counts(&self) -> HashMap<Uuid, usize> {
+        self.handlers
+            .subscribers
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|handle| {
+                let subscribers = self.subscribers(&handle.characteristic);
+                (handle.characteristic, subscribers.len())
+            })
+            .collect()
+    }
+
+    /// How many distinct centrals are subscribed to any characteristic. Windows doesn't report
+    /// connections to a `GattServiceProvider`, only subscriptions.
+    pub fn central_count_receiver(&self) -> watch::Receiver<usize> {
+        self.handlers.centrals.subscribe()
+    }
+
+    pub fn metrics(&self) -> PeripheralMetrics {
+        self.handlers.metrics.snapshot()
+    }
+
+    /// Windows answers the reads of a characteristic with a value by itself, see
+    /// `GattLocalCharacteristicParameters::StaticValue`.
+    pub fn read_source(&self, characteristic_uuid: &Uuid) -> Option<ReadSource> {
+        self.services
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|service| service.characteristics.iter())
+            .find(|characteristic| characteristic.uuid == *characteristic_uuid)
+            .map(|characteristic| {
+                if characteristic.value.is_some() {
+                    ReadSource::Cached
+                } else if characteristic.properties.read.is_some() {
+                    ReadSource::Handler
+                } else {
+                    ReadSource::NotReadable
+                }
+            })
+    }
+
+    /// The providers of the registered services, to advertise them with.
+    pub fn providers(&self) -> Vec<(Uuid, GattServiceProvider)> {
+        self.registered
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .map(|service| (service.uuid, service.provider.clone()))
+            .collect()
+    }
+
+    /// Creates a provider for every added service and publishes it, connectable but not
+    /// discoverable until advertising starts. If one fails the ones published before it are
+    /// stopped again.
+    pub async fn register(&self) -> Result<(), Error> {
+        if self.is_registered() {
+            return Err(already_registered());
+        }
+        let services = self.services.lock().unwrap().clone();
+        let mut registered = vec![];
+        for service in services.iter() {
+            let result = match Service::new(service, &self.handlers).await {
+                Ok(service) => publish(&service.provider, true, false, None).map(|_| service),
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(service) => registered.push(service),
+                Err(err) => {
+                    for service in registered {
+                        service.provider.StopAdvertising().ok();
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        *self.registered.lock().unwrap() = Some(registered);
+        Ok(())
+    }
+
+    pub fn is_registered(&self) -> bool {
+        self.registered.lock().unwrap().is_some()
+    }
+
+    /// Stops publishing the services. Their subscriptions end with them, so the handlers are
+    /// told about it.
+    pub async fn unregister(&self) -> Result<(), Error> {
+        let services = self.registered.lock().unwrap().take().ok_or_else(|| {
+            Error::new(
+                "NotRegistered",
+                "The services aren't registered",
+                ErrorType::Windows,
+            )
+        })?;
+        let subscribed = std::mem::take(&mut *self.handlers.subscribers.lock().unwrap());
+        self.handlers.centrals.send_replace(0);
+        for service in services.iter() {
+            if is_started(&service.provider) {
+                service.provider.StopAdvertising()?;
+            }
+            for (handle, characteristic) in service.characteristics.iter() {
+                if let Some(centrals) = subscribed.get(handle) {
+                    for _ in centrals {
+                        self.handlers.metrics.unsubscribed();
+                    }
+                    unsubscribed(&characteristic.characteristic, &self.handlers);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn already_registered() -> Error {
+    Error::new(
+        "AlreadyRegistered",
+        "Services can't be added after they were registered",
+        ErrorType::Windows,
+    )
+}
@@ -0,0 +1,80 @@
+use std::sync::Arc;
+use uuid::Uuid;
+use windows::Devices::Bluetooth::GenericAttributeProfile::{
+    GattServiceProvider, GattServiceProviderAdvertisementStatus,
+    GattServiceProviderAdvertisingParameters,
+};
+
+use super::{
+    super::{
+        common::{guid, to_buffer},
+        error::check,
+    },
+    characteristic::Characteristic,
+    Handlers,
+};
+use crate::{
+    gatt::{self, characteristic::CharacteristicHandle},
+    Error,
+};
+
+/// A service published by its own `GattServiceProvider`, Windows has no GATT database of an
+/// application the services are added to.
+#[derive(Debug, Clone)]
+pub struct Service {
+    pub uuid: Uuid,
+    pub provider: GattServiceProvider,
+    pub characteristics: Vec<(CharacteristicHandle, Characteristic)>,
+}
+
+impl Service {
+    pub async fn new(service: &gatt::service::Service, handlers: &Handlers) -> Result<Self, Error> {
+        let result = GattServiceProvider::CreateAsync(guid(&service.uuid))?.await?;
+        check("CreateServiceFailed", result.Error()?)?;
+        let provider = result.ServiceProvider()?;
+        let local = provider.Service()?;
+
+        let mut characteristics = vec![];
+        for characteristic in service.resolved_characteristics() {
+            let handle = characteristic.handle(service.uuid);
+            let characteristic =
+                Characteristic::new(&local, &Arc::new(characteristic), handle, handlers).await?;
+            characteristics.push((handle, characteristic));
+        }
+
+        Ok(Service {
+            uuid: service.uuid,
+            provider,
+            characteristics,
+        })
+    }
+}
+
+/// (Re)starts publishing the service. Windows only lets a provider's advertising parameters be
+/// set when it starts, so a running provider is stopped first.
+pub fn publish(
+    provider: &GattServiceProvider,
+    connectable: bool,
+    discoverable: bool,
+    service_data: Option<&[u8]>,
+) -> Result<(), Error> {
+    let parameters = GattServiceProviderAdvertisingParameters::new()?;
+    parameters.SetIsConnectable(connectable)?;
+    parameters.SetIsDiscoverable(discoverable)?;
+    if let Some(service_data) = service_data {
+        parameters.SetServiceData(&to_buffer(service_data)?)?;
+    }
+    if is_started(provider) {
+        provider.StopAdvertising()?;
+    }
+    provider.StartAdvertisingWithParameters(&parameters)?;
+    Ok(())
+}
+
+pub fn is_started(provider: &GattServiceProvider) -> bool {
+    matches!(
+        provider.AdvertisementStatus(),
+        Ok(GattServiceProviderAdvertisementStatus::Started)
+            | Ok(GattServiceProviderAdvertisementStatus::StartedWithoutAllAdvertisementData)
+    )
+}
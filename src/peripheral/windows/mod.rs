@@ -0,0 +1,489 @@
+mod adapter;
+mod advertisement;
+mod common;
+mod error;
+mod gatt;
+
+use futures::channel::mpsc;
+use log::warn;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{runtime::Handle, sync::watch};
+use uuid::Uuid;
+
+use self::{adapter::Adapter, advertisement::Advertisement, gatt::Gatt};
+use super::PeripheralBuilder;
+use crate::{
+    gatt::{
+        characteristic::{CharacteristicHandle, ReadSource},
+        default_handler::RequestContext,
+        event::{ReadRequest, WriteRequest},
+        notify_pause::PausePolicy,
+        service::Service,
+    },
+    state, AdapterState, AdvertisingOptions, AdvertisingState, Capabilities, CentralId,
+    CentralInfo, Error, ErrorType, NotifyError, PeripheralMetrics, PeripheralSnapshot,
+};
+
+/// Cheap to clone, all clones drive the same service providers and advertisement.
+///
+/// Windows calls the handlers on threads of its own, the requests are handed to them on the
+/// tokio runtime `new` is awaited on, which has to keep running for as long as the peripheral
+/// serves them.
+#[derive(Debug, Clone)]
+pub struct Peripheral {
+    adapter: Adapter,
+    gatt: Gatt,
+    advertisement: Advertisement,
+    state_receiver: watch::Receiver<AdapterState>,
+    advertising_options: Arc<Mutex<Option<AdvertisingOptions>>>,
+    /// Set while advertising is stopped for `auto_stop_advertising_on_connect`.
+    advertising_paused: Arc<AtomicBool>,
+}
+
+impl Peripheral {
+    #[allow(clippy::new_ret_no_self)]
+    pub async fn new() -> Result<Self, Error> {
+        Self::from_builder(PeripheralBuilder::default()).await
+    }
+
+    pub(crate) async fn from_builder(builder: PeripheralBuilder) -> Result<Self, Error> {
+        if builder.restore_identifier.is_some() {
+            return Err(Error::new(
+                "Unsupported",
+                "State restoration is only supported on iOS",
+                ErrorType::Windows,
+            ));
+        }
+        let adapter = Adapter::new().await?;
+        let state_receiver = adapter.state_receiver();
+        if builder.fail_if_unsupported && *state_receiver.borrow() == AdapterState::Unsupported {
+            return Err(AdapterState::unsupported_error(ErrorType::Windows));
+        }
+        let gatt = Gatt::new(Handle::current());
+        let advertisement = Advertisement::new()?;
+
+        let advertising_paused = Arc::new(AtomicBool::new(false));
+        if builder.auto_stop_advertising {
+            tokio::spawn(pause_advertising(
+                advertisement.clone(),
+                Arc::clone(&advertising_paused),
+                gatt.central_count_receiver(),
+            ));
+        }
+
+        Ok(Peripheral {
+            adapter,
+            gatt,
+            advertisement,
+            state_receiver,
+            advertising_options: Arc::new(Mutex::new(None)),
+            advertising_paused,
+        })
+    }
+
+    pub async fn is_powered(&self) -> Result<bool, Error> {
+        self.adapter.is_powered()
+    }
+
+    /// The adapter state, which `new` already read from the radio so this doesn't wait.
+    pub async fn current_state(&self) -> AdapterState {
+        state::current_state(self.state_receiver.clone()).await
+    }
+
+    /// The adapter state last reported by the radio, like `current_state` without awaiting.
+    pub fn state(&self) -> AdapterState {
+        *self.state_receiver.borrow()
+    }
+
+    /// Resolves once the radio is turned on. Fails right away if the adapter can't act as a
+    /// BLE peripheral.
+    pub async fn wait_until_powered_on(&self) -> Result<(), Error> {
+        state::wait_until_powered_on(self.state_receiver.clone(), ErrorType::Windows).await
+    }
+
+    pub async fn central_rssi(&self, _central: &CentralId) -> Result<Option<i16>, Error> {
+        Err(Error::new(
+            "Unsupported",
+            "Reading the RSSI of a central is not supported on Windows",
+            ErrorType::Windows,
+        ))
+    }
+
+    /// The name of the central's device, `None` if Windows doesn't know it.
+    pub async fn central_name(&self, central: &CentralId) -> Option<String> {
+        self.adapter.get_device_name(central).await
+    }
+
+    /// Windows doesn't tell how many connections the controller supports, so this is always
+    /// `None`.
+    pub fn max_connections(&self) -> Option<usize> {
+        None
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            advertising_service_data: true,
+            advertising_manufacturer_data: true,
+            advertising_interval: false,
+            advertising_non_connectable: true,
+            advertising_scan_response: false,
+            central_rssi: false,
+            descriptors: true,
+            subscribers: true,
+            services_after_registration: false,
+            state_restoration: false,
+            max_connections: self.max_connections(),
+        }
+    }
+
+    /// Everything tracked about the peripheral in one go, without asking Windows.
+    pub fn snapshot(&self) -> PeripheralSnapshot {
+        PeripheralSnapshot {
+            adapter_state: *self.state_receiver.borrow(),
+            advertising_state: *self.advertisement.state_receiver().borrow(),
+            advertising_options: self.advertising_options.lock().unwrap().clone(),
+            services: self.gatt.service_uuids(),
+            subscribers: self.gatt.subscriber_counts(),
+            metrics: self.gatt.metrics(),
+        }
+    }
+
+    /// A readable outline of the added services, see `Service::describe`.
+    pub fn describe(&self) -> String {
+        self.gatt.describe()
+    }
+
+    /// Whether a registered service contains the characteristic.
+    pub fn has_characteristic(&self, characteristic_uuid: &Uuid) -> bool {
+        self.gatt.has_characteristic(characteristic_uuid)
+    }
+
+    /// Notifies the subscribers of the characteristic, the one with the lowest instance if several
+    /// share the UUID, and waits until Windows sent it. Fails unless at least one of them got it.
+    pub async fn notify(
+        &self,
+        characteristic_uuid: &Uuid,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        self.gatt.notify(characteristic_uuid, value).await
+    }
+
+    /// The most bytes one notification of the characteristic carries to every subscriber, the
+    /// 20 bytes of the default ATT MTU without any.
+    pub fn max_notification_length(&self, characteristic_uuid: &Uuid) -> usize {
+        self.gatt.max_notification_length(characteristic_uuid)
+    }
+
+    /// Splits the value into chunks of `max_notification_length` and notifies them in order like
+    /// `notify` does. Stops at the first chunk that fails.
+    pub async fn notify_chunked(
+        &self,
+        characteristic_uuid: &Uuid,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        let length = self.max_notification_length(characteristic_uuid);
+        for chunk in value.chunks(length.max(1)) {
+            self.notify(characteristic_uuid, chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// Notifies every subscriber of the characteristic the value `value` returns for it, the
+    /// characteristic with the lowest instance if several share the UUID. The values aren't held
+    /// while notifications are paused. Every subscriber is tried, the last failure is returned.
+    pub async fn update_all<F>(
+        &self,
+        characteristic_uuid: &Uuid,
+        value: F,
+    ) -> Result<(), NotifyError>
+    where
+        F: Fn(&CentralId) -> Vec<u8>,
+    {
+        self.gatt.update_all(characteristic_uuid, value).await
+    }
+
+    /// Like `notify`, for the characteristic of one of the handles `add_service` returned.
+    pub async fn notify_handle(
+        &self,
+        handle: &CharacteristicHandle,
+        value: &[u8],
+    ) -> Result<(), NotifyError> {
+        self.gatt.notify_handle(handle, value).await
+    }
+
+    /// Receives every adapter state change, `changed().await` resolves on the next one.
+    pub fn power_state_stream(&self) -> watch::Receiver<AdapterState> {
+        self.state_receiver.clone()
+    }
+
+    /// Reports when Windows aborts advertising on its own, e.g. because the radio was turned off.
+    pub fn advertising_state_stream(&self) -> watch::Receiver<AdvertisingState> {
+        self.advertisement.state_receiver()
+    }
+
+    /// Creates a `GattServiceProvider` for every added service and publishes it. The services
+    /// only become discoverable once advertising starts.
+    pub async fn register_gatt(&self) -> Result<(), Error> {
+        self.gatt.register().await
+    }
+
+    pub async fn unregister_gatt(&self) -> Result<(), Error> {
+        self.gatt.unregister().await
+    }
+
+    /// Windows doesn't let apps set the advertised name, so `name` is ignored.
+    pub async fn start_advertising(&self, name: &str, uuids: &[Uuid]) -> Result<(), Error> {
+        self.start_advertising_with(&AdvertisingOptions::new(
+            Some(name.to_string()).filter(|name| !name.is_empty()),
+            uuids.to_vec(),
+        ))
+        .await
+    }
+
+    /// Windows advertises the computer's name by itself and doesn't tell which, so this is always
+    /// `None`.
+    pub async fn system_default_name(&self) -> Option<String> {
+        None
+    }
+
+    /// Windows only advertises the UUIDs and service data of registered services, through their
+    /// `GattServiceProvider`, so any other UUID results in an error. The manufacturer data goes
+    /// out in a separate, non-connectable advertisement. The local name, intervals and
+    /// `scan_response` are ignored, Windows doesn't let apps choose them.
+    pub async fn start_advertising_with(&self, options: &AdvertisingOptions) -> Result<(), Error> {
+        self.advertise(options, None)
+    }
+
+    /// Advertises until `duration` elapsed. Stopping or restarting the advertisement earlier
+    /// cancels the timeout, afterwards `is_advertising` returns `false` and
+    /// `advertising_state_stream` reports `AdvertisingState::TimedOut`.
+    pub async fn advertise_for(
+        &self,
+        options: &AdvertisingOptions,
+        duration: Duration,
+    ) -> Result<(), Error> {
+        self.advertise(options, Some(duration))
+    }
+
+    fn advertise(
+        &self,
+        options: &AdvertisingOptions,
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        if options.local_name.is_some() {
+            warn!("Windows doesn't let apps set the advertised local name");
+        }
+        options.warn_if_truncated();
+        let providers = self.gatt.providers();
+        let mut uuids = options.service_uuids.clone();
+        uuids.extend(
+            options
+                .service_data
+                .keys()
+                .filter(|uuid| !options.service_uuids.contains(uuid)),
+        );
+        let services = uuids
+            .iter()
+            .map(|uuid| {
+                let provider = providers
+                    .iter()
+                    .find(|(service, _)| service == uuid)
+                    .map(|(_, provider)| provider.clone())
+                    .ok_or_else(|| {
+                        Error::new(
+                            String::from("UnsupportedAdvertisingField"),
+                            format!(
+                                "Windows only advertises registered services, {} isn't one",
+                                uuid
+                            ),
+                            ErrorType::Windows,
+                        )
+                    })?;
+                Ok((provider, options.service_data.get(uuid).cloned()))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        self.advertising_paused.store(false, Ordering::SeqCst);
+        self.advertisement.add_services(services);
+        self.advertisement.add_connectable(options.connectable);
+        self.advertisement
+            .add_manufacturer_data(options.manufacturer_data.clone());
+        self.advertisement.register()?;
+        *self.advertising_options.lock().unwrap() = Some(options.clone());
+        if let Some(duration) = duration {
+            self.advertisement.stop_after(duration);
+        }
+        Ok(())
+    }
+
+    pub async fn stop_advertising(&self) -> Result<(), Error> {
+        self.advertising_paused.store(false, Ordering::SeqCst);
+        self.advertisement.unregister()
+    }
+
+    /// Stops advertising, then stops publishing the services. Clones of this `Peripheral` share
+    /// them, so they are torn down for them as well.
+    pub async fn shutdown(self) -> Result<(), Error> {
+        self.advertising_paused.store(false, Ordering::SeqCst);
+        if self.advertisement.is_advertising() {
+            self.advertisement.unregister()?;
+        }
+        if self.gatt.is_registered() {
+            self.gatt.unregister().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn is_advertising(&self) -> Result<bool, Error> {
+        Ok(self.advertisement.is_advertising())
+    }
+
+    /// Returns a handle for every characteristic, which tells apart characteristics sharing a
+    /// UUID. A service repeating a UUID without `Service::allow_duplicate_characteristics` is an
+    /// error, and so is a service including others.
+    pub fn add_service(&self, service: &Service) -> Result<Vec<CharacteristicHandle>, Error> {
+        self.gatt.add_service(service)
+    }
+
+    /// Whether reads of the characteristic reach its handler. Windows serves the value a
+    /// characteristic was added with by itself, which makes it `ReadSource::Cached`. `None` if no
+    /// added service contains it.
+    pub fn read_source(&self, characteristic_uuid: &Uuid) -> Option<ReadSource> {
+        self.gatt.read_source(characteristic_uuid)
+    }
+
+    /// Either all services are added or, if one fails, none are.
+    pub async fn add_services(&self, services: &[Service]) -> Result<(), Error> {
+        self.gatt.add_services(services)
+    }
+
+    /// Makes `services` the added services. Like `add_service` this only works before
+    /// `register_gatt`, a published `GattServiceProvider` can't change its characteristics. If
+    /// one fails the previous ones stay.
+    pub fn replace_services(&self, services: &[Service]) -> Result<(), Error> {
+        self.gatt.replace_services(services)
+    }
+
+    /// Like `replace_services` with every service but this one, so it only works before
+    /// `register_gatt`. Removing a service that isn't added does nothing.
+    pub fn remove_service(&self, uuid: &Uuid) -> Result<(), Error> {
+        self.gatt.remove_service(uuid)
+    }
+
+    pub fn remove_all_services(&self) -> Result<(), Error> {
+        self.gatt.replace_services(&[])
+    }
+
+    /// The value the characteristic was added with, `None` if it has none or no added service
+    /// contains it.
+    pub fn get_value(&self, characteristic_uuid: &Uuid) -> Option<Vec<u8>> {
+        self.gatt.get_value(characteristic_uuid)
+    }
+
+    /// The centrals subscribed to any of the characteristics with the UUID.
+    pub fn subscribers(&self, characteristic_uuid: &Uuid) -> Vec<CentralId> {
+        self.gatt.subscribers(characteristic_uuid)
+    }
+
+    /// Every central subscribed to any characteristic, with what it's subscribed to.
+    pub fn centrals(&self) -> Vec<CentralInfo> {
+        self.gatt.centrals()
+    }
+
+    /// Whether the central is subscribed to the characteristic's notifications or indications.
+    pub fn is_subscribed(&self, central: &CentralId, characteristic_uuid: &Uuid) -> bool {
+        self.gatt.is_subscribed(central, characteristic_uuid)
+    }
+
+    /// Does nothing, Windows ends the subscriptions of centrals once they disconnect.
+    pub fn forget_central(&self, _central: &CentralId) {}
+
+    /// Counters of the operations served since the peripheral was created, shared by all clones.
+    pub fn metrics(&self) -> PeripheralMetrics {
+        self.gatt.metrics()
+    }
+
+    /// Holds back the notifications `notify`, `notify_handle` and `notify_chunked` send until
+    /// `resume_notifications`, e.g. during a firmware update. They return `Ok` right away while
+    /// paused and `policy` decides whether the values are kept. Keeping the latest value of a
+    /// characteristic also means only the last chunk of a `notify_chunked` call survives.
+    /// Notifications sent through a subscription's `notification` channel aren't paused.
+    pub fn pause_notifications(&self, policy: PausePolicy) {
+        self.gatt.pause_notifications(policy);
+    }
+
+    /// Sends the values kept while notifications were paused, in no particular order, and lets
+    /// notifications through again. Values nobody is subscribed to anymore are dropped, the first
+    /// other failure is returned once the rest were sent.
+    pub async fn resume_notifications(&self) -> Result<(), NotifyError> {
+        self.gatt.resume_notifications().await
+    }
+
+    /// Hands the payload of every notification `notify`, `notify_handle`, `notify_chunked` and
+    /// `update_all` send to `interceptor` right before it's sent, which may change it. It runs on
+    /// the thread that awaits the notify call, once per chunk and central. Notifications sent
+    /// through a subscription's `notification` channel don't pass it. Replaces the previous
+    /// interceptor.
+    pub fn set_notify_interceptor<F>(&self, interceptor: F)
+    where
+        F: Fn(&Uuid, &mut Vec<u8>) + Send + Sync + 'static,
+    {
+        self.gatt.set_notify_interceptor(Arc::new(interceptor));
+    }
+
+    /// Handles the reads of characteristics declared with `default_handler::unhandled` as their
+    /// event sender. A characteristic's own handler always takes precedence. Replaces the
+    /// previous default handler.
+    pub fn set_default_read_handler<F>(&self, handler: F)
+    where
+        F: Fn(RequestContext, ReadRequest) + Send + Sync + 'static,
+    {
+        self.gatt.set_default_read_handler(Arc::new(handler));
+    }
+
+    /// Like `set_default_read_handler`, but for writes with and without response.
+    pub fn set_default_write_handler<F>(&self, handler: F)
+    where
+        F: Fn(RequestContext, WriteRequest) + Send + Sync + 'static,
+    {
+        self.gatt.set_default_write_handler(Arc::new(handler));
+    }
+
+    /// The writes to the characteristic, with and without response, for handling them in a
+    /// `while let Some(write) = writes.next().await` loop, e.g. for a control point. While the
+    /// stream is alive writes reach it instead of the characteristic's handler or the default
+    /// one. Opening another stream for the characteristic ends this one.
+    pub fn writes(&self, characteristic_uuid: &Uuid) -> mpsc::UnboundedReceiver<WriteRequest> {
+        self.gatt.writes(characteristic_uuid)
+    }
+}
+
+/// Stops advertising once a central subscribed and starts it again once the last one
+/// unsubscribed, unless advertising was started or stopped in between. Windows doesn't report
+/// connections to a `GattServiceProvider`, so a subscription is taken as one.
+async fn pause_advertising(
+    advertisement: Advertisement,
+    advertising_paused: Arc<AtomicBool>,
+    mut centrals: watch::Receiver<usize>,
+) {
+    while centrals.changed().await.is_ok() {
+        let connected = *centrals.borrow_and_update() > 0;
+        let result = if connected && advertisement.is_advertising() {
+            advertising_paused.store(true, Ordering::SeqCst);
+            advertisement.unregister()
+        } else if !connected && advertising_paused.swap(false, Ordering::SeqCst) {
+            advertisement.register()
+        } else {
+            continue;
+        };
+        if let Err(error) = result {
+            warn!("Couldn't pause or resume advertising: {:?}", error);
+        }
+    }
+}
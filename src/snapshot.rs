@@ -0,0 +1,20 @@
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::{AdapterState, AdvertisingOptions, AdvertisingState, PeripheralMetrics};
+
+/// The state of a `Peripheral` at one point in time, see `Peripheral::snapshot`. It owns all of
+/// its data, so it can be handed to a status endpoint or logged from any thread.
+#[derive(Debug, Clone)]
+pub struct PeripheralSnapshot {
+    pub adapter_state: AdapterState,
+    pub advertising_state: AdvertisingState,
+    /// The options advertising was last started with, `None` if it never was.
+    pub advertising_options: Option<AdvertisingOptions>,
+    /// The UUIDs of the added services and their characteristics.
+    pub services: HashMap<Uuid, Vec<Uuid>>,
+    /// How many centrals are subscribed to each characteristic that has subscribers. Empty on
+    /// backends that don't track subscribers, see `Capabilities::subscribers`.
+    pub subscribers: HashMap<Uuid, usize>,
+    pub metrics: PeripheralMetrics,
+}
@@ -0,0 +1,62 @@
+use std::future::Future;
+use uuid::Uuid;
+
+use crate::CentralId;
+
+/// A `tracing` span around one read, write or notification, carrying the characteristic and the
+/// central. Without the `tracing` feature it does nothing.
+///
+/// It's created where the request arrives, e.g. on CoreBluetooth's dispatch queue, and handed to
+/// the task answering it, so it's only ever entered on the thread polling that task.
+#[derive(Debug, Clone)]
+pub(crate) struct Span {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl Span {
+    pub fn new(
+        operation: &'static str,
+        characteristic: &Uuid,
+        central: Option<&CentralId>,
+    ) -> Self {
+        #[cfg(feature = "tracing")]
+        {
+            Span {
+                span: tracing::info_span!(
+                    "gatt",
+                    operation,
+                    characteristic = %characteristic,
+                    central = central.map_or("unknown", CentralId::as_str),
+                ),
+            }
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = (operation, characteristic, central);
+            Span {}
+        }
+    }
+
+    pub fn in_scope<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        #[cfg(feature = "tracing")]
+        {
+            self.span.in_scope(f)
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            f()
+        }
+    }
+
+    pub async fn instrument<F: Future>(self, future: F) -> F::Output {
+        #[cfg(feature = "tracing")]
+        {
+            tracing::Instrument::instrument(future, self.span).await
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            future.await
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use tokio::sync::watch;
+
+use crate::{Error, ErrorType};
+
+/// State of the Bluetooth adapter backing a `Peripheral`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AdapterState {
+    #[default]
+    Unknown,
+    Resetting,
+    Unsupported,
+    Unauthorized,
+    PoweredOff,
+    PoweredOn,
+}
+
+impl AdapterState {
+    pub fn is_powered_on(self) -> bool {
+        self == AdapterState::PoweredOn
+    }
+
+    pub(crate) fn unsupported_error(error_type: ErrorType) -> Error {
+        Error::new(
+            "Unsupported",
+            "The adapter doesn't support acting as a BLE peripheral",
+            error_type,
+        )
+    }
+
+    pub(crate) fn unauthorized_error(error_type: ErrorType) -> Error {
+        Error::new(
+            "Unauthorized",
+            "The app isn't authorized to use Bluetooth",
+            error_type,
+        )
+    }
+}
+
+/// Resolves once `states` reports `PoweredOn`, or fails as soon as it reports `Unsupported` or
+/// `Unauthorized` since the adapter can't be used then.
+pub(crate) async fn wait_until_powered_on(
+    mut states: watch::Receiver<AdapterState>,
+    error_type: ErrorType,
+) -> Result<(), Error> {
+    loop {
+        match *states.borrow_and_update() {
+            AdapterState::PoweredOn => return Ok(()),
+            AdapterState::Unsupported => return Err(AdapterState::unsupported_error(error_type)),
+            AdapterState::Unauthorized => return Err(AdapterState::unauthorized_error(error_type)),
+            _ => {}
+        }
+        if states.changed().await.is_err() {
+            return Err(Error::new(
+                "StateUnavailable",
+                "The adapter state is no longer reported",
+                error_type,
+            ));
+        }
+    }
+}
+
+/// The first state `states` reports other than `Unknown`, which is all there is until the backend
+/// first reported the state. Should it stop reporting before, that's the state returned.
+pub(crate) async fn current_state(mut states: watch::Receiver<AdapterState>) -> AdapterState {
+    loop {
+        let state = *states.borrow_and_update();
+        if state != AdapterState::Unknown || states.changed().await.is_err() {
+            return state;
+        }
+    }
+}
+
+/// Whether CoreBluetooth's dispatch queue keeps up, reported by the watchdog enabled with
+/// `PeripheralBuilder::queue_watchdog`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueueHealth {
+    #[default]
+    Responsive,
+    /// A no-op took longer than the watchdog's threshold to run, every operation on the
+    /// peripheral is likely hanging. Goes back to `Responsive` once it ran.
+    Stalled,
+}
+
+/// Whether the `Peripheral` advertises, see `Peripheral::advertising_state_stream`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AdvertisingState {
+    #[default]
+    Stopped,
+    Advertising,
+    /// The system stopped advertising without `stop_advertising` being called, e.g. because the
+    /// app went to the background or the adapter was powered off. Stays until advertising is
+    /// started or stopped again.
+    Interrupted,
+    /// The duration passed to `Peripheral::advertise_for` elapsed and advertising stopped. Stays
+    /// until advertising is started or stopped again.
+    TimedOut,
+}
@@ -1,3 +1,4 @@
+use std::{convert::TryFrom, error, fmt, str::FromStr};
 use uuid::Uuid;
 
 const BASE_UUID: (u32, u16, u16, &[u8; 8]) = (0, 0, 0x1000, b"\x80\x00\x00\x80\x5F\x9B\x34\xFB");
@@ -10,3 +11,174 @@ pub trait SdpShortUuid<T: Into<u32>> {
 
 impl SdpShortUuid<u16> for Uuid {}
 impl SdpShortUuid<u32> for Uuid {}
+
+/// A 16 or 32 bit UUID assigned by the Bluetooth SIG, e.g. `"180D"` for the Heart Rate service.
+/// It stands for `0000180D-0000-1000-8000-00805F9B34FB`, the value added to the Bluetooth base
+/// UUID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShortUuid(u32);
+
+impl ShortUuid {
+    pub const fn to_uuid(self) -> Uuid {
+        Uuid::from_fields(self.0, BASE_UUID.1, BASE_UUID.2, BASE_UUID.3)
+    }
+
+    /// The short form of `uuid`, `None` if it isn't based on the Bluetooth base UUID.
+    pub fn from_uuid(uuid: &Uuid) -> Option<Self> {
+        let (value, data2, data3, data4) = uuid.as_fields();
+        if (data2, data3, data4) == (BASE_UUID.1, BASE_UUID.2, BASE_UUID.3) {
+            Some(ShortUuid(value))
+        } else {
+            None
+        }
+    }
+}
+
+/// The short form of SIG assigned UUIDs, the hyphenated full form of any other.
+pub(crate) fn display_uuid(uuid: &Uuid) -> String {
+    match ShortUuid::from_uuid(uuid) {
+        Some(short_uuid) => short_uuid.to_string(),
+        None => uuid.hyphenated().to_string(),
+    }
+}
+
+/// Parses a UUID in a constant expression, a malformed one fails to compile. Takes either the short
+/// form of a SIG assigned UUID, like `"180D"` or `"0x180D"`, or the full form.
+#[macro_export]
+macro_rules! uuid {
+    ($uuid:expr) => {{
+        const UUID: $crate::__private::Uuid = $crate::__private::parse_uuid($uuid);
+        UUID
+    }};
+}
+
+#[doc(hidden)]
+pub const fn parse_uuid(uuid: &str) -> Uuid {
+    let bytes = uuid.as_bytes();
+    let start = if bytes.len() > 2 && bytes[0] == b'0' && (bytes[1] == b'x' || bytes[1] == b'X') {
+        2
+    } else {
+        0
+    };
+    if bytes.len() - start != 4 && bytes.len() - start != 8 {
+        return match Uuid::try_parse(uuid) {
+            Ok(uuid) => uuid,
+            Err(_) => panic!("invalid UUID"),
+        };
+    }
+    let mut value = 0u32;
+    let mut index = start;
+    while index < bytes.len() {
+        let digit = match bytes[index] {
+            b'0'..=b'9' => bytes[index] - b'0',
+            b'a'..=b'f' => bytes[index] - b'a' + 10,
+            b'A'..=b'F' => bytes[index] - b'A' + 10,
+            _ => panic!("invalid short UUID"),
+        };
+        value = value << 4 | digit as u32;
+        index += 1;
+    }
+    ShortUuid(value).to_uuid()
+}
+
+impl From<u16> for ShortUuid {
+    fn from(uuid: u16) -> Self {
+        ShortUuid(uuid.into())
+    }
+}
+
+impl From<u32> for ShortUuid {
+    fn from(uuid: u32) -> Self {
+        ShortUuid(uuid)
+    }
+}
+
+impl From<ShortUuid> for Uuid {
+    fn from(uuid: ShortUuid) -> Self {
+        uuid.to_uuid()
+    }
+}
+
+impl FromStr for ShortUuid {
+    type Err = ParseShortUuidError;
+
+    /// Parses 4 or 8 hexadecimal digits, optionally prefixed with `0x`.
+    fn from_str(uuid: &str) -> Result<Self, Self::Err> {
+        let digits = uuid
+            .strip_prefix("0x")
+            .or_else(|| uuid.strip_prefix("0X"))
+            .unwrap_or(uuid);
+        if (digits.len() != 4 && digits.len() != 8)
+            || !digits.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return Err(ParseShortUuidError(uuid.to_string()));
+        }
+        u32::from_str_radix(digits, 16)
+            .map(ShortUuid)
+            .map_err(|_| ParseShortUuidError(uuid.to_string()))
+    }
+}
+
+impl TryFrom<&str> for ShortUuid {
+    type Error = ParseShortUuidError;
+
+    fn try_from(uuid: &str) -> Result<Self, Self::Error> {
+        uuid.parse()
+    }
+}
+
+/// The compact form, 4 digits if the value fits into 16 bits and 8 otherwise.
+impl fmt::Display for ShortUuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 <= u32::from(u16::MAX) {
+            write!(f, "{:04X}", self.0)
+        } else {
+            write!(f, "{:08X}", self.0)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseShortUuidError(String);
+
+impl fmt::Display for ParseShortUuidError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "\"{}\" is not a 16 or 32 bit UUID of 4 or 8 hexadecimal digits",
+            self.0
+        )
+    }
+}
+
+impl error::Error for ParseShortUuidError {}
+
+// `display_uuid` is private to the crate, so it can't be tested from `tests/`.
+#[cfg(test)]
+mod tests {
+    use super::{display_uuid, SdpShortUuid};
+    use uuid::Uuid;
+
+    #[test]
+    fn display_uuid_shortens_sig_uuids() {
+        assert_eq!(display_uuid(&Uuid::from_sdp_short_uuid(0x180D_u16)), "180D");
+        assert_eq!(display_uuid(&Uuid::from_sdp_short_uuid(0x0001_u16)), "0001");
+        assert_eq!(
+            display_uuid(&Uuid::from_sdp_short_uuid(0x1234_ABCD_u32)),
+            "1234ABCD"
+        );
+        assert_eq!(
+            display_uuid(&Uuid::from_sdp_short_uuid(0x0001_0000_u32)),
+            "00010000"
+        );
+    }
+
+    #[test]
+    fn display_uuid_keeps_other_uuids_whole() {
+        let nordic_uart = Uuid::parse_str("6E400001-B5A3-F393-E0A9-E50E24DCCA9E").unwrap();
+        assert_eq!(
+            display_uuid(&nordic_uart),
+            "6e400001-b5a3-f393-e0a9-e50e24dcca9e"
+        );
+    }
+}
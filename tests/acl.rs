@@ -0,0 +1,17 @@
+use bluster::{gatt::acl::CharacteristicAcl, CentralId};
+
+#[test]
+fn test_acl_allows_listed_centrals() {
+    let acl = CharacteristicAcl::new();
+    let central = CentralId::new("/org/bluez/hci0/dev_00_11_22_33_44_55");
+    assert!(!acl.is_allowed(Some(&central)));
+
+    // Clones share the list, like the one attached to a characteristic
+    acl.clone().allow(central.clone());
+    assert!(acl.is_allowed(Some(&central)));
+    assert!(!acl.is_allowed(Some(&CentralId::new("other"))));
+    assert!(!acl.is_allowed(None));
+
+    acl.revoke(&central);
+    assert!(!acl.is_allowed(Some(&central)));
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use bluster::{AdvertisingField, AdvertisingOptions, SdpShortUuid};
+
+#[test]
+fn test_truncated_fields_keeps_fitting_advertisement() {
+    let options = AdvertisingOptions::new(
+        Some(String::from("Thermometer")),
+        vec![
+            Uuid::from_sdp_short_uuid(0x1809_u16),
+            Uuid::from_sdp_short_uuid(0x180F_u16),
+        ],
+    );
+    // Flags 3, 16 bit UUIDs 2 + 4, name 2 + 11
+    assert_eq!(options.truncated_fields(), vec![]);
+}
+
+#[test]
+fn test_truncated_fields_drops_name_after_uuids() {
+    let nus = Uuid::parse_str("6e400001-b5a3-f393-e0a9-e50e24dcca9e").unwrap();
+    let options = AdvertisingOptions::new(Some(String::from("Sensor")), vec![nus]);
+    // Flags 3, 128 bit UUID 2 + 16, name 2 + 6 is 29
+    assert_eq!(options.truncated_fields(), vec![]);
+
+    let options = AdvertisingOptions::new(Some(String::from("Sensor 12")), vec![nus]);
+    assert_eq!(
+        options.truncated_fields(),
+        vec![AdvertisingField::LocalName]
+    );
+
+    let other = Uuid::parse_str("0000fe59-0000-1000-8000-00805f9b34fc").unwrap();
+    let options = AdvertisingOptions {
+        service_data: vec![(Uuid::from_sdp_short_uuid(0x180F_u16), vec![100])]
+            .into_iter()
+            .collect::<HashMap<_, _>>(),
+        ..AdvertisingOptions::new(None, vec![nus, other])
+    };
+    assert_eq!(
+        options.truncated_fields(),
+        vec![AdvertisingField::ServiceUuid(other)]
+    );
+}
+
+#[test]
+fn test_truncated_fields_counts_scan_response_separately() {
+    let nus = Uuid::parse_str("6e400001-b5a3-f393-e0a9-e50e24dcca9e").unwrap();
+    let options = AdvertisingOptions::new(Some(String::from("Nordic UART Sensor")), vec![nus]);
+    assert_eq!(
+        options.truncated_fields(),
+        vec![AdvertisingField::LocalName]
+    );
+
+    let options = options.with_scan_response(AdvertisingField::LocalName);
+    assert_eq!(options.truncated_fields(), vec![]);
+
+    let options = AdvertisingOptions {
+        local_name: Some("A name far too long for any scan response".to_string()),
+        ..options
+    };
+    assert_eq!(
+        options.truncated_fields(),
+        vec![AdvertisingField::LocalName]
+    );
+}
+
+#[test]
+fn test_truncated_fields_counts_manufacturer_data() {
+    let options = AdvertisingOptions::new(Some(String::from("Beacon")), vec![])
+        .with_manufacturer_data(0x004C, vec![0; 20]);
+    assert_eq!(
+        options.truncated_fields(),
+        vec![AdvertisingField::LocalName]
+    );
+
+    let options = options.with_manufacturer_data(0x004C, vec![0; 25]);
+    assert_eq!(
+        options.truncated_fields(),
+        vec![AdvertisingField::ManufacturerData]
+    );
+}
@@ -0,0 +1,51 @@
+use bluster::gatt::event::{AttError, Response};
+
+const ATT_ERRORS: [(AttError, u8); 17] = [
+    (AttError::InvalidHandle, 0x01),
+    (AttError::ReadNotPermitted, 0x02),
+    (AttError::WriteNotPermitted, 0x03),
+    (AttError::InvalidPdu, 0x04),
+    (AttError::InsufficientAuthentication, 0x05),
+    (AttError::RequestNotSupported, 0x06),
+    (AttError::InvalidOffset, 0x07),
+    (AttError::InsufficientAuthorization, 0x08),
+    (AttError::PrepareQueueFull, 0x09),
+    (AttError::AttributeNotFound, 0x0A),
+    (AttError::AttributeNotLong, 0x0B),
+    (AttError::InsufficientEncryptionKeySize, 0x0C),
+    (AttError::InvalidAttributeValueLength, 0x0D),
+    (AttError::UnlikelyError, 0x0E),
+    (AttError::InsufficientEncryption, 0x0F),
+    (AttError::UnsupportedGroupType, 0x10),
+    (AttError::InsufficientResources, 0x11),
+];
+
+#[test]
+fn test_att_error_codes() {
+    for (error, code) in ATT_ERRORS.iter() {
+        assert_eq!(error.code(), *code, "{:?}", error);
+    }
+}
+
+#[test]
+fn test_error_response_is_not_an_empty_value() {
+    let denied = Response::from(Err(AttError::ReadNotPermitted));
+    assert_eq!(denied.into_result(), Err(AttError::ReadNotPermitted));
+    assert_eq!(Response::Success(vec![]).into_result(), Ok(vec![]));
+}
+
+#[test]
+fn test_legacy_responses_map_to_att_errors() {
+    assert_eq!(
+        Response::InvalidOffset.into_result(),
+        Err(AttError::InvalidOffset)
+    );
+    assert_eq!(
+        Response::InvalidAttributeLength.into_result(),
+        Err(AttError::InvalidAttributeValueLength)
+    );
+    assert_eq!(
+        Response::UnlikelyError.into_result(),
+        Err(AttError::UnlikelyError)
+    );
+}
@@ -0,0 +1,43 @@
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use bluster::{
+    gatt::{
+        characteristic::{Characteristic, CharacteristicHandle, Properties},
+        service::Service,
+    },
+    SdpShortUuid,
+};
+
+fn characteristic(instance: u16) -> Characteristic {
+    Characteristic::new(
+        Uuid::from_sdp_short_uuid(0x2A6E_u16),
+        Properties::new(None, None, None, None),
+        None,
+        HashSet::new(),
+    )
+    .with_instance(instance)
+}
+
+#[test]
+fn test_service_keeps_characteristics_sharing_a_uuid() {
+    let service_uuid = Uuid::from_sdp_short_uuid(0x181A_u16);
+    let service = Service::new(
+        service_uuid,
+        true,
+        vec![characteristic(0), characteristic(1), characteristic(1)]
+            .into_iter()
+            .collect(),
+    );
+    assert_eq!(service.handles().len(), 2);
+    let handles = service.handles().into_iter().collect::<HashSet<_>>();
+    let expected = [0, 1]
+        .iter()
+        .map(|&instance| CharacteristicHandle {
+            service: service_uuid,
+            characteristic: Uuid::from_sdp_short_uuid(0x2A6E_u16),
+            instance,
+        })
+        .collect::<HashSet<_>>();
+    assert_eq!(handles, expected);
+}
@@ -0,0 +1,9 @@
+use bluster::gatt::default_handler::unhandled;
+
+#[test]
+fn test_unhandled_sender_is_closed() {
+    // Requests for characteristics using it go to the peripheral's default handlers
+    let sender = unhandled();
+    assert!(sender.is_closed());
+    assert!(sender.clone().is_closed());
+}
@@ -0,0 +1,113 @@
+use futures::channel::mpsc::channel;
+use uuid::Uuid;
+
+use bluster::{
+    gatt::{
+        characteristic::{self, Characteristic},
+        descriptor::{self, Descriptor},
+        service::Service,
+    },
+    SdpShortUuid,
+};
+
+#[test]
+fn test_describe_outlines_service() {
+    let (sender, _) = channel(1);
+    let level = Characteristic::new(
+        Uuid::parse_str("6e400003-b5a3-f393-e0a9-e50e24dcca9e").unwrap(),
+        characteristic::Properties::new(
+            Some(characteristic::Read(characteristic::Secure::Secure(
+                sender.clone(),
+            ))),
+            Some(characteristic::Write::WithoutResponse(sender.clone())),
+            Some(sender.clone()),
+            None,
+        ),
+        None,
+        vec![Descriptor::new(
+            Uuid::from_sdp_short_uuid(0x2904_u16),
+            descriptor::Properties::new(
+                Some(descriptor::Read(descriptor::Secure::Insecure(sender))),
+                None,
+            ),
+            None,
+        )]
+        .into_iter()
+        .collect(),
+    )
+    .with_description("Level");
+    let battery_level = Characteristic::from_raw(
+        Uuid::from_sdp_short_uuid(0x2A19_u16),
+        0x02,
+        0x01,
+        Some(vec![100]),
+    );
+    let service = Service::new(
+        Uuid::from_sdp_short_uuid(0x180F_u16),
+        true,
+        vec![level, battery_level].into_iter().collect(),
+    )
+    .with_max_value_length(20);
+
+    assert_eq!(
+        service.describe(),
+        "service 180F primary\n\
+         \x20 characteristic 2A19 #0: raw properties 0x02 permissions 0x01, value 1 bytes, max length 20\n\
+         \x20 characteristic 6e400003-b5a3-f393-e0a9-e50e24dcca9e #0: read (encrypted), write without response, notify, no value, max length 20\n\
+         \x20   descriptor 2901: value 5 bytes\n\
+         \x20   descriptor 2904: read, no value\n"
+    );
+}
+
+#[test]
+fn test_handle_count_counts_declarations_descriptors_and_cccd() {
+    let (sender, _) = channel(1);
+    let notifying = Characteristic::new(
+        Uuid::from_sdp_short_uuid(0x2A37_u16),
+        characteristic::Properties::new(None, None, Some(sender), None),
+        None,
+        Default::default(),
+    )
+    .with_description("Heart Rate");
+    let raw_indicating =
+        Characteristic::from_raw(Uuid::from_sdp_short_uuid(0x2A38_u16), 0x22, 0x01, None);
+    let readable =
+        Characteristic::from_raw(Uuid::from_sdp_short_uuid(0x2A39_u16), 0x02, 0x01, None);
+    let service = Service::new(
+        Uuid::from_sdp_short_uuid(0x180D_u16),
+        true,
+        vec![notifying, raw_indicating, readable]
+            .into_iter()
+            .collect(),
+    );
+
+    // Declaration, 2 + description + CCCD, 2 + CCCD, 2
+    assert_eq!(service.handle_count(), 1 + 4 + 3 + 2);
+    assert_eq!(
+        Service::new(
+            Uuid::from_sdp_short_uuid(0x180D_u16),
+            true,
+            Default::default()
+        )
+        .handle_count(),
+        1
+    );
+}
+
+#[test]
+fn test_included_services_are_described_and_counted() {
+    let battery = Uuid::from_sdp_short_uuid(0x180F_u16);
+    let service = Service::new(
+        Uuid::from_sdp_short_uuid(0x180A_u16),
+        true,
+        Default::default(),
+    )
+    .with_included_service(battery)
+    .with_included_service(battery);
+
+    assert_eq!(service.handle_count(), 2);
+    assert_eq!(
+        service.describe(),
+        "service 180A primary\n  includes 180F\n"
+    );
+}
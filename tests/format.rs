@@ -0,0 +1,31 @@
+use bluster::gatt::format;
+
+#[test]
+fn test_integer_formats() {
+    assert_eq!(format::uint16_le(0x1234), [0x34, 0x12]);
+    assert_eq!(format::sint16_le(-2), [0xFE, 0xFF]);
+    assert_eq!(format::uint32_le(0x1234_5678), [0x78, 0x56, 0x34, 0x12]);
+    assert_eq!(format::percent8(42), [42]);
+    assert_eq!(format::percent8(200), [100]);
+}
+
+#[test]
+fn test_sfloat() {
+    // 365 * 10^-1
+    assert_eq!(format::sfloat(36.5), [0x6D, 0xF1]);
+    // -12 * 10^0
+    assert_eq!(format::sfloat(-12.0), [0xF4, 0x0F]);
+    // 1200 * 10^2 as the mantissa can't hold more digits, sent as 12 * 10^4
+    assert_eq!(format::sfloat(120_049.0), [0x0C, 0x40]);
+    assert_eq!(format::sfloat(f32::NAN), [0xFF, 0x07]);
+    assert_eq!(format::sfloat(f32::INFINITY), [0xFE, 0x07]);
+    assert_eq!(format::sfloat(-1e12), [0x02, 0x08]);
+}
+
+#[test]
+fn test_float() {
+    // 36512 * 10^-3
+    assert_eq!(format::float(36.512), [0xA0, 0x8E, 0x00, 0xFD]);
+    assert_eq!(format::float(f64::NAN), [0xFF, 0xFF, 0x7F, 0x00]);
+    assert_eq!(format::float(f64::NEG_INFINITY), [0x02, 0x00, 0x80, 0x00]);
+}
@@ -0,0 +1,88 @@
+#![cfg(feature = "serde")]
+
+use bluster::{
+    gatt::config::{Access, CharacteristicConfig, ServiceConfig, WriteAccess},
+    SdpShortUuid,
+};
+use futures::channel::mpsc::channel;
+use uuid::Uuid;
+
+const BATTERY_SERVICE: &str = r#"{
+    "uuid": "180F",
+    "max_value_length": 20,
+    "characteristics": [
+        {
+            "uuid": "2A19",
+            "read": "insecure",
+            "notify": true,
+            "value": "64",
+            "descriptors": [{ "uuid": "2901", "read": "insecure", "value": "42617474657279" }]
+        },
+        {
+            "uuid": "6e400002-b5a3-f393-e0a9-e50e24dcca9e",
+            "write": "without_response"
+        }
+    ]
+}"#;
+
+#[test]
+fn test_service_config_from_json() {
+    let config: ServiceConfig = serde_json::from_str(BATTERY_SERVICE).unwrap();
+    assert_eq!(config.uuid, Uuid::from_sdp_short_uuid(0x180F_u16));
+    assert!(config.primary);
+    assert_eq!(config.max_value_length, Some(20));
+
+    let battery_level = &config.characteristics[0];
+    assert_eq!(battery_level.uuid, Uuid::from_sdp_short_uuid(0x2A19_u16));
+    assert_eq!(battery_level.read, Some(Access::Insecure));
+    assert!(battery_level.notify);
+    assert_eq!(battery_level.value, Some(vec![100]));
+    assert_eq!(
+        battery_level.descriptors[0].value,
+        Some(b"Battery".to_vec())
+    );
+
+    let command = &config.characteristics[1];
+    assert_eq!(
+        command.uuid,
+        Uuid::parse_str("6e400002-b5a3-f393-e0a9-e50e24dcca9e").unwrap()
+    );
+    assert_eq!(command.write, Some(WriteAccess::WithoutResponse));
+}
+
+#[test]
+fn test_service_config_round_trip() {
+    let config: ServiceConfig = serde_json::from_str(BATTERY_SERVICE).unwrap();
+    let json = serde_json::to_string(&config).unwrap();
+    assert!(json.contains(r#""uuid":"180F""#));
+    assert_eq!(
+        serde_json::from_str::<ServiceConfig>(&json).unwrap(),
+        config
+    );
+}
+
+#[test]
+fn test_service_config_rejects_invalid_values() {
+    let config = r#"{ "uuid": "2A19", "value": "6" }"#;
+    assert!(serde_json::from_str::<CharacteristicConfig>(config).is_err());
+    let config = r#"{ "uuid": "not a uuid" }"#;
+    assert!(serde_json::from_str::<CharacteristicConfig>(config).is_err());
+}
+
+#[test]
+fn test_service_config_attaches_handlers() {
+    let config: ServiceConfig = serde_json::from_str(BATTERY_SERVICE).unwrap();
+    let mut requested = vec![];
+    config.into_service(|uuid| {
+        requested.push(*uuid);
+        channel(1).0
+    });
+    requested.sort();
+    let mut expected = vec![
+        Uuid::from_sdp_short_uuid(0x2A19_u16),
+        Uuid::from_sdp_short_uuid(0x2901_u16),
+        Uuid::parse_str("6e400002-b5a3-f393-e0a9-e50e24dcca9e").unwrap(),
+    ];
+    expected.sort();
+    assert_eq!(requested, expected);
+}
@@ -0,0 +1,14 @@
+use bluster::gatt::{generic_access::GenericAccess, service::Service};
+
+#[test]
+fn test_generic_access_serves_name_and_appearance() {
+    // Generic watch
+    let service = GenericAccess::new("Watch", 0x00C0).into_service();
+    assert_eq!(
+        service.describe(),
+        "service 1800 primary\n\
+         \x20 characteristic 2A00 #0: raw properties 0x02 permissions 0x01, value 5 bytes\n\
+         \x20 characteristic 2A01 #0: raw properties 0x02 permissions 0x01, value 2 bytes\n"
+    );
+    assert_eq!(Service::validate_all(&[service]), Ok(()));
+}
@@ -0,0 +1,51 @@
+use bluster::gatt::{
+    event::{Event, NotifySubscribe},
+    notification_source::NotificationSource,
+};
+use futures::{channel::mpsc, prelude::*, stream};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+#[tokio::test]
+async fn test_notification_source_runs_while_subscribed() {
+    let started = Arc::new(AtomicUsize::new(0));
+    let mut event_sender = {
+        let started = Arc::clone(&started);
+        NotificationSource::new(move || {
+            started.fetch_add(1, Ordering::SeqCst);
+            stream::iter(vec![vec![1], vec![2]]).chain(stream::pending())
+        })
+        .into_event_sender()
+    };
+
+    let (notification, mut values) = mpsc::channel(1);
+    event_sender
+        .send(Event::NotifySubscribe(NotifySubscribe { notification }))
+        .await
+        .unwrap();
+    assert_eq!(values.next().await, Some(vec![1]));
+    assert_eq!(values.next().await, Some(vec![2]));
+
+    // A second subscriber shares the running source
+    let (notification, _) = mpsc::channel(1);
+    event_sender
+        .send(Event::NotifySubscribe(NotifySubscribe { notification }))
+        .await
+        .unwrap();
+    event_sender.send(Event::NotifyUnsubscribe).await.unwrap();
+    assert_eq!(started.load(Ordering::SeqCst), 1);
+
+    // Dropping the source after the last unsubscribe closes the notification channel
+    event_sender.send(Event::NotifyUnsubscribe).await.unwrap();
+    assert_eq!(values.next().await, None);
+
+    let (notification, mut values) = mpsc::channel(1);
+    event_sender
+        .send(Event::NotifySubscribe(NotifySubscribe { notification }))
+        .await
+        .unwrap();
+    assert_eq!(values.next().await, Some(vec![1]));
+    assert_eq!(started.load(Ordering::SeqCst), 2);
+}
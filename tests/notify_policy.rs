@@ -0,0 +1,57 @@
+use bluster::gatt::notify_policy::NotifyPolicy;
+use futures::{channel::mpsc, prelude::*};
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+const INTERVAL: Duration = Duration::from_millis(100);
+
+#[tokio::test(start_paused = true)]
+async fn test_notify_policy_paces_values() {
+    let (sender, receiver) = mpsc::unbounded();
+    for value in 0..3_u8 {
+        sender.unbounded_send(vec![value]).unwrap();
+    }
+    drop(sender);
+
+    let start = Instant::now();
+    let mut notifications = Box::pin(NotifyPolicy::new(INTERVAL, false).apply(receiver));
+    let mut sent = vec![];
+    while let Some(value) = notifications.next().await {
+        sent.push((value, start.elapsed()));
+    }
+    assert_eq!(
+        sent,
+        vec![
+            (vec![0], Duration::ZERO),
+            (vec![1], INTERVAL),
+            (vec![2], INTERVAL * 2),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_notify_policy_coalesces_to_latest_value() {
+    let (sender, receiver) = mpsc::unbounded();
+    let mut notifications = Box::pin(NotifyPolicy::new(INTERVAL, true).apply(receiver));
+
+    sender.unbounded_send(vec![0]).unwrap();
+    assert_eq!(notifications.next().await, Some(vec![0]));
+
+    for value in 1..5_u8 {
+        sender.unbounded_send(vec![value]).unwrap();
+    }
+    let start = Instant::now();
+    let notification = notifications.next().await;
+    assert_eq!(notification, Some(vec![4]));
+    assert_eq!(start.elapsed(), INTERVAL);
+
+    // A value after a quiet period goes out right away
+    time::sleep(INTERVAL * 2).await;
+    sender.unbounded_send(vec![5]).unwrap();
+    let start = Instant::now();
+    assert_eq!(notifications.next().await, Some(vec![5]));
+    assert_eq!(start.elapsed(), Duration::ZERO);
+
+    drop(sender);
+    assert_eq!(notifications.next().await, None);
+}
@@ -3,7 +3,7 @@ use std::{
     collections::HashSet,
     sync::{atomic, Arc, Mutex},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use uuid::Uuid;
 
@@ -21,6 +21,7 @@ use bluster::{
 
 const ADVERTISING_NAME: &str = "hello";
 const ADVERTISING_TIMEOUT: Duration = Duration::from_secs(60);
+const ADD_SERVICE_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[tokio::test]
 async fn it_advertises_gatt() {
@@ -117,6 +118,7 @@ async fn it_advertises_gatt() {
                     println!("GATT server got a notify unsubscribe!");
                     notifying.store(false, atomic::Ordering::Relaxed);
                 }
+                Event::NotifyQueueDrained(_) => {}
             };
         }
     };
@@ -156,16 +158,16 @@ async fn it_advertises_gatt() {
     };
 
     let peripheral = Peripheral::new().await.unwrap();
-    peripheral
-        .add_service(&Service::new(
-            Uuid::from_sdp_short_uuid(0x1234 as u16),
-            true,
-            characteristics,
-        ))
-        .unwrap();
     let main_fut = async move {
         while !peripheral.is_powered().await.unwrap() {}
         println!("Peripheral powered on");
+        peripheral
+            .add_service(&Service::new(
+                Uuid::from_sdp_short_uuid(0x1234 as u16),
+                true,
+                characteristics,
+            ))
+            .unwrap();
         peripheral.register_gatt().await.unwrap();
         peripheral
             .start_advertising(ADVERTISING_NAME, &[])
@@ -182,3 +184,149 @@ async fn it_advertises_gatt() {
 
     futures::join!(characteristic_handler, descriptor_handler, main_fut);
 }
+
+// Nothing here runs the main run loop or an `NSApplication`, the peripheral only gets a thread of
+// its own with a tokio runtime on it, like in a daemon.
+#[test]
+fn it_advertises_gatt_from_a_background_thread() {
+    if let Err(err) = pretty_env_logger::try_init() {
+        eprintln!("WARNING: failed to initialize logging framework: {}", err);
+    }
+    let peripheral_thread = thread::spawn(|| {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let (sender, mut receiver) = channel(1);
+            #[allow(clippy::mutable_key_type)]
+            let mut characteristics = HashSet::new();
+            characteristics.insert(Characteristic::new(
+                Uuid::from_sdp_short_uuid(0x2A3D_u16),
+                characteristic::Properties::new(
+                    Some(characteristic::Read(characteristic::Secure::Insecure(
+                        sender,
+                    ))),
+                    None,
+                    None,
+                    None,
+                ),
+                None,
+                HashSet::new(),
+            ));
+            let read_handler = async {
+                while let Some(event) = receiver.next().await {
+                    if let Event::ReadRequest(read_request) = event {
+                        println!("GATT server got a read request on a background thread!");
+                        read_request
+                            .response
+                            .send(Response::Success(b"hi".to_vec()))
+                            .unwrap();
+                    }
+                }
+            };
+
+            let peripheral = Peripheral::new().await.unwrap();
+            let main_fut = async move {
+                while !peripheral.is_powered().await.unwrap() {}
+                println!("Peripheral powered on");
+                peripheral
+                    .add_service(&Service::new(
+                        Uuid::from_sdp_short_uuid(0x1234_u16),
+                        true,
+                        characteristics,
+                    ))
+                    .unwrap();
+                peripheral.register_gatt().await.unwrap();
+                peripheral
+                    .start_advertising(ADVERTISING_NAME, &[])
+                    .await
+                    .unwrap();
+                while !peripheral.is_advertising().await.unwrap() {}
+                println!("Peripheral started advertising");
+                tokio::time::sleep(ADVERTISING_TIMEOUT).await;
+                peripheral.shutdown().await.unwrap();
+                println!("Peripheral shut down");
+            };
+            futures::pin_mut!(read_handler);
+            future::select(read_handler, Box::pin(main_fut)).await;
+        });
+    });
+    peripheral_thread.join().unwrap();
+}
+
+// `addService:` only queues the service, so a handler adding one doesn't wait on the dispatch
+// queue its request came from. BlueZ refuses services once the GATT application is registered,
+// which is fine as long as it answers right away.
+#[tokio::test]
+async fn it_adds_a_service_from_a_read_handler() {
+    if let Err(err) = pretty_env_logger::try_init() {
+        eprintln!("WARNING: failed to initialize logging framework: {}", err);
+    }
+    let (sender, mut receiver) = channel(1);
+    #[allow(clippy::mutable_key_type)]
+    let mut characteristics = HashSet::new();
+    characteristics.insert(Characteristic::new(
+        Uuid::from_sdp_short_uuid(0x2A3D_u16),
+        characteristic::Properties::new(
+            Some(characteristic::Read(characteristic::Secure::Insecure(
+                sender,
+            ))),
+            None,
+            None,
+            None,
+        ),
+        None,
+        HashSet::new(),
+    ));
+
+    let peripheral = Peripheral::new().await.unwrap();
+    let read_handler = {
+        let peripheral = peripheral.clone();
+        async move {
+            while let Some(event) = receiver.next().await {
+                if let Event::ReadRequest(read_request) = event {
+                    let start = Instant::now();
+                    let added = peripheral.add_service(&Service::new(
+                        Uuid::from_sdp_short_uuid(0x1235_u16),
+                        true,
+                        HashSet::new(),
+                    ));
+                    println!(
+                        "GATT server added a service from a read handler: {:?}",
+                        added
+                    );
+                    assert!(start.elapsed() < ADD_SERVICE_TIMEOUT);
+                    read_request
+                        .response
+                        .send(Response::Success(b"hi".to_vec()))
+                        .unwrap();
+                    return;
+                }
+            }
+        }
+    };
+
+    let main_fut = async move {
+        while !peripheral.is_powered().await.unwrap() {}
+        println!("Peripheral powered on");
+        peripheral
+            .add_service(&Service::new(
+                Uuid::from_sdp_short_uuid(0x1234_u16),
+                true,
+                characteristics,
+            ))
+            .unwrap();
+        peripheral.register_gatt().await.unwrap();
+        peripheral
+            .start_advertising(ADVERTISING_NAME, &[])
+            .await
+            .unwrap();
+        println!("Peripheral started advertising, read the characteristic to finish");
+        tokio::time::sleep(ADVERTISING_TIMEOUT).await;
+        peripheral.stop_advertising().await.unwrap();
+        println!("Nobody read the characteristic, peripheral stopped advertising");
+    };
+    futures::pin_mut!(read_handler);
+    future::select(read_handler, Box::pin(main_fut)).await;
+}
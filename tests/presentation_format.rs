@@ -0,0 +1,11 @@
+use bluster::gatt::presentation_format::{PresentationFormat, BLUETOOTH_SIG_NAMESPACE};
+
+#[test]
+fn test_presentation_format_to_bytes() {
+    // sint16 in hundredths of a degree Celsius
+    let presentation_format = PresentationFormat::new(0x0E, -2, 0x272F);
+    assert_eq!(
+        presentation_format.to_bytes(),
+        [0x0E, 0xFE, 0x2F, 0x27, BLUETOOTH_SIG_NAMESPACE, 0x00, 0x00]
+    );
+}
@@ -0,0 +1,84 @@
+use futures::channel::mpsc::channel;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use bluster::{
+    gatt::{
+        characteristic::{Characteristic, Properties, Read, Secure, Write},
+        event::AttError,
+    },
+    SdpShortUuid,
+};
+
+fn characteristic(read: Option<Read>, write: Option<Write>) -> Characteristic {
+    Characteristic::new(
+        Uuid::from_sdp_short_uuid(0x2A19_u16),
+        Properties::new(read, write, None, None),
+        None,
+        HashSet::new(),
+    )
+}
+
+#[test]
+fn test_read_only_characteristic_rejects_writes() {
+    let (sender, _) = channel(1);
+    let characteristic = characteristic(Some(Read(Secure::Insecure(sender))), None);
+    assert!(characteristic.check_read().is_ok());
+    assert_eq!(
+        characteristic.check_write(false).err(),
+        Some(AttError::WriteNotPermitted)
+    );
+    assert_eq!(
+        characteristic.check_write(true).err(),
+        Some(AttError::WriteNotPermitted)
+    );
+}
+
+#[test]
+fn test_write_only_characteristic_rejects_reads() {
+    let (sender, _) = channel(1);
+    let characteristic = characteristic(None, Some(Write::WithResponse(Secure::Secure(sender))));
+    assert_eq!(
+        characteristic.check_read().err(),
+        Some(AttError::ReadNotPermitted)
+    );
+    assert!(characteristic.check_write(false).is_ok());
+}
+
+#[test]
+fn test_write_of_the_wrong_kind_is_not_supported() {
+    let (sender, _) = channel(1);
+    let with_response = characteristic(
+        None,
+        Some(Write::WithResponse(Secure::Insecure(sender.clone()))),
+    );
+    assert_eq!(
+        with_response.check_write(true).err(),
+        Some(AttError::RequestNotSupported)
+    );
+
+    let without_response = characteristic(None, Some(Write::WithoutResponse(sender)));
+    assert!(without_response.check_write(true).is_ok());
+    assert_eq!(
+        without_response.check_write(false).err(),
+        Some(AttError::RequestNotSupported)
+    );
+}
+
+#[test]
+fn test_raw_characteristic_has_no_handlers() {
+    let characteristic = Characteristic::from_raw(
+        Uuid::from_sdp_short_uuid(0x2A19_u16),
+        0x0A,
+        0x03,
+        Some(vec![100]),
+    );
+    assert_eq!(
+        characteristic.check_read().err(),
+        Some(AttError::ReadNotPermitted)
+    );
+    assert_eq!(
+        characteristic.check_write(false).err(),
+        Some(AttError::WriteNotPermitted)
+    );
+}
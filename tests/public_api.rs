@@ -0,0 +1,213 @@
+//! The API every platform shares. This only has to compile, which keeps a backend from leaking
+//! platform specific types into the signatures or requiring its dependencies unconditionally.
+
+use futures::{channel::mpsc::channel, StreamExt};
+use std::{collections::HashSet, time::Duration};
+use uuid::Uuid;
+
+use bluster::{
+    gatt::{
+        characteristic::{self, Characteristic, CharacteristicHandle, ReadSource},
+        descriptor::{self, Descriptor},
+        event::{EventSender, ReadRequest, Response},
+        notify_pause::PausePolicy,
+        notify_queue::{NotifyQueue, OverflowPolicy},
+        service::Service,
+    },
+    AdapterState, AdvertisingOptions, AdvertisingState, Capabilities, CentralId, CentralInfo,
+    Error, ErrorType, NativePeripheral, NotifyError, Peripheral, PeripheralBackend,
+    PeripheralBuilder, PeripheralMetrics, PeripheralSnapshot, QualityOfService, SdpShortUuid,
+};
+
+fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+
+#[test]
+fn test_public_types_are_shareable() {
+    assert_shareable::<Peripheral>();
+    assert_shareable::<PeripheralBuilder>();
+    assert_shareable::<Service>();
+    assert_shareable::<Characteristic>();
+    assert_shareable::<Descriptor>();
+    assert_shareable::<Error>();
+    assert_shareable::<CentralInfo>();
+    assert_shareable::<EventSender>();
+    assert_shareable::<PeripheralSnapshot>();
+}
+
+#[test]
+fn test_service_can_be_declared() {
+    let (sender, _) = channel(1);
+    let characteristic = Characteristic::new(
+        Uuid::from_sdp_short_uuid(0x2A19_u16),
+        characteristic::Properties::new(
+            Some(characteristic::Read(characteristic::Secure::Insecure(
+                sender.clone(),
+            ))),
+            Some(characteristic::Write::WithResponse(
+                characteristic::Secure::Secure(sender.clone()),
+            )),
+            Some(sender.clone()),
+            None,
+        ),
+        Some(vec![100]),
+        vec![Descriptor::new(
+            Uuid::from_sdp_short_uuid(0x2901_u16),
+            descriptor::Properties::new(
+                Some(descriptor::Read(descriptor::Secure::Insecure(sender))),
+                None,
+            ),
+            Some(b"Battery Level".to_vec()),
+        )]
+        .into_iter()
+        .collect(),
+    )
+    .with_max_value_length(1)
+    .with_description("Battery Level")
+    .with_notify_queue(NotifyQueue::new(8, OverflowPolicy::DropOldest));
+    Service::new(
+        Uuid::from_sdp_short_uuid(0x180F_u16),
+        true,
+        HashSet::from([characteristic]),
+    )
+    .with_max_value_length(20);
+}
+
+#[test]
+fn test_error_describes_itself() {
+    let error = Error::new("Unsupported", "Not here", ErrorType::Unsupported);
+    assert!(error.to_string().contains("Not here"));
+    assert!(NotifyError::NoSubscribers
+        .to_string()
+        .contains("no central is subscribed"));
+}
+
+// Never called, only checks the signatures of the methods shared by all backends.
+#[allow(dead_code)]
+async fn peripheral_api(service: Service, characteristic_uuid: Uuid) -> Result<(), Error> {
+    let _: Peripheral = Peripheral::new().await?;
+    let peripheral = PeripheralBuilder::default()
+        .queue_label("bluster")
+        .quality_of_service(QualityOfService::Utility)
+        .subscription_timeout(Duration::from_secs(300))
+        .auto_restore(true)
+        .fail_if_unsupported(true)
+        .auto_stop_advertising_on_connect(true)
+        .build()
+        .await?;
+    let _: bool = peripheral.is_powered().await?;
+    let _: Option<i16> = peripheral.central_rssi(&CentralId::new("central")).await?;
+    let _: Option<String> = peripheral.central_name(&CentralId::new("central")).await;
+    let _: Option<usize> = peripheral.max_connections();
+    let _: Capabilities = peripheral.capabilities();
+    let _: PeripheralSnapshot = peripheral.snapshot();
+    let _: String = peripheral.describe();
+    let _: bool = peripheral.has_characteristic(&characteristic_uuid);
+    let _: Result<(), NotifyError> = peripheral.notify(&characteristic_uuid, &[1]).await;
+    let _: usize = peripheral.max_notification_length(&characteristic_uuid);
+    let _: Result<(), NotifyError> = peripheral
+        .notify_chunked(&characteristic_uuid, &[1, 2, 3])
+        .await;
+    let _: Result<(), NotifyError> = peripheral
+        .update_all(&characteristic_uuid, |_: &CentralId| vec![1, 2, 3])
+        .await;
+    let _: AdapterState = peripheral.current_state().await;
+    let _: AdapterState = peripheral.state();
+    peripheral.wait_until_powered_on().await?;
+    let _: AdapterState = *peripheral.power_state_stream().borrow();
+    let _: AdvertisingState = *peripheral.advertising_state_stream().borrow();
+    peripheral.register_gatt().await?;
+    let handles: Vec<CharacteristicHandle> = peripheral.add_service(&service)?;
+    let _: Result<(), NotifyError> = peripheral.notify_handle(&handles[0], &[1]).await;
+    peripheral
+        .add_services(std::slice::from_ref(&service))
+        .await?;
+    peripheral.replace_services(std::slice::from_ref(&service))?;
+    peripheral.remove_service(&Uuid::from_sdp_short_uuid(0x180F_u16))?;
+    peripheral.remove_all_services()?;
+    let _: Option<Vec<u8>> = peripheral.get_value(&characteristic_uuid);
+    let _: Option<ReadSource> = peripheral.read_source(&characteristic_uuid);
+    let _: Vec<CentralId> = peripheral.subscribers(&characteristic_uuid);
+    for central in peripheral.centrals() {
+        let CentralInfo {
+            id,
+            maximum_update_value_length,
+            subscriptions,
+        } = central;
+        let _: (CentralId, usize, HashSet<Uuid>) = (id, maximum_update_value_length, subscriptions);
+    }
+    let _: bool = peripheral.is_subscribed(&CentralId::new("central"), &characteristic_uuid);
+    peripheral.forget_central(&CentralId::new("central"));
+    let _: PeripheralMetrics = peripheral.metrics();
+    peripheral.pause_notifications(PausePolicy::KeepLatest);
+    peripheral.pause_notifications(PausePolicy::Drop);
+    let _: Result<(), NotifyError> = peripheral.resume_notifications().await;
+    peripheral.set_notify_interceptor(|characteristic, value| {
+        let _: &Uuid = characteristic;
+        value.insert(0, 0);
+    });
+    peripheral.set_default_read_handler(|context, request| {
+        let _ = request.response.send(Response::Success(vec![]));
+        let _: Uuid = context.characteristic;
+    });
+    peripheral.set_default_write_handler(|context, request| {
+        let _ = request.response.send(Response::Success(vec![]));
+        if let Some(central) = context.central {
+            let _: &CentralId = central.id();
+            let _: usize = central.maximum_update_value_length();
+        }
+    });
+    let mut writes = peripheral.writes(&characteristic_uuid);
+    if let Some(write) = writes.next().await {
+        let _ = write.response.send(Response::Success(vec![]));
+    }
+    peripheral.start_advertising("bluster", &[]).await?;
+    let options = AdvertisingOptions::new(Some(String::from("bluster")), vec![])
+        .with_interval(Duration::from_millis(1000), Duration::from_millis(1500))
+        .with_connectable(false);
+    let _: bool = options.connectable;
+    peripheral.start_advertising_with(&options).await?;
+    peripheral
+        .advertise_for(&options, Duration::from_secs(30))
+        .await?;
+    let _: bool = peripheral.is_advertising().await?;
+    let _: Option<String> = peripheral.system_default_name().await;
+    peripheral.stop_advertising().await?;
+    peripheral.unregister_gatt().await?;
+    peripheral.shutdown().await
+}
+
+#[allow(dead_code)]
+async fn read_request_api(read_request: ReadRequest) {
+    read_request
+        .respond_within(Duration::from_secs(1), async { Ok(vec![0]) })
+        .await;
+}
+
+#[allow(dead_code)]
+async fn backend_api<B: PeripheralBackend>(
+    backend: &B,
+    service: Service,
+    characteristic_uuid: Uuid,
+) -> Result<(), Error> {
+    let _: bool = backend.is_powered().await?;
+    let _: Vec<CharacteristicHandle> = backend.add_service(&service)?;
+    backend.remove_service(&Uuid::from_sdp_short_uuid(0x180F_u16))?;
+    backend.register_gatt().await?;
+    backend
+        .start_advertising_with(&AdvertisingOptions::default())
+        .await?;
+    let _: Result<(), NotifyError> = backend.notify(&characteristic_uuid, &[0]).await;
+    backend.stop_advertising().await
+}
+
+#[allow(dead_code)]
+async fn peripheral_is_backend(peripheral: Peripheral, service: Service) -> Result<(), Error> {
+    backend_api(&peripheral, service, Uuid::from_sdp_short_uuid(0x2A19_u16)).await
+}
+
+#[allow(dead_code)]
+async fn peripheral_with_backend(backend: NativePeripheral, service: Service) -> Result<(), Error> {
+    let backend: Box<dyn PeripheralBackend> = Box::new(backend);
+    let peripheral = Peripheral::with_backend(backend);
+    backend_api(&peripheral, service, Uuid::from_sdp_short_uuid(0x2A19_u16)).await
+}
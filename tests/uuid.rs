@@ -1,4 +1,5 @@
-use bluster::SdpShortUuid;
+use bluster::{SdpShortUuid, ShortUuid};
+use std::convert::TryFrom;
 use uuid::Uuid;
 
 #[test]
@@ -6,3 +7,48 @@ fn test_from_sdp_short_uuid() {
     Uuid::from_sdp_short_uuid(0x0000 as u16);
     Uuid::from_sdp_short_uuid(0x0000 as u32);
 }
+
+#[test]
+fn test_short_uuid_from_str() {
+    let heart_rate: ShortUuid = "180D".parse().unwrap();
+    assert_eq!(
+        heart_rate.to_uuid(),
+        Uuid::parse_str("0000180D-0000-1000-8000-00805F9B34FB").unwrap()
+    );
+    assert_eq!(ShortUuid::try_from("0x180d").unwrap(), heart_rate);
+    assert_eq!(
+        "1234ABCD".parse::<ShortUuid>().unwrap().to_uuid(),
+        Uuid::parse_str("1234ABCD-0000-1000-8000-00805F9B34FB").unwrap()
+    );
+    assert!("180".parse::<ShortUuid>().is_err());
+    assert!("18G0".parse::<ShortUuid>().is_err());
+    assert!("+180D".parse::<ShortUuid>().is_err());
+}
+
+#[test]
+fn test_short_uuid_round_trip() {
+    let heart_rate = Uuid::from_sdp_short_uuid(0x180D_u16);
+    let short_uuid = ShortUuid::from_uuid(&heart_rate).unwrap();
+    assert_eq!(short_uuid.to_string(), "180D");
+    assert_eq!(Uuid::from(short_uuid), heart_rate);
+    assert_eq!(ShortUuid::from(0x1234_ABCD_u32).to_string(), "1234ABCD");
+    assert_eq!(
+        ShortUuid::from_uuid(&Uuid::parse_str("6E400001-B5A3-F393-E0A9-E50E24DCCA9E").unwrap()),
+        None
+    );
+}
+
+const BATTERY_SERVICE: Uuid = bluster::uuid!("180F");
+
+#[test]
+fn test_uuid_macro() {
+    assert_eq!(BATTERY_SERVICE, Uuid::from_sdp_short_uuid(0x180F_u16));
+    assert_eq!(
+        bluster::uuid!("0x1234abcd"),
+        Uuid::from_sdp_short_uuid(0x1234_ABCD_u32)
+    );
+    assert_eq!(
+        bluster::uuid!("6e400001-b5a3-f393-e0a9-e50e24dcca9e"),
+        Uuid::parse_str("6E400001-B5A3-F393-E0A9-E50E24DCCA9E").unwrap()
+    );
+}
@@ -0,0 +1,148 @@
+use futures::channel::mpsc::channel;
+use uuid::Uuid;
+
+use bluster::{
+    gatt::{
+        characteristic::{self, Characteristic},
+        descriptor::{self, Descriptor},
+        service::Service,
+        validation::ValidationError,
+    },
+    SdpShortUuid,
+};
+
+fn uuid(short_uuid: u16) -> Uuid {
+    Uuid::from_sdp_short_uuid(short_uuid)
+}
+
+fn service(short_uuid: u16, characteristics: Vec<Characteristic>) -> Service {
+    Service::new(
+        uuid(short_uuid),
+        true,
+        characteristics.into_iter().collect(),
+    )
+}
+
+#[test]
+fn test_validate_all_accepts_coherent_services() {
+    let (sender, _) = channel(1);
+    let level = Characteristic::new(
+        uuid(0x2A19),
+        characteristic::Properties::new(
+            Some(characteristic::Read(characteristic::Secure::Insecure(
+                sender.clone(),
+            ))),
+            None,
+            Some(sender),
+            None,
+        ),
+        None,
+        Default::default(),
+    )
+    .with_description("Level");
+    let name = Characteristic::new(
+        uuid(0x2A00),
+        characteristic::Properties::new(
+            Some(characteristic::Read(characteristic::Secure::Insecure(
+                channel(1).0,
+            ))),
+            None,
+            None,
+            None,
+        ),
+        Some(b"bluster".to_vec()),
+        Default::default(),
+    );
+    let raw = Characteristic::from_raw(uuid(0x2A01), 0x02, 0x01, Some(vec![0, 0]));
+    assert_eq!(
+        Service::validate_all(&[
+            service(0x180F, vec![level]),
+            service(0x1800, vec![name, raw]),
+        ]),
+        Ok(())
+    );
+}
+
+#[test]
+fn test_validate_all_collects_every_mistake() {
+    let (sender, _) = channel(1);
+    let notified_value = Characteristic::new(
+        uuid(0x2A19),
+        characteristic::Properties::new(None, None, Some(sender.clone()), None),
+        Some(vec![0; 600]),
+        vec![Descriptor::new(
+            uuid(0x2902),
+            descriptor::Properties::new(None, None),
+            None,
+        )]
+        .into_iter()
+        .collect(),
+    );
+    let nothing = Characteristic::new(
+        uuid(0x2A1A),
+        characteristic::Properties::new(None, None, None, None),
+        None,
+        Default::default(),
+    );
+    let write_only_raw = Characteristic::from_raw(uuid(0x2A1B), 0x08, 0x01, None);
+
+    let errors = Service::validate_all(&[
+        service(0x180F, vec![notified_value, nothing, write_only_raw]),
+        service(0x180F, vec![]),
+    ])
+    .unwrap_err();
+
+    let (service, characteristic) = (uuid(0x180F), uuid(0x2A19));
+    assert_eq!(
+        errors,
+        vec![
+            ValidationError::CachedValueNotReadOnly {
+                service,
+                characteristic,
+            },
+            ValidationError::ValueTooLong {
+                service,
+                characteristic,
+                length: 600,
+                max_length: 512,
+            },
+            ValidationError::ReservedDescriptor {
+                service,
+                characteristic,
+                descriptor: uuid(0x2902),
+            },
+            ValidationError::NoProperties {
+                service,
+                characteristic: uuid(0x2A1A),
+            },
+            ValidationError::IncoherentRawFlags {
+                service,
+                characteristic: uuid(0x2A1B),
+            },
+            ValidationError::DuplicateService { service },
+        ]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "characteristic 2A19 of service 180F has a value but isn't read-only"
+    );
+}
+
+#[test]
+fn test_validate_all_reports_duplicate_characteristics_unless_allowed() {
+    let instance = |instance| {
+        Characteristic::from_raw(uuid(0x2A6E), 0x02, 0x01, Some(vec![0, 0])).with_instance(instance)
+    };
+    let service = service(0x181A, vec![instance(0), instance(1)]);
+    assert_eq!(
+        Service::validate_all(std::slice::from_ref(&service)),
+        Err(vec![ValidationError::DuplicateCharacteristic {
+            service: uuid(0x181A),
+            characteristic: uuid(0x2A6E),
+        }])
+    );
+    assert_eq!(
+        Service::validate_all(&[service.allow_duplicate_characteristics(true)]),
+        Ok(())
+    );
+}
@@ -0,0 +1,56 @@
+use bluster::gatt::{
+    event::{AttError, Event, NotifySubscribe, WriteRequest},
+    value_source::ValueSource,
+};
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+    stream::{self, BoxStream},
+};
+
+struct Counter(u8);
+
+impl ValueSource for Counter {
+    type Updates = BoxStream<'static, Vec<u8>>;
+
+    fn current_value(&mut self) -> Vec<u8> {
+        vec![self.0, self.0 + 1]
+    }
+
+    fn updates(&mut self) -> Self::Updates {
+        stream::iter(vec![vec![self.0 + 2]])
+            .chain(stream::pending())
+            .boxed()
+    }
+}
+
+#[tokio::test]
+async fn test_value_source_events() {
+    let mut event_sender = Counter(7).into_event_sender();
+
+    let (response, receiver) = oneshot::channel();
+    event_sender
+        .send(Event::WriteRequest(WriteRequest {
+            data: vec![1],
+            offset: 0,
+            without_response: false,
+            response,
+            central: None,
+        }))
+        .await
+        .unwrap();
+    assert_eq!(
+        receiver.await.unwrap().into_result(),
+        Err(AttError::RequestNotSupported)
+    );
+
+    let (notification, mut values) = mpsc::channel(1);
+    event_sender
+        .send(Event::NotifySubscribe(NotifySubscribe { notification }))
+        .await
+        .unwrap();
+    assert_eq!(values.next().await, Some(vec![9]));
+
+    event_sender.send(Event::NotifyUnsubscribe).await.unwrap();
+    assert_eq!(values.next().await, None);
+}
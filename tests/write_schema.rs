@@ -0,0 +1,61 @@
+use bluster::gatt::{event::AttError, write_schema::WriteSchema};
+
+const RESET: u8 = 0x01;
+const SET_INTERVAL: u8 = 0x02;
+
+/// A control point taking an opcode, followed by a little endian interval for `SET_INTERVAL`.
+fn control_point() -> WriteSchema {
+    WriteSchema::new()
+        .with_length_range(1, 3)
+        .with_validator(|value| match (value[0], value.len()) {
+            (RESET, 1) | (SET_INTERVAL, 3) => Ok(()),
+            (RESET, _) | (SET_INTERVAL, _) => Err(AttError::InvalidAttributeValueLength),
+            _ => Err(AttError::RequestNotSupported),
+        })
+}
+
+#[test]
+fn test_write_schema_checks_length_before_validator() {
+    let schema = control_point();
+    assert_eq!(schema.check(0, &[RESET]), Ok(()));
+    assert_eq!(schema.check(0, &[SET_INTERVAL, 0x10, 0x00]), Ok(()));
+    assert_eq!(
+        schema.check(0, &[]),
+        Err(AttError::InvalidAttributeValueLength)
+    );
+    assert_eq!(
+        schema.check(0, &[SET_INTERVAL, 0x10, 0x00, 0x00]),
+        Err(AttError::InvalidAttributeValueLength)
+    );
+    assert_eq!(schema.check(0, &[0xFF]), Err(AttError::RequestNotSupported));
+    assert_eq!(schema.check(1, &[RESET]), Err(AttError::InvalidOffset));
+    assert_eq!(WriteSchema::new().with_length(2).check(0, &[0, 0]), Ok(()));
+}
+
+#[test]
+fn test_write_schema_survives_arbitrary_values() {
+    let schema = control_point();
+    // xorshift, so every run checks the same values
+    let mut state = 0x2545_F491_u32;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    for _ in 0..10_000 {
+        let offset = if next() % 8 == 0 { next() as u16 } else { 0 };
+        let value = (0..next() % 8).map(|_| next() as u8).collect::<Vec<_>>();
+        let accepted = offset == 0
+            && matches!(
+                (value.first(), value.len()),
+                (Some(&RESET), 1) | (Some(&SET_INTERVAL), 3)
+            );
+        assert_eq!(
+            schema.check(offset, &value).is_ok(),
+            accepted,
+            "{:?}",
+            value
+        );
+    }
+}